@@ -0,0 +1,218 @@
+use std::{
+    backtrace::Backtrace,
+    cell::Cell,
+    collections::{BTreeSet, VecDeque},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use lsp_server::Message;
+use lsp_types::Url;
+use tracing::{error, warn};
+
+// #Insight
+// Only the methods and ids of the messages are recorded, not the params, the
+// reports may be attached to public issues and must not leak the sources.
+
+/// The number of recent messages in a crash report.
+const RECENT_MESSAGE_COUNT: usize = 32;
+
+/// The file of the crash report, in the report directory.
+const REPORT_FILE: &str = "crash-report.txt";
+
+/// The file of the crash report of the previous session, after it was
+/// mentioned on start.
+const PREVIOUS_REPORT_FILE: &str = "crash-report-previous.txt";
+
+/// The file of the report of the last panic of a worker task, the server
+/// recovered from it.
+const WORKER_PANIC_FILE: &str = "worker-panic.txt";
+
+/// The cache directory of the server, e.g. for crash reports, relative to the
+/// home directory.
+pub const SERVER_CACHE_DIR: &str = ".tan/lsp";
+
+/// The cache directory of the server, overrides `~/.tan/lsp`.
+pub const SERVER_CACHE_DIR_ENV_VAR: &str = "TAN_LSP_SERVER_CACHE_DIR";
+
+thread_local! {
+    /// True in the worker threads, that recover from the panics of the tasks.
+    static RECOVERS_FROM_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The state of the session included in crash reports.
+struct SessionState {
+    messages: VecDeque<String>,
+    documents: BTreeSet<String>,
+}
+
+static STATE: Mutex<SessionState> = Mutex::new(SessionState {
+    messages: VecDeque::new(),
+    documents: BTreeSet::new(),
+});
+
+/// The directory of the crash reports, set when the panic hook is installed.
+static REPORT_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+fn with_state(f: impl FnOnce(&mut SessionState)) {
+    f(&mut STATE.lock().unwrap_or_else(PoisonError::into_inner));
+}
+
+/// Records the summary of a message from the client.
+pub fn record_message(msg: &Message) {
+    let summary = match msg {
+        Message::Request(req) => format!("request {} {}", req.id, req.method),
+        Message::Response(resp) => format!("response {}", resp.id),
+        Message::Notification(notification) => format!("notification {}", notification.method),
+    };
+
+    with_state(|state| {
+        if state.messages.len() == RECENT_MESSAGE_COUNT {
+            state.messages.pop_front();
+        }
+        state.messages.push_back(summary);
+    });
+}
+
+pub fn document_opened(uri: &Url) {
+    with_state(|state| {
+        state.documents.insert(uri.to_string());
+    });
+}
+
+pub fn document_closed(uri: &Url) {
+    with_state(|state| {
+        state.documents.remove(uri.as_str());
+    });
+}
+
+/// Marks the current thread as recovering from panics, e.g. a worker thread.
+pub fn recover_from_panics() {
+    RECOVERS_FROM_PANICS.with(|recovers| recovers.set(true));
+}
+
+/// Returns the cache directory of the server, `None` if neither
+/// `TAN_LSP_SERVER_CACHE_DIR` nor the home directory is set.
+pub fn server_cache_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(SERVER_CACHE_DIR_ENV_VAR).filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(SERVER_CACHE_DIR))
+}
+
+/// Installs a panic hook that writes a crash report to the directory, before
+/// the default hook runs. Only the panics of the main thread, that runs the
+/// server loop, crash the server. The panics of the worker threads are
+/// reported separately, the other threads only log them.
+pub fn install_panic_hook(report_dir: PathBuf) {
+    let _ = REPORT_DIR.set(report_dir);
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        let thread_name = thread.name().unwrap_or("?");
+        let cause = format!("panic in thread `{thread_name}`: {info}");
+
+        if RECOVERS_FROM_PANICS.with(Cell::get) {
+            match write_report(WORKER_PANIC_FILE, &cause, &capture_backtrace()) {
+                Some(Ok(path)) => error!("{cause}, wrote a report to `{}`", path.display()),
+                Some(Err(error)) => error!("{cause}, cannot write the report: {error}"),
+                None => error!("{cause}"),
+            }
+        } else if thread_name == "main" {
+            log_crash_report(write_report(REPORT_FILE, &cause, &capture_backtrace()));
+        }
+
+        default_hook(info);
+    }));
+}
+
+fn capture_backtrace() -> String {
+    Backtrace::force_capture().to_string()
+}
+
+/// Writes a crash report for an abnormal exit of the server, e.g. a protocol
+/// error.
+pub fn report_error(error: &anyhow::Error) {
+    let cause = format!("error: {error:#}");
+    log_crash_report(write_report(
+        REPORT_FILE,
+        &cause,
+        &error.backtrace().to_string(),
+    ));
+}
+
+fn log_crash_report(result: Option<std::io::Result<PathBuf>>) {
+    match result {
+        Some(Ok(path)) => error!(
+            "the server crashed, wrote a crash report to `{}`",
+            path.display()
+        ),
+        Some(Err(error)) => error!("cannot write the crash report: {error}"),
+        None => {}
+    }
+}
+
+/// Writes a report to a file of the report directory, returns its path.
+/// `None` if the server has no report directory.
+fn write_report(file_name: &str, cause: &str, backtrace: &str) -> Option<std::io::Result<PathBuf>> {
+    let report_dir = REPORT_DIR.get()?;
+
+    // #Insight
+    // The hook may run while the state is locked, e.g. if recording a message
+    // panics, the report is written without the session state.
+    let state = STATE.try_lock().ok();
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let mut report = String::new();
+    let title = if file_name == WORKER_PANIC_FILE {
+        "worker panic report"
+    } else {
+        "crash report"
+    };
+    let _ = writeln!(report, "Tan LSP server {title}\n");
+    let _ = writeln!(report, "version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "time: {timestamp} (unix)");
+    let _ = writeln!(report, "cause: {cause}\n");
+
+    if let Some(state) = &state {
+        let _ = writeln!(report, "open documents:");
+        for uri in &state.documents {
+            let _ = writeln!(report, "  {uri}");
+        }
+        let _ = writeln!(report, "\nlast messages, the latest last:");
+        for message in &state.messages {
+            let _ = writeln!(report, "  {message}");
+        }
+        report.push('\n');
+    }
+
+    let _ = writeln!(report, "backtrace:\n{backtrace}");
+
+    let path = report_dir.join(file_name);
+    let result = std::fs::create_dir_all(report_dir).and_then(|_| std::fs::write(&path, report));
+
+    Some(result.map(|()| path))
+}
+
+/// Returns the path of the crash report of the previous session, if any. The
+/// report is mentioned once, it is kept as the previous report.
+pub fn take_previous_report(report_dir: &Path) -> Option<PathBuf> {
+    let path = report_dir.join(REPORT_FILE);
+    if !path.is_file() {
+        return None;
+    }
+
+    let previous_path = report_dir.join(PREVIOUS_REPORT_FILE);
+    if let Err(error) = std::fs::rename(&path, &previous_path) {
+        warn!("cannot move the crash report `{}`: {error}", path.display());
+        return Some(path);
+    }
+
+    Some(previous_path)
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use lsp_types::{
+    AnnotatedTextEdit, ChangeAnnotation, CreateFile, CreateFileOptions, DocumentChangeOperation,
+    DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier, ResourceOp,
+    ResourceOperationKind, TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::snapshot::Snapshot;
+
+// #Insight
+// Change annotations let the client show a refactor preview, grouped by
+// label. Edits that need confirmation (e.g. low-confidence matches in strings
+// and comments) are only applied after the user reviews them.
+
+// #Insight
+// Resource operations (e.g. creating files) can only be expressed with
+// document changes, clients advertise the supported operations separately.
+
+// #TODO support rename and delete resource operations.
+
+/// Builds a workspace edit, with optional change annotations.
+#[derive(Default)]
+pub struct EditBuilder {
+    edits: HashMap<Url, Vec<(TextEdit, Option<String>)>>,
+    annotations: HashMap<String, ChangeAnnotation>,
+    /// The files to create, applied before the text edits.
+    created_files: Vec<(Url, Option<String>)>,
+}
+
+impl EditBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a change annotation, referenced by id in `insert`.
+    pub fn annotation(
+        &mut self,
+        id: &str,
+        label: impl Into<String>,
+        description: Option<String>,
+        needs_confirmation: bool,
+    ) {
+        self.annotations.insert(
+            id.to_owned(),
+            ChangeAnnotation {
+                label: label.into(),
+                needs_confirmation: Some(needs_confirmation),
+                description,
+            },
+        );
+    }
+
+    /// Adds an edit, optionally annotated with a previously defined annotation.
+    pub fn insert(&mut self, uri: Url, edit: TextEdit, annotation_id: Option<&str>) {
+        self.edits
+            .entry(uri)
+            .or_default()
+            .push((edit, annotation_id.map(str::to_owned)));
+    }
+
+    /// Adds the creation of a file, optionally annotated. Existing files are
+    /// left untouched.
+    pub fn create_file(&mut self, uri: Url, annotation_id: Option<&str>) {
+        self.created_files
+            .push((uri, annotation_id.map(str::to_owned)));
+    }
+
+    /// Returns true if the builder has no edits, and no files to create.
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty() && self.created_files.is_empty()
+    }
+
+    /// Returns true if the client supports a resource operation in workspace
+    /// edits.
+    pub fn supports_resource_operation(snapshot: &Snapshot, kind: ResourceOperationKind) -> bool {
+        snapshot
+            .config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .is_some_and(|capabilities| {
+                capabilities.document_changes == Some(true)
+                    && capabilities
+                        .resource_operations
+                        .as_ref()
+                        .is_some_and(|operations| operations.contains(&kind))
+            })
+    }
+
+    /// Returns true if the client supports change annotations.
+    pub fn supports_annotations(snapshot: &Snapshot) -> bool {
+        snapshot
+            .config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .is_some_and(|capabilities| {
+                capabilities.document_changes == Some(true)
+                    && capabilities.change_annotation_support.is_some()
+            })
+    }
+
+    /// Builds the workspace edit, annotated if the client supports change
+    /// annotations. Fails if the edit contains resource operations that the
+    /// client does not support.
+    pub fn build(self, snapshot: &Snapshot) -> anyhow::Result<WorkspaceEdit> {
+        let annotated = Self::supports_annotations(snapshot);
+
+        if self.created_files.is_empty() {
+            if !annotated {
+                return Ok(self.build_changes());
+            }
+        } else if !Self::supports_resource_operation(snapshot, ResourceOperationKind::Create) {
+            bail!("the client does not support creating files in workspace edits");
+        }
+
+        let mut document_changes = Vec::new();
+
+        for (uri, annotation_id) in self.created_files {
+            let annotation_id = annotation_id.filter(|_| annotated);
+            document_changes.push(DocumentChangeOperation::Op(ResourceOp::Create(
+                CreateFile {
+                    uri,
+                    options: Some(CreateFileOptions {
+                        overwrite: Some(false),
+                        ignore_if_exists: Some(true),
+                    }),
+                    annotation_id,
+                },
+            )));
+        }
+
+        for (uri, edits) in self.edits {
+            let version = snapshot.vfs.version(&uri);
+            let edits = edits
+                .into_iter()
+                .filter_map(|(edit, annotation_id)| match annotation_id {
+                    Some(annotation_id) if annotated => Some(OneOf::Right(AnnotatedTextEdit {
+                        text_edit: edit,
+                        annotation_id,
+                    })),
+                    // Without annotations, the edits that need confirmation
+                    // are dropped.
+                    Some(annotation_id)
+                        if needs_confirmation(&self.annotations, &annotation_id) =>
+                    {
+                        None
+                    }
+                    _ => Some(OneOf::Left(edit)),
+                })
+                .collect();
+
+            document_changes.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri, version },
+                edits,
+            }));
+        }
+
+        Ok(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(document_changes)),
+            change_annotations: annotated.then_some(self.annotations),
+            ..Default::default()
+        })
+    }
+
+    /// Builds a plain workspace edit, for clients without change annotations.
+    /// The edits that need confirmation are dropped, they should never be
+    /// applied without review.
+    fn build_changes(self) -> WorkspaceEdit {
+        let mut changes = HashMap::new();
+
+        for (uri, edits) in self.edits {
+            let edits: Vec<TextEdit> = edits
+                .into_iter()
+                .filter(|(_, annotation_id)| {
+                    annotation_id
+                        .as_ref()
+                        .is_none_or(|id| !needs_confirmation(&self.annotations, id))
+                })
+                .map(|(edit, _)| edit)
+                .collect();
+
+            if !edits.is_empty() {
+                changes.insert(uri, edits);
+            }
+        }
+
+        WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }
+    }
+}
+
+/// Returns true if the annotation with the given id needs confirmation.
+fn needs_confirmation(annotations: &HashMap<String, ChangeAnnotation>, id: &str) -> bool {
+    annotations
+        .get(id)
+        .is_some_and(|annotation| annotation.needs_confirmation == Some(true))
+}
@@ -0,0 +1,334 @@
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tan::{
+    ann::Ann,
+    api::eval_string,
+    error::Error,
+    eval::env::Env,
+    expr::Expr,
+    range::{Range, Ranged},
+};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    cancellation::{CancellationToken, Cancelled},
+    lsp_ext::EvaluateResult,
+    sandbox::{FilesystemAccess, SandboxPolicy},
+};
+
+// #Insight
+// The forms are evaluated in a child process, the server itself, started with
+// the hidden `eval` subcommand: a runaway evaluation is killed without taking
+// the server down, and the interpreter state never leaks between evaluations.
+// The child writes the output of the program to stdout, and the outcome as a
+// JSON line to stderr.
+
+// #Insight
+// The Tan prelude has no network, process, or file writing functions, the
+// sandbox only has to deny the file reads, and the `use` forms that read the
+// module directories.
+
+// #Insight
+// The child limits its own address space to the memory limit, before reading
+// the source. An allocation beyond the limit aborts the child, or fails the
+// read of the source, the failure is reported on stderr.
+
+/// The name of the hidden subcommand that evaluates the source of stdin.
+pub const EVAL_SUBCOMMAND: &str = "eval";
+
+/// The prelude functions that read files.
+const READ_FUNCTIONS: [&str; 2] = ["File:read_as_string", "File:read_as_string$$String"];
+
+/// The interval between the checks of a running evaluation, for the time
+/// limit, the cancellation and the interrupts.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The messages of the failed allocations, of the allocator and of the I/O
+/// errors.
+const ALLOCATION_FAILURES: [&str; 2] = ["memory allocation of", "out of memory"];
+
+/// The maximum length of the captured output, in bytes, the rest is dropped.
+const MAX_OUTPUT_LEN: u64 = 64 * 1024;
+
+/// Incremented by `tan/interruptEvaluation`, the running evaluations are
+/// killed when it changes.
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of running evaluations.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// The outcome of an evaluation, reported by the child process.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Outcome {
+    value: Option<String>,
+    error: Option<String>,
+}
+
+/// Returns true if a top-level form can be evaluated, i.e. it is a call, not a
+/// definition or an import.
+pub fn is_evaluable(expr: &Ann<Expr>) -> bool {
+    matches!(expr, Ann(Expr::List(terms), _) if !terms.is_empty())
+        && !matches!(head_symbol(expr), Some("let") | Some("use"))
+}
+
+/// Returns the source that evaluates the top-level form at the (char) index,
+/// with the definitions and imports that precede it, and the range of the
+/// form.
+pub fn evaluation_source(
+    input: &str,
+    exprs: &[Ann<Expr>],
+    index: usize,
+) -> Option<(String, Range)> {
+    let position = exprs.iter().position(|expr| {
+        let range = expr.get_range();
+        range.start <= index && index < range.end
+    })?;
+
+    let form = &exprs[position];
+    if !is_evaluable(form) {
+        return None;
+    }
+
+    let text =
+        |range: Range| -> String { input.chars().skip(range.start).take(range.len()).collect() };
+
+    let mut source: Vec<String> = exprs[..position]
+        .iter()
+        .filter(|expr| matches!(head_symbol(expr), Some("let") | Some("use")))
+        .map(|expr| text(expr.get_range()))
+        .collect();
+    source.push(text(form.get_range()));
+
+    Some((source.join("\n"), form.get_range()))
+}
+
+/// Returns true if the expressions contain a `use` form, it reads the files
+/// of the module.
+pub fn contains_use(exprs: &[Ann<Expr>]) -> bool {
+    let mut found = false;
+    for_each_expr(exprs, &mut |expr| found |= head_symbol(expr) == Some("use"));
+    found
+}
+
+/// Interrupts the running evaluations, returns true if an evaluation was
+/// running.
+pub fn interrupt() -> bool {
+    INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+    RUNNING.load(Ordering::SeqCst) > 0
+}
+
+/// Evaluates a source in a child process, restricted by the sandbox policy,
+/// in the directory of the workspace.
+pub fn evaluate(
+    source: &str,
+    root_path: Option<&Path>,
+    policy: &SandboxPolicy,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<EvaluateResult> {
+    let filesystem = match policy.filesystem {
+        FilesystemAccess::None => "none",
+        FilesystemAccess::Read => "read",
+        FilesystemAccess::Write => "write",
+    };
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg(EVAL_SUBCOMMAND)
+        .arg("--filesystem")
+        .arg(filesystem)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(memory_limit) = policy.memory_limit {
+        command.arg("--memory-limit").arg(memory_limit.to_string());
+    }
+    if let Some(root_path) = root_path {
+        command.current_dir(root_path);
+    }
+
+    let mut child = command.spawn()?;
+
+    // The child reads the whole source before evaluating it.
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(source.as_bytes())?;
+    }
+
+    let stdout = child.stdout.take().map(capture);
+    let stderr = child.stderr.take().map(capture);
+
+    let interrupts = INTERRUPTS.load(Ordering::SeqCst);
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    let start = Instant::now();
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Ok(status);
+        }
+
+        let stop = if cancellation.is_cancelled() {
+            Some(Err(Cancelled.into()))
+        } else if INTERRUPTS.load(Ordering::SeqCst) != interrupts {
+            Some(Ok("the evaluation was interrupted".to_owned()))
+        } else if policy
+            .time_limit
+            .is_some_and(|limit| start.elapsed() > limit)
+        {
+            let seconds = policy.time_limit.unwrap_or_default().as_secs();
+            Some(Ok(format!(
+                "the evaluation exceeded the time limit of {seconds}s"
+            )))
+        } else {
+            None
+        };
+
+        if let Some(stop) = stop {
+            let _ = child.kill();
+            let _ = child.wait();
+            break Err(stop);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    RUNNING.fetch_sub(1, Ordering::SeqCst);
+
+    let output = stdout
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    let stderr = stderr
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+
+    let outcome = match status {
+        Ok(status) => {
+            // The outcome is the last line, the debug output of the
+            // interpreter may precede it.
+            let outcome = stderr
+                .lines()
+                .last()
+                .and_then(|line| serde_json::from_str::<Outcome>(line).ok());
+            let memory_limit = policy.memory_limit.filter(|_| {
+                ALLOCATION_FAILURES
+                    .iter()
+                    .any(|failure| stderr.contains(failure))
+            });
+            match (outcome, memory_limit) {
+                (Some(outcome), _) => outcome,
+                (None, Some(memory_limit)) => Outcome {
+                    value: None,
+                    error: Some(format!(
+                        "the evaluation exceeded the memory limit of {memory_limit} MiB"
+                    )),
+                },
+                // e.g. `(exit 1)`
+                (None, None) => Outcome {
+                    value: None,
+                    error: Some(format!("the evaluation exited with {status}")),
+                },
+            }
+        }
+        Err(Ok(error)) => Outcome {
+            value: None,
+            error: Some(error),
+        },
+        Err(Err(error)) => return Err(error),
+    };
+
+    Ok(EvaluateResult {
+        value: outcome.value,
+        output,
+        error: outcome.error,
+    })
+}
+
+/// Reads a pipe of the child process in a thread, the output beyond the
+/// maximum length is drained.
+fn capture(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.by_ref().take(MAX_OUTPUT_LEN).read_to_end(&mut bytes);
+        let _ = io::copy(&mut pipe, &mut io::sink());
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+/// Evaluates the source of stdin, in the child process started by
+/// `evaluate`. The memory limit is in MiB.
+pub fn run_evaluation(filesystem: &str, memory_limit: Option<u64>) -> anyhow::Result<()> {
+    let Some(filesystem) = FilesystemAccess::parse(filesystem) else {
+        bail!("unknown filesystem access `{filesystem}`");
+    };
+
+    if let Some(memory_limit) = memory_limit {
+        limit_memory(memory_limit)?;
+    }
+
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+
+    let mut env = Env::prelude();
+    if filesystem == FilesystemAccess::None {
+        for name in READ_FUNCTIONS {
+            env.insert(name, Expr::ForeignFunc(Rc::new(deny_file_access)));
+        }
+    }
+
+    let outcome = match eval_string(&source, &mut env) {
+        Ok(value) => Outcome {
+            value: Some(value.to_string()),
+            error: None,
+        },
+        Err(errors) => Outcome {
+            value: None,
+            error: Some(
+                errors
+                    .iter()
+                    .map(|error| error.0.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        },
+    };
+
+    io::stdout().flush()?;
+    eprintln!("{}", serde_json::to_string(&outcome)?);
+
+    Ok(())
+}
+
+/// Limits the address space of the current process, in MiB.
+#[cfg(unix)]
+fn limit_memory(mebibytes: u64) -> anyhow::Result<()> {
+    let bytes = mebibytes.saturating_mul(1024 * 1024) as libc::rlim_t;
+    let limit = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: bytes,
+    };
+
+    // SAFETY: `setrlimit` only reads the limit, that outlives the call.
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } != 0 {
+        bail!("cannot limit the memory: {}", io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// The memory limit is not enforced on the other platforms.
+#[cfg(not(unix))]
+fn limit_memory(_mebibytes: u64) -> anyhow::Result<()> {
+    Ok(())
+}
+
+fn deny_file_access(_args: &[Ann<Expr>], _env: &Env) -> Result<Ann<Expr>, Ranged<Error>> {
+    Err(Error::invalid_arguments("the sandbox denies the filesystem access").into())
+}
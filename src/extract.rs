@@ -0,0 +1,463 @@
+use std::path::Path;
+
+use lsp_types::{SymbolKind, TextEdit};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    format::{format_exprs, FormatOptions},
+    imports::{import_insertion, import_key},
+    indent::{top_level_form_ranges, whole_line_range},
+    module::{attached_comments, references_name, resolve_import, summarize_module},
+    resolve::module_level_symbols,
+    scope::{escaping_bindings, free_variables},
+    snapshot::Snapshot,
+    symbols::collect_definitions,
+    unicode::same_name,
+    util::{lsp_position_from_index, lsp_range_from_range},
+};
+
+// #Insight
+// The module is named after the first extracted definition. Workspace edits
+// cannot name a file from a snippet placeholder, the user renames the new file
+// instead, and the imports follow (see `will_rename_files`).
+
+// #Insight
+// All the top-level definitions of a module are exported, the extraction only
+// adds the imports: the new module imports the modules used by the extracted
+// forms, and the document imports the new module if it still references the
+// extracted definitions.
+
+/// The extraction of top-level forms into a new module, next to the document.
+pub struct ModuleExtraction {
+    pub module_name: String,
+    /// The text of the new module file.
+    pub module_text: String,
+    /// The edits of the document.
+    pub edits: Vec<TextEdit>,
+}
+
+/// Returns true if the expression overlaps the (char) selection. An empty
+/// selection overlaps the expression at the cursor.
+fn overlaps(expr: &Ann<Expr>, selection: &Range) -> bool {
+    let range = expr.get_range();
+    let selection_end = selection.end.max(selection.start + 1);
+    range.start < range.end && range.start < selection_end && selection.start < range.end
+}
+
+/// Extracts the selected top-level definitions (and their comments) of a
+/// document into a new module. Returns `None` if the selection contains
+/// other forms, or an error with the reason if the extraction is not
+/// possible.
+pub fn extract_module(
+    snapshot: &Snapshot,
+    exprs: &[Ann<Expr>],
+    input: &str,
+    selection: &Range,
+    base_dir: &Path,
+) -> Option<Result<ModuleExtraction, String>> {
+    let first = exprs.iter().position(|expr| overlaps(expr, selection))?;
+    let last = exprs.iter().rposition(|expr| overlaps(expr, selection))?;
+    let selected = &exprs[first..=last];
+
+    let is_extractable =
+        |expr: &Ann<Expr>| matches!(expr.0, Expr::Comment(_)) || head_symbol(expr) == Some("let");
+    if !selected.iter().all(is_extractable) {
+        return None;
+    }
+
+    let definitions = collect_definitions(selected);
+    let module_name = definitions.first()?.name.clone();
+
+    if resolve_import(&module_name, base_dir, &snapshot.config).is_some() {
+        return Some(Err(format!("The module `{module_name}` already exists")));
+    }
+
+    let (before, after) = (&exprs[..first], &exprs[last + 1..]);
+    let remaining: Vec<&Ann<Expr>> = before.iter().chain(after).collect();
+
+    // The extracted forms cannot reference the remaining definitions, the
+    // modules would import each other.
+    let symbols = module_level_symbols(exprs, selected);
+    let remaining_definitions = collect_definitions(before)
+        .into_iter()
+        .chain(collect_definitions(after))
+        .filter(|definition| definition.kind != SymbolKind::MODULE);
+    for definition in remaining_definitions {
+        let is_extracted = definitions
+            .iter()
+            .any(|extracted| same_name(&extracted.name, &definition.name));
+        if !is_extracted
+            && symbols
+                .iter()
+                .any(|(sym, _)| same_name(sym, &definition.name))
+        {
+            return Some(Err(format!(
+                "The extracted forms reference `{}`, defined in this document",
+                definition.name
+            )));
+        }
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    let text = |range: &Range| -> String { chars[range.clone()].iter().collect() };
+
+    // The new module imports the modules used by the extracted forms.
+    let mut imports = Vec::new();
+    for use_expr in remaining
+        .iter()
+        .filter(|expr| head_symbol(expr) == Some("use"))
+    {
+        let Ann(Expr::List(terms), _) = use_expr else {
+            continue;
+        };
+        let Some(Ann(Expr::Symbol(name), _)) = terms.get(1) else {
+            continue;
+        };
+        let Some((module_path, _)) = resolve_import(name, base_dir, &snapshot.config) else {
+            continue;
+        };
+        let Ok(summary) = summarize_module(&module_path, &snapshot.vfs) else {
+            continue;
+        };
+        if summary
+            .exports
+            .iter()
+            .any(|export| references_name(&symbols, export, name))
+        {
+            imports.push((
+                import_key(name, base_dir, &snapshot.config),
+                text(&use_expr.get_range()),
+            ));
+        }
+    }
+    // Unordered imports keep the order of the document.
+    imports.sort_by(|(key, _), (other_key, _)| key.cmp(other_key));
+    let imports: Vec<String> = imports.into_iter().map(|(_, import)| import).collect();
+
+    let start = selected[0].get_range().start;
+    let end = selected[selected.len() - 1].get_range().end;
+
+    let mut module_text = format!("; The `{module_name}` module.\n\n");
+    if !imports.is_empty() {
+        module_text.push_str(&imports.join("\n"));
+        module_text.push_str("\n\n");
+    }
+    module_text.push_str(text(&(start..end)).trim_end());
+    module_text.push('\n');
+
+    // The extracted lines are removed.
+    let deleted = whole_line_range(&chars, start..end);
+
+    // The document imports the new module, if it still references the
+    // extracted definitions.
+    let mut remaining_symbols = module_level_symbols(exprs, before);
+    remaining_symbols.extend(module_level_symbols(exprs, after));
+    let needs_import = definitions
+        .iter()
+        .any(|definition| references_name(&remaining_symbols, &definition.name, &module_name));
+
+    let mut edits = Vec::new();
+    let mut replacement = String::new();
+
+    if needs_import {
+        // The extracted forms are not imports, the imports of the document
+        // are the remaining ones.
+        let insertion = import_insertion(input, exprs, &module_name, base_dir, &snapshot.config);
+        // The edits must not overlap, the import replaces the extracted
+        // forms if it is inserted in their place.
+        if (deleted.start..=deleted.end).contains(&insertion.index) {
+            replacement = format!("(use {module_name})\n");
+        } else {
+            let position = lsp_position_from_index(insertion.index, input);
+            edits.push(TextEdit::new(
+                lsp_types::Range::new(position, position),
+                insertion.text,
+            ));
+        }
+    }
+
+    edits.push(TextEdit::new(
+        lsp_range_from_range(&deleted, input),
+        replacement,
+    ));
+
+    Some(Ok(ModuleExtraction {
+        module_name,
+        module_text,
+        edits,
+    }))
+}
+
+// #Insight
+// The extracted function is inserted before the top-level form of the
+// selection, the free variables of the selection, i.e. the local bindings of
+// the enclosing functions, are its parameters. The module-level names are
+// visible in the new function too.
+
+/// The name of the extracted functions, renamed by the user afterwards.
+const EXTRACTED_FUNCTION_NAME: &str = "extracted";
+
+/// The extraction of expressions into a new top-level function.
+pub struct FunctionExtraction {
+    pub name: String,
+    /// The edits of the document.
+    pub edits: Vec<TextEdit>,
+}
+
+/// Extracts the selected expressions of a function, or of a top-level form,
+/// into a new top-level function, and replaces them with a call. Returns
+/// `None` if the selection is not a sequence of sibling expressions, or an
+/// error with the reason if the extraction is not possible.
+pub fn extract_function(
+    exprs: &[Ann<Expr>],
+    input: &str,
+    selection: &Range,
+    options: &FormatOptions,
+) -> Option<Result<FunctionExtraction, String>> {
+    let (top_level, parent, selected) = selected_terms(exprs, selection)?;
+    let Ann(Expr::List(terms), _) = parent else {
+        return None;
+    };
+
+    // The heads, the parameters, and the bound names are not expressions.
+    let is_name = match head_symbol(parent) {
+        Some("Func") | Some("Macro") => selected.contains(&1),
+        Some("let") => selected.clone().any(|i| i % 2 == 1),
+        _ => false,
+    };
+    if selected.start == 0 || is_name {
+        return None;
+    }
+
+    let selected_terms = &terms[selected];
+    let code_terms = selected_terms
+        .iter()
+        .filter(|term| !matches!(term.0, Expr::Comment(_)))
+        .count();
+    if code_terms == 0 {
+        return None;
+    }
+
+    let start = selected_terms[0].get_range().start;
+    let end = selected_terms[selected_terms.len() - 1].get_range().end;
+    let range = start..end;
+
+    if let Some(name) = escaping_bindings(exprs, &range).first() {
+        return Some(Err(format!("The selection binds `{name}`, used after it")));
+    }
+
+    let params = free_variables(exprs, &range);
+
+    // The name does not shadow nor reference another name of the document.
+    let mut symbols = Vec::new();
+    for_each_expr(exprs, &mut |expr| {
+        if let Ann(Expr::Symbol(sym), _) = expr {
+            symbols.push(sym.as_str());
+        }
+    });
+    let name = (1..)
+        .map(|i| match i {
+            1 => EXTRACTED_FUNCTION_NAME.to_owned(),
+            i => format!("{EXTRACTED_FUNCTION_NAME}_{i}"),
+        })
+        .find(|name| !symbols.iter().any(|sym| same_name(sym, name)))?;
+
+    let chars: Vec<char> = input.chars().collect();
+    let text: String = chars[range.clone()].iter().collect();
+    let body = if code_terms == 1 && selected_terms.len() == 1 {
+        text
+    } else {
+        format!("(do\n{text})")
+    };
+
+    let function = format!("(let {name} (Func ({}) {body}))", params.join(" "));
+    let function = match parse_string_all(&function) {
+        Ok(function_exprs) => format_exprs(&function_exprs, options),
+        Err(_) => format!("{function}\n"),
+    };
+
+    // The function precedes the top-level form, and its comments.
+    let form_range = exprs[top_level].get_range();
+    let insertion = attached_comments(exprs, top_level, input)
+        .first()
+        .map(|comment| comment.get_range().start)
+        .or_else(|| {
+            top_level_form_ranges(input)
+                .into_iter()
+                .find(|range| range.start <= form_range.start && form_range.start < range.end)
+                .map(|range| range.start)
+        })
+        .unwrap_or(form_range.start);
+    let position = lsp_position_from_index(insertion, input);
+
+    let call = match params.as_slice() {
+        [] => format!("({name})"),
+        params => format!("({name} {})", params.join(" ")),
+    };
+
+    let edits = vec![
+        TextEdit::new(
+            lsp_types::Range::new(position, position),
+            format!("{function}\n"),
+        ),
+        TextEdit::new(lsp_range_from_range(&range, input), call),
+    ];
+
+    Some(Ok(FunctionExtraction { name, edits }))
+}
+
+/// Returns the index of the top-level form of the selection, the list of the
+/// selected sibling expressions, and their positions in the list. Returns
+/// `None` if the selection is top-level, or does not cover whole expressions.
+fn selected_terms<'a>(
+    exprs: &'a [Ann<Expr>],
+    selection: &Range,
+) -> Option<(usize, &'a Ann<Expr>, std::ops::Range<usize>)> {
+    if selection.start >= selection.end {
+        return None;
+    }
+
+    let contains = |range: &Range| range.start <= selection.start && selection.end <= range.end;
+    let is_selected = |range: &Range| selection.start <= range.start && range.end <= selection.end;
+
+    let top_level = exprs
+        .iter()
+        .position(|expr| contains(&expr.get_range()) && !is_selected(&expr.get_range()))?;
+    let mut parent = &exprs[top_level];
+
+    loop {
+        let Ann(Expr::List(terms), _) = parent else {
+            return None;
+        };
+
+        // The synthesized heads, e.g. `Array` for `[1 2]`, span the list.
+        let parent_range = parent.get_range();
+        let overlapping: Vec<usize> = terms
+            .iter()
+            .enumerate()
+            .filter(|(_, term)| {
+                let range = term.get_range();
+                range.start < range.end
+                    && range != parent_range
+                    && range.start < selection.end
+                    && selection.start < range.end
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let (&first, &last) = (overlapping.first()?, overlapping.last()?);
+
+        let first_range = terms[first].get_range();
+        if first == last && !is_selected(&first_range) {
+            parent = &terms[first];
+            continue;
+        }
+
+        if !is_selected(&first_range) || !is_selected(&terms[last].get_range()) {
+            return None;
+        }
+
+        return Some((top_level, parent, first..last + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{apply_edits, snapshot_of};
+
+    /// Returns the (char) range of the first occurrence of the text.
+    fn range_of(input: &str, text: &str) -> Range {
+        let start = input[..input.find(text).unwrap()].chars().count();
+        start..start + text.chars().count()
+    }
+
+    fn module_extraction(input: &str, selected: &str) -> Option<Result<ModuleExtraction, String>> {
+        let snapshot = snapshot_of(&[("main.tan", input)]);
+        let exprs = parse_string_all(input).unwrap();
+        extract_module(
+            &snapshot,
+            &exprs,
+            input,
+            &range_of(input, selected),
+            Path::new("/workspace"),
+        )
+    }
+
+    fn function_extraction(input: &str, selected: &str) -> Option<Result<String, String>> {
+        let exprs = parse_string_all(input).unwrap();
+        let extraction = extract_function(
+            &exprs,
+            input,
+            &range_of(input, selected),
+            &FormatOptions::default(),
+        )?;
+        Some(extraction.map(|extraction| apply_edits(input, &extraction.edits)))
+    }
+
+    #[test]
+    fn extract_module_moves_the_definitions_and_imports_the_new_module() {
+        let input = "(let a 1)\n\n; The c.\n(let c 3)\n(writeln (+ a c))\n";
+        let extraction = module_extraction(input, "; The c.\n(let c 3)")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(extraction.module_name, "c");
+        assert_eq!(
+            extraction.module_text,
+            "; The `c` module.\n\n; The c.\n(let c 3)\n"
+        );
+        assert_eq!(
+            apply_edits(input, &extraction.edits),
+            "(use c)\n\n(let a 1)\n\n(writeln (+ a c))\n"
+        );
+    }
+
+    #[test]
+    fn extract_module_refuses_the_references_to_the_remaining_definitions() {
+        let input = "(let a 1)\n(let b (+ a 1))\n";
+        let result = module_extraction(input, "(let b (+ a 1))").unwrap();
+
+        assert_eq!(
+            result.err().unwrap(),
+            "The extracted forms reference `a`, defined in this document"
+        );
+    }
+
+    #[test]
+    fn extract_module_only_extracts_definitions() {
+        let input = "(let a 1)\n(writeln a)\n";
+
+        assert!(module_extraction(input, "(writeln a)").is_none());
+    }
+
+    #[test]
+    fn extract_function_passes_the_free_variables_as_parameters() {
+        let input = "(let f (Func (x) (+ (* x 2) 1)))\n";
+
+        assert_eq!(
+            function_extraction(input, "(* x 2)").unwrap().unwrap(),
+            "(let extracted (Func (x)\n    (* x 2)\n))\n\n(let f (Func (x) (+ (extracted x) 1)))\n"
+        );
+    }
+
+    #[test]
+    fn extract_function_refuses_the_bindings_used_after_the_selection() {
+        let input = "(let f (Func () (do (let y 1) (writeln y))))\n";
+
+        assert_eq!(
+            function_extraction(input, "(let y 1)")
+                .unwrap()
+                .err()
+                .unwrap(),
+            "The selection binds `y`, used after it"
+        );
+    }
+
+    #[test]
+    fn extract_function_never_extracts_the_parameters() {
+        let input = "(let f (Func (x) x))\n";
+
+        assert!(function_extraction(input, "(x)").is_none());
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use lsp_types::{TextEdit, Url};
+
+use crate::{
+    cache::Caches,
+    cancellation::CancellationToken,
+    config::Config,
+    index::{Index, Origin},
+    snapshot::Snapshot,
+    util::index_from_lsp_position,
+    vfs::Vfs,
+};
+
+/// Returns a snapshot of a workspace at `/workspace`, with open and indexed
+/// documents, by file name.
+pub fn snapshot_of(documents: &[(&str, &str)]) -> Snapshot {
+    let config = Config {
+        root_path: Some("/workspace".into()),
+        ..Default::default()
+    };
+    let mut vfs = Vfs::default();
+    let mut index = Index::default();
+    for (name, text) in documents {
+        let uri = document_uri(name);
+        vfs.open(uri.clone(), (*text).to_owned(), 1);
+        index.insert(uri, Arc::from(*text), Origin::Workspace);
+    }
+
+    Snapshot {
+        config: Arc::new(config),
+        vfs: vfs.snapshot(),
+        index: Arc::new(index),
+        caches: Arc::new(Caches::new(usize::MAX)),
+        indexing: false,
+        cancellation: CancellationToken::default(),
+    }
+}
+
+/// Returns the uri of a document of `snapshot_of`.
+pub fn document_uri(name: &str) -> Url {
+    Url::parse(&format!("file:///workspace/{name}")).unwrap()
+}
+
+/// Applies the (non-overlapping) edits of a document.
+pub fn apply_edits(input: &str, edits: &[TextEdit]) -> String {
+    let mut ranges: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| {
+            (
+                index_from_lsp_position(&edit.range.start, input),
+                index_from_lsp_position(&edit.range.end, input),
+                edit.new_text.as_str(),
+            )
+        })
+        .collect();
+    ranges.sort_by_key(|(start, end, _)| (*start, *end));
+
+    let mut chars: Vec<char> = input.chars().collect();
+    for (start, end, text) in ranges.into_iter().rev() {
+        chars.splice(start..end, text.chars());
+    }
+    chars.into_iter().collect()
+}
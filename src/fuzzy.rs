@@ -0,0 +1,127 @@
+// #Insight
+// A query matches a name if its characters appear in order in the name,
+// ignoring case, e.g. `rds` matches `read-string`. The score favors exact
+// and prefix matches, consecutive characters, and matches at the start of the
+// words of the name.
+
+/// The bonus of a match at the start of the name.
+const PREFIX_BONUS: i64 = 16;
+
+/// The bonus of a match at the start of a word, e.g. after `-` or `/`.
+const WORD_START_BONUS: i64 = 8;
+
+/// The bonus of a match right after the previous matched character.
+const CONSECUTIVE_BONUS: i64 = 4;
+
+/// Returns true if the character separates the words of a name.
+fn is_separator(c: char) -> bool {
+    matches!(c, '-' | '_' | '/' | ':' | '.' | '$')
+}
+
+/// Returns the bonus of a match at the position of the name.
+fn position_bonus(chars: &[char], i: usize) -> i64 {
+    if i == 0 {
+        PREFIX_BONUS
+    } else if is_separator(chars[i - 1]) || (chars[i].is_uppercase() && chars[i - 1].is_lowercase())
+    {
+        WORD_START_BONUS
+    } else {
+        0
+    }
+}
+
+/// Scores the match of a query in a name, higher is better. Returns `None` if
+/// the name does not match. An empty query matches every name.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<i64> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let chars: Vec<char> = name.chars().collect();
+
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let matches = |i: usize, j: usize| chars[i].to_lowercase().eq(query[j].to_lowercase());
+
+    // #Insight
+    // The best alignment is computed with dynamic programming, `best[i]` is
+    // the score of the best match of the query so far, with the last matched
+    // character at the position `i` of the name.
+    let mut best: Vec<Option<i64>> = (0..chars.len())
+        .map(|i| matches(i, 0).then(|| 1 + position_bonus(&chars, i)))
+        .collect();
+
+    for j in 1..query.len() {
+        let mut next = vec![None; chars.len()];
+        for (i, slot) in next.iter_mut().enumerate().skip(j) {
+            if !matches(i, j) {
+                continue;
+            }
+            let previous = (0..i)
+                .filter_map(|k| {
+                    let score = best[k]?;
+                    // The gaps between the matched characters are penalized.
+                    let gap = if k + 1 == i {
+                        CONSECUTIVE_BONUS
+                    } else {
+                        -((i - k - 1).min(8) as i64)
+                    };
+                    Some(score + gap)
+                })
+                .max();
+            *slot = previous.map(|score| score + 1 + position_bonus(&chars, i));
+        }
+        best = next;
+    }
+
+    let mut score = best.into_iter().flatten().max()?;
+
+    // The exact matches rank first, the shorter names before the longer ones.
+    if chars.len() == query.len() {
+        score += 2 * PREFIX_BONUS;
+    }
+    score -= (chars.len() - query.len()).min(16) as i64 / 4;
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sorts the names by descending score, drops the names that do not match.
+    fn rank<'a>(query: &str, names: &[&'a str]) -> Vec<&'a str> {
+        let mut scored: Vec<(i64, &str)> = names
+            .iter()
+            .filter_map(|name| Some((fuzzy_score(query, name)?, *name)))
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, name)| name).collect()
+    }
+
+    #[test]
+    fn the_query_characters_match_in_order_ignoring_case() {
+        assert!(fuzzy_score("rds", "read-string").is_some());
+        assert!(fuzzy_score("RDS", "read-string").is_some());
+        assert!(fuzzy_score("srd", "read-string").is_none());
+        assert!(fuzzy_score("reads", "read").is_none());
+    }
+
+    #[test]
+    fn an_empty_query_matches_every_name() {
+        assert_eq!(fuzzy_score("", "read-string"), Some(0));
+    }
+
+    #[test]
+    fn exact_and_prefix_matches_rank_first() {
+        let names = ["thread", "read-string", "read"];
+
+        assert_eq!(rank("read", &names), ["read", "read-string", "thread"]);
+    }
+
+    #[test]
+    fn word_starts_rank_before_scattered_matches() {
+        let names = ["redshift", "read-string", "readString"];
+
+        assert_eq!(rank("rs", &names)[2], "redshift");
+    }
+}
@@ -0,0 +1,25 @@
+pub mod call_hierarchy;
+pub mod code_action;
+pub mod code_lens;
+pub mod completion;
+pub mod definition;
+pub mod document_color;
+pub mod document_highlight;
+pub mod document_link;
+pub mod document_symbol;
+pub mod embedded_documents;
+pub mod execute_command;
+pub mod file_rename;
+pub mod folding_range;
+pub mod formatting;
+pub mod hover;
+pub mod inlay_hint;
+pub mod on_type_formatting;
+pub mod references;
+pub mod rename;
+pub mod selection_range;
+pub mod semantic_tokens;
+pub mod signature_help;
+pub mod syntax_tree;
+pub mod type_at_position;
+pub mod workspace_symbol;
@@ -0,0 +1,283 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+    CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    SymbolKind, SymbolTag, Url,
+};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::for_each_expr,
+    handlers::completion::is_special_form,
+    references::{find_usages, UsageContext},
+    resolve::{lookup_symbol_at, resolve_local_symbol_at},
+    snapshot::Snapshot,
+    symbols::{collect_definitions, Definition},
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+// #Insight
+// The hierarchy is built on the module-level definitions: a call belongs to
+// the top-level definition that encloses it, the calls outside of the
+// definitions belong to the module, shown as the file. The local functions
+// are part of the function that defines them.
+
+/// A file with calls, and its definitions.
+struct CallingFile {
+    input: Arc<str>,
+    definitions: Vec<Definition>,
+}
+
+pub fn prepare_call_hierarchy(
+    snapshot: &Snapshot,
+    params: CallHierarchyPrepareParams,
+) -> anyhow::Result<Option<Vec<CallHierarchyItem>>> {
+    let uri = &params.text_document_position_params.text_document.uri;
+    let position = &params.text_document_position_params.position;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+    let index = index_from_lsp_position(position, &input);
+
+    // The locally bound names, e.g. the parameters, have no callers outside of
+    // their binding form.
+    if resolve_local_symbol_at(&exprs, index).is_some() {
+        return Ok(None);
+    }
+
+    let Some(target) = lookup_symbol_at(snapshot, uri, &input, &exprs, index) else {
+        return Ok(None);
+    };
+    let Some(target_exprs) = snapshot.caches.ast(&target.uri, &target.text) else {
+        return Ok(None);
+    };
+
+    let item = collect_definitions(&target_exprs)
+        .into_iter()
+        .find(|definition| definition.selection_range == target.binding.name_range)
+        .filter(|definition| definition.kind == SymbolKind::FUNCTION)
+        .map(|definition| definition_item(&target.uri, &target.text, &definition));
+
+    Ok(item.map(|item| vec![item]))
+}
+
+pub fn incoming_calls(
+    snapshot: &Snapshot,
+    params: CallHierarchyIncomingCallsParams,
+) -> anyhow::Result<Option<Vec<CallHierarchyIncomingCall>>> {
+    let item = params.item;
+    if item.kind == SymbolKind::FILE {
+        return Ok(Some(Vec::new()));
+    }
+
+    // The calls, grouped by caller.
+    let mut callers: Vec<(CallHierarchyItem, Vec<lsp_types::Range>)> = Vec::new();
+    let mut files: HashMap<Url, Option<CallingFile>> = HashMap::new();
+
+    for (location, context) in find_usages(snapshot, &item.name, false)? {
+        if context != UsageContext::Call {
+            continue;
+        }
+
+        let file = files.entry(location.uri.clone()).or_insert_with(|| {
+            let input = source_text(snapshot, &location.uri)?;
+            let exprs = snapshot.caches.ast(&location.uri, &input)?;
+            let definitions = collect_definitions(&exprs);
+            Some(CallingFile { input, definitions })
+        });
+        let Some(CallingFile { input, definitions }) = file else {
+            continue;
+        };
+
+        let index = index_from_lsp_position(&location.range.start, input);
+        let caller = definitions
+            .iter()
+            .filter(|definition| definition.kind != SymbolKind::MODULE)
+            .find(|definition| contains(&definition.range, index))
+            .map_or_else(
+                || module_item(&location.uri, input),
+                |definition| definition_item(&location.uri, input, definition),
+            );
+
+        match callers
+            .iter_mut()
+            .find(|(from, _)| from.uri == caller.uri && from.range == caller.range)
+        {
+            Some((_, ranges)) => ranges.push(location.range),
+            None => callers.push((caller, vec![location.range])),
+        }
+    }
+
+    // The callers are sorted, for deterministic results.
+    callers.sort_by(|(a, _), (b, _)| {
+        a.uri
+            .as_str()
+            .cmp(b.uri.as_str())
+            .then(a.range.start.cmp(&b.range.start))
+    });
+
+    let calls = callers
+        .into_iter()
+        .map(|(from, from_ranges)| CallHierarchyIncomingCall { from, from_ranges })
+        .collect();
+
+    Ok(Some(calls))
+}
+
+pub fn outgoing_calls(
+    snapshot: &Snapshot,
+    params: CallHierarchyOutgoingCallsParams,
+) -> anyhow::Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+    let item = params.item;
+    let uri = &item.uri;
+    let input = source_text(snapshot, uri).ok_or_else(|| anyhow::anyhow!("cannot read `{uri}`"))?;
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+
+    // The calls of the module are the calls outside of the definitions.
+    let definitions: Vec<Definition> = collect_definitions(&exprs)
+        .into_iter()
+        .filter(|definition| definition.kind != SymbolKind::MODULE)
+        .collect();
+    let is_module = item.kind == SymbolKind::FILE;
+    let range = if is_module {
+        0..input.chars().count()
+    } else {
+        let start = index_from_lsp_position(&item.selection_range.start, &input);
+        let Some(definition) = definitions
+            .iter()
+            .find(|definition| definition.selection_range.start == start)
+        else {
+            return Ok(None);
+        };
+        definition.range.clone()
+    };
+
+    // The heads of the calls in the range.
+    let mut heads = Vec::new();
+    for_each_expr(&exprs, &mut |expr| {
+        let Ann(Expr::List(terms), _) = expr else {
+            return;
+        };
+        let Some(head @ Ann(Expr::Symbol(sym), _)) = terms.first() else {
+            return;
+        };
+        let head_range = head.get_range();
+        if !contains(&range, head_range.start) || is_special_form(sym) {
+            return;
+        }
+        if is_module
+            && definitions
+                .iter()
+                .any(|definition| contains(&definition.range, head_range.start))
+        {
+            return;
+        }
+        heads.push(head_range);
+    });
+
+    // The calls, grouped by callee, in the order of the first call.
+    let mut callees: Vec<(CallHierarchyItem, Vec<lsp_types::Range>)> = Vec::new();
+
+    for head_range in heads {
+        snapshot.cancellation.check()?;
+
+        // The local functions are part of the caller.
+        if resolve_local_symbol_at(&exprs, head_range.start).is_some() {
+            continue;
+        }
+        let Some(target) = lookup_symbol_at(snapshot, uri, &input, &exprs, head_range.start) else {
+            continue;
+        };
+
+        let callee = if target.uri == *uri {
+            definitions
+                .iter()
+                .find(|definition| definition.selection_range == target.binding.name_range)
+                .filter(|definition| definition.kind == SymbolKind::FUNCTION)
+                .map(|definition| definition_item(uri, &input, definition))
+        } else {
+            snapshot.index.get(&target.uri).and_then(|file| {
+                file.definitions
+                    .iter()
+                    .find(|definition| definition.selection_range == target.binding.name_range)
+                    .filter(|definition| definition.kind == SymbolKind::FUNCTION)
+                    .map(|definition| definition_item(&target.uri, &target.text, definition))
+            })
+        };
+        let Some(callee) = callee else {
+            continue;
+        };
+
+        let from_range = lsp_range_from_range(&head_range, &input);
+        match callees
+            .iter_mut()
+            .find(|(to, _)| to.uri == callee.uri && to.range == callee.range)
+        {
+            Some((_, ranges)) => ranges.push(from_range),
+            None => callees.push((callee, vec![from_range])),
+        }
+    }
+
+    let calls = callees
+        .into_iter()
+        .map(|(to, from_ranges)| CallHierarchyOutgoingCall { to, from_ranges })
+        .collect();
+
+    Ok(Some(calls))
+}
+
+/// Returns true if the (char) range contains the (char) index.
+fn contains(range: &Range, index: usize) -> bool {
+    range.start <= index && index < range.end
+}
+
+/// Returns the text of a document, from the editor if the document is open,
+/// or from the index.
+fn source_text(snapshot: &Snapshot, uri: &Url) -> Option<Arc<str>> {
+    snapshot
+        .vfs
+        .get(uri)
+        .or_else(|| snapshot.index.get(uri).map(|file| file.text.clone()))
+        .or_else(|| snapshot.vfs.read(uri).ok())
+}
+
+/// Returns the item of a module-level definition.
+fn definition_item(uri: &Url, input: &str, definition: &Definition) -> CallHierarchyItem {
+    CallHierarchyItem {
+        name: definition.name.clone(),
+        kind: definition.kind,
+        tags: definition.deprecated.then(|| vec![SymbolTag::DEPRECATED]),
+        detail: file_name(uri),
+        uri: uri.clone(),
+        range: lsp_range_from_range(&definition.range, input),
+        selection_range: lsp_range_from_range(&definition.selection_range, input),
+        data: None,
+    }
+}
+
+/// Returns the item of a module, for the calls outside of the definitions.
+fn module_item(uri: &Url, input: &str) -> CallHierarchyItem {
+    let range = lsp_range_from_range(&(0..input.chars().count()), input);
+
+    CallHierarchyItem {
+        name: file_name(uri).unwrap_or_else(|| uri.to_string()),
+        kind: SymbolKind::FILE,
+        tags: None,
+        detail: None,
+        uri: uri.clone(),
+        range,
+        selection_range: lsp_range_from_range(&(0..0), input),
+        data: None,
+    }
+}
+
+fn file_name(uri: &Url) -> Option<String> {
+    let path = uri.to_file_path().ok()?;
+    Some(Path::new(&path).file_name()?.to_string_lossy().into_owned())
+}
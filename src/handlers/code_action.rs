@@ -0,0 +1,721 @@
+use std::{collections::HashMap, path::Path};
+
+use lsp_types::{
+    CodeAction, CodeActionDisabled, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionResponse, Diagnostic, Position, Range, ResourceOperationKind, SymbolKind, TextEdit,
+    Url, WorkspaceEdit,
+};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::{find_path_at, head_symbol},
+    doc_tags::{function_docs, tag_line_prefix, PARAM_TAG, TAG_PREFIX},
+    edit::EditBuilder,
+    extract::{extract_function, extract_module},
+    handlers::completion::{is_builtin, is_special_form},
+    imports::import_edit,
+    indent::{closing_insertions, is_closing_delimiter, missing_closing_delimiters},
+    index::IndexedFile,
+    inline::inline_definition,
+    module::{imported_files, resolve_import},
+    resolve::resolve_symbol_at,
+    snapshot::Snapshot,
+    unicode::same_name,
+    unused::{find_unused, UnusedKind},
+    util::{index_from_lsp_position, lsp_position_from_index, lsp_range_from_range},
+};
+
+// #Insight
+// Fixes that might change the behavior of the code are risky, their edits are
+// marked as needing confirmation, so that the user reviews them before they
+// are applied. Risky fixes are not offered to clients without change
+// annotations.
+
+/// The annotation of the edits of risky fixes.
+const RISKY_ANNOTATION: &str = "risky";
+
+/// Returns a quick fix whose edits need confirmation.
+fn risky_fix(
+    snapshot: &Snapshot,
+    title: &str,
+    description: &str,
+    uri: &Url,
+    edits: Vec<TextEdit>,
+) -> Option<CodeAction> {
+    if !EditBuilder::supports_annotations(snapshot) {
+        return None;
+    }
+
+    let mut builder = EditBuilder::new();
+    builder.annotation(RISKY_ANNOTATION, title, Some(description.to_owned()), true);
+    for edit in edits {
+        builder.insert(uri.clone(), edit, Some(RISKY_ANNOTATION));
+    }
+
+    Some(CodeAction {
+        title: title.to_owned(),
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: builder.build(snapshot).ok(),
+        ..Default::default()
+    })
+}
+
+pub fn code_action(
+    snapshot: &Snapshot,
+    params: CodeActionParams,
+) -> anyhow::Result<Option<CodeActionResponse>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let mut actions = Vec::new();
+
+    if let Some(action) = file_template_action(snapshot, uri, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    for action in syntax_fix_actions(&params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    if let Some(action) = balance_delimiters_action(snapshot, &params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    if let Some(action) = create_module_action(snapshot, &params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    for action in add_import_actions(snapshot, &params, &input)? {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    for action in remove_unused_actions(snapshot, &params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    if let Some(action) = sync_doc_params_action(&params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    if let Some(action) = extract_module_action(snapshot, &params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    if let Some(action) = extract_function_action(snapshot, &params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    if let Some(action) = inline_action(snapshot, &params, &input) {
+        actions.push(CodeActionOrCommand::CodeAction(action));
+    }
+
+    Ok(Some(actions))
+}
+
+/// Offers a skeleton for new (empty) files of the workspace: the file header
+/// (e.g. the license notice) from the project settings, and the module doc
+/// comment.
+fn file_template_action(snapshot: &Snapshot, uri: &Url, input: &str) -> Option<CodeAction> {
+    if !input.trim().is_empty() {
+        return None;
+    }
+
+    let path = uri.to_file_path().ok()?;
+    let root_path = snapshot.config.root_path.as_ref()?;
+
+    if !path.starts_with(root_path) || snapshot.config.is_dependency(&path) {
+        return None;
+    }
+
+    // #Insight
+    // A directory is a module, all files of the directory contribute to it.
+    let module_name = path
+        .parent()
+        .filter(|dir| *dir != root_path)
+        .and_then(|dir| dir.file_name())
+        .or_else(|| path.file_stem())?
+        .to_string_lossy();
+
+    let mut template = String::new();
+
+    if let Some(header) = &snapshot.config.file_header {
+        for line in header.lines() {
+            if line.is_empty() {
+                template.push_str(";\n");
+            } else {
+                template.push_str(&format!("; {line}\n"));
+            }
+        }
+        template.push('\n');
+    }
+
+    template.push_str(&format!("; The `{module_name}` module.\n\n"));
+
+    let start = Position::new(0, 0);
+    let end = Position::new(u32::MAX, u32::MAX);
+    let edit = TextEdit::new(Range::new(start, end), template);
+
+    Some(CodeAction {
+        title: "Insert file template".to_owned(),
+        kind: Some(CodeActionKind::SOURCE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+            ..Default::default()
+        }),
+        is_preferred: Some(true),
+        ..Default::default()
+    })
+}
+
+/// Offers to append the missing closing delimiters at the end of the
+/// document, for `unterminated list` errors. The fix is risky, the intended
+/// position of the delimiters is unknown.
+fn balance_delimiters_action(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> Option<CodeAction> {
+    let diagnostic = params
+        .context
+        .diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic.message == "unterminated list")?;
+
+    let closing_delimiters = missing_closing_delimiters(input);
+    if closing_delimiters.is_empty() {
+        return None;
+    }
+
+    let end = lsp_position_from_index(input.chars().count(), input);
+    let edit = TextEdit::new(Range::new(end, end), closing_delimiters);
+
+    let mut action = risky_fix(
+        snapshot,
+        "Balance the delimiters",
+        "Appends the missing closing delimiters at the end of the document, review the structure of the code.",
+        &params.text_document.uri,
+        vec![edit],
+    )?;
+    action.diagnostics = Some(vec![diagnostic.clone()]);
+
+    Some(action)
+}
+
+/// Returns a quick fix of a syntax error, that edits the document.
+fn syntax_fix(
+    params: &CodeActionParams,
+    diagnostic: &Diagnostic,
+    title: String,
+    edits: Vec<TextEdit>,
+    is_preferred: bool,
+) -> CodeAction {
+    CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(params.text_document.uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        is_preferred: Some(is_preferred),
+        ..Default::default()
+    }
+}
+
+/// Offers to fix the trivial syntax errors of the diagnostics in the context:
+/// a stray closing delimiter is removed, or replaced by the expected one, and
+/// the missing closing delimiters of an unterminated form are inserted before
+/// the next top-level form.
+fn syntax_fix_actions(params: &CodeActionParams, input: &str) -> Vec<CodeAction> {
+    let mut actions = Vec::new();
+
+    for diagnostic in &params.context.diagnostics {
+        if diagnostic.message == "unterminated list" {
+            let insertions = closing_insertions(input);
+            let title = match insertions.as_slice() {
+                [] => continue,
+                [(_, delimiters)] => format!("Insert the missing `{delimiters}`"),
+                _ => "Insert the missing closing delimiters".to_owned(),
+            };
+            let edits = insertions
+                .into_iter()
+                .map(|(index, delimiters)| {
+                    let position = lsp_position_from_index(index, input);
+                    TextEdit::new(Range::new(position, position), delimiters)
+                })
+                .collect();
+
+            actions.push(syntax_fix(params, diagnostic, title, edits, true));
+            continue;
+        }
+
+        let Some(stray) = diagnostic
+            .message
+            .strip_prefix("unexpected `")
+            .and_then(|message| message.strip_suffix('`'))
+            .filter(|token| token.len() == 1 && token.chars().all(is_closing_delimiter))
+        else {
+            continue;
+        };
+
+        // The innermost unclosed delimiter before the stray one.
+        let start = index_from_lsp_position(&diagnostic.range.start, input);
+        let prefix: String = input.chars().take(start).collect();
+        let expected = missing_closing_delimiters(&prefix).chars().next();
+
+        if let Some(expected) = expected.filter(|expected| stray != expected.to_string()) {
+            actions.push(syntax_fix(
+                params,
+                diagnostic,
+                format!("Replace `{stray}` with `{expected}`"),
+                vec![TextEdit::new(diagnostic.range, expected.to_string())],
+                true,
+            ));
+        }
+
+        actions.push(syntax_fix(
+            params,
+            diagnostic,
+            format!("Remove the stray `{stray}`"),
+            vec![TextEdit::new(diagnostic.range, String::new())],
+            expected.is_none(),
+        ));
+    }
+
+    actions
+}
+
+/// Returns true if the client can show disabled code actions.
+fn supports_disabled_actions(snapshot: &Snapshot) -> bool {
+    snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.code_action.as_ref())
+        .and_then(|code_action| code_action.disabled_support)
+        .unwrap_or(false)
+}
+
+/// Offers to create the module file of an unresolved `(use module)`
+/// expression, next to the document. Requires the `create` resource
+/// operation, for other clients the action is shown disabled, or not at all.
+fn create_module_action(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> Option<CodeAction> {
+    let uri = &params.text_document.uri;
+    let path = uri.to_file_path().ok()?;
+
+    if snapshot.config.is_dependency(&path) {
+        return None;
+    }
+
+    let exprs = parse_string_all(input).ok()?;
+    let index = index_from_lsp_position(&params.range.start, input);
+    let expr_path = find_path_at(&exprs, index);
+
+    let use_expr = expr_path
+        .iter()
+        .rev()
+        .find(|expr| head_symbol(expr) == Some("use"))?;
+
+    let Ann(Expr::List(terms), _) = use_expr else {
+        return None;
+    };
+
+    let Some(Ann(Expr::Symbol(module_name), _)) = terms.get(1) else {
+        return None;
+    };
+
+    let base_dir = path.parent()?;
+
+    if resolve_import(module_name, base_dir, &snapshot.config).is_some() {
+        return None;
+    }
+
+    let title = format!("Create module `{module_name}`");
+
+    if !EditBuilder::supports_resource_operation(snapshot, ResourceOperationKind::Create) {
+        if !supports_disabled_actions(snapshot) {
+            return None;
+        }
+        return Some(CodeAction {
+            title,
+            kind: Some(CodeActionKind::QUICKFIX),
+            disabled: Some(CodeActionDisabled {
+                reason: "The editor does not support creating files".to_owned(),
+            }),
+            ..Default::default()
+        });
+    }
+
+    let module_uri = Url::from_file_path(base_dir.join(format!("{module_name}.tan"))).ok()?;
+
+    let mut builder = EditBuilder::new();
+    builder.create_file(module_uri.clone(), None);
+    builder.insert(
+        module_uri,
+        TextEdit::new(
+            Range::new(Position::new(0, 0), Position::new(0, 0)),
+            format!("; The `{module_name}` module.\n\n"),
+        ),
+        None,
+    );
+
+    Some(CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: builder.build(snapshot).ok(),
+        ..Default::default()
+    })
+}
+
+/// Offers to import the modules that define the unresolved symbol at the
+/// cursor, i.e. a symbol that is not bound in the document, not a builtin, and
+/// not defined by the module of the document or its imports. One action per
+/// defining module of the workspace or the dependencies (e.g. the standard
+/// library).
+fn add_import_actions(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> anyhow::Result<Vec<CodeAction>> {
+    let uri = &params.text_document.uri;
+    let (Ok(exprs), Ok(path)) = (parse_string_all(input), uri.to_file_path()) else {
+        return Ok(Vec::new());
+    };
+    let Some(base_dir) = path.parent() else {
+        return Ok(Vec::new());
+    };
+
+    let index = index_from_lsp_position(&params.range.start, input);
+    let Some(symbol @ Ann(Expr::Symbol(name), _)) = find_path_at(&exprs, index).last().copied()
+    else {
+        return Ok(Vec::new());
+    };
+
+    // The qualified names, e.g. `ops/add`, need the import of their module,
+    // it is reported by the import diagnostics.
+    if name.contains('/')
+        || is_builtin(name)
+        || is_special_form(name)
+        || resolve_symbol_at(&exprs, index).is_some()
+    {
+        return Ok(Vec::new());
+    }
+
+    let defines_name = |file: &IndexedFile| {
+        file.definitions.iter().any(|definition| {
+            same_name(&definition.name, name) && definition.kind != SymbolKind::MODULE
+        })
+    };
+
+    // The files are sorted, for deterministic results.
+    let mut files: Vec<_> = snapshot
+        .index
+        .files()
+        .filter(|(file_uri, file)| *file_uri != uri && defines_name(file))
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+    // The name is defined by the module of the document, or an import.
+    let imported_files = imported_files(&path, input, &snapshot.config);
+    let is_visible = files.iter().any(|(file_uri, _)| {
+        file_uri.to_file_path().is_ok_and(|file_path| {
+            file_path.parent() == Some(base_dir) || imported_files.contains(&file_path)
+        })
+    });
+    if is_visible {
+        return Ok(Vec::new());
+    }
+
+    let symbol_range = lsp_range_from_range(&symbol.get_range(), input);
+    let diagnostics: Vec<Diagnostic> = params
+        .context
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| {
+            diagnostic.range.start <= symbol_range.end && symbol_range.start <= diagnostic.range.end
+        })
+        .cloned()
+        .collect();
+
+    let mut actions: Vec<CodeAction> = Vec::new();
+
+    for (file_uri, _) in files {
+        snapshot.cancellation.check()?;
+
+        let Some((module_name, edit)) = import_edit(snapshot, file_uri, uri)? else {
+            continue;
+        };
+        let title = format!("Import `{module_name}`");
+        if actions.iter().any(|action| action.title == title) {
+            continue;
+        }
+
+        actions.push(CodeAction {
+            title,
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: (!diagnostics.is_empty()).then(|| diagnostics.clone()),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
+    if let [action] = actions.as_mut_slice() {
+        action.is_preferred = Some(true);
+    }
+
+    Ok(actions)
+}
+
+/// Offers to remove the unused local bindings and imports in the range, see
+/// the unused lint.
+fn remove_unused_actions(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> Vec<CodeAction> {
+    let uri = &params.text_document.uri;
+    let Ok(exprs) = parse_string_all(input) else {
+        return Vec::new();
+    };
+    let path = uri.to_file_path().ok();
+    let base_dir = path.as_deref().and_then(Path::parent);
+
+    let start = index_from_lsp_position(&params.range.start, input);
+    let end = index_from_lsp_position(&params.range.end, input);
+
+    find_unused(&exprs, input, base_dir, &snapshot.config, &snapshot.vfs)
+        .into_iter()
+        .filter(|unused| unused.name_range.start <= end && start <= unused.name_range.end)
+        .map(|unused| {
+            let name_range = lsp_range_from_range(&unused.name_range, input);
+            let diagnostics: Vec<Diagnostic> = params
+                .context
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.range == name_range)
+                .cloned()
+                .collect();
+
+            let title = match unused.kind {
+                UnusedKind::Binding => format!("Remove the unused `{}`", unused.name),
+                UnusedKind::Import => format!("Remove the unused import of `{}`", unused.name),
+            };
+            let edit = TextEdit::new(lsp_range_from_range(&unused.removal, input), String::new());
+
+            CodeAction {
+                title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: (!diagnostics.is_empty()).then_some(diagnostics),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                is_preferred: Some(true),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Offers to move the selected top-level definitions into a new module, next
+/// to the document. Requires the `create` resource operation.
+fn extract_module_action(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> Option<CodeAction> {
+    let uri = &params.text_document.uri;
+    let path = uri.to_file_path().ok()?;
+
+    if snapshot.config.is_dependency(&path)
+        || !EditBuilder::supports_resource_operation(snapshot, ResourceOperationKind::Create)
+    {
+        return None;
+    }
+
+    let exprs = parse_string_all(input).ok()?;
+    let selection = index_from_lsp_position(&params.range.start, input)
+        ..index_from_lsp_position(&params.range.end, input);
+    let base_dir = path.parent()?;
+
+    let extraction = match extract_module(snapshot, &exprs, input, &selection, base_dir)? {
+        Ok(extraction) => extraction,
+        Err(reason) => {
+            if !supports_disabled_actions(snapshot) {
+                return None;
+            }
+            return Some(CodeAction {
+                title: "Extract into a new module".to_owned(),
+                kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+                disabled: Some(CodeActionDisabled { reason }),
+                ..Default::default()
+            });
+        }
+    };
+
+    let module_name = &extraction.module_name;
+    let module_uri = Url::from_file_path(base_dir.join(format!("{module_name}.tan"))).ok()?;
+
+    let mut builder = EditBuilder::new();
+    builder.create_file(module_uri.clone(), None);
+    builder.insert(
+        module_uri,
+        TextEdit::new(
+            Range::new(Position::new(0, 0), Position::new(0, 0)),
+            extraction.module_text,
+        ),
+        None,
+    );
+    for edit in extraction.edits {
+        builder.insert(uri.clone(), edit, None);
+    }
+
+    Some(CodeAction {
+        title: format!("Extract into module `{module_name}`"),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        edit: builder.build(snapshot).ok(),
+        ..Default::default()
+    })
+}
+
+/// Offers to extract the selected expressions into a new top-level function.
+fn extract_function_action(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> Option<CodeAction> {
+    let exprs = parse_string_all(input).ok()?;
+    let selection = index_from_lsp_position(&params.range.start, input)
+        ..index_from_lsp_position(&params.range.end, input);
+
+    let title = "Extract into a function".to_owned();
+
+    let extraction = match extract_function(&exprs, input, &selection, &snapshot.config.format)? {
+        Ok(extraction) => extraction,
+        Err(reason) => {
+            if !supports_disabled_actions(snapshot) {
+                return None;
+            }
+            return Some(CodeAction {
+                title,
+                kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+                disabled: Some(CodeActionDisabled { reason }),
+                ..Default::default()
+            });
+        }
+    };
+
+    let mut builder = EditBuilder::new();
+    for edit in extraction.edits {
+        builder.insert(params.text_document.uri.clone(), edit, None);
+    }
+
+    Some(CodeAction {
+        title: format!("Extract into function `{}`", extraction.name),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        edit: builder.build(snapshot).ok(),
+        ..Default::default()
+    })
+}
+
+/// Offers to inline the `let` binding at the cursor into its references.
+fn inline_action(
+    snapshot: &Snapshot,
+    params: &CodeActionParams,
+    input: &str,
+) -> Option<CodeAction> {
+    let uri = &params.text_document.uri;
+    let exprs = parse_string_all(input).ok()?;
+    let index = index_from_lsp_position(&params.range.start, input);
+
+    let inlining = match inline_definition(snapshot, uri, &exprs, input, index)? {
+        Ok(inlining) => inlining,
+        Err(reason) => {
+            if !supports_disabled_actions(snapshot) {
+                return None;
+            }
+            return Some(CodeAction {
+                title: "Inline the definition".to_owned(),
+                kind: Some(CodeActionKind::REFACTOR_INLINE),
+                disabled: Some(CodeActionDisabled { reason }),
+                ..Default::default()
+            });
+        }
+    };
+
+    let mut builder = EditBuilder::new();
+    for edit in inlining.edits {
+        builder.insert(uri.clone(), edit, None);
+    }
+
+    Some(CodeAction {
+        title: format!("Inline `{}`", inlining.name),
+        kind: Some(CodeActionKind::REFACTOR_INLINE),
+        edit: builder.build(snapshot).ok(),
+        ..Default::default()
+    })
+}
+
+/// Offers to sync the `@param` tags of the function at the cursor with its
+/// signature: the tags of unknown parameters are removed, tags for the
+/// undocumented parameters are appended after the last tag.
+fn sync_doc_params_action(params: &CodeActionParams, input: &str) -> Option<CodeAction> {
+    let exprs = parse_string_all(input).ok()?;
+    let index = index_from_lsp_position(&params.range.start, input);
+
+    let docs = function_docs(&exprs, input);
+    let doc = docs.iter().find(|doc| doc.contains(index))?;
+
+    let unknown_params = doc.unknown_params();
+    let undocumented_params = doc.undocumented_params();
+
+    if unknown_params.is_empty() && undocumented_params.is_empty() {
+        return None;
+    }
+
+    let mut edits = Vec::new();
+
+    for doc_param in &unknown_params {
+        edits.push(TextEdit::new(
+            lsp_range_from_range(&doc_param.comment.get_range(), input),
+            String::new(),
+        ));
+    }
+
+    if let Some(last_comment @ Ann(Expr::Comment(last), _)) = doc
+        .documented_params
+        .last()
+        .map(|doc_param| doc_param.comment)
+    {
+        let prefix = tag_line_prefix(last);
+        let text: String = undocumented_params
+            .iter()
+            .map(|param| format!("{prefix}{TAG_PREFIX}{PARAM_TAG} {}\n", param.0))
+            .collect();
+
+        // #Insight
+        // The comment range includes the trailing newline.
+        let position = lsp_position_from_index(last_comment.get_range().end, input);
+        if !text.is_empty() {
+            edits.push(TextEdit::new(Range::new(position, position), text));
+        }
+    }
+
+    Some(CodeAction {
+        title: format!("Sync the documented parameters of `{}`", doc.name),
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(params.text_document.uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,218 @@
+use lsp_types::{CodeLens, CodeLensParams, Command, Position, SymbolKind, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dialect::Dialect,
+    evaluate::is_evaluable,
+    handlers::execute_command::{EVALUATE_COMMAND, UPDATE_DEPENDENCY_COMMAND},
+    manifest::dependency_entries,
+    packages::{compare_versions, latest_version, locked_versions},
+    references::find_references,
+    snapshot::Snapshot,
+    symbols::collect_definitions,
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// The lenses are emitted without commands, the commands are computed in
+// codeLens/resolve, only for the visible lenses. Counting references requires
+// scanning the workspace, it should not happen for every file at scroll-time.
+
+/// The name of the function that runs a program.
+const MAIN_FUNCTION: &str = "main";
+
+/// The lens data, used to compute the command.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum LensData {
+    /// The references to a top-level definition.
+    References { uri: Url, name: String },
+    /// Runs the program.
+    Run { uri: Url },
+    /// Evaluates a top-level form.
+    Evaluate { uri: Url, position: Position },
+    /// The versions of a dependency of the manifest.
+    Dependency { uri: Url, name: String },
+}
+
+pub fn code_lens(
+    snapshot: &Snapshot,
+    params: CodeLensParams,
+) -> anyhow::Result<Option<Vec<CodeLens>>> {
+    let uri = params.text_document.uri;
+    let input = snapshot.vfs.read(&uri)?;
+
+    if uri
+        .to_file_path()
+        .is_ok_and(|path| snapshot.config.is_manifest(&path))
+    {
+        return Ok(Some(dependency_lenses(&uri, &input)));
+    }
+
+    let Some(exprs) = snapshot.caches.ast(&uri, &input) else {
+        return Ok(None);
+    };
+
+    let mut lenses = Vec::new();
+
+    for definition in collect_definitions(&exprs) {
+        if definition.kind == SymbolKind::MODULE {
+            continue;
+        }
+
+        let range = lsp_range_from_range(&definition.range, &input);
+
+        // Programs are never executed in read-only mode.
+        if definition.name == MAIN_FUNCTION
+            && definition.kind == SymbolKind::FUNCTION
+            && snapshot.config.run_lens
+            && !snapshot.config.read_only
+        {
+            lenses.push(CodeLens {
+                range,
+                command: None,
+                data: serde_json::to_value(LensData::Run { uri: uri.clone() }).ok(),
+            });
+        }
+
+        if !snapshot.config.reference_lens {
+            continue;
+        }
+
+        lenses.push(CodeLens {
+            range,
+            command: None,
+            data: serde_json::to_value(LensData::References {
+                uri: uri.clone(),
+                name: definition.name,
+            })
+            .ok(),
+        });
+    }
+
+    // Code is never evaluated in read-only mode, data files are not code.
+    let path = uri.to_file_path().ok();
+    let is_code = Dialect::detect(path.as_deref(), &input, &snapshot.config).is_code();
+    if snapshot.config.evaluate_lens && !snapshot.config.read_only && is_code {
+        for expr in exprs.iter().filter(|expr| is_evaluable(expr)) {
+            let range = lsp_range_from_range(&expr.get_range(), &input);
+            lenses.push(CodeLens {
+                range,
+                command: None,
+                data: serde_json::to_value(LensData::Evaluate {
+                    uri: uri.clone(),
+                    position: range.start,
+                })
+                .ok(),
+            });
+        }
+    }
+
+    Ok(Some(lenses))
+}
+
+/// Returns the lenses of the dependencies of the manifest.
+fn dependency_lenses(uri: &Url, input: &str) -> Vec<CodeLens> {
+    dependency_entries(input)
+        .into_iter()
+        .map(|entry| CodeLens {
+            range: lsp_range_from_range(&entry.key_range, input),
+            command: None,
+            data: serde_json::to_value(LensData::Dependency {
+                uri: uri.clone(),
+                name: entry.name,
+            })
+            .ok(),
+        })
+        .collect()
+}
+
+/// Returns the command of a dependency lens, the locked version, and the
+/// update to the latest published version, if newer.
+fn dependency_command(snapshot: &Snapshot, uri: Url, name: &str) -> anyhow::Result<Command> {
+    let locked = snapshot
+        .config
+        .root_path
+        .as_deref()
+        .and_then(|root_path| locked_versions(root_path).remove(name));
+    let latest = snapshot
+        .config
+        .registry_index_path
+        .as_deref()
+        .and_then(|index_path| latest_version(index_path, name));
+
+    let update = match (&locked, latest) {
+        (Some(locked), Some(latest)) if compare_versions(&latest, locked).is_gt() => Some(latest),
+        (None, latest) => latest,
+        _ => None,
+    };
+
+    let locked = match &locked {
+        Some(version) => format!("locked {version}"),
+        None => "not locked".to_owned(),
+    };
+
+    // A command without id is shown as a label.
+    let command = match update {
+        Some(version) => Command {
+            title: format!("{locked}, update to {version}"),
+            command: UPDATE_DEPENDENCY_COMMAND.to_owned(),
+            arguments: Some(vec![
+                serde_json::to_value(uri)?,
+                serde_json::to_value(name)?,
+                serde_json::to_value(version)?,
+            ]),
+        },
+        None => Command {
+            title: locked,
+            command: String::new(),
+            arguments: None,
+        },
+    };
+
+    Ok(command)
+}
+
+pub fn code_lens_resolve(snapshot: &Snapshot, lens: CodeLens) -> anyhow::Result<CodeLens> {
+    let Some(data) = lens.data.clone() else {
+        return Ok(lens);
+    };
+
+    let command = match serde_json::from_value(data)? {
+        LensData::References { uri, name } => {
+            let locations = find_references(snapshot, &name, false)?;
+            let title = match locations.len() {
+                1 => "1 reference".to_owned(),
+                n => format!("{n} references"),
+            };
+            Command {
+                title,
+                command: "tan.showReferences".to_owned(),
+                arguments: Some(vec![
+                    serde_json::to_value(uri)?,
+                    serde_json::to_value(lens.range.start)?,
+                    serde_json::to_value(locations)?,
+                ]),
+            }
+        }
+        LensData::Run { uri } => Command {
+            title: "▶ Run".to_owned(),
+            command: "tan.run".to_owned(),
+            arguments: Some(vec![serde_json::to_value(uri)?]),
+        },
+        LensData::Evaluate { uri, position } => Command {
+            title: "▶ Evaluate".to_owned(),
+            command: EVALUATE_COMMAND.to_owned(),
+            arguments: Some(vec![
+                serde_json::to_value(uri)?,
+                serde_json::to_value(position)?,
+            ]),
+        },
+        LensData::Dependency { uri, name } => dependency_command(snapshot, uri, &name)?,
+    };
+
+    Ok(CodeLens {
+        command: Some(command),
+        ..lens
+    })
+}
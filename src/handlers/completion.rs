@@ -0,0 +1,557 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionItemTag, CompletionParams, CompletionResponse,
+    CompletionTextEdit, Documentation, InsertTextFormat, Range, SymbolKind, TextEdit, Url,
+};
+use serde::{Deserialize, Serialize};
+use tan::{ann::Ann, api::parse_string_all, eval::env::Env, expr::Expr};
+
+use crate::{
+    doc_tags::{function_docs, DOC_TAGS, PARAM_TAG, TAG_PREFIX},
+    handlers::hover::{describe_binding, BindingDescription},
+    imports::import_edit,
+    manifest::manifest_completion,
+    markup::{markup_content, preferred_markup_kind},
+    module::{module_files, resolve_import, summarize_module},
+    resolve::{visible_local_bindings, Binding},
+    snapshot::Snapshot,
+    symbols::{collect_definitions, Definition},
+    unicode::same_name,
+    util::{index_from_lsp_position, lsp_position_from_index},
+};
+
+// #TODO complete the local bindings in unbalanced documents, e.g. with an
+// error-tolerant parser. Only the indexed definitions are completed.
+
+/// The characters that trigger completion: `(` for function calls, `:` for
+/// key-symbols, `.` for module members, `/` for paths and `@` for doc-comment
+/// tags.
+pub const TRIGGER_CHARACTERS: [&str; 5] = ["(", ":", ".", "/", "@"];
+
+/// The special forms, with snippet templates.
+const SPECIAL_FORMS: [(&str, &str, &str); 10] = [
+    ("let", "let ${1:name} ${0:value}", "Binds values to names"),
+    (
+        "Func",
+        "Func (${1:params})\n    ${0:body}",
+        "Defines a function",
+    ),
+    (
+        "Macro",
+        "Macro (${1:params})\n    ${0:body}",
+        "Defines a macro",
+    ),
+    (
+        "if",
+        "if ${1:predicate}\n    ${2:then}\n    ${0:else}",
+        "Conditional evaluation",
+    ),
+    (
+        "do",
+        "do\n    ${0:body}",
+        "Evaluates the expressions in sequence",
+    ),
+    ("use", "use ${0:module}", "Imports a module"),
+    (
+        "for",
+        "for ${1:predicate}\n    ${0:body}",
+        "Evaluates the body while the predicate is true",
+    ),
+    ("quot", "quot ${0:expr}", "Quotes an expression"),
+    ("eval", "eval ${0:expr}", "Evaluates an expression"),
+    (
+        "ann",
+        "ann ${0:expr}",
+        "Returns the annotations of an expression",
+    ),
+];
+
+/// The names of the builtin functions, with their (return) types.
+static BUILTINS: OnceLock<Vec<(String, Option<String>)>> = OnceLock::new();
+
+/// Returns the builtin functions of the prelude, sorted by name.
+fn builtins() -> &'static [(String, Option<String>)] {
+    BUILTINS.get_or_init(|| {
+        let env = Env::prelude();
+        let mut builtins: Vec<_> = env
+            .local
+            .iter()
+            .flat_map(|scope| scope.iter())
+            // #Insight
+            // The overloads are registered with mangled names, e.g.
+            // `+$$Int$$Int`, only the plain names are completed.
+            .filter(|(name, _)| !name.contains("$$"))
+            .map(|(name, value)| {
+                let type_name = value.get_annotation("type").map(|ty| ty.to_string());
+                (name.clone(), type_name)
+            })
+            .collect();
+        builtins.sort();
+        builtins
+    })
+}
+
+/// Returns true if the name is a special form, e.g. `let`.
+pub fn is_special_form(name: &str) -> bool {
+    SPECIAL_FORMS.iter().any(|(label, _, _)| *label == name)
+}
+
+/// Returns true if the name is a builtin function of the prelude.
+pub fn is_builtin(name: &str) -> bool {
+    builtins()
+        .binary_search_by(|(builtin, _)| builtin.as_str().cmp(name))
+        .is_ok()
+}
+
+/// Characters that end a symbol.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | '"' | '\'' | ';')
+}
+
+/// Returns the (char) index where the symbol before the index starts.
+fn prefix_start(input: &str, index: usize) -> usize {
+    let chars: Vec<char> = input.chars().take(index).collect();
+    let len = chars
+        .iter()
+        .rev()
+        .take_while(|c| !is_delimiter(**c))
+        .count();
+    index - len
+}
+
+fn completion_item_kind(definition: &Definition) -> CompletionItemKind {
+    match definition.kind {
+        SymbolKind::FUNCTION => CompletionItemKind::FUNCTION,
+        SymbolKind::CONSTANT => CompletionItemKind::CONSTANT,
+        SymbolKind::MODULE => CompletionItemKind::MODULE,
+        SymbolKind::STRUCT => CompletionItemKind::STRUCT,
+        _ => CompletionItemKind::VARIABLE,
+    }
+}
+
+/// The data of a completion item, used to resolve the item.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum ItemData {
+    /// A definition of a file, completed in a document.
+    Definition {
+        uri: Url,
+        document: Url,
+        name: String,
+    },
+    /// A member of an imported module.
+    Member { module_path: PathBuf, name: String },
+}
+
+fn definition_item(definition: &Definition, data: &ItemData) -> CompletionItem {
+    let detail = match definition.kind {
+        SymbolKind::MODULE => "module",
+        _ if definition.is_macro => "macro",
+        SymbolKind::FUNCTION => "function",
+        SymbolKind::CONSTANT => "constant",
+        _ => "variable",
+    };
+
+    CompletionItem {
+        label: definition.name.clone(),
+        kind: Some(completion_item_kind(definition)),
+        detail: Some(detail.to_string()),
+        tags: definition
+            .deprecated
+            .then(|| vec![CompletionItemTag::DEPRECATED]),
+        data: serde_json::to_value(data).ok(),
+        ..Default::default()
+    }
+}
+
+/// Completes the members of an imported module, after the qualifier, e.g.
+/// `ops/`. The members replace the text after the `/`.
+fn module_member_completion(
+    snapshot: &Snapshot,
+    uri: &Url,
+    definitions: &[Definition],
+    qualifier: &str,
+    member: &str,
+    member_range: Range,
+) -> anyhow::Result<Vec<CompletionItem>> {
+    let Ok(path) = uri.to_file_path() else {
+        return Ok(Vec::new());
+    };
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    // The module is qualified with the last segment of the imported name,
+    // e.g. `ops/add` for `(use math/ops)`.
+    let Some(module) = definitions.iter().find(|definition| {
+        definition.kind == SymbolKind::MODULE
+            && definition.name.rsplit('/').next() == Some(qualifier)
+    }) else {
+        return Ok(Vec::new());
+    };
+
+    let Some((module_path, _)) = resolve_import(&module.name, base_dir, &snapshot.config) else {
+        return Ok(Vec::new());
+    };
+
+    let summary = summarize_module(&module_path, &snapshot.vfs)?;
+
+    let items = summary
+        .exports
+        .iter()
+        .filter(|export| export.starts_with(member))
+        .map(|export| {
+            let data = ItemData::Member {
+                module_path: module_path.clone(),
+                name: export.clone(),
+            };
+            CompletionItem {
+                label: export.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(format!("from `{}`", module.name)),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    member_range,
+                    export.clone(),
+                ))),
+                data: serde_json::to_value(data).ok(),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Ok(items)
+}
+
+/// Returns true if the client supports snippets in completion items.
+fn supports_snippets(snapshot: &Snapshot) -> bool {
+    snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.completion.as_ref())
+        .and_then(|completion| completion.completion_item.as_ref())
+        .and_then(|completion_item| completion_item.snippet_support)
+        .unwrap_or(false)
+}
+
+/// Completes the tags in doc comments, and the parameter names after the
+/// `@param` tag. Returns `None` outside of comments.
+fn doc_tag_completion(
+    snapshot: &Snapshot,
+    params: &CompletionParams,
+) -> anyhow::Result<Option<Vec<CompletionItem>>> {
+    let position = params.text_document_position.position;
+    let input = snapshot
+        .vfs
+        .read(&params.text_document_position.text_document.uri)?;
+
+    let Some(line) = input.lines().nth(position.line as usize) else {
+        return Ok(None);
+    };
+
+    let line_start = index_from_lsp_position(&lsp_types::Position::new(position.line, 0), &input);
+    let index = index_from_lsp_position(&position, &input);
+    let before: String = line.chars().take(index - line_start).collect();
+
+    let text = before.trim_start();
+    if !text.starts_with(';') && !text.starts_with("--") {
+        return Ok(None);
+    }
+
+    let word_len = before
+        .chars()
+        .rev()
+        .take_while(|c| !c.is_whitespace())
+        .count();
+    let word_start = index - word_len;
+    let word: String = before
+        .chars()
+        .skip(before.chars().count() - word_len)
+        .collect();
+    let range = Range::new(lsp_position_from_index(word_start, &input), position);
+
+    if word.starts_with(TAG_PREFIX) {
+        let items = DOC_TAGS
+            .iter()
+            .map(|(tag, detail)| CompletionItem {
+                label: format!("{TAG_PREFIX}{tag}"),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: Some(detail.to_string()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    range,
+                    format!("{TAG_PREFIX}{tag}"),
+                ))),
+                ..Default::default()
+            })
+            .collect();
+        return Ok(Some(items));
+    }
+
+    let previous_word = before[..before.len() - word.len()]
+        .split_whitespace()
+        .last();
+    if previous_word != Some(&format!("{TAG_PREFIX}{PARAM_TAG}")) {
+        return Ok(Some(Vec::new()));
+    }
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(Some(Vec::new()));
+    };
+
+    let docs = function_docs(&exprs, &input);
+    let Some(doc) = docs.iter().find(|doc| doc.contains(index)) else {
+        return Ok(Some(Vec::new()));
+    };
+
+    let items = doc
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            Ann(Expr::Symbol(name), _) => Some(CompletionItem {
+                label: name.clone(),
+                kind: Some(CompletionItemKind::VARIABLE),
+                detail: Some(format!("parameter of `{}`", doc.name)),
+                ..Default::default()
+            }),
+            _ => None,
+        })
+        .collect();
+
+    Ok(Some(items))
+}
+
+pub fn completion(
+    snapshot: &Snapshot,
+    params: CompletionParams,
+) -> anyhow::Result<Option<CompletionResponse>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+
+    if uri
+        .to_file_path()
+        .is_ok_and(|path| snapshot.config.is_manifest(&path))
+    {
+        let input = snapshot.vfs.read(uri)?;
+        let index = index_from_lsp_position(&position, &input);
+        let items = manifest_completion(&input, index, &snapshot.config);
+        return Ok(Some(CompletionResponse::Array(items)));
+    }
+
+    if let Some(items) = doc_tag_completion(snapshot, &params)? {
+        return Ok(Some(CompletionResponse::Array(items)));
+    }
+
+    let input = snapshot.vfs.read(uri)?;
+
+    let index = index_from_lsp_position(&position, &input);
+    let start = prefix_start(&input, index);
+    let prefix: String = input.chars().skip(start).take(index - start).collect();
+
+    let exprs = parse_string_all(&input).ok();
+
+    let definitions = match &exprs {
+        Some(exprs) => collect_definitions(exprs),
+        None => snapshot
+            .index
+            .get(uri)
+            .map(|file| file.definitions.clone())
+            .unwrap_or_default(),
+    };
+
+    if let Some((qualifier, member)) = prefix.rsplit_once('/') {
+        let member_start = index - member.chars().count();
+        let member_range = Range::new(lsp_position_from_index(member_start, &input), position);
+        let items =
+            module_member_completion(snapshot, uri, &definitions, qualifier, member, member_range)?;
+        return Ok(Some(CompletionResponse::Array(items)));
+    }
+
+    let mut items = Vec::new();
+    let mut names = HashSet::new();
+
+    // The local bindings shadow the module-level names. The name being
+    // defined, at the cursor, is skipped.
+    if let Some(exprs) = &exprs {
+        for (name, binding) in visible_local_bindings(exprs, start) {
+            if binding.name_range.start != start && names.insert(name.to_string()) {
+                items.push(CompletionItem {
+                    label: name.to_string(),
+                    kind: Some(CompletionItemKind::VARIABLE),
+                    detail: Some("local".to_string()),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    for definition in &definitions {
+        if definition.selection_range.start != start && names.insert(definition.name.clone()) {
+            let data = ItemData::Definition {
+                uri: uri.clone(),
+                document: uri.clone(),
+                name: definition.name.clone(),
+            };
+            items.push(definition_item(definition, &data));
+        }
+    }
+
+    // #Insight
+    // The definitions of the other files are completed without their
+    // documentation and imports, resolved for the selected item only.
+
+    // The files are sorted, for deterministic results.
+    let mut files: Vec<_> = snapshot
+        .index
+        .files()
+        .filter(|(file_uri, _)| *file_uri != uri)
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+    for (file_uri, file) in files {
+        for definition in &file.definitions {
+            if definition.kind == SymbolKind::MODULE
+                || !definition.name.starts_with(&prefix)
+                || !names.insert(definition.name.clone())
+            {
+                continue;
+            }
+            let data = ItemData::Definition {
+                uri: file_uri.clone(),
+                document: uri.clone(),
+                name: definition.name.clone(),
+            };
+            items.push(definition_item(definition, &data));
+        }
+    }
+
+    for (name, type_name) in builtins() {
+        if names.insert(name.clone()) {
+            items.push(CompletionItem {
+                label: name.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(match type_name {
+                    Some(type_name) => format!("builtin: {type_name}"),
+                    None => "builtin".to_string(),
+                }),
+                ..Default::default()
+            });
+        }
+    }
+
+    let snippets = supports_snippets(snapshot);
+
+    items.extend(SPECIAL_FORMS.iter().map(|(label, snippet, detail)| {
+        // #Insight
+        // Without snippet support the placeholders would be inserted
+        // literally, only the keyword is inserted.
+        let (insert_text, insert_text_format) = if snippets {
+            (snippet.to_string(), InsertTextFormat::SNIPPET)
+        } else {
+            (label.to_string(), InsertTextFormat::PLAIN_TEXT)
+        };
+
+        CompletionItem {
+            label: label.to_string(),
+            kind: Some(if snippets {
+                CompletionItemKind::SNIPPET
+            } else {
+                CompletionItemKind::KEYWORD
+            }),
+            detail: Some(detail.to_string()),
+            insert_text: Some(insert_text),
+            insert_text_format: Some(insert_text_format),
+            ..Default::default()
+        }
+    }));
+
+    items.retain(|item| item.label.starts_with(&prefix));
+
+    Ok(Some(CompletionResponse::Array(items)))
+}
+
+pub fn completion_resolve(
+    snapshot: &Snapshot,
+    mut item: CompletionItem,
+) -> anyhow::Result<CompletionItem> {
+    let Some(data) = item.data.clone() else {
+        return Ok(item);
+    };
+
+    let description = match serde_json::from_value(data)? {
+        ItemData::Definition {
+            uri,
+            document,
+            name,
+        } => {
+            if uri != document {
+                item.additional_text_edits =
+                    import_edit(snapshot, &uri, &document)?.map(|(_, edit)| vec![edit]);
+            }
+            let text = snapshot.vfs.read(&uri)?;
+            describe_definition(&text, &name)
+        }
+        ItemData::Member { module_path, name } => {
+            let mut description = None;
+            for file_path in module_files(&module_path)? {
+                let Ok(file_uri) = Url::from_file_path(&file_path) else {
+                    continue;
+                };
+                let text = snapshot.vfs.read(&file_uri)?;
+                description = describe_definition(&text, &name);
+                if description.is_some() {
+                    break;
+                }
+            }
+            description
+        }
+    };
+
+    let Some(description) = description else {
+        return Ok(item);
+    };
+
+    item.detail = Some(
+        description
+            .signature
+            .clone()
+            .unwrap_or_else(|| description.label.to_string()),
+    );
+
+    if let Some(documentation) = description.documentation() {
+        let documentation_format = snapshot
+            .config
+            .client_capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|completion_item| completion_item.documentation_format.as_deref());
+        let kind = preferred_markup_kind(documentation_format);
+        item.documentation = Some(Documentation::MarkupContent(markup_content(
+            kind,
+            &documentation,
+        )));
+    }
+
+    Ok(item)
+}
+
+/// Describes the module-level definition of a name, in the text of a file.
+fn describe_definition(text: &str, name: &str) -> Option<BindingDescription> {
+    let exprs = parse_string_all(text).ok()?;
+
+    let definition = collect_definitions(&exprs).into_iter().find(|definition| {
+        same_name(&definition.name, name) && definition.kind != SymbolKind::MODULE
+    })?;
+
+    let binding = Binding {
+        range: definition.range,
+        name_range: definition.selection_range,
+    };
+
+    Some(describe_binding(&exprs, text, &binding))
+}
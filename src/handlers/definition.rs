@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use lsp_types::{
+    GotoDefinitionParams, GotoDefinitionResponse, Location, LocationLink, Position, Range, Url,
+};
+use tan::{ann::Ann, expr::Expr};
+
+use crate::{
+    ast::{find_path_at, head_symbol},
+    packages::{missing_sources, MissingSources},
+    resolve::lookup_symbol_at,
+    snapshot::Snapshot,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+/// The forms that take a file path as the first argument.
+pub const PATH_FORMS: [&str; 4] = ["include", "load", "read", "File:read_as_string"];
+
+pub fn definition(
+    snapshot: &Snapshot,
+    params: GotoDefinitionParams,
+) -> anyhow::Result<Option<GotoDefinitionResponse>> {
+    let uri = &params.text_document_position_params.text_document.uri;
+    let position = &params.text_document_position_params.position;
+
+    // The documents that are not files, e.g. untitled, have no directory.
+    let path = uri.to_file_path().unwrap_or_default();
+    let input = snapshot.vfs.read(uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(position, &input);
+    let expr_path = find_path_at(&exprs, index);
+
+    // A symbol goes to its binding, e.g. the `let` expression or the function
+    // parameter, in the document or in an indexed file.
+    if let Some(symbol_expr @ Ann(Expr::Symbol(_), _)) = expr_path.last() {
+        let Some(target) = lookup_symbol_at(snapshot, uri, &input, &exprs, index) else {
+            return missing_sources_at(snapshot, &path, &expr_path)
+                .map_or(Ok(None), |missing| Err(missing.into()));
+        };
+
+        let name_range = lsp_range_from_range(&target.binding.name_range, &target.text);
+
+        // Clients with link support highlight the whole binding form.
+        if supports_definition_links(snapshot) {
+            let origin_range = lsp_range_from_range(&symbol_expr.get_range(), &input);
+            return Ok(Some(GotoDefinitionResponse::Link(vec![LocationLink {
+                origin_selection_range: Some(origin_range),
+                target_range: lsp_range_from_range(&target.binding.range, &target.text),
+                target_uri: target.uri,
+                target_selection_range: name_range,
+            }])));
+        }
+
+        return Ok(Some(GotoDefinitionResponse::Scalar(Location::new(
+            target.uri, name_range,
+        ))));
+    }
+
+    // A string literal that names a file, e.g. `(include "util.tan")`, goes
+    // to the start of the file.
+    let [.., form, Ann(Expr::String(file_name), _)] = expr_path.as_slice() else {
+        return Ok(None);
+    };
+
+    if !head_symbol(form).is_some_and(|head| PATH_FORMS.contains(&head)) {
+        return Ok(None);
+    }
+
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let file_path = base_dir.join(file_name);
+
+    if !file_path.is_file() {
+        return Ok(None);
+    }
+
+    let Ok(file_uri) = Url::from_file_path(&file_path) else {
+        return Ok(None);
+    };
+
+    let start = Range::new(Position::new(0, 0), Position::new(0, 0));
+
+    Ok(Some(GotoDefinitionResponse::Scalar(Location::new(
+        file_uri, start,
+    ))))
+}
+
+/// Returns the package with missing sources of the symbol at the end of the
+/// path, i.e. the module of a `(use json)` expression, or the qualifier of a
+/// `json/parse` symbol.
+fn missing_sources_at(
+    snapshot: &Snapshot,
+    path: &Path,
+    expr_path: &[&Ann<Expr>],
+) -> Option<MissingSources> {
+    let [.., parent, Ann(Expr::Symbol(name), _)] = expr_path else {
+        return None;
+    };
+
+    let module_name = match head_symbol(parent) {
+        Some("use") => name.as_str(),
+        _ => name.split_once('/')?.0,
+    };
+
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    missing_sources(module_name, base_dir, &snapshot.config)
+}
+
+fn supports_definition_links(snapshot: &Snapshot) -> bool {
+    snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.definition.as_ref())
+        .and_then(|definition| definition.link_support)
+        .unwrap_or(false)
+}
@@ -0,0 +1,220 @@
+use lsp_types::{
+    Color, ColorInformation, ColorPresentation, ColorPresentationParams, DocumentColorParams,
+    TextEdit,
+};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    snapshot::Snapshot,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+// #Insight
+// Tan has no color type, the colors are detected by convention: the hex
+// strings, e.g. `"#ff8800"`, and the `(rgb r g b)` and `(rgba r g b a)`
+// calls, with the components in 0-255 and the alpha in 0-1. The color picker
+// of the editor rewrites the literal in the same form, the other form is
+// offered too.
+
+/// The written form of a color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorForm {
+    /// A hex string, uppercase or not.
+    Hex { uppercase: bool },
+    /// An `(rgb r g b)` call.
+    Rgb,
+    /// An `(rgba r g b a)` call.
+    Rgba,
+}
+
+/// A color literal of a document.
+struct ColorLiteral {
+    /// The (char) range of the string or the call.
+    range: Range,
+    color: Color,
+    form: ColorForm,
+}
+
+pub fn document_color(
+    snapshot: &Snapshot,
+    params: DocumentColorParams,
+) -> anyhow::Result<Vec<ColorInformation>> {
+    let input = snapshot.vfs.read(&params.text_document.uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(&params.text_document.uri, &input) else {
+        return Ok(Vec::new());
+    };
+
+    let colors = color_literals(&exprs)
+        .into_iter()
+        .map(|literal| ColorInformation {
+            range: lsp_range_from_range(&literal.range, &input),
+            color: literal.color,
+        })
+        .collect();
+
+    Ok(colors)
+}
+
+pub fn color_presentation(
+    snapshot: &Snapshot,
+    params: ColorPresentationParams,
+) -> anyhow::Result<Vec<ColorPresentation>> {
+    let input = snapshot.vfs.read(&params.text_document.uri)?;
+
+    // The form of the edited literal comes first, a hex string by default.
+    let start = index_from_lsp_position(&params.range.start, &input);
+    let form = parse_string_all(&input)
+        .ok()
+        .and_then(|exprs| {
+            color_literals(&exprs)
+                .into_iter()
+                .find(|literal| literal.range.start == start)
+        })
+        .map_or(ColorForm::Hex { uppercase: false }, |literal| literal.form);
+
+    let forms = match form {
+        ColorForm::Hex { .. } => [form, ColorForm::Rgb],
+        ColorForm::Rgb | ColorForm::Rgba => [form, ColorForm::Hex { uppercase: false }],
+    };
+
+    let presentations = forms
+        .into_iter()
+        .map(|form| {
+            let label = format_color(&params.color, form);
+            ColorPresentation {
+                text_edit: Some(TextEdit::new(params.range, label.clone())),
+                label,
+                additional_text_edits: None,
+            }
+        })
+        .collect();
+
+    Ok(presentations)
+}
+
+/// Returns the color literals of a document, in document order.
+fn color_literals(exprs: &[Ann<Expr>]) -> Vec<ColorLiteral> {
+    let mut literals = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let range = expr.get_range();
+        let literal = match expr {
+            // The multi-line text literals are not colors.
+            Ann(Expr::String(value), _) if range.len() == value.chars().count() + 2 => {
+                parse_hex(value)
+            }
+            Ann(Expr::List(terms), _) => match head_symbol(expr) {
+                Some("rgb") => parse_rgb(&terms[1..], ColorForm::Rgb),
+                Some("rgba") => parse_rgb(&terms[1..], ColorForm::Rgba),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some((color, form)) = literal {
+            literals.push(ColorLiteral { range, color, form });
+        }
+    });
+
+    literals.sort_by_key(|literal| literal.range.start);
+
+    literals
+}
+
+/// Parses a hex color, `#rgb`, `#rrggbb` or `#rrggbbaa`.
+fn parse_hex(value: &str) -> Option<(Color, ColorForm)> {
+    let digits = value.strip_prefix('#')?;
+    if !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let component = |i: usize, len: usize| -> Option<f32> {
+        let hex = digits.get(i * len..(i + 1) * len)?;
+        let value = u8::from_str_radix(hex, 16).ok()?;
+        // `#f80` is `#ff8800`.
+        let value = if len == 1 { value * 17 } else { value };
+        Some(f32::from(value) / 255.0)
+    };
+
+    let len = if digits.len() == 3 { 1 } else { 2 };
+    let alpha = match digits.len() {
+        3 | 6 => 1.0,
+        8 => component(3, len)?,
+        _ => return None,
+    };
+
+    let color = Color {
+        red: component(0, len)?,
+        green: component(1, len)?,
+        blue: component(2, len)?,
+        alpha,
+    };
+    let uppercase = digits.chars().any(|ch| ch.is_ascii_uppercase());
+
+    Some((color, ColorForm::Hex { uppercase }))
+}
+
+/// Parses the arguments of an `rgb` or `rgba` call.
+fn parse_rgb(args: &[Ann<Expr>], form: ColorForm) -> Option<(Color, ColorForm)> {
+    let component = |arg: &Ann<Expr>| match arg {
+        Ann(Expr::Int(value), _) if (0..=255).contains(value) => Some(*value as f32 / 255.0),
+        _ => None,
+    };
+
+    let alpha = match (form, args) {
+        (ColorForm::Rgb, [_, _, _]) => 1.0,
+        (ColorForm::Rgba, [_, _, _, alpha]) => match alpha {
+            Ann(Expr::Float(value), _) if (0.0..=1.0).contains(value) => *value as f32,
+            Ann(Expr::Int(value @ (0 | 1)), _) => *value as f32,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let color = Color {
+        red: component(&args[0])?,
+        green: component(&args[1])?,
+        blue: component(&args[2])?,
+        alpha,
+    };
+
+    Some((color, form))
+}
+
+/// Formats a color in a form, the opaque colors as `rgb` calls and the
+/// translucent ones as `rgba` calls.
+fn format_color(color: &Color, form: ColorForm) -> String {
+    let byte = |component: f32| (component.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let (red, green, blue, alpha) = (
+        byte(color.red),
+        byte(color.green),
+        byte(color.blue),
+        byte(color.alpha),
+    );
+
+    match form {
+        ColorForm::Hex { uppercase } => {
+            let hex = if alpha == 255 {
+                format!("#{red:02x}{green:02x}{blue:02x}")
+            } else {
+                format!("#{red:02x}{green:02x}{blue:02x}{alpha:02x}")
+            };
+            let hex = if uppercase { hex.to_uppercase() } else { hex };
+            format!("\"{hex}\"")
+        }
+        ColorForm::Rgb if alpha == 255 => format!("(rgb {red} {green} {blue})"),
+        ColorForm::Rgb | ColorForm::Rgba => {
+            // The alpha is a Float literal, e.g. `0.5` or `1.0`.
+            let alpha = format!("{:.2}", color.alpha.clamp(0.0, 1.0));
+            let alpha = alpha.trim_end_matches('0');
+            let alpha = if alpha.ends_with('.') {
+                format!("{alpha}0")
+            } else {
+                alpha.to_owned()
+            };
+            format!("(rgba {red} {green} {blue} {alpha})")
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use lsp_types::{DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::{find_path_at, for_each_expr, head_symbol},
+    handlers::completion::is_special_form,
+    resolve::resolve_symbol_at,
+    snapshot::Snapshot,
+    unicode::same_name,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+// #Insight
+// Tan has no `return`, the exits of a function are the expressions in tail
+// position of its body: the last expression of a `do`, and the branches of an
+// `if`. An `if` without a false clause is an exit too, it produces `One`.
+// On any other symbol, the occurrences of the symbol are highlighted.
+
+pub fn document_highlight(
+    snapshot: &Snapshot,
+    params: DocumentHighlightParams,
+) -> anyhow::Result<Option<Vec<DocumentHighlight>>> {
+    let uri = &params.text_document_position_params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(&params.text_document_position_params.position, &input);
+    let path = find_path_at(&exprs, index);
+
+    if let Some(ranges) = exit_ranges(&path) {
+        return Ok(Some(
+            ranges
+                .iter()
+                .map(|range| DocumentHighlight {
+                    range: lsp_range_from_range(range, &input),
+                    kind: Some(DocumentHighlightKind::TEXT),
+                })
+                .collect(),
+        ));
+    }
+
+    let Some(symbol @ Ann(Expr::Symbol(name), _)) = path.last() else {
+        return Ok(None);
+    };
+    if is_special_form(name) {
+        return Ok(None);
+    }
+
+    let highlights = symbol_occurrences(&exprs, name, symbol.get_range().start)
+        .into_iter()
+        .map(|(range, kind)| DocumentHighlight {
+            range: lsp_range_from_range(&range, &input),
+            kind: Some(kind),
+        })
+        .collect();
+
+    Ok(Some(highlights))
+}
+
+/// Returns the occurrences of the symbol at the (char) index, the symbols
+/// that resolve to the same binding: the bound name is a write, the other
+/// occurrences are reads. The names not bound in the document, e.g. the
+/// imported names, are matched by name.
+fn symbol_occurrences(
+    exprs: &[Ann<Expr>],
+    name: &str,
+    index: usize,
+) -> Vec<(Range, DocumentHighlightKind)> {
+    let binding = resolve_symbol_at(exprs, index);
+    let mut occurrences = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        if range.start >= range.end || !same_name(sym, name) {
+            return;
+        }
+        if resolve_symbol_at(exprs, range.start) != binding {
+            return;
+        }
+
+        let is_definition = binding
+            .as_ref()
+            .is_some_and(|binding| binding.name_range == range);
+        let kind = if is_definition {
+            DocumentHighlightKind::WRITE
+        } else {
+            DocumentHighlightKind::READ
+        };
+        occurrences.push((range, kind));
+    });
+
+    occurrences
+}
+
+/// Returns the ranges of the function head and of the exits of the enclosing
+/// function, if the path ends on the head of the function, or on the head of a
+/// `do` or `if` in tail position.
+fn exit_ranges(path: &[&Ann<Expr>]) -> Option<Vec<Range>> {
+    let [.., parent, head @ Ann(Expr::Symbol(_), _)] = path else {
+        return None;
+    };
+    let Ann(Expr::List(terms), _) = parent else {
+        return None;
+    };
+    if terms.first().map(|term| term.get_range()) != Some(head.get_range()) {
+        return None;
+    }
+
+    let function = path
+        .iter()
+        .rev()
+        .find(|expr| matches!(head_symbol(expr), Some("Func") | Some("Macro")))?;
+    let Ann(Expr::List(function_terms), _) = function else {
+        return None;
+    };
+    let body = function_terms.get(2)?;
+
+    let mut exits = Vec::new();
+    let mut tail_forms = Vec::new();
+    collect_exits(body, &mut exits, &mut tail_forms);
+
+    let is_trigger = std::ptr::eq(*parent, *function)
+        || tail_forms.iter().any(|form| std::ptr::eq(*form, *parent));
+    if !is_trigger {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    ranges.extend(function_terms.first().map(|term| term.get_range()));
+    ranges.extend(exits);
+
+    Some(ranges)
+}
+
+/// Collects the ranges of the exits of an expression in tail position, and the
+/// `do` and `if` forms that lead to them.
+fn collect_exits<'a>(
+    expr: &'a Ann<Expr>,
+    exits: &mut Vec<Range>,
+    tail_forms: &mut Vec<&'a Ann<Expr>>,
+) {
+    let Ann(Expr::List(terms), _) = expr else {
+        exits.push(expr.get_range());
+        return;
+    };
+
+    let terms: Vec<&Ann<Expr>> = terms
+        .iter()
+        .filter(|term| !matches!(term, Ann(Expr::Comment(..), _)))
+        .collect();
+
+    match head_symbol(expr) {
+        Some("do") => {
+            tail_forms.push(expr);
+            match terms[1..].last() {
+                Some(last) => collect_exits(last, exits, tail_forms),
+                None => exits.push(expr.get_range()),
+            }
+        }
+        Some("if") => {
+            tail_forms.push(expr);
+            if let Some(true_clause) = terms.get(2) {
+                collect_exits(true_clause, exits, tail_forms);
+            }
+            match terms.get(3) {
+                Some(false_clause) => collect_exits(false_clause, exits, tail_forms),
+                // The `if` itself produces `One`.
+                None => exits.push(terms[0].get_range()),
+            }
+        }
+        _ => exits.push(expr.get_range()),
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::{DocumentLink, DocumentLinkParams, Url};
+use serde::{Deserialize, Serialize};
+use tan::{ann::Ann, expr::Expr};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    handlers::definition::PATH_FORMS,
+    module::{module_files, resolve_import, ModuleOrigin},
+    snapshot::Snapshot,
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// Resolving the link targets requires file system access, links are resolved
+// lazily, only when the user interacts with them.
+
+/// The link data, used to resolve the link target.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum LinkData {
+    /// A module imported with `(use module)`.
+    Module { document: Url, name: String },
+    /// A file path passed to an include/load/read form.
+    File { document: Url, path: String },
+}
+
+/// The URL schemes recognized in comments.
+const URL_SCHEMES: [&str; 2] = ["https://", "http://"];
+
+pub fn document_link(
+    snapshot: &Snapshot,
+    params: DocumentLinkParams,
+) -> anyhow::Result<Option<Vec<DocumentLink>>> {
+    let uri = params.text_document.uri;
+    let input = snapshot.vfs.read(&uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(&uri, &input) else {
+        return Ok(None);
+    };
+
+    let mut links = Vec::new();
+
+    for_each_expr(&exprs, &mut |expr| {
+        let Ann(expr_value, _) = expr;
+
+        if let Expr::Comment(text) = expr_value {
+            links.extend(comment_links(text, expr.get_range().start, &input));
+            return;
+        }
+
+        let Ann(Expr::List(terms), _) = expr else {
+            return;
+        };
+
+        let data = match (head_symbol(expr), terms.get(1)) {
+            (Some("use"), Some(Ann(Expr::Symbol(name), _))) => LinkData::Module {
+                document: uri.clone(),
+                name: name.clone(),
+            },
+            (Some(head), Some(Ann(Expr::String(path), _))) if PATH_FORMS.contains(&head) => {
+                LinkData::File {
+                    document: uri.clone(),
+                    path: path.clone(),
+                }
+            }
+            _ => return,
+        };
+
+        links.push(DocumentLink {
+            range: lsp_range_from_range(&terms[1].get_range(), &input),
+            target: None,
+            tooltip: None,
+            data: serde_json::to_value(data).ok(),
+        });
+    });
+
+    Ok(Some(links))
+}
+
+/// Returns the links to the URLs in a comment.
+fn comment_links(text: &str, start: usize, input: &str) -> Vec<DocumentLink> {
+    let mut links = Vec::new();
+
+    // #Insight
+    // Ranges are char-based, the byte offsets in the comment text are
+    // converted to char offsets.
+
+    for word in text.split_whitespace() {
+        if !URL_SCHEMES.iter().any(|scheme| word.starts_with(scheme)) {
+            continue;
+        }
+
+        // Trim trailing punctuation, e.g. in `(see https://tan.org).`
+        let word = word.trim_end_matches(['.', ',', ';', ')', ']', '>']);
+
+        let Ok(target) = Url::parse(word) else {
+            continue;
+        };
+
+        let byte_offset = word.as_ptr() as usize - text.as_ptr() as usize;
+        let offset = start + text[..byte_offset].chars().count();
+        let range = offset..(offset + word.chars().count());
+
+        links.push(DocumentLink {
+            range: lsp_range_from_range(&range, input),
+            target: Some(target),
+            tooltip: Some("Open URL".to_owned()),
+            data: None,
+        });
+    }
+
+    links
+}
+
+/// Resolves the target of a document link.
+pub fn document_link_resolve(
+    snapshot: &Snapshot,
+    link: DocumentLink,
+) -> anyhow::Result<DocumentLink> {
+    let Some(data) = link.data.clone() else {
+        return Ok(link);
+    };
+
+    let (target, tooltip) = match serde_json::from_value(data)? {
+        LinkData::Module { document, name } => {
+            let base_dir = document_dir(&document);
+            match resolve_import(&name, &base_dir, &snapshot.config) {
+                Some((path, origin)) => {
+                    let tooltip = match origin {
+                        ModuleOrigin::Local => format!("Go to module `{name}`"),
+                        ModuleOrigin::Workspace => {
+                            format!("Go to module `{name}` (workspace)")
+                        }
+                        ModuleOrigin::Package => format!("Go to package module `{name}`"),
+                    };
+                    (module_target(&path), tooltip)
+                }
+                None => (None, format!("Module `{name}` not found")),
+            }
+        }
+        LinkData::File { document, path } => {
+            let file_path = document_dir(&document).join(&path);
+            if file_path.is_file() {
+                (
+                    Url::from_file_path(&file_path).ok(),
+                    format!("Open file `{path}`"),
+                )
+            } else {
+                (None, format!("File `{path}` not found"))
+            }
+        }
+    };
+
+    Ok(DocumentLink {
+        target,
+        tooltip: Some(tooltip),
+        ..link
+    })
+}
+
+fn document_dir(uri: &Url) -> PathBuf {
+    uri.to_file_path()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Returns the link target of a module, the directory of a multi-file module
+/// cannot be opened in the editor so the link targets the first file.
+fn module_target(path: &Path) -> Option<Url> {
+    if path.is_file() {
+        return Url::from_file_path(path).ok();
+    }
+
+    let files = module_files(path).ok()?;
+    Url::from_file_path(files.first()?).ok()
+}
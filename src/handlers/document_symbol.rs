@@ -0,0 +1,143 @@
+use lsp_types::{DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Location};
+use tan::{ann::Ann, expr::Expr};
+
+use crate::{
+    ast::head_symbol,
+    doc_tags::signature_params,
+    snapshot::Snapshot,
+    symbols::{collect_definitions, let_definitions, SymbolSupport},
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// The outline nests the local definitions, e.g. the `let` bindings of a
+// function body, in the symbols of the enclosing definitions.
+
+pub fn document_symbol(
+    snapshot: &Snapshot,
+    params: DocumentSymbolParams,
+) -> anyhow::Result<Option<DocumentSymbolResponse>> {
+    let uri = params.text_document.uri;
+    let input = snapshot.vfs.read(&uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(&uri, &input) else {
+        // Parse errors are reported as diagnostics.
+        return Ok(None);
+    };
+
+    let capabilities = snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.document_symbol.as_ref());
+    let support = SymbolSupport::new(
+        capabilities.and_then(|c| c.symbol_kind.as_ref()),
+        capabilities.and_then(|c| c.tag_support.as_ref()),
+    );
+
+    let is_hierarchical = capabilities
+        .and_then(|c| c.hierarchical_document_symbol_support)
+        .unwrap_or(false);
+
+    if is_hierarchical {
+        let symbols = module_symbols(&exprs, &input, &support);
+        return Ok(Some(DocumentSymbolResponse::Nested(symbols)));
+    }
+
+    let symbols = collect_definitions(&exprs)
+        .iter()
+        .map(|definition| {
+            let range = lsp_range_from_range(&definition.range, &input);
+            support.symbol_information(definition, Location::new(uri.clone(), range))
+        })
+        .collect();
+
+    Ok(Some(DocumentSymbolResponse::Flat(symbols)))
+}
+
+/// Returns the symbols of the module-level definitions, i.e. the top-level
+/// `let` and `use` expressions, including the ones in top-level `do` blocks.
+fn module_symbols(
+    exprs: &[Ann<Expr>],
+    input: &str,
+    support: &SymbolSupport,
+) -> Vec<DocumentSymbol> {
+    let mut symbols = Vec::new();
+
+    for expr in exprs {
+        match head_symbol(expr) {
+            Some("let") => symbols.extend(let_symbols(expr, input, support)),
+            Some("use") => {
+                for definition in collect_definitions(std::slice::from_ref(expr)) {
+                    let range = lsp_range_from_range(&definition.range, input);
+                    let selection_range = lsp_range_from_range(&definition.selection_range, input);
+                    symbols.push(support.document_symbol(
+                        &definition,
+                        range,
+                        selection_range,
+                        None,
+                        Vec::new(),
+                    ));
+                }
+            }
+            Some("do") => {
+                if let Ann(Expr::List(terms), _) = expr {
+                    symbols.extend(module_symbols(&terms[1..], input, support));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    symbols
+}
+
+/// Returns the symbols of the definitions of a `let` expression, with the
+/// nested definitions of the values as children.
+fn let_symbols(expr: &Ann<Expr>, input: &str, support: &SymbolSupport) -> Vec<DocumentSymbol> {
+    let definitions = let_definitions(expr);
+    let is_single = definitions.len() == 1;
+
+    definitions
+        .into_iter()
+        .map(|(definition, value)| {
+            // The range of a binding in a `let` with multiple bindings spans
+            // the name and the value.
+            let range = if is_single {
+                definition.range.clone()
+            } else {
+                definition.selection_range.start..value.get_range().end
+            };
+
+            let detail = signature_params(value).map(|params| {
+                let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+                format!("({})", params.join(" "))
+            });
+
+            support.document_symbol(
+                &definition,
+                lsp_range_from_range(&range, input),
+                lsp_range_from_range(&definition.selection_range, input),
+                detail,
+                nested_symbols(value, input, support),
+            )
+        })
+        .collect()
+}
+
+/// Returns the symbols of the `let` definitions nested in an expression.
+fn nested_symbols(expr: &Ann<Expr>, input: &str, support: &SymbolSupport) -> Vec<DocumentSymbol> {
+    if head_symbol(expr) == Some("let") {
+        return let_symbols(expr, input, support);
+    }
+
+    let Ann(Expr::List(terms), _) = expr else {
+        return Vec::new();
+    };
+
+    terms
+        .iter()
+        .flat_map(|term| nested_symbols(term, input, support))
+        .collect()
+}
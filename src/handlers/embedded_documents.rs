@@ -0,0 +1,35 @@
+use tan::api::parse_string_all;
+
+use crate::{
+    embedded::embedded_strings,
+    lsp_ext::{EmbeddedDocument, EmbeddedDocumentsParams},
+    snapshot::Snapshot,
+    util::lsp_range_from_range,
+};
+
+pub fn embedded_documents(
+    snapshot: &Snapshot,
+    params: EmbeddedDocumentsParams,
+) -> anyhow::Result<Option<Vec<EmbeddedDocument>>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let documents = embedded_strings(&exprs, &input)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, string)| {
+            Some(EmbeddedDocument {
+                uri: string.uri(uri, index)?,
+                language_id: string.language.id().to_owned(),
+                range: lsp_range_from_range(&string.range, &input),
+                text: string.text,
+            })
+        })
+        .collect();
+
+    Ok(Some(documents))
+}
@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use lsp_types::{ExecuteCommandParams, Position, Range, TextEdit, Url, WorkspaceEdit};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::find_path_at,
+    edit::EditBuilder,
+    evaluate::{contains_use, evaluate, evaluation_source},
+    handlers::rename::{insert_text_edits, text_annotation},
+    indent::join_lines,
+    lsp_ext::{
+        EvaluateResult, FileUsage, FormatWorkspaceResult, ModuleUsage, MoveItemResult,
+        SafeDeleteResult, SymbolStats, UnusedSymbol, UsageCounts,
+    },
+    manifest::{dependency_entries, update_constraint},
+    move_item::{move_item, Direction},
+    references::{find_unused_public_definitions, find_usages, UsageContext},
+    resolve::resolve_local_symbol_at,
+    safe_delete::{safe_delete, SafeDelete},
+    sandbox::FilesystemAccess,
+    snapshot::Snapshot,
+    ssr::Rule,
+    util::{index_from_lsp_position, LineIndex},
+    workspace_format::{format_source, workspace_format_files},
+};
+
+// #Insight
+// The server applies the edits of the commands with `workspace/applyEdit`,
+// before the response is sent. The response still contains the edit, e.g. to
+// move the cursor after `tan.moveItemUp`, the client should not apply it
+// again. In dry-run mode the edit is only returned.
+
+/// Structural search and replace, the argument is a rule of the form
+/// `pattern ==>> template`. Applies and returns the workspace edit.
+pub const SSR_COMMAND: &str = "tan.ssr";
+
+/// Joins the selected lines, the arguments are the document uri and the
+/// selected range. Applies and returns the text edits.
+pub const JOIN_LINES_COMMAND: &str = "tan.joinLines";
+
+/// Swaps the item at the cursor (let binding, or top-level form) with the
+/// previous one, the arguments are the document uri and the cursor position.
+/// Applies and returns the text edits, and returns the new cursor position.
+pub const MOVE_ITEM_UP_COMMAND: &str = "tan.moveItemUp";
+
+/// Swaps the item at the cursor with the next one, see `tan.moveItemUp`.
+pub const MOVE_ITEM_DOWN_COMMAND: &str = "tan.moveItemDown";
+
+/// Finds the top-level definitions of the workspace that are never
+/// referenced. Returns the names and locations of the definitions.
+pub const UNUSED_PUBLIC_SYMBOLS_COMMAND: &str = "tan.unusedPublicSymbols";
+
+/// Replaces a name in the strings and the comments of the workspace, e.g.
+/// after a rename. The arguments are the old and the new name. Applies and
+/// returns the workspace edit, the edits need confirmation.
+pub const REPLACE_IN_STRINGS_AND_COMMENTS_COMMAND: &str = "tan.replaceInStringsAndComments";
+
+/// Deletes the top-level definition at the position, if it is never
+/// referenced, with the imports used only by the definition. The arguments
+/// are the document uri and the position. Applies and returns the workspace
+/// edit, or returns the references that prevent the deletion.
+pub const SAFE_DELETE_COMMAND: &str = "tan.safeDelete";
+
+/// Updates the version constraint of a dependency in the manifest, to require
+/// at least the version. The arguments are the manifest uri, the dependency
+/// name and the version. Applies and returns the workspace edit.
+pub const UPDATE_DEPENDENCY_COMMAND: &str = "tan.updateDependency";
+
+/// Counts the references to the definition at the position, per file and per
+/// module, by context (call, value or re-export). The arguments are the
+/// document uri and the position. Returns the statistics.
+pub const SYMBOL_STATS_COMMAND: &str = "tan.symbolStats";
+
+/// Formats the Tan files of the workspace, except the dependencies and the
+/// files ignored by the `[format]` table of the manifest. Applies and returns
+/// the workspace edit, and returns the files skipped for syntax errors.
+pub const FORMAT_WORKSPACE_COMMAND: &str = "tan.formatWorkspace";
+
+/// Evaluates the top-level form at the position, after the definitions and
+/// imports that precede it, in the sandbox. The arguments are the document
+/// uri and the position. Returns the value, the output and the error, also
+/// shown to the user.
+pub const EVALUATE_COMMAND: &str = "tan.evaluate";
+
+/// The commands executed by the server.
+pub const COMMANDS: [&str; 11] = [
+    SSR_COMMAND,
+    JOIN_LINES_COMMAND,
+    MOVE_ITEM_UP_COMMAND,
+    MOVE_ITEM_DOWN_COMMAND,
+    UNUSED_PUBLIC_SYMBOLS_COMMAND,
+    REPLACE_IN_STRINGS_AND_COMMENTS_COMMAND,
+    SAFE_DELETE_COMMAND,
+    UPDATE_DEPENDENCY_COMMAND,
+    SYMBOL_STATS_COMMAND,
+    FORMAT_WORKSPACE_COMMAND,
+    EVALUATE_COMMAND,
+];
+
+/// The annotation of the structural replace edits.
+const SSR_ANNOTATION: &str = "ssr";
+
+/// The annotation of the workspace formatting edits.
+const FORMAT_ANNOTATION: &str = "format";
+
+/// The output of a command, the result, and the edit applied before the result
+/// is sent.
+pub struct CommandOutput {
+    pub result: Option<serde_json::Value>,
+    /// The label of the edit, e.g. shown in the undo history, and the edit.
+    pub edit: Option<(String, WorkspaceEdit)>,
+}
+
+impl CommandOutput {
+    /// The output of a command without an edit.
+    pub fn result(result: Option<serde_json::Value>) -> Self {
+        Self { result, edit: None }
+    }
+
+    /// The output of a command with an edit.
+    fn edit(label: &str, edit: WorkspaceEdit, result: serde_json::Value) -> Self {
+        Self {
+            result: Some(result),
+            edit: Some((label.to_owned(), edit)),
+        }
+    }
+}
+
+pub fn execute_command(
+    snapshot: &Snapshot,
+    params: ExecuteCommandParams,
+) -> anyhow::Result<CommandOutput> {
+    match params.command.as_str() {
+        SSR_COMMAND => ssr(snapshot, &params.arguments),
+        JOIN_LINES_COMMAND => join_lines_command(snapshot, &params.arguments),
+        MOVE_ITEM_UP_COMMAND => move_item_command(snapshot, &params.arguments, Direction::Up),
+        MOVE_ITEM_DOWN_COMMAND => move_item_command(snapshot, &params.arguments, Direction::Down),
+        UNUSED_PUBLIC_SYMBOLS_COMMAND => unused_public_symbols(snapshot).map(CommandOutput::result),
+        REPLACE_IN_STRINGS_AND_COMMENTS_COMMAND => {
+            replace_in_strings_and_comments(snapshot, &params.arguments)
+        }
+        SAFE_DELETE_COMMAND => safe_delete_command(snapshot, &params.arguments),
+        UPDATE_DEPENDENCY_COMMAND => update_dependency(snapshot, &params.arguments),
+        SYMBOL_STATS_COMMAND => {
+            symbol_stats(snapshot, &params.arguments).map(CommandOutput::result)
+        }
+        FORMAT_WORKSPACE_COMMAND => format_workspace(snapshot),
+        EVALUATE_COMMAND => {
+            let result = evaluate_form(snapshot, &params.arguments)?;
+            Ok(CommandOutput::result(Some(serde_json::to_value(result)?)))
+        }
+        command => bail!("unknown command `{command}`"),
+    }
+}
+
+fn ssr(snapshot: &Snapshot, arguments: &[serde_json::Value]) -> anyhow::Result<CommandOutput> {
+    let Some(rule) = arguments.first().and_then(|rule| rule.as_str()) else {
+        bail!("expected a rule argument");
+    };
+
+    let rule = Rule::parse(rule)?;
+
+    let mut builder = EditBuilder::new();
+    builder.annotation(SSR_ANNOTATION, "Structural replace", None, false);
+
+    for (uri, input) in snapshot.sources() {
+        snapshot.cancellation.check()?;
+
+        // Dependency sources are never edited.
+        if uri
+            .to_file_path()
+            .is_ok_and(|path| snapshot.config.is_dependency(&path))
+        {
+            continue;
+        }
+
+        let Ok(exprs) = parse_string_all(input) else {
+            continue;
+        };
+
+        let matches = rule.find_matches(&exprs, input);
+        if matches.is_empty() {
+            continue;
+        }
+
+        let line_index = LineIndex::new(input);
+        for m in matches {
+            let range = Range::new(line_index.position(m.start), line_index.position(m.end));
+            builder.insert(
+                uri.clone(),
+                TextEdit::new(range, m.replacement),
+                Some(SSR_ANNOTATION),
+            );
+        }
+    }
+
+    if builder.is_empty() {
+        return Ok(CommandOutput::result(None));
+    }
+
+    let edit = builder.build(snapshot)?;
+    let result = serde_json::to_value(&edit)?;
+    Ok(CommandOutput::edit("Structural replace", edit, result))
+}
+
+/// Evaluates the top-level form of the `tan.evaluate` command, the server
+/// also shows the result.
+pub fn evaluate_form(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+) -> anyhow::Result<EvaluateResult> {
+    if snapshot.config.read_only {
+        bail!("Tan code is never evaluated in read-only mode");
+    }
+
+    let [uri, position] = arguments else {
+        bail!("expected the uri and position arguments");
+    };
+
+    let uri: Url = serde_json::from_value(uri.clone())?;
+    let position: Position = serde_json::from_value(position.clone())?;
+
+    let input = snapshot.vfs.read(&uri)?;
+    let Ok(exprs) = parse_string_all(&input) else {
+        bail!("the document has syntax errors");
+    };
+
+    let index = index_from_lsp_position(&position, &input);
+    let Some((source, _)) = evaluation_source(&input, &exprs, index) else {
+        bail!("no evaluable form at the position");
+    };
+
+    // The `use` forms read the module directories.
+    if snapshot.config.sandbox.filesystem == FilesystemAccess::None
+        && parse_string_all(&source).is_ok_and(|exprs| contains_use(&exprs))
+    {
+        bail!("the sandbox denies the filesystem access, `use` is not allowed");
+    }
+
+    evaluate(
+        &source,
+        snapshot.config.root_path.as_deref(),
+        &snapshot.config.sandbox,
+        &snapshot.cancellation,
+    )
+}
+
+fn format_workspace(snapshot: &Snapshot) -> anyhow::Result<CommandOutput> {
+    let mut builder = EditBuilder::new();
+    builder.annotation(FORMAT_ANNOTATION, "Format workspace", None, false);
+
+    let mut skipped = Vec::new();
+
+    for path in workspace_format_files(&snapshot.config) {
+        snapshot.cancellation.check()?;
+
+        let Ok(uri) = Url::from_file_path(&path) else {
+            continue;
+        };
+
+        // The open documents are formatted with their unsaved changes.
+        let input = snapshot.vfs.read(&uri)?;
+
+        let Some(formatted) = format_source(&input, &snapshot.config) else {
+            skipped.push(uri);
+            continue;
+        };
+
+        if formatted == *input {
+            continue;
+        }
+
+        // The exact end of the document, the files that are not open are
+        // edited by the client without a document to clamp the range to.
+        let end = LineIndex::new(&input).position(input.chars().count());
+        let range = Range::new(Position::new(0, 0), end);
+        builder.insert(
+            uri,
+            TextEdit::new(range, formatted),
+            Some(FORMAT_ANNOTATION),
+        );
+    }
+
+    let is_empty = builder.is_empty();
+    let edit = builder.build(snapshot)?;
+    let result = FormatWorkspaceResult {
+        edit: edit.clone(),
+        skipped,
+    };
+    let result = serde_json::to_value(result)?;
+
+    // The formatted workspace has nothing to apply.
+    if is_empty {
+        return Ok(CommandOutput::result(Some(result)));
+    }
+
+    Ok(CommandOutput::edit("Format workspace", edit, result))
+}
+
+fn join_lines_command(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+) -> anyhow::Result<CommandOutput> {
+    let [uri, range] = arguments else {
+        bail!("expected the uri and range arguments");
+    };
+
+    let uri: Url = serde_json::from_value(uri.clone())?;
+    let range: Range = serde_json::from_value(range.clone())?;
+
+    let input = snapshot.vfs.read(&uri)?;
+    let edits = join_lines(&input, range.start.line as usize, range.end.line as usize);
+    if edits.is_empty() {
+        return Ok(CommandOutput::result(Some(serde_json::to_value(edits)?)));
+    }
+
+    let result = serde_json::to_value(&edits)?;
+    Ok(CommandOutput::edit(
+        "Join lines",
+        document_edit(uri, edits),
+        result,
+    ))
+}
+
+/// Returns the workspace edit of text edits of a document.
+fn document_edit(uri: Url, edits: Vec<TextEdit>) -> WorkspaceEdit {
+    WorkspaceEdit {
+        changes: Some(HashMap::from([(uri, edits)])),
+        ..Default::default()
+    }
+}
+
+fn move_item_command(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+    direction: Direction,
+) -> anyhow::Result<CommandOutput> {
+    let [uri, position] = arguments else {
+        bail!("expected the uri and position arguments");
+    };
+
+    let uri: Url = serde_json::from_value(uri.clone())?;
+    let position: Position = serde_json::from_value(position.clone())?;
+
+    let input = snapshot.vfs.read(&uri)?;
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(CommandOutput::result(None));
+    };
+
+    let index = index_from_lsp_position(&position, &input);
+    let Some(item_move) = move_item(&exprs, &input, index, direction) else {
+        return Ok(CommandOutput::result(None));
+    };
+
+    let line_index = LineIndex::new(&input);
+    let range = Range::new(
+        line_index.position(item_move.start),
+        line_index.position(item_move.end),
+    );
+
+    // The cursor position is computed on the edited text.
+    let edited: String = input
+        .chars()
+        .take(item_move.start)
+        .chain(item_move.text.chars())
+        .chain(input.chars().skip(item_move.end))
+        .collect();
+
+    let result = MoveItemResult {
+        edits: vec![TextEdit::new(range, item_move.text)],
+        cursor: LineIndex::new(&edited).position(item_move.cursor),
+    };
+
+    let edit = document_edit(uri, result.edits.clone());
+    Ok(CommandOutput::edit(
+        "Move item",
+        edit,
+        serde_json::to_value(result)?,
+    ))
+}
+
+fn replace_in_strings_and_comments(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+) -> anyhow::Result<CommandOutput> {
+    let [name, new_name] = arguments else {
+        bail!("expected the name and new name arguments");
+    };
+
+    let (Some(name), Some(new_name)) = (name.as_str(), new_name.as_str()) else {
+        bail!("expected the name and new name arguments");
+    };
+
+    if name.is_empty() {
+        bail!("empty name");
+    }
+
+    let mut builder = EditBuilder::new();
+    text_annotation(&mut builder, name);
+    insert_text_edits(&mut builder, snapshot, name, new_name)?;
+    if builder.is_empty() {
+        return Ok(CommandOutput::result(None));
+    }
+
+    let edit = builder.build(snapshot)?;
+    let result = serde_json::to_value(&edit)?;
+    Ok(CommandOutput::edit(
+        "Replace in strings and comments",
+        edit,
+        result,
+    ))
+}
+
+fn safe_delete_command(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+) -> anyhow::Result<CommandOutput> {
+    let [uri, position] = arguments else {
+        bail!("expected the uri and position arguments");
+    };
+
+    let uri: Url = serde_json::from_value(uri.clone())?;
+    let position: Position = serde_json::from_value(position.clone())?;
+
+    // Dependency sources are never edited.
+    if uri
+        .to_file_path()
+        .is_ok_and(|path| snapshot.config.is_dependency(&path))
+    {
+        bail!("cannot delete a definition of a dependency");
+    }
+
+    let input = snapshot.vfs.read(&uri)?;
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(CommandOutput::result(None));
+    };
+
+    let index = index_from_lsp_position(&position, &input);
+    match safe_delete(snapshot, &uri, &exprs, &input, index)? {
+        None => Ok(CommandOutput::result(None)),
+        Some(SafeDelete::Blocked(blockers)) => {
+            let result = SafeDeleteResult {
+                edit: None,
+                blockers,
+            };
+            Ok(CommandOutput::result(Some(serde_json::to_value(result)?)))
+        }
+        Some(SafeDelete::Edits(edits)) => {
+            let mut builder = EditBuilder::new();
+            for edit in edits {
+                builder.insert(uri.clone(), edit, None);
+            }
+            let edit = builder.build(snapshot)?;
+            let result = SafeDeleteResult {
+                edit: Some(edit.clone()),
+                blockers: Vec::new(),
+            };
+            Ok(CommandOutput::edit(
+                "Safe delete",
+                edit,
+                serde_json::to_value(result)?,
+            ))
+        }
+    }
+}
+
+fn update_dependency(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+) -> anyhow::Result<CommandOutput> {
+    let [uri, name, version] = arguments else {
+        bail!("expected the uri, name and version arguments");
+    };
+
+    let uri: Url = serde_json::from_value(uri.clone())?;
+    let (Some(name), Some(version)) = (name.as_str(), version.as_str()) else {
+        bail!("expected the uri, name and version arguments");
+    };
+
+    if !uri
+        .to_file_path()
+        .is_ok_and(|path| snapshot.config.is_manifest(&path))
+    {
+        bail!("`{uri}` is not the manifest of the workspace");
+    }
+
+    let input = snapshot.vfs.read(&uri)?;
+    let Some(entry) = dependency_entries(&input)
+        .into_iter()
+        .find(|entry| entry.name == name)
+    else {
+        bail!("unknown dependency `{name}`");
+    };
+
+    let line_index = LineIndex::new(&input);
+    let range = Range::new(
+        line_index.position(entry.value_range.start),
+        line_index.position(entry.value_range.end),
+    );
+    let new_text = format!("\"{}\"", update_constraint(&entry.constraint, version));
+
+    let mut builder = EditBuilder::new();
+    builder.insert(uri, TextEdit::new(range, new_text), None);
+
+    let edit = builder.build(snapshot)?;
+    let result = serde_json::to_value(&edit)?;
+    Ok(CommandOutput::edit("Update dependency", edit, result))
+}
+
+fn symbol_stats(
+    snapshot: &Snapshot,
+    arguments: &[serde_json::Value],
+) -> anyhow::Result<Option<serde_json::Value>> {
+    let [uri, position] = arguments else {
+        bail!("expected the uri and position arguments");
+    };
+
+    let uri: Url = serde_json::from_value(uri.clone())?;
+    let position: Position = serde_json::from_value(position.clone())?;
+
+    let input = snapshot.vfs.read(&uri)?;
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(&position, &input);
+    let Some(Ann(Expr::Symbol(name), _)) = find_path_at(&exprs, index).last() else {
+        return Ok(None);
+    };
+
+    if resolve_local_symbol_at(&exprs, index).is_some() {
+        bail!("`{name}` is a local binding, not a definition");
+    }
+
+    let count = |counts: &mut UsageCounts, context: UsageContext| {
+        counts.total += 1;
+        match context {
+            UsageContext::Call => counts.calls += 1,
+            UsageContext::Value => counts.values += 1,
+            UsageContext::ReExport => counts.re_exports += 1,
+        }
+    };
+
+    let mut counts = UsageCounts::default();
+    let mut file_counts: HashMap<Url, UsageCounts> = HashMap::new();
+    let mut module_counts: HashMap<String, UsageCounts> = HashMap::new();
+
+    for (location, context) in find_usages(snapshot, name, false)? {
+        count(&mut counts, context);
+        count(
+            module_counts
+                .entry(module_name(snapshot, &location.uri))
+                .or_default(),
+            context,
+        );
+        count(file_counts.entry(location.uri).or_default(), context);
+    }
+
+    let mut files: Vec<FileUsage> = file_counts
+        .into_iter()
+        .map(|(uri, counts)| FileUsage {
+            module: module_name(snapshot, &uri),
+            uri,
+            counts,
+        })
+        .collect();
+    files.sort_by(|a, b| (b.counts.total, a.uri.as_str()).cmp(&(a.counts.total, b.uri.as_str())));
+
+    let mut modules: Vec<ModuleUsage> = module_counts
+        .into_iter()
+        .map(|(module, counts)| ModuleUsage { module, counts })
+        .collect();
+    modules.sort_by(|a, b| (b.counts.total, &a.module).cmp(&(a.counts.total, &b.module)));
+
+    let stats = SymbolStats {
+        name: name.clone(),
+        counts,
+        files,
+        modules,
+    };
+
+    Ok(Some(serde_json::to_value(stats)?))
+}
+
+/// Returns the module of a file, i.e. its directory relative to the
+/// dependency directory or the workspace root, `.` for the root.
+fn module_name(snapshot: &Snapshot, uri: &Url) -> String {
+    let Ok(path) = uri.to_file_path() else {
+        return uri.to_string();
+    };
+    let Some(dir) = path.parent() else {
+        return ".".to_owned();
+    };
+
+    // The dependency directories may be in the workspace, e.g. `.tan/deps`.
+    let relative_dir = snapshot
+        .config
+        .dependency_paths()
+        .iter()
+        .chain(&snapshot.config.root_path)
+        .find_map(|base| dir.strip_prefix(base).ok())
+        .unwrap_or(dir);
+
+    let components: Vec<_> = relative_dir
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+
+    match components.is_empty() {
+        true => ".".to_owned(),
+        false => components.join("/"),
+    }
+}
+
+fn unused_public_symbols(snapshot: &Snapshot) -> anyhow::Result<Option<serde_json::Value>> {
+    let symbols: Vec<UnusedSymbol> = find_unused_public_definitions(snapshot)?
+        .into_iter()
+        .map(|(name, location)| UnusedSymbol { name, location })
+        .collect();
+
+    Ok(Some(serde_json::to_value(symbols)?))
+}
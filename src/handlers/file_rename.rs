@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::{RenameFilesParams, TextEdit, Url, WorkspaceEdit};
+use tan::{ann::Ann, expr::Expr};
+
+use crate::{
+    edit::EditBuilder, handlers::rename::is_read_only, imports::imported_name,
+    module::resolve_import, snapshot::Snapshot, util::lsp_range_from_range,
+};
+
+// #Insight
+// A module is named after its file, or its directory. Renaming a module file
+// renames the module, the imports of the module are updated before the file
+// is renamed, while the imports still resolve to the old path. E.g. the module
+// extraction names the new module after the first extracted definition, the
+// file can be renamed afterwards.
+
+// #TODO also update the imports of the moved modules, and of the modules
+// within a renamed directory.
+
+/// Returns the edits of the imports of the renamed modules.
+pub fn will_rename_files(
+    snapshot: &Snapshot,
+    params: RenameFilesParams,
+) -> anyhow::Result<Option<WorkspaceEdit>> {
+    // The old paths of the renamed modules, and the new module names.
+    let renames: Vec<(PathBuf, String)> = params
+        .files
+        .iter()
+        .filter_map(|rename| {
+            let old_path = Url::parse(&rename.old_uri).ok()?.to_file_path().ok()?;
+            let new_path = Url::parse(&rename.new_uri).ok()?.to_file_path().ok()?;
+            if old_path.parent() != new_path.parent() {
+                return None;
+            }
+            let new_name = module_name(&old_path, &new_path)?;
+            Some((old_path, new_name))
+        })
+        .collect();
+
+    if renames.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = EditBuilder::new();
+    let mut is_empty = true;
+
+    for (uri, input) in snapshot.sources() {
+        snapshot.cancellation.check()?;
+
+        if is_read_only(snapshot, uri) || !input.contains("use") {
+            continue;
+        }
+        let Some(base_dir) = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            continue;
+        };
+        let Some(exprs) = snapshot.caches.ast(uri, input) else {
+            continue;
+        };
+
+        for expr in exprs.iter() {
+            let Some(name) = imported_name(expr) else {
+                continue;
+            };
+            let Some((path, _)) = resolve_import(name, &base_dir, &snapshot.config) else {
+                continue;
+            };
+            let Some((_, new_name)) = renames.iter().find(|(old_path, _)| *old_path == path) else {
+                continue;
+            };
+            let Ann(Expr::List(terms), _) = expr else {
+                continue;
+            };
+
+            // The last segment of a nested name, e.g. `util/helpers`, is the
+            // name of the module.
+            let name_range = terms[1].get_range();
+            let segment = name.rsplit('/').next().unwrap_or(name);
+            let start = name_range.end - segment.chars().count();
+
+            builder.insert(
+                uri.clone(),
+                TextEdit::new(
+                    lsp_range_from_range(&(start..name_range.end), input),
+                    new_name.clone(),
+                ),
+                None,
+            );
+            is_empty = false;
+        }
+    }
+
+    if is_empty {
+        return Ok(None);
+    }
+
+    builder.build(snapshot).map(Some)
+}
+
+/// Returns the new name of a renamed module, `None` if the path is not a
+/// module, e.g. a file that is not a Tan file.
+fn module_name(old_path: &Path, new_path: &Path) -> Option<String> {
+    let is_tan_file = |path: &Path| path.extension().is_some_and(|ext| ext == "tan");
+
+    let name = if is_tan_file(old_path) {
+        if !is_tan_file(new_path) {
+            return None;
+        }
+        new_path.file_stem()?
+    } else if old_path.extension().is_none() {
+        new_path.file_name()?
+    } else {
+        return None;
+    };
+
+    Some(name.to_string_lossy().into_owned())
+}
@@ -0,0 +1,96 @@
+use std::cmp::Reverse;
+
+use lsp_types::{FoldingRange, FoldingRangeKind, FoldingRangeParams};
+
+use crate::{
+    indent::{is_closing_delimiter, is_opening_delimiter},
+    snapshot::Snapshot,
+};
+
+// #Insight
+// The folds are computed with a lexical scan, like the indentation, so that
+// incomplete documents can be folded while editing.
+
+pub fn folding_range(
+    snapshot: &Snapshot,
+    params: FoldingRangeParams,
+) -> anyhow::Result<Option<Vec<FoldingRange>>> {
+    let input = snapshot.vfs.read(&params.text_document.uri)?;
+
+    let mut ranges = folding_ranges(&input);
+
+    let range_limit = snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.folding_range.as_ref())
+        .and_then(|folding_range| folding_range.range_limit);
+    if let Some(range_limit) = range_limit {
+        ranges.truncate(range_limit as usize);
+    }
+
+    Ok(Some(ranges))
+}
+
+fn fold(start_line: usize, end_line: usize, kind: Option<FoldingRangeKind>) -> FoldingRange {
+    FoldingRange {
+        start_line: start_line as u32,
+        end_line: end_line as u32,
+        kind,
+        ..Default::default()
+    }
+}
+
+/// Returns the folds of the multi-line forms, and of the blocks of
+/// consecutive comment lines, by start line. Of the forms that start on the
+/// same line only the outermost one is folded.
+fn folding_ranges(input: &str) -> Vec<FoldingRange> {
+    let mut ranges = Vec::new();
+    let mut opening_lines: Vec<usize> = Vec::new();
+    let mut comment_block: Option<(usize, usize)> = None;
+    let mut in_string = false;
+
+    for (line_number, line) in input.lines().enumerate() {
+        let code = line.trim_start();
+        if !in_string && (code.starts_with(';') || code.starts_with("--")) {
+            let (start, _) = comment_block.unwrap_or((line_number, line_number));
+            comment_block = Some((start, line_number));
+            continue;
+        }
+
+        if let Some((start, end)) = comment_block.take().filter(|(start, end)| end > start) {
+            ranges.push(fold(start, end, Some(FoldingRangeKind::Comment)));
+        }
+
+        let mut chars = line.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if in_string {
+                in_string = ch != '"';
+                continue;
+            }
+
+            match ch {
+                '"' => in_string = true,
+                ';' => break,
+                '-' if chars.peek() == Some(&'-') => break,
+                _ if is_opening_delimiter(ch) => opening_lines.push(line_number),
+                _ if is_closing_delimiter(ch) => {
+                    if let Some(start) = opening_lines.pop().filter(|start| *start < line_number) {
+                        ranges.push(fold(start, line_number, None));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if let Some((start, end)) = comment_block.filter(|(start, end)| end > start) {
+        ranges.push(fold(start, end, Some(FoldingRangeKind::Comment)));
+    }
+
+    ranges.sort_by_key(|range| (range.start_line, Reverse(range.end_line)));
+    ranges.dedup_by_key(|range| range.start_line);
+
+    ranges
+}
@@ -0,0 +1,122 @@
+use lsp_types::{
+    DocumentFormattingParams, DocumentRangeFormattingParams, FormattingOptions, Position, Range,
+    TextEdit,
+};
+use tan::api::parse_string_all;
+
+use crate::{
+    format::{convert_indentation, format_exprs},
+    indent::{expand_to_balanced_lines, reindent_lines, top_level_form_ranges},
+    snapshot::Snapshot,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+pub fn formatting(
+    snapshot: &Snapshot,
+    params: DocumentFormattingParams,
+) -> anyhow::Result<Option<Vec<TextEdit>>> {
+    let input = snapshot.vfs.read(&params.text_document.uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Err(anyhow::anyhow!(
+            "cannot format a document with syntax errors"
+        ));
+    };
+
+    let formatted = format_exprs(&exprs, &snapshot.config.format);
+    let formatted = apply_formatting_options(&formatted, &input, &params.options);
+
+    if formatted == *input {
+        return Ok(Some(Vec::new()));
+    }
+
+    // Select the whole document dore replacement
+    let start = Position::new(0, 0);
+    let end = Position::new(u32::MAX, u32::MAX);
+    let document_range = Range::new(start, end);
+
+    Ok(Some(vec![TextEdit::new(document_range, formatted)]))
+}
+
+/// Applies the formatting options of the client to the formatted text: the
+/// indentation, and the final newline.
+fn apply_formatting_options(formatted: &str, input: &str, options: &FormattingOptions) -> String {
+    let mut formatted = apply_indentation_options(formatted, options);
+
+    // The formatter ends the text with a newline, it is removed only if the
+    // client asks to not insert one, and the input has none.
+    if options.insert_final_newline == Some(false) && !input.ends_with('\n') {
+        formatted.truncate(formatted.trim_end_matches('\n').len());
+    }
+
+    formatted
+}
+
+/// Applies the indentation options of the client to the formatted text.
+fn apply_indentation_options(formatted: &str, options: &FormattingOptions) -> String {
+    let tab_size = options.tab_size as usize;
+    if tab_size > 0 {
+        convert_indentation(formatted, tab_size, !options.insert_spaces)
+    } else {
+        formatted.to_owned()
+    }
+}
+
+/// Formats the top-level forms that intersect the range, the rest of the
+/// document is neither parsed nor formatted. If a form has syntax errors, e.g.
+/// a pasted snippet, the lines of the range are re-indented instead.
+pub fn range_formatting(
+    snapshot: &Snapshot,
+    params: DocumentRangeFormattingParams,
+) -> anyhow::Result<Option<Vec<TextEdit>>> {
+    let input = snapshot.vfs.read(&params.text_document.uri)?;
+
+    let start = index_from_lsp_position(&params.range.start, &input);
+    // An empty range formats the form at the cursor.
+    let end = index_from_lsp_position(&params.range.end, &input).max(start + 1);
+
+    let chars: Vec<char> = input.chars().collect();
+    let mut edits = Vec::new();
+
+    for form_range in top_level_form_ranges(&input)
+        .into_iter()
+        .filter(|form_range| form_range.start < end && start < form_range.end)
+    {
+        let text: String = chars[form_range.clone()].iter().collect();
+
+        let Ok(exprs) = parse_string_all(&text) else {
+            return Ok(Some(reindent_range(&input, &params.range)));
+        };
+
+        let formatted = format_exprs(&exprs, &snapshot.config.format);
+        let formatted = apply_indentation_options(&formatted, &params.options);
+        let formatted = formatted.trim_end_matches('\n');
+
+        if formatted != text {
+            edits.push(TextEdit::new(
+                lsp_range_from_range(&form_range, &input),
+                formatted.to_owned(),
+            ));
+        }
+    }
+
+    Ok(Some(edits))
+}
+
+/// Re-indents the lines of the range, relative to the surrounding code. The
+/// range is expanded to balanced lines, e.g. a pasted snippet that ends in
+/// the middle of a form is re-indented up to the end of the form.
+fn reindent_range(input: &str, range: &Range) -> Vec<TextEdit> {
+    let start_line = range.start.line as usize;
+    let mut end_line = range.end.line as usize;
+
+    // A range that ends at the start of a line, e.g. a pasted block with the
+    // trailing newline, does not include the line.
+    if range.end.character == 0 && end_line > start_line {
+        end_line -= 1;
+    }
+
+    let (start_line, end_line) = expand_to_balanced_lines(input, start_line, end_line);
+
+    reindent_lines(input, start_line, end_line)
+}
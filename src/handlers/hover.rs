@@ -0,0 +1,319 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::{Hover, HoverContents, HoverParams, MarkupKind, SymbolKind, Url};
+use tan::{
+    ann::Ann,
+    api::parse_string_all,
+    expr::{format_value, Expr},
+};
+
+use crate::{
+    ast::{comments_to_doc, find_path_at, head_symbol},
+    doc_tags::signature_params,
+    manifest::manifest_hover,
+    markup::{markup_content, preferred_markup_kind},
+    module::{attached_comments, resolve_import, summarize_module, ModuleSummary},
+    resolve::{lookup_symbol_at, Binding},
+    snapshot::Snapshot,
+    symbols::definition_kind,
+    util::{index_from_lsp_position, lsp_position_from_index, lsp_range_from_range},
+};
+
+// #Insight
+// The hover links to the definition, for clients without peek-definition.
+// The line fragment (e.g. `#L5`) is understood by most editors.
+
+pub fn hover(snapshot: &Snapshot, params: HoverParams) -> anyhow::Result<Option<Hover>> {
+    let uri = &params.text_document_position_params.text_document.uri;
+    let position = &params.text_document_position_params.position;
+
+    // The documents that are not files, e.g. untitled, have no directory.
+    let path = uri.to_file_path().unwrap_or_default();
+    let input = snapshot.vfs.read(uri)?;
+    let index = index_from_lsp_position(position, &input);
+
+    let content_format = snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.hover.as_ref())
+        .and_then(|hover| hover.content_format.as_deref());
+    let kind = preferred_markup_kind(content_format);
+
+    if uri
+        .to_file_path()
+        .is_ok_and(|path| snapshot.config.is_manifest(&path))
+    {
+        let hover =
+            manifest_hover(&input, index, &snapshot.config).map(|(markdown, range)| Hover {
+                contents: markup(&kind, &markdown),
+                range: Some(lsp_range_from_range(&range, &input)),
+            });
+        return Ok(hover);
+    }
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+
+    let expr_path = find_path_at(&exprs, index);
+
+    // Hover on a `(use module)` expression shows a summary of the module.
+    let Some(use_expr) = expr_path
+        .iter()
+        .rev()
+        .find(|expr| head_symbol(expr) == Some("use"))
+    else {
+        // Hover on a symbol shows the signature and the documentation of the
+        // binding.
+        let Some(symbol_expr @ Ann(Expr::Symbol(name), _)) = expr_path.last() else {
+            return Ok(None);
+        };
+
+        let Some(target) = lookup_symbol_at(snapshot, uri, &input, &exprs, index) else {
+            return Ok(None);
+        };
+
+        let description = if target.uri == *uri {
+            describe_binding(&exprs, &input, &target.binding)
+        } else {
+            match parse_string_all(&target.text) {
+                Ok(target_exprs) => describe_binding(&target_exprs, &target.text, &target.binding),
+                Err(_) => BindingDescription::default(),
+            }
+        };
+
+        let line = lsp_position_from_index(target.binding.name_range.start, &target.text).line;
+
+        let mut markdown = format!("**{}** `{name}`", description.label);
+        if let Some(documentation) = description.documentation() {
+            markdown.push_str(&format!("\n\n{documentation}"));
+        }
+        markdown.push_str(&format!(
+            "\n\n{}",
+            defined_in(snapshot, &target.uri, Some(line))
+        ));
+
+        return Ok(Some(Hover {
+            contents: markup(&kind, &markdown),
+            range: Some(lsp_range_from_range(&symbol_expr.get_range(), &input)),
+        }));
+    };
+
+    let Ann(Expr::List(terms), _) = use_expr else {
+        return Ok(None);
+    };
+
+    let Some(module_expr @ Ann(Expr::Symbol(module_name), _)) = terms.get(1) else {
+        return Ok(None);
+    };
+
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let Some((module_path, _)) = resolve_import(module_name, base_dir, &snapshot.config) else {
+        return Ok(Some(Hover {
+            contents: markup(&kind, &format!("module `{module_name}` not found")),
+            range: Some(lsp_range_from_range(&module_expr.get_range(), &input)),
+        }));
+    };
+
+    let summary = summarize_module(&module_path, &snapshot.vfs)?;
+
+    let mut markdown = format_module_summary(module_name, &summary);
+    if let Ok(module_uri) = Url::from_file_path(&module_path) {
+        markdown.push_str(&format!("\n\n{}", defined_in(snapshot, &module_uri, None)));
+    }
+
+    Ok(Some(Hover {
+        contents: markup(&kind, &markdown),
+        range: Some(lsp_range_from_range(&module_expr.get_range(), &input)),
+    }))
+}
+
+/// The hover description of a binding.
+pub struct BindingDescription {
+    pub label: &'static str,
+    /// The signature of a function, e.g. `(add a b)`.
+    pub signature: Option<String>,
+    pub annotations: Vec<String>,
+    /// The attached doc comments of a top-level binding.
+    pub doc: Option<String>,
+}
+
+impl BindingDescription {
+    /// Returns the markdown documentation: the signature, the annotations and
+    /// the doc comments.
+    pub fn documentation(&self) -> Option<String> {
+        let mut sections = Vec::new();
+
+        if let Some(signature) = &self.signature {
+            sections.push(format!("```tan\n{signature}\n```"));
+        }
+        if !self.annotations.is_empty() {
+            let annotations: Vec<String> = self
+                .annotations
+                .iter()
+                .map(|annotation| format!("`{annotation}`"))
+                .collect();
+            sections.push(annotations.join(" "));
+        }
+        if let Some(doc) = &self.doc {
+            sections.push(doc.clone());
+        }
+
+        (!sections.is_empty()).then(|| sections.join("\n\n"))
+    }
+}
+
+impl Default for BindingDescription {
+    fn default() -> Self {
+        Self {
+            label: "variable",
+            signature: None,
+            annotations: Vec::new(),
+            doc: None,
+        }
+    }
+}
+
+/// Describes a binding, from the parsed text of the binding file.
+pub fn describe_binding(exprs: &[Ann<Expr>], input: &str, binding: &Binding) -> BindingDescription {
+    let path = find_path_at(exprs, binding.name_range.start);
+
+    let Some(form) = path.iter().find(|expr| expr.get_range() == binding.range) else {
+        return BindingDescription::default();
+    };
+
+    if matches!(head_symbol(form), Some("Func") | Some("Macro")) {
+        return BindingDescription {
+            label: "parameter",
+            ..Default::default()
+        };
+    }
+
+    let Ann(Expr::List(terms), _) = form else {
+        return BindingDescription::default();
+    };
+
+    let Some(name_position) = terms
+        .iter()
+        .position(|term| term.get_range() == binding.name_range)
+    else {
+        return BindingDescription::default();
+    };
+
+    let (Some(name_expr @ Ann(Expr::Symbol(name), _)), Some(value)) =
+        (terms.get(name_position), terms.get(name_position + 1))
+    else {
+        return BindingDescription::default();
+    };
+
+    let label = match definition_kind(name, value) {
+        SymbolKind::FUNCTION if head_symbol(value) == Some("Macro") => "macro",
+        SymbolKind::FUNCTION => "function",
+        SymbolKind::CONSTANT => "constant",
+        _ => "variable",
+    };
+
+    let signature = signature_params(value).map(|params| {
+        let mut signature = format!("({name}");
+        for param in params {
+            signature.push_str(&format!(" {param}"));
+        }
+        signature.push(')');
+        signature
+    });
+
+    // Only the top-level bindings have doc comments.
+    let doc = exprs
+        .iter()
+        .position(|expr| std::ptr::eq(expr, *form))
+        .and_then(|position| comments_to_doc(&attached_comments(exprs, position, input)));
+
+    // The prefix annotations of a list are attached to its head.
+    let mut annotated = vec![*form, name_expr, value];
+    annotated.extend(terms.first());
+    if let Ann(Expr::List(value_terms), _) = value {
+        annotated.extend(value_terms.first());
+    }
+
+    BindingDescription {
+        label,
+        signature,
+        annotations: annotations(&annotated),
+        doc,
+    }
+}
+
+/// Returns the annotations of the expressions, e.g. `#deprecated`, sorted.
+fn annotations(exprs: &[&Ann<Expr>]) -> Vec<String> {
+    let mut annotations = Vec::new();
+
+    for expr in exprs {
+        let Some(map) = &expr.1 else {
+            continue;
+        };
+        for (key, value) in map {
+            let annotation = match (key.as_str(), value) {
+                ("range", _) => continue,
+                ("type", value) => format!("#{}", format_value(value)),
+                (key, Expr::Bool(true)) => format!("#{key}"),
+                (_, value) => format!("#{value}"),
+            };
+            annotations.push(annotation);
+        }
+    }
+
+    annotations.sort();
+    annotations.dedup();
+
+    annotations
+}
+
+/// Returns a markdown link to a file, with an optional (0-based) line. The
+/// label is relative to the workspace root.
+fn defined_in(snapshot: &Snapshot, uri: &Url, line: Option<u32>) -> String {
+    let path = uri
+        .to_file_path()
+        .unwrap_or_else(|()| PathBuf::from(uri.as_str()));
+    let relative_path = snapshot
+        .config
+        .root_path
+        .as_ref()
+        .and_then(|root_path| path.strip_prefix(root_path).ok())
+        .unwrap_or(&path);
+
+    match line {
+        Some(line) => format!(
+            "Defined in [{}:{}]({uri}#L{})",
+            relative_path.display(),
+            line + 1,
+            line + 1
+        ),
+        None => format!("Defined in [{}]({uri})", relative_path.display()),
+    }
+}
+
+fn markup(kind: &MarkupKind, markdown: &str) -> HoverContents {
+    HoverContents::Markup(markup_content(kind.clone(), markdown))
+}
+
+fn format_module_summary(name: &str, summary: &ModuleSummary) -> String {
+    let mut text = format!("**module** `{name}`\n\n`{}`", summary.path.display());
+
+    if let Some(doc) = &summary.doc {
+        text.push_str(&format!("\n\n{doc}"));
+    }
+
+    if summary.exports.is_empty() {
+        text.push_str("\n\n*No exported symbols.*");
+    } else {
+        text.push_str("\n\n**Exports:**\n");
+        for name in &summary.exports {
+            text.push_str(&format!("\n- `{name}`"));
+        }
+    }
+
+    text
+}
@@ -0,0 +1,222 @@
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, InlayHintParams, Url};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    doc_tags::signature_params,
+    resolve::{bound_value, lookup_symbol_at, resolve_symbol_at},
+    snapshot::Snapshot,
+    types::{annotated_type, infer_type},
+    unicode::same_name,
+    util::{index_from_lsp_position, LineIndex},
+};
+
+// #Insight
+// The arguments of a call are annotated with the parameter names of the
+// callee, resolved like hover, e.g. `(add a: 1 b: 2)`. The arguments named
+// like the parameter, e.g. `(add a b)`, are not annotated.
+
+// #Insight
+// The bound names are annotated with their inferred types, e.g.
+// `(let x: Int 1)`, unless the type is annotated in the source. The type of a
+// parameter is inferred from the calls in the document, if all the arguments
+// have the same type.
+
+pub fn inlay_hint(
+    snapshot: &Snapshot,
+    params: InlayHintParams,
+) -> anyhow::Result<Option<Vec<InlayHint>>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+
+    let start = index_from_lsp_position(&params.range.start, &input);
+    let end = index_from_lsp_position(&params.range.end, &input);
+
+    let mut hints = Vec::new();
+
+    if snapshot.config.parameter_hints {
+        hints.extend(parameter_hints(snapshot, uri, &input, &exprs, start..end)?);
+    }
+
+    if snapshot.config.type_hints {
+        hints.extend(type_hints(&input, &exprs, start..end));
+    }
+
+    Ok(Some(hints))
+}
+
+/// Returns the parameter name hints of the calls that intersect the (char)
+/// range.
+fn parameter_hints(
+    snapshot: &Snapshot,
+    uri: &Url,
+    input: &Arc<str>,
+    exprs: &[Ann<Expr>],
+    range: Range<usize>,
+) -> anyhow::Result<Vec<InlayHint>> {
+    let mut calls = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::List(terms), _) = expr else {
+            return;
+        };
+        let Some((head @ Ann(Expr::Symbol(name), _), args)) = terms.split_first() else {
+            return;
+        };
+        let call_range = expr.get_range();
+        let head_range = head.get_range();
+        // The synthesized heads, e.g. `Array` for `[1 2]`, are not callees in
+        // the text.
+        if head_range.len() == name.chars().count()
+            && call_range.start < range.end
+            && range.start < call_range.end
+        {
+            calls.push((head_range.start, args));
+        }
+    });
+
+    // The binding files are parsed once per request.
+    let mut parsed_files: HashMap<Url, Option<Vec<Ann<Expr>>>> = HashMap::new();
+    let line_index = LineIndex::new(input);
+    let mut hints = Vec::new();
+
+    for (head_start, args) in calls {
+        snapshot.cancellation.check()?;
+
+        let Some(target) = lookup_symbol_at(snapshot, uri, input, exprs, head_start) else {
+            continue;
+        };
+
+        let target_exprs = if target.uri == *uri {
+            Some(exprs)
+        } else {
+            parsed_files
+                .entry(target.uri.clone())
+                .or_insert_with(|| parse_string_all(&target.text).ok())
+                .as_deref()
+        };
+
+        let Some(params) = target_exprs
+            .and_then(|target_exprs| bound_value(target_exprs, &target.binding))
+            .and_then(signature_params)
+        else {
+            continue;
+        };
+
+        let args = args
+            .iter()
+            .filter(|arg| !matches!(arg, Ann(Expr::Comment(..), _)));
+
+        for (arg, param) in args.zip(params) {
+            let Ann(Expr::Symbol(param_name), _) = param else {
+                continue;
+            };
+            if matches!(arg, Ann(Expr::Symbol(name), _) if same_name(name, param_name)) {
+                continue;
+            }
+
+            hints.push(InlayHint {
+                position: line_index.position(arg.get_range().start),
+                label: InlayHintLabel::String(format!("{param_name}:")),
+                kind: Some(InlayHintKind::PARAMETER),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: Some(true),
+                data: None,
+            });
+        }
+    }
+
+    Ok(hints)
+}
+
+/// Returns the type hints of the bound names in the (char) range.
+fn type_hints(input: &str, exprs: &[Ann<Expr>], range: Range<usize>) -> Vec<InlayHint> {
+    let mut bindings = Vec::new();
+    let mut calls: HashMap<Range<usize>, Vec<&[Ann<Expr>]>> = HashMap::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::List(terms), _) = expr else {
+            return;
+        };
+        let Some((head @ Ann(Expr::Symbol(_), _), args)) = terms.split_first() else {
+            return;
+        };
+
+        if head_symbol(expr) == Some("let") {
+            for pair in args.chunks(2) {
+                if let [name @ Ann(Expr::Symbol(_), _), value] = pair {
+                    bindings.push((name, value));
+                }
+            }
+        } else if let Some(binding) = resolve_symbol_at(exprs, head.get_range().start) {
+            calls.entry(binding.name_range).or_default().push(args);
+        }
+    });
+
+    let line_index = LineIndex::new(input);
+    let mut hints = Vec::new();
+    let mut push_hint = |name: &Ann<Expr>, type_name: String| {
+        let name_range = name.get_range();
+        if name_range.start < range.end && range.start < name_range.end {
+            hints.push(InlayHint {
+                position: line_index.position(name_range.end),
+                label: InlayHintLabel::String(format!(": {type_name}")),
+                kind: Some(InlayHintKind::TYPE),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: Some(true),
+                data: None,
+            });
+        }
+    };
+
+    for (name, value) in bindings {
+        if annotated_type(name).is_some() {
+            continue;
+        }
+
+        let Some(params) = signature_params(value) else {
+            if let Some(type_name) = infer_type(exprs, value) {
+                push_hint(name, type_name);
+            }
+            continue;
+        };
+
+        // The functions are visible in the source, their parameters are
+        // annotated instead.
+        let Some(calls) = calls.get(&name.get_range()) else {
+            continue;
+        };
+
+        for (i, param) in params.into_iter().enumerate() {
+            if annotated_type(param).is_some() {
+                continue;
+            }
+
+            let mut arg_types = calls.iter().map(|args| {
+                args.iter()
+                    .filter(|arg| !matches!(arg, Ann(Expr::Comment(..), _)))
+                    .nth(i)
+                    .and_then(|arg| infer_type(exprs, arg))
+            });
+
+            let Some(Some(type_name)) = arg_types.next() else {
+                continue;
+            };
+            if arg_types.all(|arg_type| arg_type.as_ref() == Some(&type_name)) {
+                push_hint(param, type_name);
+            }
+        }
+    }
+
+    hints
+}
@@ -0,0 +1,47 @@
+use lsp_types::{DocumentOnTypeFormattingParams, TextEdit};
+
+use crate::{
+    indent::{find_opening_line, on_enter_edits, reindent_lines},
+    snapshot::Snapshot,
+    util::index_from_lsp_position,
+};
+
+/// The characters that trigger on-type formatting.
+pub const TRIGGER_CHARACTERS: [&str; 4] = [")", "]", "}", "\n"];
+
+/// Re-indents the form that was just closed by the typed delimiter. On a line
+/// break, continues the comment of the previous line.
+pub fn on_type_formatting(
+    snapshot: &Snapshot,
+    params: DocumentOnTypeFormattingParams,
+) -> anyhow::Result<Option<Vec<TextEdit>>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = &params.text_document_position.position;
+
+    let input = snapshot.vfs.read(uri)?;
+
+    if params.ch == "\n" {
+        return Ok(Some(on_enter_edits(&input, position.line as usize)));
+    }
+
+    // The position is right after the typed character.
+    let index = index_from_lsp_position(position, &input);
+    if index == 0 {
+        return Ok(None);
+    }
+
+    let Some(opening_line) = find_opening_line(&input, index - 1) else {
+        return Ok(None);
+    };
+
+    let closing_line = position.line as usize;
+
+    if opening_line == closing_line {
+        // Single-line forms don't need re-indentation.
+        return Ok(None);
+    }
+
+    let edits = reindent_lines(&input, opening_line + 1, closing_line);
+
+    Ok(Some(edits))
+}
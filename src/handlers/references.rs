@@ -0,0 +1,85 @@
+use lsp_types::{Location, ReferenceParams, Url};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::{find_path_at, for_each_expr},
+    references::find_references,
+    resolve::{resolve_local_symbol_at, Binding},
+    snapshot::Snapshot,
+    unicode::same_name,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+pub fn references(
+    snapshot: &Snapshot,
+    params: ReferenceParams,
+) -> anyhow::Result<Option<Vec<Location>>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = &params.text_document_position.position;
+    let include_declaration = params.context.include_declaration;
+
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(position, &input);
+
+    let Some(Ann(Expr::Symbol(name), _)) = find_path_at(&exprs, index).last() else {
+        return Ok(None);
+    };
+
+    // A local binding is only referenced in the binding form.
+    if let Some(binding) = resolve_local_symbol_at(&exprs, index) {
+        return Ok(Some(local_references(
+            uri,
+            &exprs,
+            &input,
+            name,
+            &binding,
+            include_declaration,
+        )));
+    }
+
+    Ok(Some(find_references(snapshot, name, include_declaration)?))
+}
+
+/// Finds the references to a local binding, the symbols after the binding form
+/// that resolve to the binding. The `let` of a `do` block is visible until the
+/// end of the block, after its own form.
+pub fn local_references(
+    uri: &Url,
+    exprs: &[Ann<Expr>],
+    input: &str,
+    name: &str,
+    binding: &Binding,
+    include_declaration: bool,
+) -> Vec<Location> {
+    let mut locations = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+
+        let range = expr.get_range();
+
+        if !same_name(sym, name) || range.start < binding.range.start {
+            return;
+        }
+
+        if !include_declaration && range == binding.name_range {
+            return;
+        }
+
+        if resolve_local_symbol_at(exprs, range.start).as_ref() == Some(binding) {
+            locations.push(Location::new(
+                uri.clone(),
+                lsp_range_from_range(&range, input),
+            ));
+        }
+    });
+
+    locations
+}
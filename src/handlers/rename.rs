@@ -0,0 +1,547 @@
+use std::sync::Arc;
+
+use lsp_types::{
+    PrepareRenameResponse, RenameParams, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
+};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+
+use crate::{
+    ast::{find_path_at, for_each_expr},
+    doc_tags::signature_params,
+    edit::EditBuilder,
+    handlers::{
+        completion::{is_builtin, is_special_form},
+        references::local_references,
+    },
+    references::find_references,
+    resolve::{bound_value, lookup_symbol_at, resolve_local_symbol_at, Binding, Target},
+    snapshot::Snapshot,
+    unicode::{is_name_char, may_contain_name, normalize_name, same_name},
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+// #Insight
+// Keywords are used as dict keys and as named arguments, e.g.
+// `(greet :name "Ada")`. A keyword rename updates the keyword literals of the
+// workspace, and the parameters named like the keyword in the functions
+// called with it, e.g. `(let greet (Func (name) ...))`, with their references
+// in the function bodies.
+
+/// The annotation of the edits of the symbol references.
+const REFERENCES_ANNOTATION: &str = "references";
+
+/// The annotation of the edits of the parameters that accept a keyword
+/// argument.
+const PARAMETERS_ANNOTATION: &str = "parameters";
+
+/// The annotation of the (low-confidence) edits in strings and comments.
+pub const TEXT_ANNOTATION: &str = "text";
+
+/// Returns the symbol at the (char) index, if it can be renamed. The special
+/// forms and the builtins cannot be renamed, unless a binding of the workspace
+/// shadows them.
+fn renamable_symbol_at<'a>(
+    snapshot: &Snapshot,
+    uri: &Url,
+    input: &Arc<str>,
+    exprs: &'a [Ann<Expr>],
+    index: usize,
+) -> anyhow::Result<Option<&'a Ann<Expr>>> {
+    let Some(symbol_expr @ Ann(Expr::Symbol(name), _)) = find_path_at(exprs, index).last().copied()
+    else {
+        return Ok(None);
+    };
+
+    // The local bindings, e.g. the parameters, may shadow the builtins.
+    if resolve_local_symbol_at(exprs, index).is_some() {
+        return Ok(Some(symbol_expr));
+    }
+
+    if lookup_symbol_at(snapshot, uri, input, exprs, index).is_none() {
+        if is_special_form(name) {
+            anyhow::bail!("cannot rename the special form `{name}`");
+        }
+        if is_builtin(name) {
+            anyhow::bail!("cannot rename the builtin `{name}`");
+        }
+    }
+
+    Ok(Some(symbol_expr))
+}
+
+/// Returns the keyword at the (char) index, e.g. `:name`.
+fn keyword_at(exprs: &[Ann<Expr>], index: usize) -> Option<&Ann<Expr>> {
+    find_path_at(exprs, index)
+        .last()
+        .copied()
+        .filter(|expr| matches!(expr, Ann(Expr::KeySymbol(_), _)))
+}
+
+pub fn prepare_rename(
+    snapshot: &Snapshot,
+    params: TextDocumentPositionParams,
+) -> anyhow::Result<Option<PrepareRenameResponse>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(&params.position, &input);
+
+    // The name of a keyword is renamed, without the colon.
+    if let Some(keyword_expr) = keyword_at(&exprs, index) {
+        let range = keyword_expr.get_range();
+        return Ok(Some(PrepareRenameResponse::Range(lsp_range_from_range(
+            &((range.start + 1)..range.end),
+            &input,
+        ))));
+    }
+
+    let Some(symbol_expr) = renamable_symbol_at(snapshot, uri, &input, &exprs, index)? else {
+        return Ok(None);
+    };
+
+    Ok(Some(PrepareRenameResponse::Range(lsp_range_from_range(
+        &symbol_expr.get_range(),
+        &input,
+    ))))
+}
+
+pub fn rename(snapshot: &Snapshot, params: RenameParams) -> anyhow::Result<Option<WorkspaceEdit>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = &params.text_document_position.position;
+    let new_name = normalize_name(&params.new_name).into_owned();
+
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(position, &input);
+
+    if let Some(Ann(Expr::KeySymbol(name), _)) = keyword_at(&exprs, index) {
+        let new_name = new_name.strip_prefix(':').unwrap_or(&new_name);
+        if !is_valid_name(new_name) {
+            return Err(anyhow::anyhow!("invalid name `{new_name}`"));
+        }
+        return Ok(Some(rename_keyword(snapshot, name, new_name)?));
+    }
+
+    let Some(Ann(Expr::Symbol(name), _)) =
+        renamable_symbol_at(snapshot, uri, &input, &exprs, index)?
+    else {
+        return Ok(None);
+    };
+
+    if !is_valid_name(&new_name) {
+        return Err(anyhow::anyhow!("invalid name `{new_name}`"));
+    }
+
+    let mut builder = EditBuilder::new();
+    builder.annotation(
+        REFERENCES_ANNOTATION,
+        format!("Rename `{name}` to `{new_name}`"),
+        None,
+        false,
+    );
+
+    // A local binding is only referenced in the binding form, the strings and
+    // the comments of the workspace are not renamed.
+    if let Some(binding) = resolve_local_symbol_at(&exprs, index) {
+        if is_read_only(snapshot, uri) {
+            return Ok(None);
+        }
+        for location in local_references(uri, &exprs, &input, name, &binding, true) {
+            builder.insert(
+                location.uri,
+                TextEdit::new(location.range, new_name.clone()),
+                Some(REFERENCES_ANNOTATION),
+            );
+        }
+        return Ok(Some(builder.build(snapshot)?));
+    }
+
+    text_annotation(&mut builder, name);
+
+    for location in find_references(snapshot, name, true)? {
+        if is_read_only(snapshot, &location.uri) {
+            continue;
+        }
+
+        builder.insert(
+            location.uri,
+            TextEdit::new(location.range, new_name.clone()),
+            Some(REFERENCES_ANNOTATION),
+        );
+    }
+
+    insert_text_edits(&mut builder, snapshot, name, &new_name)?;
+
+    Ok(Some(builder.build(snapshot)?))
+}
+
+/// Returns true if the new name of a rename is a valid name.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || "()[]{}\";".contains(c))
+}
+
+/// Renames a keyword, e.g. `:name`, in the workspace, and the parameters that
+/// accept it as a named argument.
+fn rename_keyword(
+    snapshot: &Snapshot,
+    name: &str,
+    new_name: &str,
+) -> anyhow::Result<WorkspaceEdit> {
+    snapshot.check_indexed()?;
+
+    let mut builder = EditBuilder::new();
+    builder.annotation(
+        REFERENCES_ANNOTATION,
+        format!("Rename `:{name}` to `:{new_name}`"),
+        None,
+        false,
+    );
+    builder.annotation(
+        PARAMETERS_ANNOTATION,
+        format!("Rename the `{name}` parameters"),
+        Some("The parameters of the functions called with the keyword.".to_owned()),
+        false,
+    );
+    text_annotation(&mut builder, name);
+
+    // The functions called with the keyword as an argument.
+    let mut callees: Vec<Target> = Vec::new();
+
+    for (uri, input) in snapshot.sources() {
+        snapshot.cancellation.check()?;
+
+        if !may_contain_name(input, name) {
+            continue;
+        }
+
+        let Ok(exprs) = parse_string_all(input) else {
+            continue;
+        };
+
+        let is_keyword =
+            |expr: &Ann<Expr>| matches!(expr, Ann(Expr::KeySymbol(sym), _) if same_name(sym, name));
+        let read_only = is_read_only(snapshot, uri);
+        let mut call_heads = Vec::new();
+
+        for_each_expr(&exprs, &mut |expr| {
+            if is_keyword(expr) {
+                if !read_only {
+                    builder.insert(
+                        uri.clone(),
+                        TextEdit::new(
+                            lsp_range_from_range(&expr.get_range(), input),
+                            format!(":{new_name}"),
+                        ),
+                        Some(REFERENCES_ANNOTATION),
+                    );
+                }
+            } else if let Ann(Expr::List(terms), _) = expr {
+                if let Some((head @ Ann(Expr::Symbol(_), _), args)) = terms.split_first() {
+                    if args.iter().any(is_keyword) {
+                        call_heads.push(head.get_range().start);
+                    }
+                }
+            }
+        });
+
+        for head_start in call_heads {
+            let Some(target) = lookup_symbol_at(snapshot, uri, input, &exprs, head_start) else {
+                continue;
+            };
+            if !callees
+                .iter()
+                .any(|callee| callee.uri == target.uri && callee.binding == target.binding)
+            {
+                callees.push(target);
+            }
+        }
+    }
+
+    for callee in callees {
+        if is_read_only(snapshot, &callee.uri) {
+            continue;
+        }
+
+        let Ok(exprs) = parse_string_all(&callee.text) else {
+            continue;
+        };
+
+        for range in parameter_references(&exprs, &callee.binding, name) {
+            builder.insert(
+                callee.uri.clone(),
+                TextEdit::new(
+                    lsp_range_from_range(&range, &callee.text),
+                    new_name.to_owned(),
+                ),
+                Some(PARAMETERS_ANNOTATION),
+            );
+        }
+    }
+
+    insert_text_edits(&mut builder, snapshot, name, new_name)?;
+
+    builder.build(snapshot)
+}
+
+/// Returns the ranges of the parameter with the name of a function binding,
+/// and of its references in the function body.
+fn parameter_references(exprs: &[Ann<Expr>], binding: &Binding, name: &str) -> Vec<Range> {
+    let Some(value) = bound_value(exprs, binding) else {
+        return Vec::new();
+    };
+
+    let Some(param) = signature_params(value).and_then(|params| {
+        params
+            .into_iter()
+            .find(|param| matches!(param, Ann(Expr::Symbol(sym), _) if same_name(sym, name)))
+    }) else {
+        return Vec::new();
+    };
+
+    let param_range = param.get_range();
+    let mut ranges = Vec::new();
+
+    for_each_expr(std::slice::from_ref(value), &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        if !same_name(sym, name) {
+            return;
+        }
+        if range == param_range
+            || resolve_local_symbol_at(exprs, range.start)
+                .is_some_and(|binding| binding.name_range == param_range)
+        {
+            ranges.push(range);
+        }
+    });
+
+    ranges
+}
+
+/// Adds the annotation of the edits in strings and comments, the edits need
+/// confirmation.
+pub fn text_annotation(builder: &mut EditBuilder, name: &str) {
+    builder.annotation(
+        TEXT_ANNOTATION,
+        format!("Rename `{name}` in strings and comments"),
+        Some("The matches in strings and comments may be unrelated.".to_owned()),
+        true,
+    );
+}
+
+/// Inserts the edits of the whole-word matches of the name, in the strings
+/// and the comments (including the doc examples) of the workspace. The
+/// dependencies are not edited.
+pub fn insert_text_edits(
+    builder: &mut EditBuilder,
+    snapshot: &Snapshot,
+    name: &str,
+    new_name: &str,
+) -> anyhow::Result<()> {
+    for (uri, input) in snapshot.sources() {
+        snapshot.cancellation.check()?;
+
+        if is_read_only(snapshot, uri) || !input.contains(name) {
+            continue;
+        }
+
+        for edit in text_edits(input, name, new_name) {
+            builder.insert(uri.clone(), edit, Some(TEXT_ANNOTATION));
+        }
+    }
+
+    Ok(())
+}
+
+/// Dependency sources are never edited.
+pub fn is_read_only(snapshot: &Snapshot, uri: &Url) -> bool {
+    uri.to_file_path()
+        .is_ok_and(|path| snapshot.config.is_dependency(&path))
+}
+
+/// Returns the edits of the whole-word matches of the name, in the strings
+/// and the comments of the input.
+fn text_edits(input: &str, name: &str, new_name: &str) -> Vec<TextEdit> {
+    let Ok(exprs) = parse_string_all(input) else {
+        return Vec::new();
+    };
+
+    let mut edits = Vec::new();
+
+    for_each_expr(&exprs, &mut |expr| {
+        let range = expr.get_range();
+
+        // #Insight
+        // The string range includes the quotes. Strings with escapes are
+        // skipped, the text does not match the source.
+        let (text, start) = match &expr.0 {
+            Expr::Comment(text) => (text.as_str(), range.start),
+            Expr::String(text) if !text.contains(['\\', '\n']) => (text.as_str(), range.start + 1),
+            _ => return,
+        };
+
+        for (byte_offset, _) in text.match_indices(name) {
+            let before = text[..byte_offset].chars().next_back();
+            let after = text[byte_offset + name.len()..].chars().next();
+            if before.is_some_and(is_name_char) || after.is_some_and(is_name_char) {
+                continue;
+            }
+
+            let offset = start + text[..byte_offset].chars().count();
+            let match_range = offset..(offset + name.chars().count());
+            edits.push(TextEdit::new(
+                lsp_range_from_range(&match_range, input),
+                new_name.to_owned(),
+            ));
+        }
+    });
+
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, TextDocumentIdentifier, WorkDoneProgressParams};
+
+    use super::*;
+    use crate::fixture::{document_uri, snapshot_of};
+
+    const MAIN: &str = "(let x 1)\n(let f (Func (x) (+ x 1)))\n; x\n(let g (Func () x))\n";
+
+    fn position_params(name: &str, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: document_uri(name),
+            },
+            position: Position::new(line, character),
+        }
+    }
+
+    fn rename_edits(snapshot: &Snapshot, line: u32, character: u32) -> Vec<(Url, TextEdit)> {
+        rename_edits_to(snapshot, line, character, "y")
+    }
+
+    fn rename_edits_to(
+        snapshot: &Snapshot,
+        line: u32,
+        character: u32,
+        new_name: &str,
+    ) -> Vec<(Url, TextEdit)> {
+        let params = RenameParams {
+            text_document_position: position_params("main.tan", line, character),
+            new_name: new_name.to_owned(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let edit = rename(snapshot, params).unwrap().unwrap();
+
+        let mut edits: Vec<(Url, TextEdit)> = edit
+            .changes
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(uri, edits)| edits.into_iter().map(move |edit| (uri.clone(), edit)))
+            .collect();
+        edits.sort_by_key(|(uri, edit)| (uri.to_string(), edit.range.start));
+        edits
+    }
+
+    fn ranges(edits: &[(Url, TextEdit)]) -> Vec<(u32, u32)> {
+        edits
+            .iter()
+            .map(|(_, edit)| (edit.range.start.line, edit.range.start.character))
+            .collect()
+    }
+
+    #[test]
+    fn prepare_rename_accepts_a_parameter() {
+        let snapshot = snapshot_of(&[("main.tan", MAIN)]);
+        let response = prepare_rename(&snapshot, position_params("main.tan", 1, 20)).unwrap();
+
+        let Some(PrepareRenameResponse::Range(range)) = response else {
+            panic!("the parameter is not renamable");
+        };
+        assert_eq!(range.start, Position::new(1, 20));
+        assert_eq!(range.end, Position::new(1, 21));
+    }
+
+    #[test]
+    fn rename_of_a_parameter_edits_only_its_references() {
+        let snapshot = snapshot_of(&[("main.tan", MAIN), ("other.tan", "(use main)\nx\n")]);
+        let edits = rename_edits(&snapshot, 1, 20);
+
+        // The parameter and its use, not the module-level `x`, the comment or
+        // the other document.
+        assert_eq!(ranges(&edits), [(1, 14), (1, 20)]);
+        assert!(edits.iter().all(|(_, edit)| edit.new_text == "y"));
+    }
+
+    #[test]
+    fn rename_of_a_nested_let_edits_only_its_references() {
+        let input = "(let x 1)\n(let f (Func () (do (let x 2) x)))\n(f x)\n";
+        let snapshot = snapshot_of(&[("main.tan", input)]);
+        let edits = rename_edits(&snapshot, 1, 25);
+
+        assert_eq!(ranges(&edits), [(1, 25), (1, 30)]);
+    }
+
+    #[test]
+    fn rename_of_a_module_level_name_edits_the_workspace() {
+        let snapshot = snapshot_of(&[("main.tan", MAIN)]);
+        let edits = rename_edits(&snapshot, 0, 5);
+
+        // The definition and the use in `g`, not the parameter. The edit of
+        // the comment needs a confirmation, the client has no annotations.
+        assert_eq!(ranges(&edits), [(0, 5), (3, 16)]);
+    }
+
+    const KEYWORDS: &str = "(let greet (Func (name) (writeln name)))\n(greet :name \"Ada\")\n";
+
+    #[test]
+    fn prepare_rename_of_a_keyword_excludes_the_colon() {
+        let snapshot = snapshot_of(&[("main.tan", KEYWORDS)]);
+        let response = prepare_rename(&snapshot, position_params("main.tan", 1, 8)).unwrap();
+
+        let Some(PrepareRenameResponse::Range(range)) = response else {
+            panic!("the keyword is not renamable");
+        };
+        assert_eq!(range.start, Position::new(1, 8));
+        assert_eq!(range.end, Position::new(1, 12));
+    }
+
+    #[test]
+    fn rename_of_a_keyword_edits_the_parameters_that_accept_it() {
+        let other = "(use main)\n(greet :name \"Grace\")\n(let d {:name 1})\n";
+        let snapshot = snapshot_of(&[("main.tan", KEYWORDS), ("other.tan", other)]);
+        let edits = rename_edits_to(&snapshot, 1, 8, ":who");
+
+        let main_edits: Vec<(u32, u32, &str)> = edits
+            .iter()
+            .filter(|(uri, _)| *uri == document_uri("main.tan"))
+            .map(|(_, edit)| {
+                (
+                    edit.range.start.line,
+                    edit.range.start.character,
+                    edit.new_text.as_str(),
+                )
+            })
+            .collect();
+        // The parameter and its reference, and the keyword argument.
+        assert_eq!(main_edits, [(0, 18, "who"), (0, 33, "who"), (1, 7, ":who")]);
+
+        let other_edits: Vec<&str> = edits
+            .iter()
+            .filter(|(uri, _)| *uri == document_uri("other.tan"))
+            .map(|(_, edit)| edit.new_text.as_str())
+            .collect();
+        assert_eq!(other_edits, [":who", ":who"]);
+    }
+}
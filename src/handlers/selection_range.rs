@@ -0,0 +1,216 @@
+use lsp_types::{SelectionRange, SelectionRangeParams};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+
+use crate::{
+    ast::find_path_at,
+    indent::{is_closing_delimiter, is_opening_delimiter, top_level_form_ranges},
+    snapshot::Snapshot,
+    unicode::is_name_char,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+// #Insight
+// Inside strings and comments the selection expands from the word under the
+// cursor to the text (without the quotes, or the comment marker) before the
+// whole expression. A form expands to its terms before the delimiters, and
+// the top-level form to its annotations.
+
+// #Insight
+// Unparsable documents, e.g. while typing, expand from the word under the
+// cursor through the balanced delimiters, with a lexical scan.
+pub fn selection_range(
+    snapshot: &Snapshot,
+    params: SelectionRangeParams,
+) -> anyhow::Result<Option<Vec<SelectionRange>>> {
+    let input = snapshot.vfs.read(&params.text_document.uri)?;
+
+    let exprs = parse_string_all(&input).ok();
+    let chars: Vec<char> = input.chars().collect();
+    let form_ranges = top_level_form_ranges(&input);
+
+    let selection_ranges = params
+        .positions
+        .iter()
+        .map(|position| {
+            let index = index_from_lsp_position(position, &input);
+            let mut ranges = match &exprs {
+                Some(exprs) => selection_ranges_at(exprs, &chars, index),
+                None => lexical_ranges_at(&chars, index),
+            };
+            // The annotations of the top-level form, e.g. `#(Func Int Int)`.
+            if let Some(form_range) = form_ranges.iter().find(|range| range.contains(&index)) {
+                if ranges.last().is_none_or(|last| {
+                    form_range.start <= last.start
+                        && last.end <= form_range.end
+                        && form_range != last
+                }) {
+                    ranges.push(form_range.clone());
+                }
+            }
+            to_selection_range(&ranges, &input, index)
+        })
+        .collect();
+
+    Ok(Some(selection_ranges))
+}
+
+/// Returns the (char) ranges that contain the index, from the innermost to
+/// the outermost.
+fn selection_ranges_at(exprs: &[Ann<Expr>], chars: &[char], index: usize) -> Vec<Range> {
+    let path = find_path_at(exprs, index);
+    let mut ranges = Vec::new();
+
+    if let Some(innermost) = path.last() {
+        let range = innermost.get_range();
+        let text_range = match &innermost.0 {
+            Expr::String(_) => Some(range.start + 1..range.end.saturating_sub(1)),
+            Expr::Comment(_) => Some(comment_text_range(chars, &range)),
+            _ => None,
+        };
+
+        if let Some(text_range) = text_range.filter(|text_range| text_range.start < text_range.end)
+        {
+            if let Some(word_range) = word_range_at(chars, &text_range, index) {
+                ranges.push(word_range);
+            }
+            ranges.push(text_range);
+        }
+    }
+
+    for expr in path.iter().rev() {
+        // The terms of the form, without the delimiters. The synthesized
+        // terms, e.g. the `Array` head of `[a b]`, are skipped.
+        if let Ann(Expr::List(terms), _) = expr {
+            let form_range = expr.get_range();
+            let mut term_ranges = terms
+                .iter()
+                .map(|term| term.get_range())
+                .filter(|range| form_range.start < range.start && range.end < form_range.end);
+            if let Some(first) = term_ranges.next() {
+                let end = term_ranges.next_back().map_or(first.end, |last| last.end);
+                ranges.push(first.start..end);
+            }
+        }
+
+        let mut range = expr.get_range();
+        // The comment range includes the trailing newline.
+        if let Ann(Expr::Comment(_), _) = expr {
+            while range.end > range.start && chars.get(range.end - 1) == Some(&'\n') {
+                range.end -= 1;
+            }
+        }
+        ranges.push(range);
+    }
+
+    ranges.dedup();
+
+    ranges
+}
+
+/// Returns the (char) ranges that contain the index, from the innermost to
+/// the outermost: the word, and the balanced delimited forms.
+fn lexical_ranges_at(chars: &[char], index: usize) -> Vec<Range> {
+    let mut ranges = Vec::new();
+
+    if let Some(word_range) = word_range_at(chars, &(0..chars.len()), index) {
+        ranges.push(word_range);
+    }
+
+    let mut opening_indices: Vec<usize> = Vec::new();
+    let mut in_string = false;
+    let mut in_comment = false;
+
+    for (i, &ch) in chars.iter().enumerate() {
+        if in_comment {
+            in_comment = ch != '\n';
+            continue;
+        }
+
+        if in_string {
+            in_string = ch != '"';
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            ';' => in_comment = true,
+            '-' if chars.get(i + 1) == Some(&'-') => in_comment = true,
+            _ if is_opening_delimiter(ch) => opening_indices.push(i),
+            _ if is_closing_delimiter(ch) => {
+                // The inner forms are closed first.
+                if let Some(start) = opening_indices.pop().filter(|start| *start <= index) {
+                    if index <= i {
+                        ranges.push(start + 1..i);
+                        ranges.push(start..i + 1);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    ranges.retain(|range| range.start < range.end);
+    ranges.dedup();
+
+    ranges
+}
+
+/// Returns the (char) range of the text of a comment, without the marker and
+/// the trailing newline.
+fn comment_text_range(chars: &[char], range: &Range) -> Range {
+    let mut start = range.start;
+    while start < range.end && matches!(chars.get(start), Some(';') | Some('-')) {
+        start += 1;
+    }
+    while start < range.end && chars.get(start) == Some(&' ') {
+        start += 1;
+    }
+
+    let mut end = range.end;
+    while end > start && chars.get(end - 1).is_some_and(|c| c.is_whitespace()) {
+        end -= 1;
+    }
+
+    start..end
+}
+
+/// Returns the (char) range of the word that contains the index, within the
+/// text range.
+fn word_range_at(chars: &[char], text_range: &Range, index: usize) -> Option<Range> {
+    let is_word_char = |i: usize| chars.get(i).is_some_and(|c| is_name_char(*c));
+
+    if !text_range.contains(&index) || !is_word_char(index) {
+        return None;
+    }
+
+    let mut start = index;
+    while start > text_range.start && is_word_char(start - 1) {
+        start -= 1;
+    }
+
+    let mut end = index;
+    while end < text_range.end && is_word_char(end) {
+        end += 1;
+    }
+
+    Some(start..end)
+}
+
+/// Converts the ranges, from the innermost to the outermost, to a chain of
+/// selection ranges. An empty range at the position is returned if no
+/// expression contains the position.
+fn to_selection_range(ranges: &[Range], input: &str, index: usize) -> SelectionRange {
+    let mut selection_range: Option<SelectionRange> = None;
+
+    for range in ranges.iter().rev() {
+        selection_range = Some(SelectionRange {
+            range: lsp_range_from_range(range, input),
+            parent: selection_range.map(Box::new),
+        });
+    }
+
+    selection_range.unwrap_or_else(|| SelectionRange {
+        range: lsp_range_from_range(&(index..index), input),
+        parent: None,
+    })
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use lsp_types::{
+    SemanticToken, SemanticTokenType, SemanticTokens, SemanticTokensLegend, SemanticTokensParams,
+    SemanticTokensResult, SymbolKind,
+};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::for_each_expr,
+    index::{text_hash, Index},
+    resolve::{local_binding_kind, LocalKind},
+    snapshot::Snapshot,
+    symbols::{collect_definitions, Definition},
+    util::LineIndex,
+};
+
+/// The token types, the index in this list is the encoded token type.
+pub const TOKEN_TYPES: [SemanticTokenType; 10] = [
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::DECORATOR,
+];
+
+const COMMENT: u32 = 0;
+const STRING: u32 = 1;
+const NUMBER: u32 = 2;
+const KEYWORD: u32 = 3;
+const VARIABLE: u32 = 4;
+const KEY_SYMBOL: u32 = 5;
+const FUNCTION: u32 = 6;
+const MACRO: u32 = 7;
+const PARAMETER: u32 = 8;
+const ANNOTATION: u32 = 9;
+
+// #Insight
+// The locally bound symbols are classified by their binding, e.g. parameters.
+// The other symbols are classified by name, from the definitions of the index
+// and the document. When the index changes the classification, the server
+// asks the client to refresh the tokens.
+
+/// The token types of defined names, for the names that are not highlighted
+/// as variables.
+pub type Classification = HashMap<String, u32>;
+
+fn classify_definition(definition: &Definition, classification: &mut Classification) {
+    let token_type = if definition.is_macro {
+        MACRO
+    } else if definition.kind == SymbolKind::FUNCTION {
+        FUNCTION
+    } else {
+        return;
+    };
+
+    classification.insert(definition.name.clone(), token_type);
+}
+
+/// Classifies the names defined in the indexed files.
+pub fn classify_index(index: &Index) -> Classification {
+    let mut classification = Classification::new();
+
+    for (_, file) in index.files() {
+        for definition in &file.definitions {
+            classify_definition(definition, &mut classification);
+        }
+    }
+
+    classification
+}
+
+/// Returns the hash of the inputs of the tokens of a text: the text and the
+/// classification.
+fn tokens_key(input: &str, classification: &Classification) -> u64 {
+    // The hash of a map is independent of the iteration order.
+    let classification_hash = classification
+        .iter()
+        .map(|entry| {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add);
+
+    text_hash(input) ^ classification_hash.rotate_left(1)
+}
+
+/// The special forms, highlighted as keywords.
+const KEYWORDS: [&str; 9] = [
+    "let", "do", "if", "Func", "Macro", "use", "quot", "eval", "for",
+];
+
+/// Documents larger than this (in chars) are processed in chunks.
+const CHUNKED_SIZE_THRESHOLD: usize = 256 * 1024;
+
+/// The number of top-level expressions processed per chunk.
+const CHUNK_SIZE: usize = 512;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: Vec::new(),
+    }
+}
+
+/// Returns the token type of an expression, `None` for expressions that are
+/// not highlighted (e.g. lists). The symbols are resolved in the top-level
+/// expression.
+fn token_type(
+    expr: &Ann<Expr>,
+    top_level_expr: &Ann<Expr>,
+    classification: &Classification,
+) -> Option<u32> {
+    if let Expr::Symbol(sym) = &expr.0 {
+        if !KEYWORDS.contains(&sym.as_str()) {
+            let local_kind =
+                local_binding_kind(std::slice::from_ref(top_level_expr), expr.get_range().start);
+            match local_kind {
+                Some(LocalKind::Parameter) => return Some(PARAMETER),
+                Some(LocalKind::Function) => return Some(FUNCTION),
+                Some(LocalKind::Macro) => return Some(MACRO),
+                Some(LocalKind::Variable) => return Some(VARIABLE),
+                None => (),
+            }
+        }
+    }
+
+    match &expr.0 {
+        Expr::Comment(_) => Some(COMMENT),
+        Expr::String(_) | Expr::Char(_) => Some(STRING),
+        Expr::Int(_) | Expr::Float(_) => Some(NUMBER),
+        Expr::Bool(_) => Some(KEYWORD),
+        Expr::Symbol(sym) if KEYWORDS.contains(&sym.as_str()) => Some(KEYWORD),
+        Expr::Symbol(sym) => Some(classification.get(sym).copied().unwrap_or(VARIABLE)),
+        Expr::KeySymbol(_) => Some(KEY_SYMBOL),
+        _ => None,
+    }
+}
+
+/// A token, with absolute (char) start index.
+struct Token {
+    start: usize,
+    end: usize,
+    token_type: u32,
+}
+
+fn collect_tokens(
+    exprs: &[Ann<Expr>],
+    input_chars: &[char],
+    classification: &Classification,
+    tokens: &mut Vec<Token>,
+) {
+    for top_level_expr in exprs {
+        for_each_expr(std::slice::from_ref(top_level_expr), &mut |expr| {
+            let range = expr.get_range();
+
+            // Skip synthesized expressions, e.g. the `Array` head of `[a b]`
+            // with the range of the bracket.
+            if range.start >= range.end {
+                return;
+            }
+            if let Expr::Symbol(sym) = &expr.0 {
+                if sym.chars().count() != range.end - range.start {
+                    return;
+                }
+            }
+
+            let Some(token_type) = token_type(expr, top_level_expr, classification) else {
+                return;
+            };
+
+            push_token(range, token_type, input_chars, tokens);
+        });
+    }
+}
+
+/// Pushes the token of a (char) range, split in lines.
+fn push_token(range: Range, token_type: u32, input_chars: &[char], tokens: &mut Vec<Token>) {
+    // #Insight
+    // Tokens cannot span multiple lines, multi-line strings are split, and
+    // the comment range includes the trailing newline.
+
+    let mut start = range.start;
+    let end = range.end.min(input_chars.len());
+    let chars = input_chars.get(range.start..end).unwrap_or_default();
+    for (i, c) in chars.iter().enumerate() {
+        let i = range.start + i;
+        if *c == '\n' {
+            if i > start {
+                tokens.push(Token {
+                    start,
+                    end: i,
+                    token_type,
+                });
+            }
+            start = i + 1;
+        }
+    }
+    if range.end > start {
+        tokens.push(Token {
+            start,
+            end: range.end,
+            token_type,
+        });
+    }
+}
+
+/// Collects the tokens of the annotations, e.g. `#(Func Int Int)`. The
+/// annotations are not part of the parsed expressions, they are scanned.
+fn collect_annotation_tokens(input_chars: &[char], tokens: &mut Vec<Token>) {
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut i = 0;
+
+    while i < input_chars.len() {
+        let ch = input_chars[i];
+
+        if in_comment {
+            in_comment = ch != '\n';
+        } else if in_string {
+            in_string = ch != '"';
+        } else if ch == '"' {
+            in_string = true;
+        } else if ch == ';' || (ch == '-' && input_chars.get(i + 1) == Some(&'-')) {
+            in_comment = true;
+        } else if ch == '#'
+            && (i == 0 || input_chars[i - 1].is_whitespace() || input_chars[i - 1] == '(')
+        {
+            // An annotation ends at a whitespace outside parentheses.
+            let start = i;
+            let mut nesting = 0usize;
+            while let Some(&ch) = input_chars.get(i) {
+                match ch {
+                    '(' => nesting += 1,
+                    ')' if nesting == 0 => break,
+                    ')' => nesting -= 1,
+                    _ if ch.is_whitespace() && nesting == 0 => break,
+                    _ => (),
+                }
+                i += 1;
+            }
+            push_token(start..i, ANNOTATION, input_chars, tokens);
+            continue;
+        }
+
+        i += 1;
+    }
+}
+
+/// Encodes the tokens relative to the previous token.
+fn encode_tokens(tokens: &[Token], line_index: &LineIndex) -> Vec<SemanticToken> {
+    let mut data = Vec::with_capacity(tokens.len());
+    let mut prev_line = 0;
+    let mut prev_start = 0;
+
+    for token in tokens {
+        let position = line_index.position(token.start);
+        let end_position = line_index.position(token.end);
+
+        let delta_line = position.line - prev_line;
+        let delta_start = if delta_line == 0 {
+            position.character - prev_start
+        } else {
+            position.character
+        };
+
+        data.push(SemanticToken {
+            delta_line,
+            delta_start,
+            // Tokens are single-line, the length is in code units.
+            length: end_position.character - position.character,
+            token_type: token.token_type,
+            token_modifiers_bitset: 0,
+        });
+
+        prev_line = position.line;
+        prev_start = position.character;
+    }
+
+    data
+}
+
+pub fn semantic_tokens_full(
+    snapshot: &Snapshot,
+    params: SemanticTokensParams,
+) -> anyhow::Result<Option<SemanticTokensResult>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Some(exprs) = snapshot.caches.ast(uri, &input) else {
+        return Ok(None);
+    };
+
+    let mut classification = classify_index(&snapshot.index);
+    for definition in collect_definitions(&exprs) {
+        classify_definition(&definition, &mut classification);
+    }
+
+    let key = tokens_key(&input, &classification);
+    if let Some(data) = snapshot.caches.tokens(uri, key) {
+        return Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: data.to_vec(),
+        })));
+    }
+
+    let input_chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+
+    if input_chars.len() > CHUNKED_SIZE_THRESHOLD {
+        // #Insight
+        // Huge (e.g. generated) documents are processed in chunks of top-level
+        // expressions. Between chunks the handler stops if the request was
+        // cancelled and yields the worker thread, so it never monopolizes a
+        // worker for seconds.
+        for chunk in exprs.chunks(CHUNK_SIZE) {
+            snapshot.cancellation.check()?;
+            collect_tokens(chunk, &input_chars, &classification, &mut tokens);
+            std::thread::yield_now();
+        }
+        snapshot.cancellation.check()?;
+    } else {
+        collect_tokens(&exprs, &input_chars, &classification, &mut tokens);
+    }
+
+    collect_annotation_tokens(&input_chars, &mut tokens);
+
+    tokens.sort_by_key(|token| token.start);
+
+    let line_index = LineIndex::new(&input);
+    let data = encode_tokens(&tokens, &line_index);
+    snapshot
+        .caches
+        .insert_tokens(uri, key, Arc::from(data.as_slice()));
+
+    Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+        result_id: None,
+        data,
+    })))
+}
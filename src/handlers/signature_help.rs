@@ -0,0 +1,253 @@
+use std::sync::Arc;
+
+use lsp_types::{
+    Documentation, ParameterInformation, ParameterLabel, SignatureHelp, SignatureHelpParams,
+    SignatureInformation,
+};
+use tan::api::parse_string_all;
+
+use crate::{
+    doc_tags::param_tag,
+    handlers::{completion::is_special_form, hover::describe_binding},
+    indent::{is_closing_delimiter, is_opening_delimiter, missing_closing_delimiters},
+    markup::{markup_content, preferred_markup_kind},
+    resolve::lookup_symbol_at,
+    snapshot::Snapshot,
+    util::index_from_lsp_position,
+};
+
+// #Insight
+// While typing a call the document is usually unbalanced, e.g. `(add 1 `, and
+// does not parse. The call at the cursor is found by scanning the text, and
+// its head is resolved in the text before the cursor, with the unclosed
+// delimiters closed.
+
+/// The characters that trigger signature help: `(` opens a call, a space
+/// separates the arguments.
+pub const TRIGGER_CHARACTERS: [&str; 2] = ["(", " "];
+
+/// The characters that re-trigger signature help while it is active, `)`
+/// closes a nested call.
+pub const RETRIGGER_CHARACTERS: [&str; 1] = [")"];
+
+/// The call that encloses the cursor.
+struct CallContext {
+    /// The name of the called function.
+    head: String,
+    /// The (char) index of the head.
+    head_start: usize,
+    /// The position of the argument at the cursor.
+    active_arg: usize,
+}
+
+pub fn signature_help(
+    snapshot: &Snapshot,
+    params: SignatureHelpParams,
+) -> anyhow::Result<Option<SignatureHelp>> {
+    let uri = &params.text_document_position_params.text_document.uri;
+    let position = &params.text_document_position_params.position;
+    let input = snapshot.vfs.read(uri)?;
+    let index = index_from_lsp_position(position, &input);
+
+    let Some(call) = call_context(&input, index) else {
+        return Ok(None);
+    };
+    if is_special_form(&call.head) {
+        return Ok(None);
+    }
+
+    let text = match parse_string_all(&input) {
+        Ok(_) => input.clone(),
+        Err(_) => {
+            let mut prefix: String = input.chars().take(index).collect();
+            prefix.push_str(&missing_closing_delimiters(&prefix));
+            Arc::from(prefix)
+        }
+    };
+    let Ok(exprs) = parse_string_all(&text) else {
+        return Ok(None);
+    };
+
+    let Some(target) = lookup_symbol_at(snapshot, uri, &text, &exprs, call.head_start) else {
+        return Ok(None);
+    };
+    let Ok(target_exprs) = parse_string_all(&target.text) else {
+        return Ok(None);
+    };
+    let description = describe_binding(&target_exprs, &target.text, &target.binding);
+    let Some(label) = description.signature.clone() else {
+        return Ok(None);
+    };
+
+    let documentation_format = snapshot
+        .config
+        .client_capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.signature_help.as_ref())
+        .and_then(|signature_help| signature_help.signature_information.as_ref())
+        .and_then(|information| information.documentation_format.as_deref());
+    let kind = preferred_markup_kind(documentation_format);
+
+    let parameters = param_offsets(&label)
+        .into_iter()
+        .map(|(name, offsets)| ParameterInformation {
+            label: ParameterLabel::LabelOffsets(offsets),
+            documentation: description
+                .doc
+                .as_deref()
+                .and_then(|doc| param_doc(doc, &name))
+                .map(|doc| Documentation::MarkupContent(markup_content(kind.clone(), &doc))),
+        })
+        .collect();
+
+    let signature = SignatureInformation {
+        label,
+        documentation: description
+            .doc
+            .as_deref()
+            .map(|doc| Documentation::MarkupContent(markup_content(kind.clone(), doc))),
+        parameters: Some(parameters),
+        active_parameter: None,
+    };
+
+    Ok(Some(SignatureHelp {
+        signatures: vec![signature],
+        active_signature: Some(0),
+        active_parameter: Some(call.active_arg as u32),
+    }))
+}
+
+/// Returns the innermost call that encloses the (char) index, `None` in a
+/// string or a comment.
+fn call_context(input: &str, index: usize) -> Option<CallContext> {
+    let chars: Vec<char> = input.chars().take(index).collect();
+
+    // The open lists before the index.
+    let mut open_lists = Vec::new();
+    let mut in_string = false;
+    let mut in_comment = false;
+    for (i, ch) in chars.iter().enumerate() {
+        if in_comment {
+            in_comment = *ch != '\n';
+        } else if in_string {
+            in_string = *ch != '"';
+        } else if *ch == '"' {
+            in_string = true;
+        } else if *ch == ';' {
+            in_comment = true;
+        } else if is_opening_delimiter(*ch) {
+            open_lists.push((*ch, i));
+        } else if is_closing_delimiter(*ch) {
+            open_lists.pop();
+        }
+    }
+    if in_string || in_comment {
+        return None;
+    }
+
+    let (_, open) = open_lists.iter().rev().find(|(ch, _)| *ch == '(')?;
+
+    // The terms of the call before the index, the nested lists and strings
+    // are single terms.
+    let mut term_starts = Vec::new();
+    let mut depth = 0;
+    // After a space, or after a nested list or a string.
+    let mut after_space = true;
+    let mut after_term = false;
+    let mut in_string = false;
+    let mut in_comment = false;
+    for (i, ch) in chars.iter().enumerate().skip(open + 1) {
+        if in_comment {
+            in_comment = *ch != '\n';
+            continue;
+        }
+        if in_string {
+            in_string = *ch != '"';
+            after_term = !in_string && depth == 0;
+            continue;
+        }
+        if depth == 0 && (ch.is_whitespace() || *ch == ';') {
+            in_comment = *ch == ';';
+            after_space = true;
+            continue;
+        }
+        if depth == 0 && (after_space || after_term || is_opening_delimiter(*ch) || *ch == '"') {
+            term_starts.push(i);
+        }
+        after_space = false;
+        after_term = false;
+        if *ch == '"' {
+            in_string = true;
+        } else if is_opening_delimiter(*ch) {
+            depth += 1;
+        } else if is_closing_delimiter(*ch) {
+            depth -= 1;
+            after_term = depth == 0;
+        }
+    }
+
+    let head_start = *term_starts.first()?;
+    let head: String = chars[head_start..]
+        .iter()
+        .take_while(|ch| {
+            !ch.is_whitespace() && !is_opening_delimiter(**ch) && !is_closing_delimiter(**ch)
+        })
+        .collect();
+    if head.is_empty() || head.starts_with(['"', ';']) {
+        return None;
+    }
+
+    // The cursor at the end of a term is on the term, not on the next one.
+    let args = term_starts.len() - 1;
+    let in_term = !after_space || depth > 0;
+    let active_arg = if in_term {
+        args.saturating_sub(1)
+    } else {
+        args
+    };
+
+    Some(CallContext {
+        head,
+        head_start,
+        active_arg,
+    })
+}
+
+/// Returns the parameters of a signature label, e.g. `(add a b)`, with their
+/// (UTF-16) offsets in the label.
+fn param_offsets(label: &str) -> Vec<(String, [u32; 2])> {
+    let inner = label
+        .strip_prefix('(')
+        .and_then(|label| label.strip_suffix(')'))
+        .unwrap_or(label);
+
+    let mut params = Vec::new();
+    // The offset of the opening parenthesis.
+    let mut offset = 1;
+    for (i, word) in inner.split(' ').enumerate() {
+        let len = word.encode_utf16().count() as u32;
+        if i > 0 {
+            params.push((word.to_owned(), [offset, offset + len]));
+        }
+        offset += len + 1;
+    }
+
+    params
+}
+
+/// Returns the description of a parameter, from its `@param` tag.
+fn param_doc(doc: &str, name: &str) -> Option<String> {
+    doc.lines().find_map(|line| {
+        let (tag_name, start) = param_tag(line)?;
+        if tag_name != name {
+            return None;
+        }
+        let description: String = line
+            .chars()
+            .skip(start + tag_name.chars().count())
+            .collect();
+        let description = description.trim();
+        (!description.is_empty()).then(|| description.to_owned())
+    })
+}
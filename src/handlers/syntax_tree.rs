@@ -0,0 +1,139 @@
+use lsp_types::TextDocumentPositionParams;
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::contains_index,
+    lsp_ext::{NodeAtPositionResult, SyntaxNode, SyntaxTree, ViewSyntaxTreeParams},
+    snapshot::Snapshot,
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+/// Returns the kind of an expression.
+fn expr_kind(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::One => "One",
+        Expr::Comment(_) => "Comment",
+        Expr::Bool(_) => "Bool",
+        Expr::Int(_) => "Int",
+        Expr::Float(_) => "Float",
+        Expr::Symbol(_) => "Symbol",
+        Expr::KeySymbol(_) => "KeySymbol",
+        Expr::Char(_) => "Char",
+        Expr::String(_) => "String",
+        Expr::List(_) => "List",
+        Expr::Array(_) => "Array",
+        Expr::Dict(_) => "Dict",
+        Expr::Func(..) => "Func",
+        Expr::Macro(..) => "Macro",
+        Expr::ForeignFunc(_) => "ForeignFunc",
+        Expr::Do => "Do",
+        Expr::Let => "Let",
+        Expr::If(..) => "If",
+    }
+}
+
+/// Converts an expression to a syntax node, without children.
+fn syntax_node(expr: &Ann<Expr>, id: String, input: &str) -> SyntaxNode {
+    let range = expr.get_range();
+
+    let text = match &expr.0 {
+        Expr::List(_) => None,
+        expr => Some(expr.to_string()),
+    };
+
+    SyntaxNode {
+        id,
+        kind: expr_kind(&expr.0).to_owned(),
+        text,
+        range: (range.start < range.end).then(|| lsp_range_from_range(&range, input)),
+        children: Vec::new(),
+    }
+}
+
+fn syntax_nodes(exprs: &[Ann<Expr>], parent_id: Option<&str>, input: &str) -> Vec<SyntaxNode> {
+    exprs
+        .iter()
+        .enumerate()
+        .map(|(i, expr)| {
+            let id = match parent_id {
+                Some(parent_id) => format!("{parent_id}.{i}"),
+                None => i.to_string(),
+            };
+
+            let children = match &expr.0 {
+                Expr::List(terms) => syntax_nodes(terms, Some(&id), input),
+                _ => Vec::new(),
+            };
+
+            SyntaxNode {
+                children,
+                ..syntax_node(expr, id, input)
+            }
+        })
+        .collect()
+}
+
+pub fn view_syntax_tree(
+    snapshot: &Snapshot,
+    params: ViewSyntaxTreeParams,
+) -> anyhow::Result<Option<SyntaxTree>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    Ok(Some(SyntaxTree {
+        version: snapshot.vfs.version(uri),
+        nodes: syntax_nodes(&exprs, None, &input),
+    }))
+}
+
+pub fn node_at_position(
+    snapshot: &Snapshot,
+    params: TextDocumentPositionParams,
+) -> anyhow::Result<Option<NodeAtPositionResult>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let index = index_from_lsp_position(&params.position, &input);
+
+    // #Insight
+    // Same as `find_path_at`, but also computes the node ids.
+
+    let mut path: Vec<(String, &Ann<Expr>)> = Vec::new();
+    let mut exprs: &[Ann<Expr>] = &exprs;
+
+    'outer: loop {
+        for (i, expr) in exprs.iter().enumerate() {
+            if contains_index(expr, index) {
+                let id = match path.last() {
+                    Some((parent_id, _)) => format!("{parent_id}.{i}"),
+                    None => i.to_string(),
+                };
+                path.push((id, expr));
+                if let Ann(Expr::List(terms), _) = expr {
+                    exprs = terms;
+                    continue 'outer;
+                }
+                break 'outer;
+            }
+        }
+        break;
+    }
+
+    let Some((id, expr)) = path.pop() else {
+        return Ok(None);
+    };
+
+    Ok(Some(NodeAtPositionResult {
+        version: snapshot.vfs.version(uri),
+        node: syntax_node(expr, id, &input),
+        ancestors: path.into_iter().map(|(id, _)| id).collect(),
+    }))
+}
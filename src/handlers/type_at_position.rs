@@ -0,0 +1,62 @@
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::find_path_at,
+    lsp_ext::{TypeAtPositionParams, TypeAtPositionResult},
+    snapshot::Snapshot,
+    types::{annotated_type, infer_type},
+    util::{index_from_lsp_position, lsp_range_from_range},
+};
+
+pub fn type_at_position(
+    snapshot: &Snapshot,
+    params: TypeAtPositionParams,
+) -> anyhow::Result<Option<TypeAtPositionResult>> {
+    let uri = &params.text_document.uri;
+    let input = snapshot.vfs.read(uri)?;
+
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let (start, end) = match &params.range {
+        Some(range) => (
+            index_from_lsp_position(&range.start, &input),
+            index_from_lsp_position(&range.end, &input),
+        ),
+        None => {
+            let index = index_from_lsp_position(&params.position, &input);
+            (index, index)
+        }
+    };
+
+    let path = find_path_at(&exprs, start);
+
+    // The smallest expression that contains the selection.
+    let Some(position) = path.iter().rposition(|expr| expr.get_range().end >= end) else {
+        return Ok(None);
+    };
+
+    // The head of a call stands for the call, e.g. `+` in `(+ 1 2)`.
+    let expr = match path[..position] {
+        [.., parent @ Ann(Expr::List(terms), _)]
+            if terms
+                .first()
+                .is_some_and(|head| std::ptr::eq(head, path[position])) =>
+        {
+            parent
+        }
+        _ => path[position],
+    };
+
+    let annotated = annotated_type(expr);
+    let Some(type_name) = annotated.clone().or_else(|| infer_type(&exprs, expr)) else {
+        return Ok(None);
+    };
+
+    Ok(Some(TypeAtPositionResult {
+        type_name,
+        annotated: annotated.is_some(),
+        range: lsp_range_from_range(&expr.get_range(), &input),
+    }))
+}
@@ -0,0 +1,79 @@
+use lsp_types::{Location, Url, WorkspaceSymbolParams, WorkspaceSymbolResponse};
+use tan::api::parse_string_all;
+
+use crate::{
+    fuzzy::fuzzy_score,
+    index::Origin,
+    snapshot::Snapshot,
+    symbols::{collect_definitions, Definition, SymbolSupport},
+    util::lsp_range_from_range,
+};
+
+/// The maximum number of symbols returned, the best matches.
+const MAX_SYMBOLS: usize = 256;
+
+pub fn workspace_symbol(
+    snapshot: &Snapshot,
+    params: WorkspaceSymbolParams,
+) -> anyhow::Result<Option<WorkspaceSymbolResponse>> {
+    let query = params.query;
+
+    let capabilities = snapshot
+        .config
+        .client_capabilities
+        .workspace
+        .as_ref()
+        .and_then(|workspace| workspace.symbol.as_ref());
+    let support = SymbolSupport::new(
+        capabilities.and_then(|c| c.symbol_kind.as_ref()),
+        capabilities.and_then(|c| c.tag_support.as_ref()),
+    );
+
+    // The matches, with the score and the origin rank.
+    let mut matches = Vec::new();
+
+    let mut push_matches = |uri: &Url, definitions: &[Definition], input: &str, origin: Origin| {
+        for definition in definitions {
+            let Some(score) = fuzzy_score(&query, &definition.name) else {
+                continue;
+            };
+            let range = lsp_range_from_range(&definition.selection_range, input);
+            let symbol = support.symbol_information(definition, Location::new(uri.clone(), range));
+            matches.push((score, origin == Origin::Dependency, symbol));
+        }
+    };
+
+    // The open documents shadow the indexed files.
+
+    for (uri, input) in snapshot.vfs.documents() {
+        snapshot.cancellation.check()?;
+        if let Ok(exprs) = parse_string_all(input) {
+            let origin = snapshot
+                .index
+                .get(uri)
+                .map_or(Origin::Workspace, |file| file.origin);
+            push_matches(uri, &collect_definitions(&exprs), input, origin);
+        }
+    }
+
+    for (uri, file) in snapshot.index.files() {
+        if snapshot.vfs.get(uri).is_none() {
+            push_matches(uri, &file.definitions, &file.text, file.origin);
+        }
+    }
+
+    // The best matches first, the workspace symbols before the dependency
+    // symbols with the same score.
+    matches.sort_by(|(a_score, a_dependency, a), (b_score, b_dependency, b)| {
+        b_score
+            .cmp(a_score)
+            .then(a_dependency.cmp(b_dependency))
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.location.uri.as_str().cmp(b.location.uri.as_str()))
+    });
+    matches.truncate(MAX_SYMBOLS);
+
+    let symbols = matches.into_iter().map(|(_, _, symbol)| symbol).collect();
+
+    Ok(Some(WorkspaceSymbolResponse::Flat(symbols)))
+}
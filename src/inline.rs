@@ -0,0 +1,444 @@
+use lsp_types::{TextEdit, Url};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::{find_path_at, for_each_expr, head_symbol},
+    module::attached_comments,
+    references::find_references,
+    resolve::{
+        bound_value, resolve_local_symbol_at, resolve_symbol_at, visible_local_bindings, Binding,
+    },
+    snapshot::Snapshot,
+    symbols::collect_definitions,
+    unicode::same_name,
+    unused::removal_range,
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// Inlining must not change the meaning of the code: the value is evaluated
+// once where it was bound, and its names are resolved there. The inlining is
+// refused if a value, or an argument of an inlined call, with possible side
+// effects would be evaluated more or less than once, or if a name of the
+// inlined code would resolve to another binding at the reference.
+
+/// The inlining of a definition into its references.
+pub struct Inlining {
+    pub name: String,
+    /// The edits of the document.
+    pub edits: Vec<TextEdit>,
+}
+
+/// Inlines the `let` binding at the (char) index of a document, on the name
+/// or on a reference, and removes it. The calls of a function are replaced by
+/// its body, with the arguments in place of the parameters. Returns `None` if
+/// the index is not on a `let` binding in the document, or an error with the
+/// reason if the inlining is not safe.
+pub fn inline_definition(
+    snapshot: &Snapshot,
+    uri: &Url,
+    exprs: &[Ann<Expr>],
+    input: &str,
+    index: usize,
+) -> Option<Result<Inlining, String>> {
+    let binding = resolve_symbol_at(exprs, index)?;
+    let value = bound_value(exprs, &binding)?;
+
+    let chars: Vec<char> = input.chars().collect();
+    let text = |range: &Range| chars[range.clone()].iter().collect::<String>();
+    let name = text(&binding.name_range);
+
+    let value_range = value.get_range();
+    let references = binding_references(exprs, &binding);
+
+    if head_symbol(value) == Some("Macro") {
+        return Some(Err(format!("The macro `{name}` cannot be inlined")));
+    }
+
+    if references.is_empty() {
+        return Some(Err(format!("`{name}` is never used")));
+    }
+
+    if references
+        .iter()
+        .any(|range| value_range.start <= range.start && range.end <= value_range.end)
+    {
+        return Some(Err(format!("`{name}` is recursive")));
+    }
+
+    // The top-level definitions are exported.
+    let is_module_level = resolve_local_symbol_at(exprs, binding.name_range.start).is_none();
+    if is_module_level {
+        let other_files = match find_references(snapshot, &name, false) {
+            Ok(locations) => locations.into_iter().any(|location| &location.uri != uri),
+            Err(error) => return Some(Err(error.to_string())),
+        };
+        if other_files {
+            return Some(Err(format!("`{name}` is used by other modules")));
+        }
+    }
+
+    let function = match value {
+        Ann(Expr::List(terms), _) if head_symbol(value) == Some("Func") => match terms.as_slice() {
+            [_, Ann(Expr::List(params), _), body] => Some((params.as_slice(), body)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    let mut edits = Vec::new();
+
+    for reference in &references {
+        let call = function.and_then(|function| Some((function, call_at(exprs, reference)?)));
+
+        let (range, new_text) = match call {
+            Some(((params, body), (call_range, args))) => {
+                match inline_call(exprs, &chars, &binding, params, body, args, &call_range) {
+                    Ok(new_text) => (call_range, new_text),
+                    Err(reason) => return Some(Err(reason)),
+                }
+            }
+            None => {
+                if !is_pure(value)
+                    && (references.len() > 1 || is_in_function(exprs, reference, &binding))
+                {
+                    return Some(Err(format!(
+                        "The value of `{name}` would be evaluated more than once"
+                    )));
+                }
+                if let Some(shadowed) = shadowed_name(exprs, value, &[], reference.start) {
+                    return Some(Err(format!("`{shadowed}` is shadowed at a reference")));
+                }
+                (reference.clone(), text(&value_range))
+            }
+        };
+
+        edits.push(TextEdit::new(lsp_range_from_range(&range, input), new_text));
+    }
+
+    edits.push(TextEdit::new(
+        lsp_range_from_range(&definition_removal(exprs, &chars, &binding, input)?, input),
+        String::new(),
+    ));
+
+    Some(Ok(Inlining { name, edits }))
+}
+
+/// Returns the (char) ranges of the symbols of the document bound by the
+/// binding, except the bound name.
+fn binding_references(exprs: &[Ann<Expr>], binding: &Binding) -> Vec<Range> {
+    let mut references = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(_), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        if range.start >= range.end || range == binding.name_range {
+            return;
+        }
+        if resolve_symbol_at(exprs, range.start).as_ref() == Some(binding) {
+            references.push(range);
+        }
+    });
+
+    references
+}
+
+/// Returns the (char) range and the arguments of the call whose head is the
+/// symbol with the (char) range.
+fn call_at<'a>(exprs: &'a [Ann<Expr>], range: &Range) -> Option<(Range, &'a [Ann<Expr>])> {
+    let path = find_path_at(exprs, range.start);
+    let [.., parent, _] = path.as_slice() else {
+        return None;
+    };
+    let Ann(Expr::List(terms), _) = parent else {
+        return None;
+    };
+
+    (terms.first()?.get_range() == *range).then(|| (parent.get_range(), &terms[1..]))
+}
+
+/// Returns the body of a function, with the arguments of a call in place of
+/// the parameters.
+fn inline_call(
+    exprs: &[Ann<Expr>],
+    chars: &[char],
+    binding: &Binding,
+    params: &[Ann<Expr>],
+    body: &Ann<Expr>,
+    args: &[Ann<Expr>],
+    call_range: &Range,
+) -> Result<String, String> {
+    let name: String = chars[binding.name_range.clone()].iter().collect();
+    if args.len() != params.len() {
+        return Err(format!(
+            "A call of `{name}` has {} arguments, {} expected",
+            args.len(),
+            params.len()
+        ));
+    }
+
+    let body_range = body.get_range();
+
+    // The references to the parameters in the body.
+    let mut substitutions: Vec<(Range, usize)> = Vec::new();
+    for_each_expr(std::slice::from_ref(body), &mut |expr| {
+        let Ann(Expr::Symbol(_), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        let Some(param_binding) = resolve_local_symbol_at(exprs, range.start) else {
+            return;
+        };
+        if let Some(i) = params
+            .iter()
+            .position(|param| param.get_range() == param_binding.name_range)
+        {
+            substitutions.push((range, i));
+        }
+    });
+
+    for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+        let count = substitutions.iter().filter(|(_, j)| *j == i).count();
+        if count != 1 && !is_pure(arg) {
+            let param: String = chars[param.get_range()].iter().collect();
+            return Err(format!(
+                "The argument of `{param}` would be evaluated {count} times"
+            ));
+        }
+    }
+
+    // The names of the arguments must not be bound in the body.
+    let body_bindings = bound_names_in(exprs, body);
+    for arg in args {
+        let mut shadowed = None;
+        for_each_expr(std::slice::from_ref(arg), &mut |expr| {
+            if let Ann(Expr::Symbol(sym), _) = expr {
+                if body_bindings.iter().any(|name| same_name(name, sym)) {
+                    shadowed.get_or_insert(sym.clone());
+                }
+            }
+        });
+        if let Some(shadowed) = shadowed {
+            return Err(format!("`{shadowed}` is shadowed in the body of `{name}`"));
+        }
+    }
+
+    if let Some(shadowed) = shadowed_name(exprs, body, params, call_range.start) {
+        return Err(format!("`{shadowed}` is shadowed at a call of `{name}`"));
+    }
+
+    substitutions.sort_by_key(|(range, _)| range.start);
+
+    let mut new_text = String::new();
+    let mut start = body_range.start;
+    for (range, i) in substitutions {
+        new_text.extend(&chars[start..range.start]);
+        new_text.extend(&chars[args[i].get_range()]);
+        start = range.end;
+    }
+    new_text.extend(&chars[start..body_range.end]);
+
+    Ok(new_text)
+}
+
+/// Returns true if the evaluation of the expression has no side effects and
+/// is cheap, i.e. a literal or a name.
+fn is_pure(expr: &Ann<Expr>) -> bool {
+    !matches!(expr.0, Expr::List(_))
+}
+
+/// Returns true if the symbol with the (char) range is in a function nested
+/// in the scope of the binding, evaluated on each call.
+fn is_in_function(exprs: &[Ann<Expr>], range: &Range, binding: &Binding) -> bool {
+    find_path_at(exprs, range.start).into_iter().any(|expr| {
+        let expr_range = expr.get_range();
+        matches!(head_symbol(expr), Some("Func") | Some("Macro"))
+            && !(expr_range.start <= binding.range.start && binding.range.end <= expr_range.end)
+    })
+}
+
+/// Returns the names bound in an expression, by the nested `let` expressions
+/// and functions.
+fn bound_names_in<'a>(exprs: &[Ann<Expr>], expr: &'a Ann<Expr>) -> Vec<&'a str> {
+    let mut names = Vec::new();
+
+    for_each_expr(std::slice::from_ref(expr), &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        if resolve_local_symbol_at(exprs, range.start)
+            .is_some_and(|binding| binding.name_range == range)
+        {
+            names.push(sym.as_str());
+        }
+    });
+
+    names
+}
+
+/// Returns the first name of the inlined expression that resolves to another
+/// binding at the (char) index. The names bound in the expression, and the
+/// (substituted) parameters, are skipped.
+fn shadowed_name(
+    exprs: &[Ann<Expr>],
+    expr: &Ann<Expr>,
+    params: &[Ann<Expr>],
+    index: usize,
+) -> Option<String> {
+    let expr_range = expr.get_range();
+    let mut shadowed = None;
+
+    for_each_expr(std::slice::from_ref(expr), &mut |sym_expr| {
+        let Ann(Expr::Symbol(sym), _) = sym_expr else {
+            return;
+        };
+        let range = sym_expr.get_range();
+        if range.start >= range.end || shadowed.is_some() {
+            return;
+        }
+
+        let binding = resolve_symbol_at(exprs, range.start).map(|binding| binding.name_range);
+        let is_internal = binding.as_ref().is_some_and(|name_range| {
+            (expr_range.start <= name_range.start && name_range.end <= expr_range.end)
+                || params.iter().any(|param| param.get_range() == *name_range)
+        });
+        if is_internal {
+            return;
+        }
+
+        if binding != binding_of_name_at(exprs, sym, index) {
+            shadowed = Some(sym.clone());
+        }
+    });
+
+    shadowed
+}
+
+/// Returns the (char) range of the bound name that a name resolves to at the
+/// (char) index: a local binding, or else a module-level definition.
+fn binding_of_name_at(exprs: &[Ann<Expr>], name: &str, index: usize) -> Option<Range> {
+    if let Some((_, binding)) = visible_local_bindings(exprs, index)
+        .into_iter()
+        .find(|(bound_name, _)| same_name(bound_name, name))
+    {
+        return Some(binding.name_range);
+    }
+
+    collect_definitions(exprs)
+        .into_iter()
+        .find(|definition| same_name(&definition.name, name))
+        .map(|definition| definition.selection_range)
+}
+
+/// Returns the (char) range deleted to remove the binding: the `let` with its
+/// comments if it has a single binding, otherwise only the binding.
+fn definition_removal(
+    exprs: &[Ann<Expr>],
+    chars: &[char],
+    binding: &Binding,
+    input: &str,
+) -> Option<Range> {
+    let form = find_path_at(exprs, binding.name_range.start)
+        .into_iter()
+        .rev()
+        .find(|expr| expr.get_range() == binding.range)?;
+    let Ann(Expr::List(terms), _) = form else {
+        return None;
+    };
+    let pair = terms
+        .iter()
+        .position(|term| term.get_range() == binding.name_range)?;
+
+    if terms.len() != 3 {
+        return Some(terms[pair - 1].get_range().end..terms.get(pair + 1)?.get_range().end);
+    }
+
+    let start = exprs
+        .iter()
+        .position(|expr| expr.get_range() == binding.range)
+        .and_then(|position| {
+            attached_comments(exprs, position, input)
+                .first()
+                .map(|comment| comment.get_range().start)
+        })
+        .unwrap_or(binding.range.start);
+
+    Some(removal_range(chars, start..binding.range.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use tan::api::parse_string_all;
+
+    use super::*;
+    use crate::fixture::{apply_edits, document_uri, snapshot_of};
+
+    /// Inlines the definition at the first occurrence of `at`, in `main.tan`.
+    fn inlined(documents: &[(&str, &str)], at: &str) -> Result<String, String> {
+        let snapshot = snapshot_of(documents);
+        let input = documents[0].1;
+        let exprs = parse_string_all(input).unwrap();
+        let index = input[..input.find(at).unwrap()].chars().count();
+
+        let inlining =
+            inline_definition(&snapshot, &document_uri("main.tan"), &exprs, input, index)
+                .unwrap()?;
+        Ok(apply_edits(input, &inlining.edits))
+    }
+
+    #[test]
+    fn inline_definition_replaces_the_references_with_the_value() {
+        let input = "(let a 1)\n(writeln (+ a a))\n";
+
+        assert_eq!(
+            inlined(&[("main.tan", input)], "a 1").unwrap(),
+            "(writeln (+ 1 1))\n"
+        );
+    }
+
+    #[test]
+    fn inline_definition_substitutes_the_arguments_of_the_calls() {
+        let input = "(let double (Func (x) (* x 2)))\n(writeln (double 3))\n";
+
+        assert_eq!(
+            inlined(&[("main.tan", input)], "double 3").unwrap(),
+            "(writeln (* 3 2))\n"
+        );
+    }
+
+    #[test]
+    fn inline_definition_never_evaluates_a_value_twice() {
+        let input = "(let a (read))\n(writeln (+ a a))\n";
+
+        assert_eq!(
+            inlined(&[("main.tan", input)], "a (read)").err().unwrap(),
+            "The value of `a` would be evaluated more than once"
+        );
+    }
+
+    #[test]
+    fn inline_definition_refuses_the_shadowed_names() {
+        let input = "(let b 1)\n(let a b)\n(let f (Func (b) a))\n";
+
+        assert_eq!(
+            inlined(&[("main.tan", input)], "a b").err().unwrap(),
+            "`b` is shadowed at a reference"
+        );
+    }
+
+    #[test]
+    fn inline_definition_refuses_the_definitions_used_by_other_modules() {
+        let documents = [
+            ("main.tan", "(let a 1)\n(writeln a)\n"),
+            ("other.tan", "(use main)\n(writeln a)\n"),
+        ];
+
+        assert_eq!(
+            inlined(&documents, "a 1").err().unwrap(),
+            "`a` is used by other modules"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+mod crash;
+mod edit;
+mod evaluate;
+mod extract;
+#[cfg(test)]
+mod fixture;
+mod fuzzy;
+mod handlers;
+mod inline;
+mod lsp_ext;
+mod manifest;
+mod markup;
+mod message_log;
+mod move_item;
+mod outgoing;
+mod packages;
+mod publish;
+mod references;
+mod safe_delete;
+mod scope;
+mod server;
+mod ssr;
+mod transport;
+mod types;
+mod watcher;
+mod worker;
+mod workspace_format;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use lsp_server::{Message, Notification};
+use lsp_types::{
+    notification::{Notification as _, ShowMessage},
+    CallHierarchyServerCapability, CodeActionProviderCapability, CodeLensOptions,
+    ColorProviderCapability, CompletionOptions, DocumentLinkOptions,
+    DocumentOnTypeFormattingOptions, ExecuteCommandOptions, FileOperationFilter,
+    FileOperationPattern, FileOperationPatternKind, FileOperationRegistrationOptions,
+    FoldingRangeProviderCapability, HoverProviderCapability, MessageType, OneOf,
+    PositionEncodingKind, RenameOptions, SelectionRangeProviderCapability,
+    SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensServerCapabilities,
+    ServerCapabilities, ShowMessageParams, SignatureHelpOptions, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions,
+    WorkspaceFileOperationsServerCapabilities, WorkspaceServerCapabilities,
+};
+use message_log::MessageLog;
+use server::Server;
+// #Insight
+// The analysis modules are implemented by the `tan_lsp` crate, they are
+// imported at the root of the crate, e.g. `crate::index`, next to the modules
+// of the server.
+use tan_lsp::{
+    ast, cache, cancellation,
+    config::{self, Config},
+    diagnostics, dialect, doc_tags, embedded, format, glob, imports, indent, index, module,
+    module_graph, resolve, sandbox, snapshot, symbols, unicode, unused,
+    util::{self, PositionEncoding},
+    vfs,
+};
+use tracing::{info, warn};
+use tracing_subscriber::{filter::LevelFilter, util::SubscriberInitExt};
+
+/// Returns a filter of the file operations on the local files.
+fn file_operation_filter(glob: &str, matches: FileOperationPatternKind) -> FileOperationFilter {
+    FileOperationFilter {
+        scheme: Some("file".to_owned()),
+        pattern: FileOperationPattern {
+            glob: glob.to_owned(),
+            matches: Some(matches),
+            options: None,
+        },
+    }
+}
+
+/// Converts a list of trigger characters to owned strings.
+fn to_strings(strs: &[&str]) -> Vec<String> {
+    strs.iter().map(|s| s.to_string()).collect()
+}
+
+/// Creates the configuration from the environment variables and the
+/// command-line arguments, which take precedence.
+fn config_from_args(matches: &ArgMatches) -> Config {
+    let mut config = Config::default();
+    config.update_from_env(|name| std::env::var(name).ok());
+
+    // The analysis of the library has no default package cache, the server
+    // caches the packages in the home directory.
+    if config.package_cache_path.is_none() {
+        config.package_cache_path =
+            std::env::var_os("HOME").map(|home| Path::new(&home).join(config::PACKAGE_CACHE_DIR));
+    }
+
+    config.read_only |= matches.get_flag("read-only");
+    config.dry_run |= matches.get_flag("dry-run");
+
+    if let Some(max) = matches.get_one::<usize>("max-diagnostics") {
+        config.max_diagnostics_per_file = *max;
+    }
+
+    config
+}
+
+/// Formats the workspace files in headless mode, prints the files that are
+/// not formatted.
+fn format_workspace(mut config: Config, matches: &ArgMatches) -> anyhow::Result<()> {
+    let root_path = match matches.get_one::<String>("root") {
+        Some(root) => PathBuf::from(root),
+        None => std::env::current_dir()?,
+    };
+    let root_path = root_path.canonicalize()?;
+    config.root_path = Some(root_path.clone());
+    config.update_from_manifest();
+
+    let check = matches.get_flag("check");
+    let report = workspace_format::format_workspace_files(&config, check)?;
+
+    for path in &report.invalid {
+        warn!("cannot format `{}`, it has syntax errors", path.display());
+    }
+    for path in &report.unformatted {
+        println!(
+            "{}",
+            path.strip_prefix(&root_path).unwrap_or(path).display()
+        );
+    }
+
+    if check && !(report.unformatted.is_empty() && report.invalid.is_empty()) {
+        bail!(
+            "{} files are not formatted, {} files have syntax errors",
+            report.unformatted.len(),
+            report.invalid.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the server over stdio (or a socket), configured by the command-line arguments.
+pub fn run() -> anyhow::Result<()> {
+    let matches = Command::new("tan_lsp_server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("An LSP server for the Tan Language")
+        .arg(
+            Arg::new("stdio")
+                .long("stdio")
+                .help("Use stdio as the transport (default)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .help("Listen on a TCP address, e.g. 127.0.0.1:9257, and serve the first client")
+                .conflicts_with("stdio"),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .help("Never execute Tan code or write files, for untrusted workspaces")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Return the edits of the commands without applying them, e.g. for previews")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("log-messages")
+                .long("log-messages")
+                .value_name("MODE")
+                .help("Log the LSP messages, the redacted logs only keep the methods, URIs, positions and kinds")
+                .value_parser(["full", "redacted"]),
+        )
+        .arg(
+            Arg::new("max-diagnostics")
+                .long("max-diagnostics")
+                .value_name("N")
+                .help("The maximum number of diagnostics published per file")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .subcommand(
+            Command::new("format")
+                .about("Format the Tan files of a workspace, without starting the server")
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Only list the unformatted files, and fail if any, e.g. in CI")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("root")
+                        .value_name("ROOT")
+                        .help("The workspace root (default: the current directory)"),
+                ),
+        )
+        .subcommand(
+            Command::new(evaluate::EVAL_SUBCOMMAND)
+                .about("Evaluate the Tan source of stdin, in the sandbox of the server")
+                .hide(true)
+                .arg(
+                    Arg::new("filesystem")
+                        .long("filesystem")
+                        .value_name("ACCESS")
+                        .default_value("read"),
+                )
+                .arg(
+                    Arg::new("memory-limit")
+                        .long("memory-limit")
+                        .value_name("MIB")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .get_matches();
+
+    // The evaluation process reports the outcome on stderr, without logs.
+    if let Some(eval_matches) = matches.subcommand_matches(evaluate::EVAL_SUBCOMMAND) {
+        let filesystem = eval_matches
+            .get_one::<String>("filesystem")
+            .map_or("read", String::as_str);
+        let memory_limit = eval_matches.get_one::<u64>("memory-limit").copied();
+        return evaluate::run_evaluation(filesystem, memory_limit);
+    }
+
+    let log_level = std::env::var(config::LOG_ENV_VAR).ok();
+    let max_level = log_level
+        .as_deref()
+        .and_then(|level| level.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::INFO);
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(max_level)
+        .finish()
+        .init();
+
+    if let Some(level) = log_level.filter(|level| level.parse::<LevelFilter>().is_err()) {
+        warn!("invalid `{}` value `{level}`", config::LOG_ENV_VAR);
+    }
+
+    let config = config_from_args(&matches);
+
+    if let Some(format_matches) = matches.subcommand_matches("format") {
+        return format_workspace(config, format_matches);
+    }
+
+    let report_dir = crash::server_cache_path();
+    if let Some(report_dir) = &report_dir {
+        crash::install_panic_hook(report_dir.clone());
+    }
+    let previous_report = report_dir.as_deref().and_then(crash::take_previous_report);
+    if let Some(path) = &previous_report {
+        warn!(
+            "the server crashed in the previous session, see the crash report `{}`",
+            path.display()
+        );
+    }
+
+    info!("starting LSP server");
+
+    // Create the connection using stdio (or a socket) as the transport kind.
+    let (connection, io_threads) = match matches.get_one::<String>("listen") {
+        Some(addr) => transport::listen(addr)?,
+        None => transport::stdio(),
+    };
+
+    let message_log = matches
+        .get_one::<String>("log-messages")
+        .cloned()
+        .or_else(|| std::env::var(config::LOG_MESSAGES_ENV_VAR).ok())
+        .and_then(|mode| match mode.parse::<MessageLog>() {
+            Ok(message_log) => Some(message_log),
+            Err(error) => {
+                warn!("{error}");
+                None
+            }
+        });
+    let connection = match message_log {
+        Some(message_log) => message_log::log_connection(connection, message_log),
+        None => connection,
+    };
+
+    let (initialize_id, initialization_params) = connection.initialize_start()?;
+
+    let position_encodings = initialization_params
+        .pointer("/capabilities/general/positionEncodings")
+        .and_then(|encodings| {
+            serde_json::from_value::<Vec<PositionEncodingKind>>(encodings.clone()).ok()
+        });
+    let position_encoding = PositionEncoding::negotiate(position_encodings.as_deref());
+    util::set_position_encoding(position_encoding);
+
+    // Only the clients that support `textDocument/prepareRename` may be told
+    // about it.
+    let prepare_rename = initialization_params
+        .pointer("/capabilities/textDocument/rename/prepareSupport")
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false);
+
+    let server_capabilities = serde_json::to_value(&ServerCapabilities {
+        position_encoding: Some(position_encoding.kind()),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        document_highlight_provider: Some(OneOf::Left(true)),
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::INCREMENTAL),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                ..Default::default()
+            },
+        )),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: handlers::on_type_formatting::TRIGGER_CHARACTERS[0].to_owned(),
+            more_trigger_character: Some(to_strings(
+                &handlers::on_type_formatting::TRIGGER_CHARACTERS[1..],
+            )),
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        code_lens_provider: Some(CodeLensOptions {
+            resolve_provider: Some(true),
+        }),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(to_strings(&handlers::completion::TRIGGER_CHARACTERS)),
+            resolve_provider: Some(true),
+            ..Default::default()
+        }),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(to_strings(&handlers::signature_help::TRIGGER_CHARACTERS)),
+            retrigger_characters: Some(to_strings(&handlers::signature_help::RETRIGGER_CHARACTERS)),
+            ..Default::default()
+        }),
+        document_link_provider: Some(DocumentLinkOptions {
+            resolve_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: to_strings(&handlers::execute_command::COMMANDS),
+            work_done_progress_options: Default::default(),
+        }),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(if prepare_rename {
+            OneOf::Right(RenameOptions {
+                prepare_provider: Some(true),
+                work_done_progress_options: Default::default(),
+            })
+        } else {
+            OneOf::Left(true)
+        }),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        inlay_hint_provider: Some(OneOf::Left(true)),
+        color_provider: Some(ColorProviderCapability::Simple(true)),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+        // The imports of the renamed module files and directories are updated.
+        workspace: Some(WorkspaceServerCapabilities {
+            workspace_folders: None,
+            file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                will_rename: Some(FileOperationRegistrationOptions {
+                    filters: vec![
+                        file_operation_filter("**/*.tan", FileOperationPatternKind::File),
+                        file_operation_filter("**", FileOperationPatternKind::Folder),
+                    ],
+                }),
+                ..Default::default()
+            }),
+        }),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: handlers::semantic_tokens::legend(),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        experimental: Some(lsp_ext::experimental_capabilities()),
+        ..Default::default()
+    })
+    .unwrap();
+
+    connection.initialize_finish(
+        initialize_id,
+        serde_json::json!({ "capabilities": server_capabilities }),
+    )?;
+
+    if let Some(path) = previous_report {
+        let params = ShowMessageParams {
+            typ: MessageType::WARNING,
+            message: format!(
+                "The Tan LSP server crashed in the previous session. Please attach the crash report `{}` when reporting the issue.",
+                path.display()
+            ),
+        };
+        let notification = Notification::new(ShowMessage::METHOD.to_owned(), params);
+        connection
+            .sender
+            .send(Message::Notification(notification))?;
+    }
+
+    // Run the server.
+    if let Err(error) =
+        Server::new(connection, initialization_params, config).and_then(|mut server| server.run())
+    {
+        crash::report_error(&error);
+        return Err(error);
+    }
+
+    // Wait for the two threads to end (typically by trigger LSP Exit event).
+    io_threads.join()?;
+
+    info!("shutting down server");
+
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use lsp_types::{
+    notification::Notification, request::Request, Diagnostic, Location, Position, Range,
+    TextDocumentIdentifier, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// #Insight
+// Client extensions feature-detect the custom methods through the
+// `experimental` server capabilities, instead of checking the server version.
+
+/// The custom (non-standard) methods supported by the server, add new
+/// methods here.
+pub const METHODS: &[&str] = &[
+    Metrics::METHOD,
+    ServerStatus::METHOD,
+    ViewSyntaxTree::METHOD,
+    NodeAtPosition::METHOD,
+    TypeAtPosition::METHOD,
+    InterruptEvaluation::METHOD,
+    ValidateConfig::METHOD,
+    EmbeddedDocuments::METHOD,
+    MemoryUsage::METHOD,
+];
+
+/// Returns the `experimental` section of the server capabilities.
+pub fn experimental_capabilities() -> serde_json::Value {
+    json!({
+        "tan": {
+            "methods": METHODS,
+        }
+    })
+}
+
+/// A notification with server health metrics, sent periodically if enabled
+/// with the `metricsInterval` initialization option.
+pub enum Metrics {}
+
+impl Notification for Metrics {
+    type Params = MetricsParams;
+    const METHOD: &'static str = "tan/metrics";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsParams {
+    /// The number of documents open in the editor.
+    pub open_documents: usize,
+    /// The number of indexed files.
+    pub indexed_files: usize,
+    /// The number of indexed definitions.
+    pub indexed_definitions: usize,
+    /// The number of tasks waiting in the interactive queue.
+    pub interactive_queue_len: usize,
+    /// The number of tasks waiting in the background queue.
+    pub background_queue_len: usize,
+    /// The number of evaluations waiting in the evaluation queue.
+    pub evaluation_queue_len: usize,
+    /// The server uptime, in seconds.
+    pub uptime: u64,
+    /// The number of cache entries evicted to stay within the memory budget.
+    pub cache_evictions: u64,
+    /// The time since the last eviction of the caches, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_cache_eviction: Option<u64>,
+}
+
+/// A notification with the state of the server, sent when the state changes,
+/// e.g. when the initial indexing completes.
+pub enum ServerStatus {}
+
+/// The health of the workspace, a warning is reported e.g. for import cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Ok,
+    Warning,
+}
+
+impl Notification for ServerStatus {
+    type Params = ServerStatusParams;
+    const METHOD: &'static str = "tan/serverStatus";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatusParams {
+    /// True if the server has no pending background work, e.g. indexing.
+    pub quiescent: bool,
+    pub health: Health,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// #Insight
+// Node ids are the child-index paths from the top-level expressions, e.g.
+// `0.2.1`, they are stable for a given document version.
+
+/// A node of the syntax tree.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxNode {
+    pub id: String,
+    /// The kind of the expression, e.g. `List`, `Symbol`.
+    pub kind: String,
+    /// The text of leaf expressions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// The range of the node, `None` for synthesized nodes.
+    pub range: Option<Range>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub children: Vec<SyntaxNode>,
+}
+
+/// Returns the syntax tree of a document.
+pub enum ViewSyntaxTree {}
+
+impl Request for ViewSyntaxTree {
+    type Params = ViewSyntaxTreeParams;
+    type Result = Option<SyntaxTree>;
+    const METHOD: &'static str = "tan/viewSyntaxTree";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewSyntaxTreeParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxTree {
+    /// The version of the document, `None` if the document is not open.
+    pub version: Option<i32>,
+    pub nodes: Vec<SyntaxNode>,
+}
+
+/// Returns the innermost syntax node at a position.
+pub enum NodeAtPosition {}
+
+impl Request for NodeAtPosition {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<NodeAtPositionResult>;
+    const METHOD: &'static str = "tan/nodeAtPosition";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeAtPositionResult {
+    /// The version of the document, `None` if the document is not open.
+    pub version: Option<i32>,
+    /// The innermost node, without children.
+    pub node: SyntaxNode,
+    /// The ids of the enclosing nodes, from the top-level expression.
+    pub ancestors: Vec<String>,
+}
+
+/// Returns the type of the expression at a position, or of the smallest
+/// expression that contains the selection.
+pub enum TypeAtPosition {}
+
+impl Request for TypeAtPosition {
+    type Params = TypeAtPositionParams;
+    type Result = Option<TypeAtPositionResult>;
+    const METHOD: &'static str = "tan/typeAtPosition";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeAtPositionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    /// The selection, takes precedence over the position.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeAtPositionResult {
+    /// The type, e.g. `Int`.
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// True if the expression itself is annotated with the type, e.g.
+    /// `#Int x`, false if the type is inferred.
+    pub annotated: bool,
+    /// The range of the typed expression.
+    pub range: Range,
+}
+
+/// Returns the documents embedded in the string literals of a document, e.g.
+/// regular expressions.
+pub enum EmbeddedDocuments {}
+
+impl Request for EmbeddedDocuments {
+    type Params = EmbeddedDocumentsParams;
+    type Result = Option<Vec<EmbeddedDocument>>;
+    const METHOD: &'static str = "tan/embeddedDocuments";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedDocumentsParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// A virtual document embedded in a string literal.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedDocument {
+    /// The URI of the virtual document, with the `tan-embedded` scheme.
+    pub uri: Url,
+    /// The language of the document, e.g. `regex`, `json` or `sql`.
+    pub language_id: String,
+    /// The range of the text in the host document, without the quotes. The
+    /// positions of the virtual document are relative to the start of the
+    /// range.
+    pub range: Range,
+    pub text: String,
+}
+
+/// Interrupts the running Tan evaluation, e.g. of the eval, run and test
+/// commands.
+pub enum InterruptEvaluation {}
+
+impl Request for InterruptEvaluation {
+    type Params = ();
+    type Result = InterruptEvaluationResult;
+    const METHOD: &'static str = "tan/interruptEvaluation";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterruptEvaluationResult {
+    /// True if an evaluation was running, and was interrupted.
+    pub interrupted: bool,
+    /// True if the evaluation stopped cooperatively, false if it was killed.
+    /// Also false if no evaluation was running, nothing stopped cleanly.
+    pub clean: bool,
+}
+
+/// Returns the estimated memory usage of the caches of the server.
+pub enum MemoryUsage {}
+
+impl Request for MemoryUsage {
+    type Params = ();
+    type Result = MemoryUsageResult;
+    const METHOD: &'static str = "tan/memoryUsage";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryUsageResult {
+    pub caches: Vec<CacheMemoryUsage>,
+    /// The memory budget of the caches, in bytes.
+    pub budget: usize,
+    /// The number of cache entries evicted to stay within the budget.
+    pub evictions: u64,
+    /// The time since the last eviction, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_eviction: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheMemoryUsage {
+    /// The name of the cache, e.g. `ast`.
+    pub name: String,
+    pub entries: usize,
+    /// The estimated size of the entries, in bytes.
+    pub size: usize,
+}
+
+/// Validates the manifest of the workspace, the diagnostics are also published
+/// against the manifest file.
+pub enum ValidateConfig {}
+
+impl Request for ValidateConfig {
+    type Params = ();
+    type Result = ValidateConfigResult;
+    const METHOD: &'static str = "tan/validateConfig";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateConfigResult {
+    /// The uri of the manifest, `None` if the workspace has no manifest.
+    pub uri: Option<Url>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A definition reported by the `tan.unusedPublicSymbols` command.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnusedSymbol {
+    pub name: String,
+    pub location: Location,
+}
+
+/// The reference counts of a name, by context.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageCounts {
+    pub total: usize,
+    /// The references in the head of a list, e.g. `(add 1 2)`.
+    pub calls: usize,
+    /// The references as a value, e.g. an argument.
+    pub values: usize,
+    /// The references as the value of a top-level `let`, e.g. `(let plus add)`.
+    pub re_exports: usize,
+}
+
+/// The reference counts of a name in a file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileUsage {
+    pub uri: Url,
+    pub module: String,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+/// The reference counts of a name in a module.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleUsage {
+    pub module: String,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+/// The result of the `tan.symbolStats` command, the files and the modules are
+/// sorted by decreasing reference count.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolStats {
+    pub name: String,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+    pub files: Vec<FileUsage>,
+    pub modules: Vec<ModuleUsage>,
+}
+
+/// The result of the `tan.formatWorkspace` command.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatWorkspaceResult {
+    pub edit: WorkspaceEdit,
+    /// The files with syntax errors, that are not formatted.
+    pub skipped: Vec<Url>,
+}
+
+/// The result of the `tan.evaluate` command.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluateResult {
+    /// The value of the form, `None` if the evaluation failed.
+    pub value: Option<String>,
+    /// The output of the evaluation, e.g. of `writeln`.
+    pub output: String,
+    /// The error, e.g. an undefined symbol, or the exceeded time limit.
+    pub error: Option<String>,
+}
+
+/// The result of the `tan.safeDelete` command, either the edit that deletes
+/// the definition, or the references that prevent the deletion.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeDeleteResult {
+    pub edit: Option<WorkspaceEdit>,
+    pub blockers: Vec<Location>,
+}
+
+/// The result of the `tan.moveItemUp` and `tan.moveItemDown` commands.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveItemResult {
+    pub edits: Vec<TextEdit>,
+    /// The position of the cursor, after the edits are applied.
+    pub cursor: Position,
+}
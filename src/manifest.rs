@@ -0,0 +1,833 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Diagnostic, DiagnosticSeverity,
+    Documentation, TextEdit,
+};
+
+use crate::{
+    config::{Config, MANIFEST_FILE},
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// The `toml` tables do not keep the positions of the keys, the keys are
+// located in the text line by line. Keys in inline tables are reported at the
+// position of the enclosing key.
+
+/// The kind of the value of a manifest key.
+#[derive(Debug, Clone, Copy)]
+pub enum ValueKind {
+    String,
+    Integer,
+    /// One of the strings.
+    OneOf(&'static [&'static str]),
+    /// An array of directory paths, relative to the workspace root.
+    Paths,
+    /// A version constraint, e.g. `^1.2`.
+    Version,
+    /// An array of glob patterns, relative to the workspace root.
+    Globs,
+}
+
+impl ValueKind {
+    /// Describes the expected values, e.g. in diagnostics.
+    fn describe(&self) -> String {
+        match self {
+            ValueKind::String => "a string".to_owned(),
+            ValueKind::Integer => "a positive integer".to_owned(),
+            ValueKind::OneOf(values) => {
+                let values: Vec<String> = values.iter().map(|v| format!("`{v}`")).collect();
+                format!("one of {}", values.join(", "))
+            }
+            ValueKind::Paths => "an array of paths".to_owned(),
+            ValueKind::Version => "a version constraint, e.g. `^1.2`".to_owned(),
+            ValueKind::Globs => "an array of glob patterns".to_owned(),
+        }
+    }
+}
+
+/// A key of the manifest, e.g. `line-width` in the `format` table.
+#[derive(Debug, Clone, Copy)]
+pub struct ManifestKey {
+    pub table: &'static str,
+    pub name: &'static str,
+    pub kind: ValueKind,
+    pub doc: &'static str,
+}
+
+/// The table of the dependencies, the keys other than `paths` are the names
+/// of the dependencies.
+const DEPENDENCIES_TABLE: &str = "dependencies";
+
+/// The key of a dependency, the name is the key in the manifest.
+const DEPENDENCY_KEY: ManifestKey = ManifestKey {
+    table: DEPENDENCIES_TABLE,
+    name: "",
+    kind: ValueKind::Version,
+    doc: "A dependency of the package, with a version constraint.",
+};
+
+/// The keys of the manifest, add new keys here.
+pub const MANIFEST_KEYS: [ManifestKey; 10] = [
+    ManifestKey {
+        table: "package",
+        name: "name",
+        kind: ValueKind::String,
+        doc: "The name of the package.",
+    },
+    ManifestKey {
+        table: "package",
+        name: "version",
+        kind: ValueKind::String,
+        doc: "The version of the package.",
+    },
+    ManifestKey {
+        table: "package",
+        name: "description",
+        kind: ValueKind::String,
+        doc: "A short description of the package.",
+    },
+    ManifestKey {
+        table: "package",
+        name: "license",
+        kind: ValueKind::String,
+        doc: "The SPDX license identifier of the package. New files get a license notice header.",
+    },
+    ManifestKey {
+        table: "template",
+        name: "header",
+        kind: ValueKind::String,
+        doc: "The header of new files, overrides the license notice.",
+    },
+    ManifestKey {
+        table: "format",
+        name: "line-width",
+        kind: ValueKind::Integer,
+        doc: "The maximum line width of the formatter (default: 80).",
+    },
+    ManifestKey {
+        table: "format",
+        name: "wrap",
+        kind: ValueKind::OneOf(&["onePerLine", "one-per-line", "fill"]),
+        doc: "How the arguments of the calls that do not fit in a line are wrapped.",
+    },
+    ManifestKey {
+        table: "format",
+        name: "ignore",
+        kind: ValueKind::Globs,
+        doc: "The glob patterns of the files skipped by the workspace formatting, e.g. `generated/**`.",
+    },
+    ManifestKey {
+        table: "dialects",
+        name: "data",
+        kind: ValueKind::Globs,
+        doc: "The glob patterns of the data files, e.g. `config/**`. The lints of the code are skipped.",
+    },
+    ManifestKey {
+        table: DEPENDENCIES_TABLE,
+        name: "paths",
+        kind: ValueKind::Paths,
+        doc: "Additional dependency directories, relative to the workspace root.",
+    },
+];
+
+/// Returns the schema of a key of a table, `None` for unknown keys.
+fn find_key(table: &str, name: &str) -> Option<ManifestKey> {
+    MANIFEST_KEYS
+        .iter()
+        .find(|key| key.table == table && key.name == name)
+        .copied()
+        .or_else(|| (table == DEPENDENCIES_TABLE).then_some(DEPENDENCY_KEY))
+}
+
+/// Returns the tables of the manifest, in schema order.
+fn tables() -> Vec<&'static str> {
+    let mut tables: Vec<&str> = MANIFEST_KEYS.iter().map(|key| key.table).collect();
+    tables.dedup();
+    tables
+}
+
+/// The operators of the version constraints, the longest first.
+const VERSION_OPERATORS: [&str; 7] = [">=", "<=", ">", "<", "=", "^", "~"];
+
+/// Returns the syntax error of a version constraint, e.g. `^1.2` or
+/// `>=1.0, <2`, `None` if the constraint is valid.
+pub fn version_constraint_error(constraint: &str) -> Option<String> {
+    if constraint.trim().is_empty() {
+        return Some("empty version constraint".to_owned());
+    }
+
+    constraint.split(',').map(str::trim).find_map(|comparator| {
+        if comparator == "*" {
+            return None;
+        }
+        let version = VERSION_OPERATORS
+            .iter()
+            .find_map(|op| comparator.strip_prefix(op))
+            .unwrap_or(comparator)
+            .trim_start();
+        (!is_version(version))
+            .then(|| format!("invalid version constraint `{comparator}`, expected e.g. `^1.2.3`"))
+    })
+}
+
+/// Returns the constraint that requires at least the version, keeping the
+/// operator of a constraint, e.g. `~1.2` to `~1.4.0`. A compound constraint is
+/// replaced with a caret requirement.
+pub fn update_constraint(constraint: &str, version: &str) -> String {
+    let constraint = constraint.trim();
+    let operator = match constraint.contains(',') {
+        true => None,
+        false => VERSION_OPERATORS
+            .iter()
+            .find(|op| constraint.starts_with(**op)),
+    };
+
+    format!("{}{version}", operator.unwrap_or(&"^"))
+}
+
+/// Returns true if the text is a (partial) version, e.g. `1`, `1.2.*` or
+/// `1.2.3-beta.1+build`.
+fn is_version(version: &str) -> bool {
+    let is_identifier = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+    };
+
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+
+    let parts: Vec<&str> = core.split('.').collect();
+
+    (1..=3).contains(&parts.len())
+        && parts.iter().enumerate().all(|(i, part)| {
+            (!part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+                || (i > 0 && matches!(*part, "*" | "x"))
+        })
+        && pre.is_none_or(is_identifier)
+        && build.is_none_or(is_identifier)
+}
+
+/// Returns the names of the dependencies available in the dependency
+/// directories, with their directory, sorted by name.
+fn available_dependencies(config: &Config) -> Vec<(String, PathBuf)> {
+    let mut dependencies: Vec<(String, PathBuf)> = Vec::new();
+
+    for dir in config.dependency_paths() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.is_dir() && !name.starts_with('.') {
+                dependencies.push((name.to_owned(), path.clone()));
+            }
+        }
+    }
+
+    // The first directory that provides a name wins.
+    dependencies.sort_by(|(a, _), (b, _)| a.cmp(b));
+    dependencies.dedup_by(|(a, _), (b, _)| a == b);
+
+    dependencies
+}
+
+/// Returns the name of the table of a header line, e.g. `format` for
+/// `[format]`.
+fn table_name(header: &str) -> &str {
+    header
+        .trim_start_matches('[')
+        .split(']')
+        .next()
+        .unwrap_or("")
+        .trim()
+}
+
+/// Returns the table and the name of a key of a table. A dotted key of the
+/// root table, e.g. `format.wrap = "fill"`, is a key of the `format` table.
+fn split_key<'a>(table: &str, key: &'a str) -> (String, &'a str) {
+    let key = key.trim_matches('"');
+
+    match key.split_once('.') {
+        Some((key_table, key)) if table.is_empty() => (key_table.to_owned(), key),
+        _ => (table.to_owned(), key),
+    }
+}
+
+/// Returns the (char) ranges of the keys of the manifest text, by table and
+/// key. The range of a table header is stored with an empty key.
+fn key_ranges(text: &str) -> HashMap<(String, String), Range<usize>> {
+    let mut ranges = HashMap::new();
+    let mut table = String::new();
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.chars().count();
+
+        let code = line.trim_start();
+        let indent = line.chars().count() - code.chars().count();
+        let start = line_start + indent;
+
+        if code.starts_with('[') {
+            table = table_name(code).to_owned();
+            let end = start + code.trim_end().chars().count();
+            ranges
+                .entry((table.clone(), String::new()))
+                .or_insert(start..end);
+            continue;
+        }
+
+        if code.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, _)) = code.split_once('=') else {
+            continue;
+        };
+        let key = key.trim_end();
+        let end = start + key.chars().count();
+        let (key_table, key) = split_key(&table, key);
+
+        ranges
+            .entry((key_table, key.to_owned()))
+            .or_insert(start..end);
+    }
+
+    ranges
+}
+
+fn diagnostic(
+    range: &Range<usize>,
+    text: &str,
+    severity: DiagnosticSeverity,
+    message: String,
+) -> Diagnostic {
+    Diagnostic {
+        range: lsp_range_from_range(range, text),
+        severity: Some(severity),
+        source: Some(MANIFEST_FILE.to_owned()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Validates the text of the manifest: the syntax, the unknown tables and keys,
+/// the types of the values, and the paths relative to the workspace root.
+pub fn validate_manifest(text: &str, root_path: &Path) -> Vec<Diagnostic> {
+    let manifest: toml::Table = match toml::from_str(text) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            // The span of the error is a byte range.
+            let range = error.span().map_or(0..0, |span| {
+                let start = text.get(..span.start).map_or(0, |s| s.chars().count());
+                let end = text.get(..span.end).map_or(start, |s| s.chars().count());
+                start..end
+            });
+            return vec![diagnostic(
+                &range,
+                text,
+                DiagnosticSeverity::ERROR,
+                error.message().to_owned(),
+            )];
+        }
+    };
+
+    let ranges = key_ranges(text);
+    let range_of = |table: &str, key: &str| {
+        ranges
+            .get(&(table.to_owned(), key.to_owned()))
+            .or_else(|| ranges.get(&(table.to_owned(), String::new())))
+            .cloned()
+            .unwrap_or(0..0)
+    };
+
+    let mut diagnostics = Vec::new();
+
+    for (table_name, value) in &manifest {
+        let is_known_table = MANIFEST_KEYS.iter().any(|key| key.table == table_name);
+        let Some(table) = value.as_table().filter(|_| is_known_table) else {
+            diagnostics.push(diagnostic(
+                &range_of(table_name, ""),
+                text,
+                DiagnosticSeverity::WARNING,
+                format!("unknown section `{table_name}`"),
+            ));
+            continue;
+        };
+
+        for (name, value) in table {
+            let range = range_of(table_name, name);
+            let Some(key) = find_key(table_name, name) else {
+                diagnostics.push(diagnostic(
+                    &range,
+                    text,
+                    DiagnosticSeverity::WARNING,
+                    format!("unknown key `{name}` in `[{table_name}]`"),
+                ));
+                continue;
+            };
+
+            let error =
+                |message: String| diagnostic(&range, text, DiagnosticSeverity::ERROR, message);
+            let invalid = || {
+                error(format!(
+                    "`{table_name}.{name}` must be {}",
+                    key.kind.describe()
+                ))
+            };
+
+            match key.kind {
+                ValueKind::String if !value.is_str() => diagnostics.push(invalid()),
+                ValueKind::Integer if value.as_integer().is_none_or(|n| n <= 0) => {
+                    diagnostics.push(invalid());
+                }
+                ValueKind::OneOf(values)
+                    if !value.as_str().is_some_and(|v| values.contains(&v)) =>
+                {
+                    diagnostics.push(invalid());
+                }
+                ValueKind::Version => match value.as_str() {
+                    Some(constraint) => {
+                        if let Some(message) = version_constraint_error(constraint) {
+                            diagnostics.push(error(message));
+                        }
+                    }
+                    None => diagnostics.push(invalid()),
+                },
+                ValueKind::Globs
+                    if !value
+                        .as_array()
+                        .is_some_and(|patterns| patterns.iter().all(|p| p.is_str())) =>
+                {
+                    diagnostics.push(invalid());
+                }
+                ValueKind::Paths => {
+                    let Some(paths) = value.as_array().and_then(|paths| {
+                        paths.iter().map(|p| p.as_str()).collect::<Option<Vec<_>>>()
+                    }) else {
+                        diagnostics.push(invalid());
+                        continue;
+                    };
+                    for path in paths
+                        .into_iter()
+                        .filter(|path| !root_path.join(path).is_dir())
+                    {
+                        diagnostics.push(diagnostic(
+                            &range,
+                            text,
+                            DiagnosticSeverity::WARNING,
+                            format!("the directory `{path}` does not exist"),
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+
+    diagnostics
+}
+
+/// A dependency entry of the manifest, e.g. `json = "^1.2"`.
+pub struct DependencyEntry {
+    pub name: String,
+    /// The version constraint, without the quotes.
+    pub constraint: String,
+    /// The (char) range of the key.
+    pub key_range: Range<usize>,
+    /// The (char) range of the value, including the quotes.
+    pub value_range: Range<usize>,
+}
+
+/// Returns the dependency entries of the manifest text, in text order. The
+/// entries with a value that is not a string are skipped.
+pub fn dependency_entries(text: &str) -> Vec<DependencyEntry> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.chars().count();
+
+        let code = line.trim_start();
+        if code.starts_with('[') {
+            table = table_name(code).to_owned();
+            continue;
+        }
+
+        if code.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = code.split_once('=') else {
+            continue;
+        };
+        let (key_table, name) = split_key(&table, key.trim_end());
+        // The dependencies are the keys with a version constraint, e.g. not
+        // `paths`.
+        let is_dependency = key_table == DEPENDENCIES_TABLE
+            && matches!(
+                find_key(&key_table, name).map(|key| key.kind),
+                Some(ValueKind::Version)
+            );
+        if !is_dependency {
+            continue;
+        }
+
+        let Some(constraint) = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|value| value.split_once('"'))
+            .map(|(constraint, _)| constraint)
+        else {
+            continue;
+        };
+
+        let key_start = line_start + (line.chars().count() - code.chars().count());
+        let value_start = key_start
+            + key.chars().count()
+            + 1
+            + (value.chars().count() - value.trim_start().chars().count());
+
+        entries.push(DependencyEntry {
+            name: name.to_owned(),
+            constraint: constraint.to_owned(),
+            key_range: key_start..key_start + key.trim_end().chars().count(),
+            value_range: value_start..value_start + constraint.chars().count() + 2,
+        });
+    }
+
+    entries
+}
+
+/// The line of a position in the manifest text.
+struct Line {
+    /// The table of the line, empty for the root table.
+    table: String,
+    text: String,
+    /// The (char) index of the start of the line.
+    start: usize,
+    /// The (char) index of the first non-whitespace char of the line.
+    code_start: usize,
+    /// The (char) offset of the position in the line.
+    offset: usize,
+}
+
+fn line_at(text: &str, index: usize) -> Line {
+    let chars: Vec<char> = text.chars().collect();
+    let index = index.min(chars.len());
+
+    let start = chars[..index]
+        .iter()
+        .rposition(|&c| c == '\n')
+        .map_or(0, |i| i + 1);
+    let end = chars[index..]
+        .iter()
+        .position(|&c| c == '\n')
+        .map_or(chars.len(), |i| index + i);
+
+    let before: String = chars[..start].iter().collect();
+    let table = before
+        .lines()
+        .rev()
+        .map(str::trim_start)
+        .find(|line| line.starts_with('['))
+        .map_or("", table_name)
+        .to_owned();
+
+    let line: String = chars[start..end].iter().collect();
+    let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+
+    Line {
+        table,
+        text: line,
+        start,
+        code_start: start + indent,
+        offset: index - start,
+    }
+}
+
+/// Returns the completions at the (char) index of the manifest text: the
+/// tables, the keys of the table (and the available dependencies), or the
+/// values of the key.
+pub fn manifest_completion(text: &str, index: usize, config: &Config) -> Vec<CompletionItem> {
+    let line = line_at(text, index);
+    let prefix: String = line.text.chars().take(line.offset).collect();
+    let code = prefix.trim_start();
+
+    let item = |label: &str, kind, replaced: Range<usize>, new_text: String| CompletionItem {
+        label: label.to_owned(),
+        kind: Some(kind),
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+            lsp_range_from_range(&replaced, text),
+            new_text,
+        ))),
+        ..Default::default()
+    };
+
+    // The tables, in a header or in the root table.
+    if code.starts_with('[') || (line.table.is_empty() && !code.contains('=')) {
+        let (replaced, open) = match code.starts_with('[') {
+            true => (line.code_start + 1..index, ""),
+            false => (line.code_start..index, "["),
+        };
+        return tables()
+            .into_iter()
+            .map(|table| {
+                let new_text = format!("{open}{table}]");
+                item(
+                    table,
+                    CompletionItemKind::MODULE,
+                    replaced.clone(),
+                    new_text,
+                )
+            })
+            .collect();
+    }
+
+    // The values of the key.
+    if let Some((key, value)) = code.split_once('=') {
+        let (table, name) = split_key(&line.table, key.trim());
+        let Some(ValueKind::OneOf(values)) = find_key(&table, name).map(|key| key.kind) else {
+            return Vec::new();
+        };
+        let value_prefix = value.trim_start().chars().count();
+        return values
+            .iter()
+            .map(|value| {
+                item(
+                    value,
+                    CompletionItemKind::ENUM_MEMBER,
+                    index - value_prefix..index,
+                    format!("\"{value}\""),
+                )
+            })
+            .collect();
+    }
+
+    let replaced = line.code_start..index;
+
+    let mut items: Vec<CompletionItem> = MANIFEST_KEYS
+        .iter()
+        .filter(|key| key.table == line.table)
+        .map(|key| CompletionItem {
+            detail: Some(key.kind.describe()),
+            documentation: Some(Documentation::String(key.doc.to_owned())),
+            ..item(
+                key.name,
+                CompletionItemKind::PROPERTY,
+                replaced.clone(),
+                format!("{} = ", key.name),
+            )
+        })
+        .collect();
+
+    if line.table == DEPENDENCIES_TABLE {
+        items.extend(
+            available_dependencies(config)
+                .into_iter()
+                .map(|(name, path)| CompletionItem {
+                    detail: Some(path.display().to_string()),
+                    ..item(
+                        &name,
+                        CompletionItemKind::MODULE,
+                        replaced.clone(),
+                        format!("{name} = \"*\""),
+                    )
+                }),
+        );
+    }
+
+    items
+}
+
+/// Returns the documentation (markdown) of the key at the (char) index of the
+/// manifest text, with the (char) range of the key.
+pub fn manifest_hover(text: &str, index: usize, config: &Config) -> Option<(String, Range<usize>)> {
+    let line = line_at(text, index);
+    let code = line.text.trim_start();
+
+    if code.starts_with('[') || code.starts_with('#') {
+        return None;
+    }
+
+    let (key, _) = code.split_once('=')?;
+    let key = key.trim_end();
+    let range = line.code_start..line.code_start + key.chars().count();
+    if !(range.start..=range.end).contains(&(line.start + line.offset)) {
+        return None;
+    }
+
+    let (table, name) = split_key(&line.table, key);
+    let schema = find_key(&table, name)?;
+
+    let mut markdown = format!(
+        "`{table}.{name}`: {}\n\n{}",
+        schema.kind.describe(),
+        schema.doc
+    );
+
+    if let ValueKind::Version = schema.kind {
+        match available_dependencies(config)
+            .into_iter()
+            .find(|(dependency, _)| dependency == name)
+        {
+            Some((_, path)) => markdown.push_str(&format!("\n\nFound in `{}`.", path.display())),
+            None => markdown.push_str("\n\nNot found in the dependency directories."),
+        }
+    }
+
+    Some((markdown, range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "[package]\nname = \"demo\"\nauthor = \"Ada\"\n\n[format]\nline-width = 0\nwrap = \"fill\"\n\n[dependencies]\njson = \"^1.2\"\nhttp = \">=1.0, ~x\"\n";
+
+    fn messages(text: &str) -> Vec<(u32, String)> {
+        validate_manifest(text, Path::new("/workspace"))
+            .into_iter()
+            .map(|d| (d.range.start.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn version_constraint_error_accepts_the_constraints() {
+        for constraint in [
+            "^1.2",
+            "~1.2.3",
+            ">=1.0, <2",
+            "1.2.*",
+            "*",
+            "=1.0.0-beta.1+build",
+        ] {
+            assert_eq!(version_constraint_error(constraint), None, "{constraint}");
+        }
+        for constraint in ["", "^", "1.2.3.4", "^a.b", ">=1.0,", "*.1"] {
+            assert!(
+                version_constraint_error(constraint).is_some(),
+                "{constraint}"
+            );
+        }
+    }
+
+    #[test]
+    fn update_constraint_keeps_the_operator() {
+        assert_eq!(update_constraint("~1.2", "1.4.0"), "~1.4.0");
+        assert_eq!(update_constraint("1.2", "1.4.0"), "^1.4.0");
+        assert_eq!(update_constraint(">=1.0, <2", "1.4.0"), "^1.4.0");
+    }
+
+    #[test]
+    fn validate_manifest_reports_the_keys_and_the_values() {
+        assert_eq!(
+            messages(MANIFEST),
+            [
+                (2, "unknown key `author` in `[package]`".to_owned()),
+                (
+                    5,
+                    "`format.line-width` must be a positive integer".to_owned()
+                ),
+                (
+                    10,
+                    "invalid version constraint `~x`, expected e.g. `^1.2.3`".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_manifest_reports_the_syntax_errors() {
+        let diagnostics = validate_manifest("[package]\nname = \n", Path::new("/workspace"));
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].range.start.line, 1);
+    }
+
+    #[test]
+    fn validate_manifest_reports_the_dotted_keys_in_their_table() {
+        assert_eq!(
+            messages("format.wrap = \"none\"\n"),
+            [(
+                0,
+                "`format.wrap` must be one of `onePerLine`, `one-per-line`, `fill`".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn dependency_entries_locate_the_constraints() {
+        let entries = dependency_entries(MANIFEST);
+        let chars: Vec<char> = MANIFEST.chars().collect();
+        let text = |range: &Range<usize>| chars[range.clone()].iter().collect::<String>();
+
+        let found: Vec<(String, String, String)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    text(&entry.key_range),
+                    entry.constraint.clone(),
+                    text(&entry.value_range),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("json".to_owned(), "^1.2".to_owned(), "\"^1.2\"".to_owned()),
+                (
+                    "http".to_owned(),
+                    ">=1.0, ~x".to_owned(),
+                    "\">=1.0, ~x\"".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_completion_offers_the_keys_of_the_table() {
+        let text = "[format]\nli";
+        let items = manifest_completion(text, text.chars().count(), &Config::default());
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+
+        assert_eq!(labels, ["line-width", "wrap", "ignore"]);
+    }
+
+    #[test]
+    fn manifest_completion_offers_the_values_of_the_key() {
+        let text = "[format]\nwrap = ";
+        let items = manifest_completion(text, text.chars().count(), &Config::default());
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+
+        assert_eq!(labels, ["onePerLine", "one-per-line", "fill"]);
+    }
+
+    #[test]
+    fn manifest_hover_documents_the_key() {
+        let (markdown, range) = manifest_hover(MANIFEST, 66, &Config::default()).unwrap();
+
+        assert!(markdown.starts_with("`format.wrap`: one of"));
+        assert_eq!(range, 64..68);
+    }
+}
@@ -0,0 +1,84 @@
+use lsp_types::{MarkupContent, MarkupKind};
+
+// #Insight
+// The server composes markdown, which is converted to plain text for clients
+// that don't render markdown.
+
+/// Returns the preferred markup kind of the client, from the ordered list of
+/// supported content formats. Defaults to markdown.
+pub fn preferred_markup_kind(content_format: Option<&[MarkupKind]>) -> MarkupKind {
+    content_format
+        .and_then(|formats| formats.first())
+        .cloned()
+        .unwrap_or(MarkupKind::Markdown)
+}
+
+/// Converts markdown text to markup content of the given kind.
+pub fn markup_content(kind: MarkupKind, markdown: &str) -> MarkupContent {
+    let value = match kind {
+        MarkupKind::Markdown => sanitize_markdown(markdown),
+        MarkupKind::PlainText => markdown_to_plain_text(markdown),
+    };
+
+    MarkupContent { kind, value }
+}
+
+/// Escapes raw HTML outside of code spans, so that e.g. doc comments cannot
+/// inject markup in clients with limited renderers.
+pub fn sanitize_markdown(markdown: &str) -> String {
+    let mut output = String::with_capacity(markdown.len());
+    let mut in_code = false;
+
+    for ch in markdown.chars() {
+        match ch {
+            '`' => {
+                in_code = !in_code;
+                output.push(ch);
+            }
+            '<' if !in_code => output.push_str("&lt;"),
+            '>' if !in_code => output.push_str("&gt;"),
+            _ => output.push(ch),
+        }
+    }
+
+    output
+}
+
+/// Converts the markdown links of a line, e.g. `[label](url)`, to
+/// `label (url)`.
+fn plain_text_links(line: &str) -> String {
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find('[') {
+        let Some((label, after)) = rest[start + 1..].split_once("](") else {
+            break;
+        };
+        let Some((url, after)) = after.split_once(')') else {
+            break;
+        };
+
+        output.push_str(&rest[..start]);
+        output.push_str(&format!("{label} ({url})"));
+        rest = after;
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Strips the markdown emphasis and code markers.
+pub fn markdown_to_plain_text(markdown: &str) -> String {
+    markdown
+        .lines()
+        .map(|line| plain_text_links(line).replace("**", "").replace('`', ""))
+        .map(|line| {
+            // Emphasized lines, e.g. `*No exported symbols.*`
+            match line.strip_prefix('*').and_then(|l| l.strip_suffix('*')) {
+                Some(line) => line.to_owned(),
+                None => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
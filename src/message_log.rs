@@ -0,0 +1,187 @@
+use std::{str::FromStr, thread};
+
+use crossbeam_channel::unbounded;
+use lsp_server::{Connection, Message};
+use serde_json::Value;
+use tracing::{info, warn};
+
+// #Insight
+// The messages are logged between the transport and the server, in both
+// directions, including the responses sent by the workers. The redacted logs
+// keep the structure of the messages (methods, URIs, positions, kinds), e.g.
+// to share a trace of a proprietary codebase in a bug report. Any other string
+// may contain code, e.g. the diagnostic messages, the completion labels or
+// the output of an evaluation, only its length is logged.
+
+/// The keys of the strings that are kept in the redacted logs.
+const STRUCTURAL_KEYS: [&str; 9] = [
+    "jsonrpc",
+    "id",
+    "method",
+    "uri",
+    "oldUri",
+    "newUri",
+    "targetUri",
+    "kind",
+    "range",
+];
+
+/// What is logged of the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageLog {
+    /// The full messages.
+    Full,
+    /// The structure of the messages, without the strings that may contain
+    /// code.
+    Redacted,
+}
+
+impl FromStr for MessageLog {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "redacted" => Ok(Self::Redacted),
+            _ => Err(format!(
+                "invalid message log `{s}`, expected `full` or `redacted`"
+            )),
+        }
+    }
+}
+
+impl MessageLog {
+    /// Returns the logged text of a message.
+    pub fn format(self, msg: &Message) -> String {
+        let mut value = match serde_json::to_value(msg) {
+            Ok(value) => value,
+            Err(error) => return format!("<unserializable message: {error}>"),
+        };
+
+        if self == Self::Redacted {
+            redact(&mut value, false);
+        }
+
+        value.to_string()
+    }
+}
+
+/// Wraps a connection, to log the messages in both directions.
+pub fn log_connection(connection: Connection, log: MessageLog) -> Connection {
+    let (incoming_sender, incoming_receiver) = unbounded::<Message>();
+    let (outgoing_sender, outgoing_receiver) = unbounded::<Message>();
+
+    let receiver = connection.receiver;
+    thread::spawn(move || {
+        for msg in receiver {
+            info!("<- {}", log.format(&msg));
+            if incoming_sender.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    let sender = connection.sender;
+    thread::spawn(move || {
+        for msg in outgoing_receiver {
+            info!("-> {}", log.format(&msg));
+            if sender.send(msg).is_err() {
+                warn!("cannot send a message, the transport is closed");
+                break;
+            }
+        }
+    });
+
+    Connection {
+        sender: outgoing_sender,
+        receiver: incoming_receiver,
+    }
+}
+
+/// Redacts a JSON value in place: the strings of the structural keys are
+/// kept, the other strings are replaced by their length. The numbers, e.g. the
+/// positions, are kept.
+fn redact(value: &mut Value, is_structural: bool) {
+    match value {
+        Value::String(s) if !is_structural => {
+            *s = format!("<redacted: {} chars>", s.chars().count());
+        }
+        Value::Array(values) => {
+            for value in values {
+                redact(value, is_structural);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                redact(value, STRUCTURAL_KEYS.contains(&key.as_str()));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_server::{Notification, Response};
+    use serde_json::json;
+
+    use super::*;
+
+    fn redacted(msg: Message) -> Value {
+        serde_json::from_str(&MessageLog::Redacted.format(&msg)).unwrap()
+    }
+
+    #[test]
+    fn redaction_keeps_the_structure() {
+        let params = json!({
+            "textDocument": { "uri": "file:///src/main.tan", "version": 3 },
+            "position": { "line": 1, "character": 4 },
+        });
+        let msg = Message::Request(lsp_server::Request::new(
+            7.into(),
+            "textDocument/hover".to_owned(),
+            params.clone(),
+        ));
+
+        assert_eq!(redacted(msg)["params"], params);
+    }
+
+    #[test]
+    fn redaction_removes_the_messages_and_labels() {
+        let params = json!({
+            "uri": "file:///src/main.tan",
+            "diagnostics": [{
+                "range": { "start": { "line": 0, "character": 5 }, "end": { "line": 0, "character": 11 } },
+                "severity": 2,
+                "message": "`secret` is never used",
+            }],
+        });
+        let msg = Message::Notification(Notification::new(
+            "textDocument/publishDiagnostics".to_owned(),
+            params,
+        ));
+
+        let value = redacted(msg);
+        let diagnostic = &value["params"]["diagnostics"][0];
+        assert_eq!(value["params"]["uri"], "file:///src/main.tan");
+        assert_eq!(diagnostic["range"]["end"]["character"], 11);
+        assert_eq!(diagnostic["message"], "<redacted: 22 chars>");
+    }
+
+    #[test]
+    fn redaction_removes_the_results() {
+        let result = json!([
+            { "label": "secret-fn", "detail": "(Func (token))", "kind": 3 },
+            { "value": "42", "output": "token=\"abc\"\n", "error": null },
+        ]);
+        let msg = Message::Response(Response::new_ok(1.into(), result));
+
+        let value = redacted(msg);
+        assert_eq!(value["result"][0]["label"], "<redacted: 9 chars>");
+        assert_eq!(value["result"][0]["detail"], "<redacted: 14 chars>");
+        assert_eq!(value["result"][0]["kind"], 3);
+        assert_eq!(value["result"][1]["output"], "<redacted: 12 chars>");
+        assert_eq!(value["result"][1]["error"], Value::Null);
+        assert!(!value.to_string().contains("abc"));
+    }
+}
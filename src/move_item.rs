@@ -0,0 +1,190 @@
+use tan::{ann::Ann, expr::Expr};
+
+use crate::{
+    ast::{find_path_at, head_symbol},
+    module::is_separated,
+};
+
+// #Insight
+// Top-level forms move with their attached (doc) comments, the text between
+// the swapped items is preserved.
+
+/// The direction of a move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// The result of a move, the (char) range `start..end` is replaced with the
+/// text, `cursor` is the new (char) index of the cursor.
+pub struct Move {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    pub cursor: usize,
+}
+
+/// Returns the (char) spans of the top-level forms, including the attached
+/// comments.
+fn top_level_spans(exprs: &[Ann<Expr>], input: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut comments: Vec<&Ann<Expr>> = Vec::new();
+
+    for expr in exprs {
+        if let Ann(Expr::Comment(_), _) = expr {
+            if comments
+                .last()
+                .is_some_and(|last| is_separated(last, expr, input))
+            {
+                comments.clear();
+            }
+            comments.push(expr);
+            continue;
+        }
+
+        let start = match comments.last() {
+            Some(last) if !is_separated(last, expr, input) => comments[0].get_range().start,
+            _ => expr.get_range().start,
+        };
+        comments.clear();
+
+        spans.push((start, expr.get_range().end));
+    }
+
+    spans
+}
+
+/// Returns the (char) spans of the bindings of the innermost `let` expression
+/// with multiple bindings that contains the index.
+fn binding_spans(exprs: &[Ann<Expr>], index: usize) -> Option<Vec<(usize, usize)>> {
+    let path = find_path_at(exprs, index);
+
+    let let_expr = path.iter().rev().find(|expr| {
+        head_symbol(expr) == Some("let")
+            && matches!(expr, Ann(Expr::List(terms), _) if terms.len() >= 5)
+    })?;
+
+    let Ann(Expr::List(terms), _) = let_expr else {
+        return None;
+    };
+
+    let spans: Vec<(usize, usize)> = terms[1..]
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [name, value] => Some((name.get_range().start, value.get_range().end)),
+            _ => None,
+        })
+        .collect();
+
+    // Only move bindings if the index is inside a binding, not the `let`.
+    spans
+        .iter()
+        .any(|(start, end)| *start <= index && index < *end)
+        .then_some(spans)
+}
+
+/// Swaps the item (let binding, or top-level form) that contains the index
+/// with its neighbor.
+pub fn move_item(
+    exprs: &[Ann<Expr>],
+    input: &str,
+    index: usize,
+    direction: Direction,
+) -> Option<Move> {
+    let spans = binding_spans(exprs, index).unwrap_or_else(|| top_level_spans(exprs, input));
+
+    let current = spans
+        .iter()
+        .position(|(start, end)| *start <= index && index < *end)?;
+
+    let (first, second) = match direction {
+        Direction::Up if current > 0 => (current - 1, current),
+        Direction::Down if current + 1 < spans.len() => (current, current + 1),
+        _ => return None,
+    };
+
+    let chars: Vec<char> = input.chars().collect();
+    let text = |start: usize, end: usize| -> String {
+        chars
+            .get(start..end.min(chars.len()))
+            .unwrap_or_default()
+            .iter()
+            .collect()
+    };
+
+    let (first_start, first_end) = spans[first];
+    let (second_start, second_end) = spans[second];
+
+    let first_text = text(first_start, first_end);
+    let second_text = text(second_start, second_end);
+    let gap = text(first_end, second_start);
+
+    let offset = index - spans[current].0;
+    let cursor = match direction {
+        Direction::Up => first_start + offset,
+        Direction::Down => first_start + second_text.chars().count() + gap.chars().count() + offset,
+    };
+
+    Some(Move {
+        start: first_start,
+        end: second_end,
+        text: format!("{second_text}{gap}{first_text}"),
+        cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tan::api::parse_string_all;
+
+    use super::*;
+
+    /// Moves the item at the index of the marker `|`, returns the new input
+    /// with the marker at the new cursor.
+    fn moved(input: &str, direction: Direction) -> Option<String> {
+        let index = input.chars().position(|c| c == '|').unwrap();
+        let input = input.replacen('|', "", 1);
+        let exprs = parse_string_all(&input).unwrap();
+
+        let result = move_item(&exprs, &input, index, direction)?;
+
+        let mut chars: Vec<char> = input.chars().collect();
+        chars.splice(result.start..result.end, result.text.chars());
+        chars.insert(result.cursor, '|');
+        Some(chars.into_iter().collect())
+    }
+
+    #[test]
+    fn move_item_swaps_the_top_level_forms_with_their_comments() {
+        let input = "; the a\n(let a 1)\n\n; the b\n(let |b 2)\n";
+
+        assert_eq!(
+            moved(input, Direction::Up).unwrap(),
+            "; the b\n(let |b 2)\n\n; the a\n(let a 1)\n"
+        );
+    }
+
+    #[test]
+    fn move_item_moves_down_and_keeps_the_cursor_in_the_item() {
+        let input = "(f |1)\n(g 2)\n(h 3)\n";
+
+        assert_eq!(
+            moved(input, Direction::Down).unwrap(),
+            "(g 2)\n(f |1)\n(h 3)\n"
+        );
+    }
+
+    #[test]
+    fn move_item_swaps_the_bindings_of_a_let() {
+        let input = "(let a 1 |b 2)\n";
+
+        assert_eq!(moved(input, Direction::Up).unwrap(), "(let |b 2 a 1)\n");
+    }
+
+    #[test]
+    fn move_item_stops_at_the_first_and_the_last_item() {
+        assert!(moved("(f |1)\n(g 2)\n", Direction::Up).is_none());
+        assert!(moved("(f 1)\n(g |2)\n", Direction::Down).is_none());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use lsp_server::{ErrorCode, RequestId, Response, ResponseError};
+use tracing::warn;
+
+// #Insight
+// The response handlers run in the message loop, with mutable access to the
+// server state, e.g. to start polling when the client refuses to register the
+// file watchers.
+
+/// The time after which a request sent to the client is considered failed.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The outcome of a request sent to the client.
+pub type ResponseResult = Result<serde_json::Value, ResponseError>;
+
+/// Processes the response of a request sent to the client.
+pub type ResponseHandler<S> = Box<dyn FnOnce(&mut S, ResponseResult) -> anyhow::Result<()>>;
+
+struct PendingRequest<S> {
+    method: String,
+    sent_at: Instant,
+    handler: ResponseHandler<S>,
+}
+
+/// The requests sent to the client, waiting for a response.
+pub struct OutgoingRequests<S> {
+    next_id: i32,
+    pending: HashMap<RequestId, PendingRequest<S>>,
+}
+
+impl<S> Default for OutgoingRequests<S> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<S> OutgoingRequests<S> {
+    /// Registers a request, returns the id of the request.
+    pub fn register(&mut self, method: &str, handler: ResponseHandler<S>) -> RequestId {
+        let id = RequestId::from(self.next_id);
+        self.next_id += 1;
+
+        self.pending.insert(
+            id.clone(),
+            PendingRequest {
+                method: method.to_owned(),
+                sent_at: Instant::now(),
+                handler,
+            },
+        );
+
+        id
+    }
+
+    /// Matches a response to its request, returns the handler and the result.
+    pub fn complete(&mut self, resp: Response) -> Option<(ResponseHandler<S>, ResponseResult)> {
+        let Some(request) = self.pending.remove(&resp.id) else {
+            warn!("got response to unknown request `{}`", resp.id);
+            return None;
+        };
+
+        let result = match resp.error {
+            Some(error) => {
+                warn!("request `{}` failed: {}", request.method, error.message);
+                Err(error)
+            }
+            None => Ok(resp.result.unwrap_or_default()),
+        };
+
+        Some((request.handler, result))
+    }
+
+    /// Removes the requests that timed out, returns their handlers with a
+    /// failed result.
+    pub fn expire(&mut self) -> Vec<(ResponseHandler<S>, ResponseResult)> {
+        let expired: Vec<RequestId> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| request.sent_at.elapsed() >= REQUEST_TIMEOUT)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id))
+            .map(|request| {
+                warn!("request `{}` timed out", request.method);
+                let error = ResponseError {
+                    code: ErrorCode::RequestFailed as i32,
+                    message: "request timed out".to_owned(),
+                    data: None,
+                };
+                (request.handler, Err(error))
+            })
+            .collect()
+    }
+}
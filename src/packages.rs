@@ -0,0 +1,208 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::bail;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, MANIFEST_FILE},
+    manifest::dependency_entries,
+    module::resolve_import,
+};
+
+// #Insight
+// The lockfile is written by the package manager, it records the resolved
+// version of every dependency, e.g.
+//
+// [[package]]
+// name = "json"
+// version = "1.2.3"
+//
+// The server never resolves the dependencies, it only reads the versions.
+
+// #TODO query the HTTP registry, only a local registry index is supported.
+
+/// The lockfile of a Tan project, relative to the workspace root.
+pub const LOCK_FILE: &str = "tan.lock";
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+}
+
+/// Returns the locked versions of the dependencies, by package name. Empty if
+/// the workspace has no lockfile.
+pub fn locked_versions(root_path: &Path) -> HashMap<String, String> {
+    let Ok(text) = std::fs::read_to_string(root_path.join(LOCK_FILE)) else {
+        return HashMap::new();
+    };
+
+    match toml::from_str::<Lockfile>(&text) {
+        Ok(lockfile) => lockfile
+            .package
+            .into_iter()
+            .map(|package| (package.name, package.version))
+            .collect(),
+        Err(error) => {
+            warn!("cannot parse `{LOCK_FILE}`: {error}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Returns true if the name is a valid package name, i.e. a file name, e.g.
+/// not `../secrets`.
+fn is_package_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// Parses a version into its numeric components, e.g. `1.2.3` into
+/// `[1, 2, 3]`. Returns `None` for pre-release versions, e.g. `1.0.0-beta`.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect()
+}
+
+/// Compares two versions, the invalid versions are ordered first.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    parse_version(a).cmp(&parse_version(b))
+}
+
+/// Returns the latest published version of a package in the registry index,
+/// i.e. a directory with a file per package that lists the published
+/// versions, one per line. The pre-release versions are skipped.
+pub fn latest_version(index_path: &Path, name: &str) -> Option<String> {
+    if !is_package_name(name) {
+        return None;
+    }
+
+    let text = std::fs::read_to_string(index_path.join(name)).ok()?;
+
+    text.lines()
+        .map(str::trim)
+        .filter(|version| parse_version(version).is_some())
+        .max_by(|a, b| compare_versions(a, b))
+        .map(str::to_owned)
+}
+
+/// The error returned by handlers that cannot navigate into a dependency of
+/// the manifest, because its sources are not downloaded. The client is asked
+/// to download the sources.
+#[derive(Debug, Clone)]
+pub struct MissingSources {
+    pub name: String,
+    /// The locked version, if any.
+    pub version: Option<String>,
+}
+
+impl fmt::Display for MissingSources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the sources of the package `{}` are not downloaded",
+            self.name
+        )
+    }
+}
+
+impl std::error::Error for MissingSources {}
+
+/// Returns the package of an imported module if it is a dependency of the
+/// manifest and its sources are not available, e.g. `json` for `json/parse`.
+pub fn missing_sources(
+    module_name: &str,
+    base_dir: &Path,
+    config: &Config,
+) -> Option<MissingSources> {
+    let root_path = config.root_path.as_ref()?;
+    let name = module_name.split('/').next()?;
+
+    if !is_package_name(name) || resolve_import(name, base_dir, config).is_some() {
+        return None;
+    }
+
+    let text = std::fs::read_to_string(root_path.join(MANIFEST_FILE)).ok()?;
+    if !dependency_entries(&text)
+        .iter()
+        .any(|entry| entry.name == name)
+    {
+        return None;
+    }
+
+    Some(MissingSources {
+        name: name.to_owned(),
+        version: locked_versions(root_path).remove(name),
+    })
+}
+
+// #Insight
+// The sources are fetched with the download command of the configuration,
+// e.g. the package manager, the server has no HTTP client. The command runs
+// with the placeholders `{name}`, `{version}` and `{dir}` replaced in its
+// arguments, and unpacks the sources into the directory.
+
+/// Downloads the sources of a package into the package cache, returns the
+/// directory of the sources.
+pub fn download_sources(missing: &MissingSources, config: &Config) -> anyhow::Result<PathBuf> {
+    let Some((program, args)) = config.source_download_command.split_first() else {
+        bail!("no source download command is configured");
+    };
+
+    if config.read_only {
+        bail!("the sources are never downloaded in read-only mode");
+    }
+
+    if !is_package_name(&missing.name) {
+        bail!("invalid package name `{}`", missing.name);
+    }
+
+    let Some(cache_path) = &config.package_cache_path else {
+        bail!("no package cache directory");
+    };
+
+    let dir = cache_path.join(&missing.name);
+    let is_new = !dir.exists();
+    std::fs::create_dir_all(&dir)?;
+
+    let version = missing.version.as_deref().unwrap_or("latest");
+    let replace = |arg: &str| {
+        arg.replace("{name}", &missing.name)
+            .replace("{version}", version)
+            .replace("{dir}", &dir.to_string_lossy())
+    };
+
+    info!("downloading the sources of `{}` {version}", missing.name);
+
+    let output = Command::new(replace(program))
+        .args(args.iter().map(|arg| replace(arg)))
+        .output()?;
+
+    if !output.status.success() {
+        // A partial download would shadow a later one.
+        if is_new {
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+        bail!(
+            "the download command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(dir)
+}
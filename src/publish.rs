@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crossbeam_channel::Sender;
+use lsp_server::Message;
+use lsp_types::{
+    notification::{Notification, PublishDiagnostics},
+    Diagnostic, PublishDiagnosticsParams, Url,
+};
+use tracing::trace;
+
+use crate::{diagnostics::file_diagnostics, module_graph::ModuleGraph, snapshot::Snapshot};
+
+// #Insight
+// The diagnostics are computed by the worker threads, a run that started
+// later may finish earlier. The runs are numbered when they are spawned, the
+// diagnostics of a document are published only if no newer run of the
+// document published or started.
+
+#[derive(Default)]
+struct RunsState {
+    /// The generation of the last started run.
+    generation: u64,
+    /// The generation of the latest run of each document.
+    latest: HashMap<Url, u64>,
+}
+
+/// The runs of the diagnostics of the documents, shared with the workers.
+#[derive(Clone, Default)]
+pub struct DiagnosticsRuns(Arc<Mutex<RunsState>>);
+
+impl DiagnosticsRuns {
+    /// Starts a run, that supersedes the runs in progress of the given
+    /// documents.
+    pub fn start<'a>(&self, uris: impl IntoIterator<Item = &'a Url>) -> DiagnosticsRun {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        state.generation += 1;
+        let generation = state.generation;
+        for uri in uris {
+            state.latest.insert(uri.clone(), generation);
+        }
+
+        DiagnosticsRun {
+            runs: self.clone(),
+            generation,
+        }
+    }
+
+    /// Forgets the documents that are not retained, e.g. the deleted files.
+    pub fn retain(&self, mut retained: impl FnMut(&Url) -> bool) {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        state.latest.retain(|uri, _| retained(uri));
+        state.latest.shrink_to_fit();
+    }
+}
+
+/// A run of the diagnostics of documents.
+pub struct DiagnosticsRun {
+    runs: DiagnosticsRuns,
+    generation: u64,
+}
+
+impl DiagnosticsRun {
+    /// Publishes the diagnostics of a document, unless a newer run of the
+    /// document started. Returns `None` if the run is superseded.
+    ///
+    /// The lock is held while publishing, the publications of a document are
+    /// sent in the order of the runs.
+    pub fn publish<T>(&self, uri: &Url, publish: impl FnOnce() -> T) -> Option<T> {
+        let mut state = self.runs.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let latest = state.latest.entry(uri.clone()).or_default();
+        if *latest > self.generation {
+            trace!("the diagnostics of `{uri}` are superseded by a newer run");
+            return None;
+        }
+        *latest = self.generation;
+
+        Some(publish())
+    }
+}
+
+/// Computes and publishes the diagnostics of a document, for a run. Returns
+/// false if the run is superseded, the diagnostics are dropped.
+pub fn send_diagnostics(
+    sender: &Sender<Message>,
+    run: &DiagnosticsRun,
+    uri: Url,
+    input: &str,
+    version: Option<i32>,
+    snapshot: &Snapshot,
+    graph: &ModuleGraph,
+) -> anyhow::Result<bool> {
+    let diagnostics = file_diagnostics(&uri, input, snapshot, graph)?;
+
+    // #Insight
+    // We send a notification even for empty diagnostics to clear previous
+    // diagnostics.
+
+    // if diagnostics.is_empty() {
+    //     return Ok(());
+    // }
+
+    let published = run.publish(&uri, || {
+        publish_diagnostics(sender, uri.clone(), version, diagnostics)
+    });
+    published.transpose().map(|published| published.is_some())
+}
+
+/// Publishes the diagnostics of a file, e.g. of the manifest, for the version
+/// of the open document.
+pub fn publish_diagnostics(
+    sender: &Sender<Message>,
+    uri: Url,
+    version: Option<i32>,
+    diagnostics: Vec<Diagnostic>,
+) -> anyhow::Result<()> {
+    let pdm = PublishDiagnosticsParams {
+        uri,
+        diagnostics,
+        version,
+    };
+
+    let notification = lsp_server::Notification {
+        method: PublishDiagnostics::METHOD.to_owned(),
+        params: serde_json::to_value(&pdm).unwrap(),
+    };
+
+    sender.send(Message::Notification(notification))?;
+
+    Ok(())
+}
+
+/// Clears the diagnostics of a document, e.g. when the file is deleted.
+pub fn clear_diagnostics(sender: &Sender<Message>, uri: Url) -> anyhow::Result<()> {
+    publish_diagnostics(sender, uri, None, Vec::new())
+}
@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+
+use lsp_types::{Location, Url};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+
+use crate::{
+    ast::{find_path_at, for_each_expr, head_symbol},
+    module::top_level_definitions,
+    resolve::resolve_local_symbol_at,
+    snapshot::Snapshot,
+    symbols::collect_definitions,
+    unicode::{may_contain_name, normalize_name, same_name},
+    util::lsp_range_from_range,
+};
+
+// #TODO resolve the imported names, the same name imported from different
+// modules is also matched.
+
+/// Finds the references to a module-level name, in the open documents and the
+/// indexed files. The local bindings with the same name, e.g. function
+/// parameters, are skipped. Fails while the workspace is being indexed, the
+/// references would be incomplete.
+pub fn find_references(
+    snapshot: &Snapshot,
+    name: &str,
+    include_declaration: bool,
+) -> anyhow::Result<Vec<Location>> {
+    let usages = find_usages(snapshot, name, include_declaration)?;
+
+    Ok(usages.into_iter().map(|(location, _)| location).collect())
+}
+
+/// The context of a reference to a name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UsageContext {
+    /// The head of a list, e.g. `(add 1 2)`.
+    Call,
+    /// The value of a top-level `let`, e.g. `(let plus add)`.
+    ReExport,
+    /// Any other reference, e.g. an argument.
+    Value,
+}
+
+/// Returns the context of the symbol with the (char) range.
+fn usage_context(exprs: &[Ann<Expr>], range: &Range) -> UsageContext {
+    let path = find_path_at(exprs, range.start);
+    let [.., parent, _] = path.as_slice() else {
+        return UsageContext::Value;
+    };
+    let Ann(Expr::List(terms), _) = parent else {
+        return UsageContext::Value;
+    };
+
+    let Some(position) = terms.iter().position(|term| term.get_range() == *range) else {
+        return UsageContext::Value;
+    };
+
+    if position == 0 {
+        UsageContext::Call
+    } else if path.len() == 2 && head_symbol(parent) == Some("let") && position % 2 == 0 {
+        UsageContext::ReExport
+    } else {
+        UsageContext::Value
+    }
+}
+
+/// Finds the references to a module-level name with their context, see
+/// `find_references`.
+pub fn find_usages(
+    snapshot: &Snapshot,
+    name: &str,
+    include_declaration: bool,
+) -> anyhow::Result<Vec<(Location, UsageContext)>> {
+    snapshot.check_indexed()?;
+
+    let mut usages = Vec::new();
+
+    for (uri, input) in snapshot.sources() {
+        snapshot.cancellation.check()?;
+
+        // Skip files that cannot contain the name, without parsing.
+        if !may_contain_name(input, name) {
+            continue;
+        }
+
+        // Skip the indexed files whose forms never reference the name.
+        if let Some(file) = snapshot.index.get(uri) {
+            if *file.text == **input && !file.mentions(name) {
+                continue;
+            }
+        }
+
+        find_references_in(uri, input, name, include_declaration, &mut usages);
+    }
+
+    Ok(usages)
+}
+
+/// Finds the public (top-level) definitions of the workspace that are never
+/// referenced, in the open documents and the indexed files. The definitions
+/// of the dependencies are not reported.
+pub fn find_unused_public_definitions(
+    snapshot: &Snapshot,
+) -> anyhow::Result<Vec<(String, Location)>> {
+    snapshot.check_indexed()?;
+
+    let mut candidates = Vec::new();
+    let mut used: HashSet<String> = HashSet::new();
+
+    for (uri, input) in snapshot.sources() {
+        snapshot.cancellation.check()?;
+
+        let Ok(exprs) = parse_string_all(input) else {
+            continue;
+        };
+
+        let is_dependency = uri
+            .to_file_path()
+            .is_ok_and(|path| snapshot.config.is_dependency(&path));
+
+        let mut declarations = HashMap::new();
+        if !is_dependency {
+            for name_expr in top_level_definitions(&exprs) {
+                let Ann(Expr::Symbol(name), _) = name_expr else {
+                    continue;
+                };
+                declarations.insert(name_expr.get_range(), name.clone());
+            }
+        }
+
+        for_each_expr(&exprs, &mut |expr| {
+            let Ann(Expr::Symbol(sym), _) = expr else {
+                return;
+            };
+
+            let range = expr.get_range();
+            if range.start >= range.end || declarations.contains_key(&range) {
+                return;
+            }
+
+            // A qualified name, e.g. `ops/add`, references the unqualified
+            // name.
+            let sym = normalize_name(sym);
+            if let Some((_, name)) = sym.rsplit_once('/') {
+                used.insert(name.to_owned());
+            }
+            used.insert(sym.into_owned());
+        });
+
+        for (range, name) in declarations {
+            let location = Location::new(uri.clone(), lsp_range_from_range(&range, input));
+            candidates.push((name, location));
+        }
+    }
+
+    let mut unused: Vec<_> = candidates
+        .into_iter()
+        .filter(|(name, _)| !used.contains(&*normalize_name(name)))
+        .collect();
+
+    unused.sort_by(|(_, a), (_, b)| {
+        (a.uri.as_str(), a.range.start.line, a.range.start.character).cmp(&(
+            b.uri.as_str(),
+            b.range.start.line,
+            b.range.start.character,
+        ))
+    });
+
+    Ok(unused)
+}
+
+fn find_references_in(
+    uri: &Url,
+    input: &str,
+    name: &str,
+    include_declaration: bool,
+    usages: &mut Vec<(Location, UsageContext)>,
+) {
+    let Ok(exprs) = parse_string_all(input) else {
+        return;
+    };
+
+    let declarations: Vec<_> = collect_definitions(&exprs)
+        .into_iter()
+        .filter(|definition| same_name(&definition.name, name))
+        .map(|definition| definition.selection_range)
+        .collect();
+
+    for_each_expr(&exprs, &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+
+        let range = expr.get_range();
+
+        if !same_name(sym, name) || range.start >= range.end {
+            return;
+        }
+
+        if !include_declaration && declarations.contains(&range) {
+            return;
+        }
+
+        if resolve_local_symbol_at(&exprs, range.start).is_some() {
+            return;
+        }
+
+        let location = Location::new(uri.clone(), lsp_range_from_range(&range, input));
+        usages.push((location, usage_context(&exprs, &range)));
+    });
+}
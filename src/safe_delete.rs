@@ -0,0 +1,176 @@
+use std::path::Path;
+
+use lsp_types::{Location, TextEdit, Url};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::head_symbol,
+    indent::{top_level_form_ranges, whole_line_range},
+    module::{attached_comments, references_name, resolve_import, summarize_module},
+    references::find_references,
+    resolve::module_level_symbols,
+    snapshot::Snapshot,
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// A definition is deleted only if it is never referenced in the workspace,
+// except by itself, e.g. a recursive function. The imports used only by the
+// deleted definition are deleted too.
+
+/// The result of a safe delete.
+pub enum SafeDelete {
+    /// The references that prevent the deletion.
+    Blocked(Vec<Location>),
+    /// The edits of the document.
+    Edits(Vec<TextEdit>),
+}
+
+/// Deletes the top-level definition at the (char) index of a document.
+/// Returns `None` if the index is not on the name of a top-level definition.
+pub fn safe_delete(
+    snapshot: &Snapshot,
+    uri: &Url,
+    exprs: &[Ann<Expr>],
+    input: &str,
+    index: usize,
+) -> anyhow::Result<Option<SafeDelete>> {
+    let Some((position, pair)) = exprs
+        .iter()
+        .enumerate()
+        .find_map(|(position, expr)| definition_pair_at(expr, index).map(|pair| (position, pair)))
+    else {
+        return Ok(None);
+    };
+
+    let Ann(Expr::List(terms), _) = &exprs[position] else {
+        return Ok(None);
+    };
+    let (name_expr, value) = (&terms[pair], &terms[pair + 1]);
+    let Ann(Expr::Symbol(name), _) = name_expr else {
+        return Ok(None);
+    };
+
+    // The references in the deleted value, e.g. recursive calls, do not
+    // block the deletion.
+    let value_range = value.get_range();
+    let lsp_value_range = lsp_range_from_range(&value_range, input);
+    let blockers: Vec<Location> = find_references(snapshot, name, false)?
+        .into_iter()
+        .filter(|location| {
+            &location.uri != uri
+                || location.range.start < lsp_value_range.start
+                || location.range.end > lsp_value_range.end
+        })
+        .collect();
+
+    if !blockers.is_empty() {
+        return Ok(Some(SafeDelete::Blocked(blockers)));
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+
+    // A `let` with a single binding is deleted with its comments and
+    // annotations, otherwise only the binding is deleted.
+    let deleted = if terms.len() == 3 {
+        let form_range = exprs[position].get_range();
+        let start = attached_comments(exprs, position, input)
+            .first()
+            .map(|comment| comment.get_range().start)
+            .or_else(|| {
+                top_level_form_ranges(input)
+                    .into_iter()
+                    .find(|range| range.start <= form_range.start && form_range.start < range.end)
+                    .map(|range| range.start)
+            })
+            .unwrap_or(form_range.start);
+        whole_line_range(&chars, start..form_range.end)
+    } else {
+        terms[pair - 1].get_range().end..value_range.end
+    };
+
+    let mut edits = vec![TextEdit::new(
+        lsp_range_from_range(&deleted, input),
+        String::new(),
+    )];
+
+    let base_dir = uri
+        .to_file_path()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    if let Some(base_dir) = base_dir {
+        for use_range in unused_imports(snapshot, exprs, &deleted, &base_dir) {
+            let use_range = whole_line_range(&chars, use_range);
+            edits.push(TextEdit::new(
+                lsp_range_from_range(&use_range, input),
+                String::new(),
+            ));
+        }
+    }
+
+    Ok(Some(SafeDelete::Edits(edits)))
+}
+
+/// Returns the position of the name of the `let` pair at the index, in the
+/// terms of the expression.
+fn definition_pair_at(expr: &Ann<Expr>, index: usize) -> Option<usize> {
+    if head_symbol(expr) != Some("let") {
+        return None;
+    }
+
+    let Ann(Expr::List(terms), _) = expr else {
+        return None;
+    };
+
+    (1..terms.len().saturating_sub(1)).step_by(2).find(|&i| {
+        let range = terms[i].get_range();
+        range.start <= index && index <= range.end
+    })
+}
+
+/// Returns the ranges of the top-level imports that are used only in the
+/// deleted (char) range.
+fn unused_imports(
+    snapshot: &Snapshot,
+    exprs: &[Ann<Expr>],
+    deleted: &Range,
+    base_dir: &Path,
+) -> Vec<Range> {
+    let is_deleted = |range: &Range| deleted.start <= range.start && range.end <= deleted.end;
+
+    let (deleted_symbols, remaining_symbols): (Vec<_>, Vec<_>) = exprs
+        .iter()
+        .filter(|expr| head_symbol(expr) != Some("use"))
+        .flat_map(|expr| module_level_symbols(exprs, std::slice::from_ref(expr)))
+        .partition(|(_, range)| is_deleted(range));
+
+    let mut ranges = Vec::new();
+
+    for use_expr in exprs.iter().filter(|expr| head_symbol(expr) == Some("use")) {
+        let Ann(Expr::List(terms), _) = use_expr else {
+            continue;
+        };
+        let Some(Ann(Expr::Symbol(module_name), _)) = terms.get(1) else {
+            continue;
+        };
+        let Some((module_path, _)) = resolve_import(module_name, base_dir, &snapshot.config) else {
+            continue;
+        };
+        let Ok(summary) = summarize_module(&module_path, &snapshot.vfs) else {
+            continue;
+        };
+
+        let is_used = |symbols: &[(&str, Range)]| {
+            summary
+                .exports
+                .iter()
+                .any(|export| references_name(symbols, export, module_name))
+        };
+
+        if is_used(&deleted_symbols) && !is_used(&remaining_symbols) {
+            ranges.push(use_expr.get_range());
+        }
+    }
+
+    ranges
+}
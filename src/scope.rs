@@ -0,0 +1,63 @@
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{ast::for_each_expr, resolve::resolve_local_symbol_at, unicode::same_name};
+
+// #Insight
+// The scopes are the ones of the name resolution, see `resolve`: a symbol of
+// a range is free if it resolves to a local binding outside of the range.
+// The module-level names are visible everywhere in the file, they are never
+// free.
+
+/// Returns true if the (char) range contains the other range.
+fn contains(range: &Range, other: &Range) -> bool {
+    range.start <= other.start && other.end <= range.end
+}
+
+/// Returns the free variables of the (char) range of the document, i.e. the
+/// names of the local bindings outside of the range referenced in the range,
+/// in the order of their first reference.
+pub fn free_variables<'a>(exprs: &'a [Ann<Expr>], range: &Range) -> Vec<&'a str> {
+    let mut names: Vec<&str> = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(name), _) = expr else {
+            return;
+        };
+        let symbol_range = expr.get_range();
+        if symbol_range.start >= symbol_range.end || !contains(range, &symbol_range) {
+            return;
+        }
+        let Some(binding) = resolve_local_symbol_at(exprs, symbol_range.start) else {
+            return;
+        };
+        if !contains(range, &binding.name_range) && !names.iter().any(|n| same_name(n, name)) {
+            names.push(name);
+        }
+    });
+
+    names
+}
+
+/// Returns the names bound in the (char) range of the document that are
+/// referenced after the range, e.g. by the following forms of a `do` block.
+pub fn escaping_bindings<'a>(exprs: &'a [Ann<Expr>], range: &Range) -> Vec<&'a str> {
+    let mut names: Vec<&str> = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(name), _) = expr else {
+            return;
+        };
+        let symbol_range = expr.get_range();
+        if symbol_range.start >= symbol_range.end || contains(range, &symbol_range) {
+            return;
+        }
+        let Some(binding) = resolve_local_symbol_at(exprs, symbol_range.start) else {
+            return;
+        };
+        if contains(range, &binding.name_range) && !names.iter().any(|n| same_name(n, name)) {
+            names.push(name);
+        }
+    });
+
+    names
+}
@@ -0,0 +1,2051 @@
+use std::{
+    collections::{HashMap, HashSet},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
+
+use crossbeam_channel::{at, never, select, tick, unbounded, Receiver, Sender};
+use lsp_server::{
+    Connection, ErrorCode, ExtractError, Message, Notification, Request, RequestId, Response,
+    ResponseError,
+};
+use lsp_types::{
+    notification::{
+        Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
+        DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument, Notification as _,
+        Progress, ShowMessage,
+    },
+    request::{
+        ApplyWorkspaceEdit, CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls,
+        CallHierarchyPrepare, CodeActionRequest, CodeLensRefresh, CodeLensRequest, CodeLensResolve,
+        ColorPresentationRequest, Completion, DocumentColor, DocumentHighlightRequest,
+        DocumentLinkRequest, DocumentLinkResolve, DocumentSymbolRequest, ExecuteCommand,
+        FoldingRangeRequest, Formatting, GotoDefinition, HoverRequest, InlayHintRefreshRequest,
+        InlayHintRequest, OnTypeFormatting, PrepareRenameRequest, RangeFormatting, References,
+        RegisterCapability, Rename, Request as _, ResolveCompletionItem, SelectionRangeRequest,
+        SemanticTokensFullRequest, SemanticTokensRefresh, ShowMessageRequest, SignatureHelpRequest,
+        WillRenameFiles, WorkDoneProgressCreate, WorkspaceConfiguration, WorkspaceSymbolRequest,
+    },
+    ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, CancelParams, ConfigurationItem,
+    ConfigurationParams, Diagnostic, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidChangeWatchedFilesParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, ExecuteCommandParams, FileChangeType, FileEvent, InitializeParams,
+    MessageActionItem, MessageType, NumberOrString, ProgressParams, ProgressParamsValue,
+    ShowMessageParams, ShowMessageRequestParams, Url, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkspaceEdit,
+};
+use serde::de::DeserializeOwned;
+use tracing::{info, trace, warn};
+
+use crate::{
+    cache::Caches,
+    cancellation::{CancellationToken, Cancelled, NotIndexed},
+    config::{Config, CONFIGURATION_SECTION, MANIFEST_FILE},
+    crash, diagnostics, evaluate,
+    handlers::{self, execute_command::CommandOutput},
+    index::{self, text_hash, Index, IndexedFile, Origin},
+    lsp_ext, manifest,
+    module::imported_files,
+    module_graph::ModuleGraph,
+    outgoing::{OutgoingRequests, ResponseHandler},
+    packages::{download_sources, MissingSources, LOCK_FILE},
+    publish::{self, DiagnosticsRun, DiagnosticsRuns},
+    snapshot::Snapshot,
+    vfs::Vfs,
+    watcher::{self, FileEventQueue, FileTimes},
+    worker::{request_priority, Priority, Workers},
+};
+
+/// The progress token of the initial indexing.
+const INDEXING_PROGRESS_TOKEN: &str = "tan/indexing";
+
+/// The progress token of the re-indexing of a burst of changed files.
+const REINDEXING_PROGRESS_TOKEN: &str = "tan/reindexing";
+
+/// The minimum number of changed files whose re-indexing reports progress.
+const REINDEXING_PROGRESS_THRESHOLD: usize = 50;
+
+/// The action of the prompt to download the sources of a package.
+const DOWNLOAD_ACTION: &str = "Download";
+
+/// The results of background tasks, applied to the server state by the
+/// message loop.
+enum Task {
+    Indexed(Index),
+    /// Changed files were re-read, `None` for deleted files.
+    Reindexed(Vec<(Url, Option<IndexedFile>)>),
+    /// A burst of changed files was re-indexed, the number of files.
+    BurstReindexed(usize),
+    /// An open document was re-indexed from the editor buffer.
+    DocumentIndexed {
+        uri: Url,
+        version: i32,
+        file: IndexedFile,
+    },
+    /// The workspace was polled for changes.
+    Polled {
+        times: FileTimes,
+        changes: Vec<FileEvent>,
+    },
+    /// A request was processed, the response is sent.
+    Completed(RequestId),
+    /// The manifest was read again after it changed on disk, `None` if it was
+    /// deleted or is invalid.
+    ManifestRead(Option<toml::Table>),
+    /// A command was executed, the response is sent once its edit is applied.
+    CommandEdited {
+        id: RequestId,
+        label: String,
+        edit: WorkspaceEdit,
+        result: Option<serde_json::Value>,
+    },
+    /// The import cycles of the workspace were counted.
+    CyclesChecked(usize),
+    /// The imports of an open document were indexed ahead of the workspace.
+    Prewarmed(Vec<(Url, IndexedFile)>),
+    /// A request needs the sources of a package that are not downloaded.
+    SourcesMissing(MissingSources),
+    /// The sources of a package were downloaded, or the download failed.
+    SourcesDownloaded {
+        name: String,
+        result: Result<PathBuf, String>,
+    },
+    /// The diagnostics of a text were published, by the latest run of the
+    /// document.
+    Analyzed {
+        uri: Url,
+        hash: u64,
+    },
+    /// The idle maintenance ended, or was interrupted by a message.
+    Maintained {
+        /// The indexed files that no longer exist.
+        stale: Vec<Url>,
+        completed: bool,
+    },
+}
+
+/// The server state, processes the messages from the client.
+pub struct Server {
+    connection: Connection,
+    config: Arc<Config>,
+    vfs: Vfs,
+    index: Arc<Index>,
+    workers: Workers,
+    task_sender: Sender<Task>,
+    task_receiver: Receiver<Task>,
+    started_at: Instant,
+    /// The requests that are being processed, by id.
+    pending_requests: HashMap<RequestId, CancellationToken>,
+    /// True while the workspace is being indexed.
+    indexing: bool,
+    /// The requests sent to the client, waiting for a response.
+    outgoing_requests: OutgoingRequests<Server>,
+    /// The parsed manifest of the workspace, applied over the settings.
+    manifest: Option<toml::Table>,
+    /// The progress tokens of the work in progress, true if the beginning of
+    /// the work was reported.
+    progress_tokens: HashMap<String, bool>,
+    /// True if the workspace is polled for changes, for clients that cannot
+    /// watch files.
+    poll_files: bool,
+    /// True while a poll is processed by a worker thread.
+    poll_in_progress: bool,
+    /// The file times of the last poll.
+    file_times: Option<FileTimes>,
+    /// The watched file events waiting to be processed.
+    file_events: FileEventQueue,
+    /// The number of bursts of changed files being re-indexed.
+    reindexing_bursts: usize,
+    /// The number of imports that participate in import cycles.
+    import_cycles: usize,
+    /// The content hashes of the texts whose diagnostics were published, by
+    /// uri.
+    analyzed: HashMap<Url, u64>,
+    /// The runs of the diagnostics, the results of superseded runs are dropped.
+    diagnostics_runs: DiagnosticsRuns,
+    /// The packages the client was asked to download the sources of, asked
+    /// once per session.
+    prompted_packages: HashSet<String>,
+    /// The time of the last message from the client.
+    last_activity: Instant,
+    /// Cancels the idle maintenance in progress.
+    maintenance: Option<CancellationToken>,
+    /// True if the idle maintenance completed since the last message.
+    maintained: bool,
+    caches: Arc<Caches>,
+}
+
+impl Server {
+    pub fn new(
+        connection: Connection,
+        params: serde_json::Value,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        // #TODO use params to get root_uri and perform initial diagnostics for all files.
+        let params: InitializeParams = serde_json::from_value(params)?;
+
+        let mut config = config;
+        config.update_from_initialization_options(params.initialization_options.as_ref());
+        config.client_capabilities = params.capabilities;
+        config.root_path = params
+            .workspace_folders
+            .as_ref()
+            .and_then(|folders| folders.first())
+            .map(|folder| &folder.uri)
+            .or(params.root_uri.as_ref())
+            .and_then(|uri| uri.to_file_path().ok());
+        let manifest = config.read_manifest();
+        if let Some(manifest) = &manifest {
+            config.apply_manifest(manifest);
+        }
+
+        if config.read_only {
+            info!("read-only mode, Tan code will not be executed and files will not be written");
+        } else {
+            info!("evaluation sandbox: {}", config.sandbox);
+        }
+
+        if config.is_single_file_mode() {
+            info!("no workspace root, single-file mode");
+        }
+
+        let (task_sender, task_receiver) = unbounded();
+
+        let caches = Caches::new(config.cache_memory_limit * 1024 * 1024);
+
+        let poll_files = config.root_path.is_some()
+            && !watcher::supports_dynamic_registration(&config.client_capabilities);
+
+        let mut server = Self {
+            connection,
+            config: Arc::new(config),
+            vfs: Vfs::default(),
+            index: Arc::default(),
+            workers: Workers::new(),
+            task_sender,
+            task_receiver,
+            started_at: Instant::now(),
+            pending_requests: HashMap::new(),
+            indexing: false,
+            outgoing_requests: OutgoingRequests::default(),
+            manifest,
+            progress_tokens: HashMap::new(),
+            poll_files: false,
+            poll_in_progress: false,
+            file_times: None,
+            file_events: FileEventQueue::default(),
+            reindexing_bursts: 0,
+            import_cycles: 0,
+            analyzed: HashMap::new(),
+            diagnostics_runs: DiagnosticsRuns::default(),
+            prompted_packages: HashSet::new(),
+            last_activity: Instant::now(),
+            maintenance: None,
+            maintained: false,
+            caches: Arc::new(caches),
+        };
+
+        server.spawn_indexing()?;
+        server.spawn_manifest_validation(None);
+
+        if poll_files {
+            server.start_polling();
+        } else if server.config.root_path.is_some() {
+            server.send_request_with::<RegisterCapability>(
+                watcher::registration_params(),
+                |server, result| {
+                    if result.is_err() {
+                        server.start_polling();
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+
+        if server.supports_configuration() {
+            server.pull_configuration()?;
+        }
+
+        Ok(server)
+    }
+
+    /// Runs the message loop, until the client requests a shutdown.
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let metrics_ticker = match self.config.metrics_interval {
+            Some(interval) => tick(interval),
+            None => never(),
+        };
+        let maintenance_ticker = tick(watcher::POLL_INTERVAL);
+
+        loop {
+            let file_events_timer = match self.file_events.deadline() {
+                Some(deadline) => at(deadline),
+                None => never(),
+            };
+            let idle_timer = match self.idle_deadline() {
+                Some(deadline) => at(deadline),
+                None => never(),
+            };
+
+            select! {
+                recv(self.connection.receiver) -> msg => {
+                    let Ok(msg) = msg else {
+                        break;
+                    };
+                    trace!("got msg: {:?}", msg);
+                    crash::record_message(&msg);
+                    self.on_activity();
+                    match msg {
+                        Message::Request(req) => {
+                            if self.connection.handle_shutdown(&req)? {
+                                return Ok(());
+                            }
+                            trace!("got request: {:?}", req);
+                            self.handle_request(req)?;
+                        }
+                        Message::Response(resp) => {
+                            trace!("got response: {:?}", resp);
+                            if let Some((handler, result)) = self.outgoing_requests.complete(resp) {
+                                handler(self, result)?;
+                            }
+                        }
+                        Message::Notification(event) => {
+                            trace!("got notification: {:?}", event);
+                            self.handle_notification(event)?;
+                        }
+                    }
+                }
+                recv(self.task_receiver) -> task => {
+                    if let Ok(task) = task {
+                        self.handle_task(task)?;
+                    }
+                }
+                recv(file_events_timer) -> _ => {
+                    let changes = self.file_events.take();
+                    self.on_watched_files_changed(changes);
+                }
+                recv(metrics_ticker) -> _ => {
+                    self.send_metrics()?;
+                }
+                recv(idle_timer) -> _ => {
+                    self.spawn_idle_maintenance();
+                }
+                recv(maintenance_ticker) -> _ => {
+                    for (handler, result) in self.outgoing_requests.expire() {
+                        handler(self, result)?;
+                    }
+                    if self.poll_files {
+                        self.spawn_poll();
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_task(&mut self, task: Task) -> anyhow::Result<()> {
+        match task {
+            Task::Indexed(index) => {
+                let message = format!("indexed {} files", index.len());
+                let classification = handlers::semantic_tokens::classify_index(&self.index);
+                self.index = Arc::new(index);
+                self.refresh_semantic_tokens(&classification)?;
+                self.indexing = false;
+                self.refresh_code_lenses()?;
+                self.refresh_inlay_hints()?;
+                self.end_progress(INDEXING_PROGRESS_TOKEN, Some(message.clone()))?;
+                self.send_status(Some(message))?;
+                // The open documents shadow the files on disk.
+                let uris: Vec<Url> = self
+                    .vfs
+                    .snapshot()
+                    .documents()
+                    .map(|(uri, _)| uri.clone())
+                    .collect();
+                for uri in uris {
+                    self.spawn_document_index(uri);
+                }
+                self.spawn_cycle_check();
+            }
+            Task::Reindexed(files) => {
+                let classification = handlers::semantic_tokens::classify_index(&self.index);
+                let index = Arc::make_mut(&mut self.index);
+                for (uri, file) in files {
+                    index.replace(uri, file);
+                }
+                self.refresh_semantic_tokens(&classification)?;
+                // The reference counts and the signatures may have changed.
+                self.refresh_code_lenses()?;
+                self.refresh_inlay_hints()?;
+                self.spawn_cycle_check();
+            }
+            Task::BurstReindexed(count) => {
+                self.reindexing_bursts = self.reindexing_bursts.saturating_sub(1);
+                if self.reindexing_bursts == 0 {
+                    let message = format!("re-indexed {count} files");
+                    self.end_progress(REINDEXING_PROGRESS_TOKEN, Some(message))?;
+                }
+            }
+            Task::DocumentIndexed { uri, version, file } => {
+                // The document may have changed again, or closed, meanwhile.
+                if self.vfs.snapshot().version(&uri) == Some(version) {
+                    Arc::make_mut(&mut self.index).replace(uri, Some(file));
+                }
+            }
+            Task::Prewarmed(files) => {
+                // The files may have been indexed meanwhile, e.g. from an
+                // editor buffer.
+                let index = Arc::make_mut(&mut self.index);
+                for (uri, file) in files {
+                    if index.get(&uri).is_none() {
+                        index.replace(uri, Some(file));
+                    }
+                }
+            }
+            Task::Maintained { stale, completed } => {
+                self.maintenance = None;
+                self.maintained = completed;
+
+                // The files may have been opened meanwhile.
+                let vfs = self.vfs.snapshot();
+                let stale: Vec<Url> = stale
+                    .into_iter()
+                    .filter(|uri| vfs.get(uri).is_none())
+                    .collect();
+                if !stale.is_empty() {
+                    trace!("removed {} deleted files from the index", stale.len());
+                }
+                let index = Arc::make_mut(&mut self.index);
+                index.compact(&stale);
+
+                self.analyzed
+                    .retain(|uri, _| vfs.get(uri).is_some() || index.get(uri).is_some());
+                self.analyzed.shrink_to_fit();
+                self.diagnostics_runs
+                    .retain(|uri| vfs.get(uri).is_some() || index.get(uri).is_some());
+
+                // The closed documents are not requested anymore.
+                self.caches.retain(|uri| vfs.get(uri).is_some());
+            }
+            Task::Analyzed { uri, hash } => {
+                self.analyzed.insert(uri, hash);
+            }
+            Task::Polled { times, changes } => {
+                self.poll_in_progress = false;
+                self.file_times = Some(times);
+                if !changes.is_empty() {
+                    self.on_watched_files_changed(changes);
+                }
+            }
+            Task::Completed(id) => {
+                self.pending_requests.remove(&id);
+            }
+            Task::ManifestRead(manifest) => {
+                if let Some(manifest) = &manifest {
+                    Arc::make_mut(&mut self.config).apply_manifest(manifest);
+                }
+                self.manifest = manifest;
+                self.spawn_manifest_validation(None);
+            }
+            Task::CommandEdited {
+                id,
+                label,
+                edit,
+                result,
+            } => self.apply_command_edit(id, label, edit, result)?,
+            Task::SourcesMissing(missing) => self.on_sources_missing(missing)?,
+            Task::SourcesDownloaded { name, result } => {
+                let (typ, message) = match result {
+                    Ok(dir) => {
+                        let changes: Vec<FileEvent> = index::tan_files(&dir, &[])
+                            .into_iter()
+                            .filter_map(|path| Url::from_file_path(path).ok())
+                            .map(|uri| FileEvent::new(uri, FileChangeType::CREATED))
+                            .collect();
+                        self.on_watched_files_changed(changes);
+                        (
+                            MessageType::INFO,
+                            format!("Downloaded the sources of `{name}`"),
+                        )
+                    }
+                    Err(error) => {
+                        // The download can be retried.
+                        self.prompted_packages.remove(&name);
+                        let message = format!("Cannot download the sources of `{name}`: {error}");
+                        (MessageType::ERROR, message)
+                    }
+                };
+                let params = ShowMessageParams { typ, message };
+                let notification = Notification::new(ShowMessage::METHOD.to_owned(), params);
+                self.connection
+                    .sender
+                    .send(Message::Notification(notification))?;
+            }
+            Task::CyclesChecked(import_cycles) => {
+                if import_cycles != self.import_cycles {
+                    self.import_cycles = import_cycles;
+                    self.send_status(None)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Asks the client to confirm the download of the sources of a package,
+    /// and downloads them in a worker.
+    fn on_sources_missing(&mut self, missing: MissingSources) -> anyhow::Result<()> {
+        if self.config.source_download_command.is_empty() || self.config.read_only {
+            info!("{missing}, no source download command is configured");
+            return Ok(());
+        }
+
+        if !self.prompted_packages.insert(missing.name.clone()) {
+            return Ok(());
+        }
+
+        let version = missing.version.as_deref().unwrap_or("latest");
+        let params = ShowMessageRequestParams {
+            typ: MessageType::INFO,
+            message: format!(
+                "The sources of the package `{}` ({version}) are not downloaded, download them to the package cache?",
+                missing.name
+            ),
+            actions: Some(vec![MessageActionItem {
+                title: DOWNLOAD_ACTION.to_owned(),
+                properties: HashMap::new(),
+            }]),
+        };
+
+        self.send_request_with::<ShowMessageRequest>(params, move |server, result| {
+            match result {
+                Ok(Some(action)) if action.title == DOWNLOAD_ACTION => {
+                    server.spawn_source_download(missing);
+                }
+                // Dismissed, not asked again in the session.
+                Ok(_) => (),
+                Err(error) => warn!("cannot ask to download the sources: {}", error.message),
+            }
+            Ok(())
+        })
+    }
+
+    /// Downloads the sources of a package in a background worker thread.
+    fn spawn_source_download(&self, missing: MissingSources) {
+        let config = self.config.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let result = download_sources(&missing, &config).map_err(|error| format!("{error:#}"));
+            let _ = task_sender.send(Task::SourcesDownloaded {
+                name: missing.name,
+                result,
+            });
+        });
+    }
+
+    /// Asks the client to refresh the semantic tokens, if the index changed the
+    /// classification of symbols, e.g. a name is now known to be a macro.
+    fn refresh_semantic_tokens(
+        &mut self,
+        previous: &handlers::semantic_tokens::Classification,
+    ) -> anyhow::Result<()> {
+        let supports_refresh = self
+            .config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.semantic_tokens.as_ref())
+            .and_then(|semantic_tokens| semantic_tokens.refresh_support)
+            .unwrap_or(false);
+
+        if !supports_refresh || handlers::semantic_tokens::classify_index(&self.index) == *previous
+        {
+            return Ok(());
+        }
+
+        self.send_request::<SemanticTokensRefresh>(())
+    }
+
+    /// Asks the client to re-query the code lenses, e.g. when the lens settings
+    /// change or the reference counts are invalidated.
+    fn refresh_code_lenses(&mut self) -> anyhow::Result<()> {
+        let supports_refresh = self
+            .config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.code_lens.as_ref())
+            .and_then(|code_lens| code_lens.refresh_support)
+            .unwrap_or(false);
+
+        if !supports_refresh {
+            return Ok(());
+        }
+
+        self.send_request::<CodeLensRefresh>(())
+    }
+
+    /// Asks the client to re-query the inlay hints, e.g. when the signatures of
+    /// the indexed files change.
+    fn refresh_inlay_hints(&mut self) -> anyhow::Result<()> {
+        let supports_refresh = self
+            .config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.inlay_hint.as_ref())
+            .and_then(|inlay_hint| inlay_hint.refresh_support)
+            .unwrap_or(false);
+
+        if !supports_refresh {
+            return Ok(());
+        }
+
+        self.send_request::<InlayHintRefreshRequest>(())
+    }
+
+    /// Applies changed settings, and asks the client to refresh the features
+    /// that depend on them.
+    fn on_configuration_changed(&mut self, settings: &serde_json::Value) -> anyhow::Result<()> {
+        let previous = self.config.clone();
+        Arc::make_mut(&mut self.config).update_from_settings(settings, self.manifest.as_ref());
+
+        if previous.reference_lens != self.config.reference_lens
+            || previous.run_lens != self.config.run_lens
+            || previous.evaluate_lens != self.config.evaluate_lens
+        {
+            self.refresh_code_lenses()?;
+        }
+
+        if previous.parameter_hints != self.config.parameter_hints
+            || previous.type_hints != self.config.type_hints
+        {
+            self.refresh_inlay_hints()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a request to the client, the response (or the failure) is
+    /// processed by the handler, in the message loop.
+    fn send_request_with<R>(
+        &mut self,
+        params: R::Params,
+        handler: impl FnOnce(&mut Self, Result<R::Result, ResponseError>) -> anyhow::Result<()>
+            + 'static,
+    ) -> anyhow::Result<()>
+    where
+        R: lsp_types::request::Request,
+        R::Result: DeserializeOwned,
+    {
+        let handler: ResponseHandler<Self> = Box::new(move |server, result| {
+            let result = result.and_then(|value| {
+                serde_json::from_value(value).map_err(|error| ResponseError {
+                    code: ErrorCode::InternalError as i32,
+                    message: format!("invalid response: {error}"),
+                    data: None,
+                })
+            });
+            handler(server, result)
+        });
+
+        let id = self.outgoing_requests.register(R::METHOD, handler);
+        let req = Request::new(id, R::METHOD.to_owned(), params);
+        self.connection.sender.send(Message::Request(req))?;
+
+        Ok(())
+    }
+
+    /// Sends a request to the client, failures are only logged.
+    fn send_request<R>(&mut self, params: R::Params) -> anyhow::Result<()>
+    where
+        R: lsp_types::request::Request,
+        R::Result: DeserializeOwned,
+    {
+        self.send_request_with::<R>(params, |_, _| Ok(()))
+    }
+
+    /// Returns true if the client supports `workspace/applyEdit`.
+    fn supports_apply_edit(&self) -> bool {
+        self.config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.apply_edit)
+            .unwrap_or(false)
+    }
+
+    /// Asks the client to apply the edit of a command, and sends the response
+    /// of the command once the edit is applied. An edit that is rejected, or
+    /// not applied in time, fails the command.
+    fn apply_command_edit(
+        &mut self,
+        id: RequestId,
+        label: String,
+        edit: WorkspaceEdit,
+        result: Option<serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        let params = ApplyWorkspaceEditParams {
+            label: Some(label.clone()),
+            edit,
+        };
+
+        self.send_request_with::<ApplyWorkspaceEdit>(params, move |server, response| {
+            let failure = match response {
+                Ok(ApplyWorkspaceEditResponse { applied: true, .. }) => None,
+                Ok(ApplyWorkspaceEditResponse { failure_reason, .. }) => Some(
+                    failure_reason.unwrap_or_else(|| "the client rejected the edit".to_owned()),
+                ),
+                Err(error) => Some(error.message),
+            };
+
+            let resp = match failure {
+                None => Response::new_ok(id.clone(), result),
+                Some(reason) => {
+                    warn!("cannot apply the edit `{label}`: {reason}");
+                    Response::new_err(
+                        id.clone(),
+                        ErrorCode::RequestFailed as i32,
+                        format!("the edit was not applied: {reason}"),
+                    )
+                }
+            };
+
+            server.pending_requests.remove(&id);
+            server.connection.sender.send(Message::Response(resp))?;
+            Ok(())
+        })
+    }
+
+    /// Returns true if the client supports `workspace/configuration`.
+    fn supports_configuration(&self) -> bool {
+        self.config
+            .client_capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false)
+    }
+
+    /// Requests the settings of the server from the client.
+    fn pull_configuration(&mut self) -> anyhow::Result<()> {
+        let params = ConfigurationParams {
+            items: vec![ConfigurationItem {
+                scope_uri: None,
+                section: Some(CONFIGURATION_SECTION.to_owned()),
+            }],
+        };
+
+        self.send_request_with::<WorkspaceConfiguration>(params, |server, result| {
+            let Ok(settings) = result else {
+                return Ok(());
+            };
+
+            match settings.first() {
+                Some(settings) if settings.is_object() => server.on_configuration_changed(settings),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Polls the workspace for changes, for clients that cannot watch files.
+    fn start_polling(&mut self) {
+        if self.poll_files {
+            return;
+        }
+
+        info!("the client cannot watch files, polling the workspace");
+        self.poll_files = true;
+        self.spawn_poll();
+    }
+
+    /// Polls the workspace for changed files in a background worker thread.
+    /// The first poll only records the file times.
+    fn spawn_poll(&mut self) {
+        // Skip the tick if the previous poll is still in progress.
+        if self.poll_in_progress {
+            return;
+        }
+        self.poll_in_progress = true;
+
+        let previous = self.file_times.take();
+
+        let config = self.config.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let times = watcher::scan(&config);
+            let changes = match &previous {
+                Some(previous) => watcher::diff(previous, &times),
+                None => Vec::new(),
+            };
+            let _ = task_sender.send(Task::Polled { times, changes });
+        });
+    }
+
+    /// Reads the manifest in a background worker thread, the configuration is
+    /// updated in the message loop.
+    fn spawn_manifest_read(&self) {
+        let config = self.config.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let _ = task_sender.send(Task::ManifestRead(config.read_manifest()));
+        });
+    }
+
+    /// Updates the configuration, the index, and the diagnostics of files
+    /// that changed on disk.
+    fn on_watched_files_changed(&mut self, changes: Vec<FileEvent>) {
+        let mut paths = Vec::new();
+        let mut module_paths = Vec::new();
+        let mut analyzed_files = Vec::new();
+
+        for change in changes {
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+
+            if path.file_name().is_some_and(|name| name == MANIFEST_FILE) {
+                if self.config.root_path.as_deref() == path.parent() {
+                    info!("the manifest changed, reloading");
+                    self.spawn_manifest_read();
+                }
+                continue;
+            }
+
+            // The dependency lenses show the locked versions.
+            if path.file_name().is_some_and(|name| name == LOCK_FILE) {
+                if self.config.root_path.as_deref() == path.parent() {
+                    if let Err(error) = self.refresh_code_lenses() {
+                        warn!("cannot refresh the code lenses: {error}");
+                    }
+                }
+                continue;
+            }
+
+            if change.typ == FileChangeType::DELETED {
+                self.analyzed.remove(&change.uri);
+                self.clear_diagnostics(change.uri);
+            } else {
+                // Editors may touch the files, e.g. on focus.
+                let hash = self.analyzed.get(&change.uri).copied();
+                analyzed_files.push((change.uri, hash));
+            }
+
+            // Creating or deleting a file may resolve or break the imports of
+            // other files.
+            if change.typ != FileChangeType::CHANGED {
+                module_paths.push(path.clone());
+            }
+
+            paths.push(path);
+        }
+
+        if paths.is_empty() {
+            return;
+        }
+
+        if !analyzed_files.is_empty() {
+            self.spawn_batch_diagnostics(analyzed_files);
+        }
+
+        if !module_paths.is_empty() {
+            self.spawn_dependent_diagnostics(module_paths);
+        }
+
+        let is_burst = paths.len() >= REINDEXING_PROGRESS_THRESHOLD;
+        if is_burst {
+            if self.reindexing_bursts == 0 {
+                if let Err(error) = self.begin_progress(REINDEXING_PROGRESS_TOKEN, "Re-indexing") {
+                    warn!("cannot report the re-indexing progress: {error}");
+                }
+            }
+            self.reindexing_bursts += 1;
+        }
+
+        let config = self.config.clone();
+        let vfs = self.vfs.snapshot();
+        let index = self.index.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            // The open documents are indexed from the editor buffers. The
+            // files with an unchanged text are not parsed again.
+            let files: Vec<_> = paths
+                .iter()
+                .filter_map(|path| {
+                    let uri = Url::from_file_path(path).ok()?;
+                    if vfs.get(&uri).is_some() {
+                        return None;
+                    }
+                    let text = std::fs::read_to_string(path).ok();
+                    let file = match (index.get(&uri), text) {
+                        (Some(previous), Some(text)) if *previous.text == *text => return None,
+                        (Some(previous), Some(text)) => Some(previous.update(Arc::from(text))),
+                        (None, Some(text)) => {
+                            Some(IndexedFile::new(Origin::of(path, &config), Arc::from(text)))
+                        }
+                        (_, None) => None,
+                    };
+                    Some((uri, file))
+                })
+                .collect();
+            if !files.is_empty() {
+                let _ = task_sender.send(Task::Reindexed(files));
+            }
+            if is_burst {
+                let _ = task_sender.send(Task::BurstReindexed(paths.len()));
+            }
+        });
+    }
+
+    /// Returns true if the document is the manifest of the workspace, it is
+    /// neither indexed nor analyzed as Tan code.
+    fn is_manifest(&self, uri: &Url) -> bool {
+        uri.to_file_path()
+            .is_ok_and(|path| self.config.is_manifest(&path))
+    }
+
+    /// Validates the manifest of the workspace in a worker, and publishes the
+    /// diagnostics against the manifest file. The diagnostics are also the
+    /// response of the `tan/validateConfig` request, if any.
+    fn spawn_manifest_validation(&self, id: Option<RequestId>) {
+        let manifest = self.config.root_path.as_ref().and_then(|root_path| {
+            let uri = Url::from_file_path(root_path.join(MANIFEST_FILE)).ok()?;
+            Some((root_path.clone(), uri))
+        });
+
+        let vfs = self.vfs.snapshot();
+        let sender = self.connection.sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let mut result = lsp_ext::ValidateConfigResult {
+                uri: None,
+                diagnostics: Vec::new(),
+            };
+
+            // The unsaved changes of an open manifest are validated too.
+            if let Some((root_path, uri)) = manifest {
+                if let Ok(text) = vfs.read(&uri) {
+                    result.diagnostics = manifest::validate_manifest(&text, &root_path);
+                    let published = publish::publish_diagnostics(
+                        &sender,
+                        uri.clone(),
+                        vfs.version(&uri),
+                        result.diagnostics.clone(),
+                    );
+                    if let Err(error) = published {
+                        warn!("cannot publish the diagnostics of the manifest: {error}");
+                    }
+                    result.uri = Some(uri);
+                }
+            }
+
+            if let Some(id) = id {
+                let _ = sender.send(Message::Response(Response::new_ok(id, result)));
+            }
+        });
+    }
+
+    /// Re-indexes an open document from the editor buffer, in a worker. Only
+    /// the changed top-level forms are parsed again.
+    fn spawn_document_index(&mut self, uri: Url) {
+        if self.is_manifest(&uri) {
+            return;
+        }
+
+        let vfs = self.vfs.snapshot();
+        let (Some(text), Some(version)) = (vfs.get(&uri), vfs.version(&uri)) else {
+            return;
+        };
+
+        let previous = self.index.get(&uri).cloned();
+        let config = self.config.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let file = match previous {
+                Some(previous) => previous.update(text),
+                None => {
+                    let origin = uri
+                        .to_file_path()
+                        .map_or(Origin::Workspace, |path| Origin::of(&path, &config));
+                    IndexedFile::new(origin, text)
+                }
+            };
+            let _ = task_sender.send(Task::DocumentIndexed { uri, version, file });
+        });
+    }
+
+    /// Re-indexes a closed document from disk, the unsaved changes are
+    /// discarded. Documents outside of the workspace and the dependencies are
+    /// removed from the index.
+    fn spawn_file_index(&mut self, uri: Url) {
+        if self.is_manifest(&uri) {
+            return;
+        }
+
+        let path = uri
+            .to_file_path()
+            .ok()
+            .filter(|path| self.config.is_indexed(path));
+
+        let config = self.config.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let file = path.and_then(|path| IndexedFile::read(&path, &config));
+            let _ = task_sender.send(Task::Reindexed(vec![(uri, file)]));
+        });
+    }
+
+    /// Indexes the files imported by an open document, in a worker, if they
+    /// are not indexed yet, e.g. while the workspace is being indexed. The
+    /// first navigation into an imported module does not wait for the index.
+    fn spawn_prewarm(&mut self, uri: Url) {
+        let (Some(text), Ok(path)) = (self.vfs.snapshot().get(&uri), uri.to_file_path()) else {
+            return;
+        };
+
+        let config = self.config.clone();
+        let vfs = self.vfs.snapshot();
+        let index = self.index.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let files: Vec<_> = imported_files(&path, &text, &config)
+                .into_iter()
+                .filter(|file_path| config.is_indexed(file_path))
+                .filter_map(|file_path| {
+                    let file_uri = Url::from_file_path(&file_path).ok()?;
+                    if index.get(&file_uri).is_some() || vfs.get(&file_uri).is_some() {
+                        return None;
+                    }
+                    Some((file_uri, IndexedFile::read(&file_path, &config)?))
+                })
+                .collect();
+            if !files.is_empty() {
+                trace!("prewarmed {} imported files of `{uri}`", files.len());
+                let _ = task_sender.send(Task::Prewarmed(files));
+            }
+        });
+    }
+
+    /// Records a message from the client: the idle maintenance in progress
+    /// is interrupted, and runs again at the next idle period.
+    fn on_activity(&mut self) {
+        self.last_activity = Instant::now();
+        self.maintained = false;
+        if let Some(token) = &self.maintenance {
+            token.cancel();
+        }
+    }
+
+    /// Returns the time of the next idle maintenance, `None` if the
+    /// maintenance is disabled, in progress, or done since the last message.
+    fn idle_deadline(&self) -> Option<Instant> {
+        let delay = self.config.idle_maintenance_delay?;
+        if self.maintenance.is_some() || self.maintained || self.indexing {
+            return None;
+        }
+        Some(self.last_activity + delay)
+    }
+
+    /// Runs the maintenance tasks of an idle period in a background worker:
+    /// removes the deleted files from the index, and publishes the
+    /// diagnostics of the workspace files that were never analyzed. The
+    /// worker stops at the next message from the client.
+    fn spawn_idle_maintenance(&mut self) {
+        let token = CancellationToken::default();
+        self.maintenance = Some(token.clone());
+
+        let snapshot = self.snapshot();
+        let analyzed = self.analyzed.clone();
+        let run = self.diagnostics_runs.start([]);
+        let sender = self.connection.sender.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            // The files deleted without a file event, e.g. while the editor
+            // was not watching.
+            let stale: Vec<Url> = snapshot
+                .index
+                .files()
+                .filter(|(uri, _)| snapshot.vfs.get(uri).is_none())
+                .filter(|(uri, _)| uri.to_file_path().is_ok_and(|path| !path.exists()))
+                .map(|(uri, _)| uri.clone())
+                .collect();
+
+            let mut pending: Vec<(&Url, &Arc<str>)> = snapshot
+                .index
+                .files()
+                .filter(|(uri, file)| {
+                    file.origin == Origin::Workspace
+                        && snapshot.vfs.get(uri).is_none()
+                        && !stale.contains(uri)
+                        && analyzed.get(*uri) != Some(&text_hash(&file.text))
+                })
+                .map(|(uri, file)| (uri, &file.text))
+                .collect();
+            pending.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+            let mut completed = !token.is_cancelled();
+            if completed && !pending.is_empty() {
+                let graph = ModuleGraph::build(&snapshot);
+                for (uri, text) in pending {
+                    if token.is_cancelled() {
+                        completed = false;
+                        break;
+                    }
+                    let published = diagnostics::file_diagnostics(uri, text, &snapshot, &graph)
+                        .and_then(|diagnostics| {
+                            let hash = text_hash(text);
+                            publish_analyzed(
+                                &sender,
+                                &task_sender,
+                                &run,
+                                uri,
+                                hash,
+                                None,
+                                diagnostics,
+                            )
+                        });
+                    if let Err(error) = published {
+                        warn!("cannot publish diagnostics for `{uri}`: {error}");
+                    }
+                }
+            }
+
+            let _ = task_sender.send(Task::Maintained { stale, completed });
+        });
+    }
+
+    /// Sends the `tan/metrics` notification.
+    fn send_metrics(&self) -> anyhow::Result<()> {
+        let usage = self.caches.usage();
+        let params = lsp_ext::MetricsParams {
+            open_documents: self.vfs.len(),
+            indexed_files: self.index.len(),
+            indexed_definitions: self.index.definition_count(),
+            interactive_queue_len: self.workers.queue_len(Priority::Interactive),
+            background_queue_len: self.workers.queue_len(Priority::Background),
+            evaluation_queue_len: self.workers.queue_len(Priority::Evaluation),
+            uptime: self.started_at.elapsed().as_secs(),
+            cache_evictions: usage.evictions,
+            last_cache_eviction: usage.last_eviction.map(|time| time.elapsed().as_secs()),
+        };
+
+        let notification = Notification::new(lsp_ext::Metrics::METHOD.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+
+        Ok(())
+    }
+
+    /// Returns an immutable snapshot of the current state.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            config: self.config.clone(),
+            vfs: self.vfs.snapshot(),
+            index: self.index.clone(),
+            caches: self.caches.clone(),
+            indexing: self.indexing,
+            cancellation: CancellationToken::default(),
+        }
+    }
+
+    /// Indexes the workspace and the dependency directories in a background
+    /// worker thread.
+    fn spawn_indexing(&mut self) -> anyhow::Result<()> {
+        // #Insight
+        // Until the index is built, requests are served from the open
+        // documents, or fail with `ContentModified` if partial results would
+        // be wrong.
+        self.indexing = true;
+        self.begin_progress(INDEXING_PROGRESS_TOKEN, "Indexing")?;
+        self.send_status(Some("indexing".to_owned()))?;
+
+        let config = self.config.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let _ = task_sender.send(Task::Indexed(Index::build(&config)));
+        });
+
+        Ok(())
+    }
+
+    /// Returns true if the client supports server-initiated progress.
+    fn supports_work_done_progress(&self) -> bool {
+        self.config
+            .client_capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false)
+    }
+
+    /// Creates a progress token and reports the beginning of the work.
+    fn begin_progress(&mut self, token: &str, title: &str) -> anyhow::Result<()> {
+        if !self.supports_work_done_progress() {
+            return Ok(());
+        }
+
+        // #Insight
+        // Progress can only be reported after the client created the token,
+        // the work may even end before that.
+        self.progress_tokens.insert(token.to_owned(), false);
+
+        let token = token.to_owned();
+        let title = title.to_owned();
+        let params = WorkDoneProgressCreateParams {
+            token: NumberOrString::String(token.clone()),
+        };
+
+        self.send_request_with::<WorkDoneProgressCreate>(params, move |server, result| {
+            let Some(begun) = server.progress_tokens.get_mut(&token) else {
+                return Ok(());
+            };
+
+            if result.is_err() {
+                server.progress_tokens.remove(&token);
+                return Ok(());
+            }
+
+            *begun = true;
+            server.send_progress(
+                NumberOrString::String(token),
+                WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                    title,
+                    ..Default::default()
+                }),
+            )
+        })
+    }
+
+    /// Reports the end of the work of a progress token.
+    fn end_progress(&mut self, token: &str, message: Option<String>) -> anyhow::Result<()> {
+        if self.progress_tokens.remove(token) != Some(true) {
+            return Ok(());
+        }
+
+        self.send_progress(
+            NumberOrString::String(token.to_owned()),
+            WorkDoneProgress::End(WorkDoneProgressEnd { message }),
+        )
+    }
+
+    fn send_progress(&self, token: NumberOrString, value: WorkDoneProgress) -> anyhow::Result<()> {
+        let params = ProgressParams {
+            token,
+            value: ProgressParamsValue::WorkDone(value),
+        };
+
+        let notification = Notification::new(Progress::METHOD.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+
+        Ok(())
+    }
+
+    /// Sends the `tan/serverStatus` notification.
+    fn send_status(&self, message: Option<String>) -> anyhow::Result<()> {
+        let health = if self.import_cycles > 0 {
+            lsp_ext::Health::Warning
+        } else {
+            lsp_ext::Health::Ok
+        };
+
+        let message = message.or_else(|| {
+            (self.import_cycles > 0).then(|| {
+                format!(
+                    "{} imports participate in import cycles",
+                    self.import_cycles
+                )
+            })
+        });
+
+        let params = lsp_ext::ServerStatusParams {
+            quiescent: !self.indexing,
+            health,
+            message,
+        };
+
+        let notification = Notification::new(lsp_ext::ServerStatus::METHOD.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+
+        Ok(())
+    }
+
+    /// Extracts the params of a request. Malformed params are answered with an
+    /// `InvalidParams` error, returns `None`, the server keeps running.
+    fn extract_request<P: DeserializeOwned>(
+        &self,
+        req: Request,
+        method: &str,
+    ) -> anyhow::Result<Option<(RequestId, P)>> {
+        let id = req.id.clone();
+        match req.extract::<P>(method) {
+            Ok(extracted) => Ok(Some(extracted)),
+            Err(ExtractError::JsonError { method, error }) => {
+                warn!("invalid params of the request `{method}`: {error}");
+                let resp =
+                    Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string());
+                self.connection.sender.send(Message::Response(resp))?;
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Processes a request in a worker thread, against a snapshot of the
+    /// current state, and sends the result (or the error) as the response.
+    fn on_request<R>(
+        &mut self,
+        req: Request,
+        handler: fn(&Snapshot, R::Params) -> anyhow::Result<R::Result>,
+    ) -> anyhow::Result<()>
+    where
+        R: lsp_types::request::Request,
+        R::Params: Send + 'static,
+        R::Result: 'static,
+    {
+        let Some((id, params)) = self.extract_request::<R::Params>(req, R::METHOD)? else {
+            return Ok(());
+        };
+        let snapshot = self.snapshot();
+        let sender = self.connection.sender.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.pending_requests
+            .insert(id.clone(), snapshot.cancellation.clone());
+
+        self.workers.spawn(request_priority(R::METHOD), move || {
+            // A panic of the handler is an internal error of the request.
+            let result = catch_unwind(AssertUnwindSafe(|| handler(&snapshot, params)))
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("the handler panicked")));
+            let resp = match result {
+                Ok(result) => Response::new_ok(id.clone(), result),
+                Err(error) => error_response(id.clone(), R::METHOD, error, &task_sender),
+            };
+
+            // The client may have disconnected, nothing to do.
+            let _ = sender.send(Message::Response(resp));
+            let _ = task_sender.send(Task::Completed(id));
+        });
+
+        Ok(())
+    }
+
+    /// Executes a command in a worker thread, like `on_request`. The edit of
+    /// the command is applied with `workspace/applyEdit` in the message loop,
+    /// before the response is sent. Without client support, the edit is only
+    /// returned.
+    fn on_execute_command(&mut self, req: Request) -> anyhow::Result<()> {
+        let Some((id, params)) =
+            self.extract_request::<ExecuteCommandParams>(req, ExecuteCommand::METHOD)?
+        else {
+            return Ok(());
+        };
+        let snapshot = self.snapshot();
+        let sender = self.connection.sender.clone();
+        let task_sender = self.task_sender.clone();
+        let apply_edits = self.supports_apply_edit() && !self.config.dry_run;
+
+        self.pending_requests
+            .insert(id.clone(), snapshot.cancellation.clone());
+
+        self.workers
+            .spawn(request_priority(ExecuteCommand::METHOD), move || {
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    handlers::execute_command::execute_command(&snapshot, params)
+                }))
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("the handler panicked")));
+
+                let resp = match result {
+                    Ok(CommandOutput {
+                        result,
+                        edit: Some((label, edit)),
+                    }) if apply_edits => {
+                        let _ = task_sender.send(Task::CommandEdited {
+                            id,
+                            label,
+                            edit,
+                            result,
+                        });
+                        return;
+                    }
+                    Ok(output) => Response::new_ok(id.clone(), output.result),
+                    Err(error) => {
+                        error_response(id.clone(), ExecuteCommand::METHOD, error, &task_sender)
+                    }
+                };
+
+                // The client may have disconnected, nothing to do.
+                let _ = sender.send(Message::Response(resp));
+                let _ = task_sender.send(Task::Completed(id));
+            });
+
+        Ok(())
+    }
+
+    /// Evaluates a top-level form (`tan.evaluate`) in the evaluation worker
+    /// thread. The result is the response, and is also shown to the user, the
+    /// lenses invoke the command without an extension to display it.
+    fn on_evaluate(&mut self, req: Request) -> anyhow::Result<()> {
+        let Some((id, params)) =
+            self.extract_request::<ExecuteCommandParams>(req, ExecuteCommand::METHOD)?
+        else {
+            return Ok(());
+        };
+        let snapshot = self.snapshot();
+        let sender = self.connection.sender.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.pending_requests
+            .insert(id.clone(), snapshot.cancellation.clone());
+
+        self.workers.spawn(Priority::Evaluation, move || {
+            let result = handlers::execute_command::evaluate_form(&snapshot, &params.arguments);
+            let resp = match result {
+                Ok(result) => {
+                    let (typ, message) = match (&result.value, &result.error) {
+                        (_, Some(error)) => {
+                            (MessageType::ERROR, format!("{}{error}", result.output))
+                        }
+                        (Some(value), None) => {
+                            (MessageType::INFO, format!("{}⇒ {value}", result.output))
+                        }
+                        (None, None) => (MessageType::INFO, result.output.clone()),
+                    };
+                    let params = ShowMessageParams { typ, message };
+                    let notification = Notification::new(ShowMessage::METHOD.to_owned(), params);
+                    let _ = sender.send(Message::Notification(notification));
+                    Response::new_ok(id.clone(), result)
+                }
+                Err(error) if error.is::<Cancelled>() => Response::new_err(
+                    id.clone(),
+                    ErrorCode::RequestCanceled as i32,
+                    error.to_string(),
+                ),
+                Err(error) => Response::new_err(
+                    id.clone(),
+                    ErrorCode::InternalError as i32,
+                    format!("{error:#}"),
+                ),
+            };
+
+            // The client may have disconnected, nothing to do.
+            let _ = sender.send(Message::Response(resp));
+            let _ = task_sender.send(Task::Completed(id));
+        });
+
+        Ok(())
+    }
+
+    /// Computes and publishes the diagnostics of a document in a background
+    /// worker thread.
+    fn spawn_diagnostics(&self, uri: Url) {
+        self.spawn_diagnostics_unless(uri, None);
+    }
+
+    /// Computes and publishes the diagnostics of a document, unless the text
+    /// is the one of the published diagnostics, e.g. when a file is saved.
+    fn spawn_diagnostics_if_changed(&self, uri: Url) {
+        let hash = self.analyzed.get(&uri).copied();
+        self.spawn_diagnostics_unless(uri, hash);
+    }
+
+    /// Computes and publishes the diagnostics of a document, unless the hash
+    /// of the text is the given one.
+    fn spawn_diagnostics_unless(&self, uri: Url, unchanged_hash: Option<u64>) {
+        self.spawn_batch_diagnostics(vec![(uri, unchanged_hash)]);
+    }
+
+    /// Computes and publishes the diagnostics of documents, unless the hash of
+    /// the text is the given one, in a single worker. The module graph is
+    /// built once for all the documents.
+    fn spawn_batch_diagnostics(&self, files: Vec<(Url, Option<u64>)>) {
+        let mut validate_manifest = false;
+        let files: Vec<(Url, Option<u64>)> = files
+            .into_iter()
+            .filter(|(uri, _)| {
+                if self.is_manifest(uri) {
+                    validate_manifest = true;
+                    return false;
+                }
+                uri.to_file_path()
+                    .map_or(true, |path| !self.config.is_dependency(&path))
+            })
+            .collect();
+
+        if validate_manifest {
+            self.spawn_manifest_validation(None);
+        }
+
+        if files.is_empty() {
+            return;
+        }
+
+        let run = self
+            .diagnostics_runs
+            .start(files.iter().map(|(uri, _)| uri));
+        let snapshot = self.snapshot();
+        let sender = self.connection.sender.clone();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let inputs: Vec<_> = files
+                .into_iter()
+                .filter_map(|(uri, unchanged_hash)| {
+                    let input = snapshot.vfs.read(&uri);
+                    let hash = input.as_ref().ok().map(|input| text_hash(input));
+                    if hash.is_some() && hash == unchanged_hash {
+                        trace!("`{uri}` is unchanged, the diagnostics are not published");
+                        return None;
+                    }
+                    Some((uri, input, hash))
+                })
+                .collect();
+
+            if inputs.is_empty() {
+                return;
+            }
+
+            let graph = ModuleGraph::build(&snapshot);
+
+            for (uri, input, _) in &inputs {
+                let input = match input {
+                    Ok(input) => input,
+                    Err(error) => {
+                        warn!("cannot publish diagnostics for `{uri}`: {error}");
+                        continue;
+                    }
+                };
+                let hash = text_hash(input);
+                let version = snapshot.vfs.version(uri);
+                let published = diagnostics::file_diagnostics(uri, input, &snapshot, &graph)
+                    .and_then(|diagnostics| {
+                        publish_analyzed(
+                            &sender,
+                            &task_sender,
+                            &run,
+                            uri,
+                            hash,
+                            version,
+                            diagnostics,
+                        )
+                    });
+                if let Err(error) = published {
+                    warn!("cannot publish diagnostics for `{uri}`: {error}");
+                }
+            }
+
+            // #Insight
+            // Editing the imports of a document may create or break import
+            // cycles through the other open documents.
+            // #TODO also republish the closed files that participate in cycles.
+            for (other_uri, input) in snapshot.vfs.documents() {
+                let Ok(path) = other_uri.to_file_path() else {
+                    continue;
+                };
+
+                let is_analyzed = inputs.iter().any(|(uri, ..)| uri == other_uri);
+                if is_analyzed || graph.imports_of(&path).is_empty() {
+                    continue;
+                }
+
+                if let Err(error) = publish::send_diagnostics(
+                    &sender,
+                    &run,
+                    other_uri.clone(),
+                    input,
+                    snapshot.vfs.version(other_uri),
+                    &snapshot,
+                    &graph,
+                ) {
+                    warn!("cannot publish diagnostics for `{other_uri}`: {error}");
+                }
+            }
+        });
+    }
+
+    /// Clears the diagnostics of a document, superseding the runs in progress.
+    fn clear_diagnostics(&self, uri: Url) {
+        let run = self.diagnostics_runs.start([&uri]);
+        let cleared = run.publish(&uri, || {
+            publish::clear_diagnostics(&self.connection.sender, uri.clone())
+        });
+        if let Some(Err(error)) = cleared {
+            warn!("cannot clear the diagnostics of `{uri}`: {error}");
+        }
+    }
+
+    /// Counts the import cycles of the workspace in a background worker
+    /// thread, the count is reported in the server status.
+    fn spawn_cycle_check(&self) {
+        let snapshot = self.snapshot();
+        let task_sender = self.task_sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let graph = ModuleGraph::build(&snapshot);
+            let _ = task_sender.send(Task::CyclesChecked(graph.cyclic_import_count()));
+        });
+    }
+
+    /// Republishes the diagnostics of the files that import the modules of
+    /// created or deleted files, in a background worker thread.
+    fn spawn_dependent_diagnostics(&self, module_paths: Vec<PathBuf>) {
+        let run = self.diagnostics_runs.start([]);
+        let snapshot = self.snapshot();
+        let sender = self.connection.sender.clone();
+
+        self.workers.spawn(Priority::Background, move || {
+            let graph = ModuleGraph::build(&snapshot);
+            let dependents = graph.dependents_of(&module_paths);
+
+            for (uri, input) in snapshot.sources() {
+                let Ok(path) = uri.to_file_path() else {
+                    continue;
+                };
+
+                if module_paths.contains(&path) || !dependents.contains(uri) {
+                    continue;
+                }
+
+                if let Err(error) = publish::send_diagnostics(
+                    &sender,
+                    &run,
+                    uri.clone(),
+                    input,
+                    snapshot.vfs.version(uri),
+                    &snapshot,
+                    &graph,
+                ) {
+                    warn!("cannot publish diagnostics for `{uri}`: {error}");
+                }
+            }
+        });
+    }
+
+    fn handle_request(&mut self, req: Request) -> anyhow::Result<()> {
+        match req.method.as_ref() {
+            CallHierarchyPrepare::METHOD => {
+                self.on_request::<CallHierarchyPrepare>(
+                    req,
+                    handlers::call_hierarchy::prepare_call_hierarchy,
+                )?;
+            }
+            CallHierarchyIncomingCalls::METHOD => {
+                self.on_request::<CallHierarchyIncomingCalls>(
+                    req,
+                    handlers::call_hierarchy::incoming_calls,
+                )?;
+            }
+            CallHierarchyOutgoingCalls::METHOD => {
+                self.on_request::<CallHierarchyOutgoingCalls>(
+                    req,
+                    handlers::call_hierarchy::outgoing_calls,
+                )?;
+            }
+            CodeActionRequest::METHOD => {
+                self.on_request::<CodeActionRequest>(req, handlers::code_action::code_action)?;
+            }
+            CodeLensRequest::METHOD => {
+                self.on_request::<CodeLensRequest>(req, handlers::code_lens::code_lens)?;
+            }
+            CodeLensResolve::METHOD => {
+                self.on_request::<CodeLensResolve>(req, handlers::code_lens::code_lens_resolve)?;
+            }
+            Completion::METHOD => {
+                self.on_request::<Completion>(req, handlers::completion::completion)?;
+            }
+            ResolveCompletionItem::METHOD => {
+                self.on_request::<ResolveCompletionItem>(
+                    req,
+                    handlers::completion::completion_resolve,
+                )?;
+            }
+            DocumentLinkRequest::METHOD => {
+                self.on_request::<DocumentLinkRequest>(
+                    req,
+                    handlers::document_link::document_link,
+                )?;
+            }
+            DocumentLinkResolve::METHOD => {
+                self.on_request::<DocumentLinkResolve>(
+                    req,
+                    handlers::document_link::document_link_resolve,
+                )?;
+            }
+            DocumentSymbolRequest::METHOD => {
+                self.on_request::<DocumentSymbolRequest>(
+                    req,
+                    handlers::document_symbol::document_symbol,
+                )?;
+            }
+            ExecuteCommand::METHOD
+                if req
+                    .params
+                    .get("command")
+                    .and_then(serde_json::Value::as_str)
+                    == Some(handlers::execute_command::EVALUATE_COMMAND) =>
+            {
+                self.on_evaluate(req)?;
+            }
+            ExecuteCommand::METHOD => self.on_execute_command(req)?,
+            Formatting::METHOD => {
+                self.on_request::<Formatting>(req, handlers::formatting::formatting)?;
+            }
+            GotoDefinition::METHOD => {
+                self.on_request::<GotoDefinition>(req, handlers::definition::definition)?;
+            }
+            HoverRequest::METHOD => {
+                self.on_request::<HoverRequest>(req, handlers::hover::hover)?;
+            }
+            OnTypeFormatting::METHOD => {
+                self.on_request::<OnTypeFormatting>(
+                    req,
+                    handlers::on_type_formatting::on_type_formatting,
+                )?;
+            }
+            RangeFormatting::METHOD => {
+                self.on_request::<RangeFormatting>(req, handlers::formatting::range_formatting)?;
+            }
+            References::METHOD => {
+                self.on_request::<References>(req, handlers::references::references)?;
+            }
+            DocumentColor::METHOD => {
+                self.on_request::<DocumentColor>(req, handlers::document_color::document_color)?;
+            }
+            ColorPresentationRequest::METHOD => {
+                self.on_request::<ColorPresentationRequest>(
+                    req,
+                    handlers::document_color::color_presentation,
+                )?;
+            }
+            DocumentHighlightRequest::METHOD => {
+                self.on_request::<DocumentHighlightRequest>(
+                    req,
+                    handlers::document_highlight::document_highlight,
+                )?;
+            }
+            InlayHintRequest::METHOD => {
+                self.on_request::<InlayHintRequest>(req, handlers::inlay_hint::inlay_hint)?;
+            }
+            FoldingRangeRequest::METHOD => {
+                self.on_request::<FoldingRangeRequest>(
+                    req,
+                    handlers::folding_range::folding_range,
+                )?;
+            }
+            PrepareRenameRequest::METHOD => {
+                self.on_request::<PrepareRenameRequest>(req, handlers::rename::prepare_rename)?;
+            }
+            Rename::METHOD => {
+                self.on_request::<Rename>(req, handlers::rename::rename)?;
+            }
+            WillRenameFiles::METHOD => {
+                self.on_request::<WillRenameFiles>(req, handlers::file_rename::will_rename_files)?;
+            }
+            SelectionRangeRequest::METHOD => {
+                self.on_request::<SelectionRangeRequest>(
+                    req,
+                    handlers::selection_range::selection_range,
+                )?;
+            }
+            SemanticTokensFullRequest::METHOD => {
+                self.on_request::<SemanticTokensFullRequest>(
+                    req,
+                    handlers::semantic_tokens::semantic_tokens_full,
+                )?;
+            }
+            SignatureHelpRequest::METHOD => {
+                self.on_request::<SignatureHelpRequest>(
+                    req,
+                    handlers::signature_help::signature_help,
+                )?;
+            }
+            WorkspaceSymbolRequest::METHOD => {
+                self.on_request::<WorkspaceSymbolRequest>(
+                    req,
+                    handlers::workspace_symbol::workspace_symbol,
+                )?;
+            }
+            lsp_ext::NodeAtPosition::METHOD => {
+                self.on_request::<lsp_ext::NodeAtPosition>(
+                    req,
+                    handlers::syntax_tree::node_at_position,
+                )?;
+            }
+            lsp_ext::TypeAtPosition::METHOD => {
+                self.on_request::<lsp_ext::TypeAtPosition>(
+                    req,
+                    handlers::type_at_position::type_at_position,
+                )?;
+            }
+            lsp_ext::InterruptEvaluation::METHOD => {
+                // Handled in the message loop, a kill switch should not wait
+                // for the workers. The evaluations cannot stop cooperatively,
+                // they are killed, a kill may even happen after `interrupt`
+                // saw no running evaluation, so `clean` is always false.
+                let Some((id, ())) =
+                    self.extract_request::<()>(req, lsp_ext::InterruptEvaluation::METHOD)?
+                else {
+                    return Ok(());
+                };
+                let interrupted = evaluate::interrupt();
+                let result = lsp_ext::InterruptEvaluationResult {
+                    interrupted,
+                    clean: false,
+                };
+                let resp = Response::new_ok(id, result);
+                self.connection.sender.send(Message::Response(resp))?;
+            }
+            lsp_ext::MemoryUsage::METHOD => {
+                let Some((id, ())) =
+                    self.extract_request::<()>(req, lsp_ext::MemoryUsage::METHOD)?
+                else {
+                    return Ok(());
+                };
+                let usage = self.caches.usage();
+                let result = lsp_ext::MemoryUsageResult {
+                    caches: usage
+                        .caches
+                        .into_iter()
+                        .map(|(kind, cache)| lsp_ext::CacheMemoryUsage {
+                            name: kind.name().to_owned(),
+                            entries: cache.entries,
+                            size: cache.size,
+                        })
+                        .collect(),
+                    budget: usage.budget,
+                    evictions: usage.evictions,
+                    last_eviction: usage.last_eviction.map(|time| time.elapsed().as_secs()),
+                };
+                let resp = Response::new_ok(id, result);
+                self.connection.sender.send(Message::Response(resp))?;
+            }
+            lsp_ext::ValidateConfig::METHOD => {
+                let Some((id, ())) =
+                    self.extract_request::<()>(req, lsp_ext::ValidateConfig::METHOD)?
+                else {
+                    return Ok(());
+                };
+                self.spawn_manifest_validation(Some(id));
+            }
+            lsp_ext::EmbeddedDocuments::METHOD => {
+                self.on_request::<lsp_ext::EmbeddedDocuments>(
+                    req,
+                    handlers::embedded_documents::embedded_documents,
+                )?;
+            }
+            lsp_ext::ViewSyntaxTree::METHOD => {
+                self.on_request::<lsp_ext::ViewSyntaxTree>(
+                    req,
+                    handlers::syntax_tree::view_syntax_tree,
+                )?;
+            }
+            _ => {
+                let resp = Response::new_err(
+                    req.id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request `{}`", req.method),
+                );
+                self.connection.sender.send(Message::Response(resp))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, event: Notification) -> anyhow::Result<()> {
+        match event.method.as_ref() {
+            Cancel::METHOD => {
+                let Some(params) = extract_notification::<CancelParams>(event, Cancel::METHOD)?
+                else {
+                    return Ok(());
+                };
+                let id = match params.id {
+                    lsp_types::NumberOrString::Number(id) => RequestId::from(id),
+                    lsp_types::NumberOrString::String(id) => RequestId::from(id),
+                };
+                if let Some(cancellation) = self.pending_requests.get(&id) {
+                    cancellation.cancel();
+                }
+            }
+            DidOpenTextDocument::METHOD => {
+                let Some(params) = extract_notification::<DidOpenTextDocumentParams>(
+                    event,
+                    DidOpenTextDocument::METHOD,
+                )?
+                else {
+                    return Ok(());
+                };
+                crash::document_opened(&params.text_document.uri);
+                self.vfs.open(
+                    params.text_document.uri.clone(),
+                    params.text_document.text,
+                    params.text_document.version,
+                );
+                // #Insight
+                // The diagnostics of open documents follow the editor
+                // buffers, not the files on disk.
+                self.spawn_document_index(params.text_document.uri.clone());
+                self.spawn_prewarm(params.text_document.uri.clone());
+                self.spawn_diagnostics(params.text_document.uri);
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(params) = extract_notification::<DidChangeTextDocumentParams>(
+                    event,
+                    DidChangeTextDocument::METHOD,
+                )?
+                else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                self.vfs
+                    .update(&uri, params.content_changes, params.text_document.version);
+                self.spawn_document_index(uri.clone());
+                self.spawn_diagnostics(uri);
+            }
+            DidSaveTextDocument::METHOD => {
+                let Some(params) = extract_notification::<DidSaveTextDocumentParams>(
+                    event,
+                    DidSaveTextDocument::METHOD,
+                )?
+                else {
+                    return Ok(());
+                };
+                self.spawn_diagnostics_if_changed(params.text_document.uri);
+            }
+            DidCloseTextDocument::METHOD => {
+                let Some(params) = extract_notification::<DidCloseTextDocumentParams>(
+                    event,
+                    DidCloseTextDocument::METHOD,
+                )?
+                else {
+                    return Ok(());
+                };
+                crash::document_closed(&params.text_document.uri);
+                self.vfs.close(&params.text_document.uri);
+                self.spawn_file_index(params.text_document.uri.clone());
+                // The unsaved changes are discarded, the diagnostics follow
+                // the file on disk. Without a workspace root, closed files
+                // are not analyzed.
+                if self.config.is_single_file_mode() {
+                    self.analyzed.remove(&params.text_document.uri);
+                    self.clear_diagnostics(params.text_document.uri);
+                } else {
+                    self.spawn_diagnostics(params.text_document.uri);
+                }
+            }
+            DidChangeConfiguration::METHOD => {
+                let Some(event) = extract_notification::<DidChangeConfigurationParams>(
+                    event,
+                    DidChangeConfiguration::METHOD,
+                )?
+                else {
+                    return Ok(());
+                };
+                // #Insight
+                // Clients that support `workspace/configuration` may not send
+                // the settings with the notification.
+                if self.supports_configuration() {
+                    self.pull_configuration()?;
+                } else {
+                    self.on_configuration_changed(&event.settings)?;
+                }
+            }
+            DidChangeWatchedFiles::METHOD => {
+                let Some(event) = extract_notification::<DidChangeWatchedFilesParams>(
+                    event,
+                    DidChangeWatchedFiles::METHOD,
+                )?
+                else {
+                    return Ok(());
+                };
+                self.file_events.push(event.changes);
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the error response of a failed request.
+fn error_response(
+    id: RequestId,
+    method: &str,
+    error: anyhow::Error,
+    task_sender: &Sender<Task>,
+) -> Response {
+    if error.is::<Cancelled>() {
+        return Response::new_err(id, ErrorCode::RequestCanceled as i32, error.to_string());
+    }
+    if error.is::<NotIndexed>() {
+        return Response::new_err(id, ErrorCode::ContentModified as i32, error.to_string());
+    }
+
+    // The request has no result, the client is asked to download the sources.
+    match error.downcast::<MissingSources>() {
+        Ok(missing) => {
+            let _ = task_sender.send(Task::SourcesMissing(missing));
+            Response::new_ok(id, serde_json::Value::Null)
+        }
+        Err(error) => {
+            warn!("request `{method}` failed: {error}");
+            Response::new_err(id, ErrorCode::InternalError as i32, error.to_string())
+        }
+    }
+}
+
+/// Extracts the params of a notification. Malformed params are logged and the
+/// notification is dropped, returns `None`, the server keeps running.
+fn extract_notification<P: DeserializeOwned>(
+    event: Notification,
+    method: &str,
+) -> anyhow::Result<Option<P>> {
+    match event.extract::<P>(method) {
+        Ok(params) => Ok(Some(params)),
+        Err(ExtractError::JsonError { method, error }) => {
+            warn!("invalid params of the notification `{method}`: {error}");
+            Ok(None)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Publishes the diagnostics of a text for a run, and records the hash of the
+/// text. The hash is sent while the run holds its lock, the recorded hash is
+/// the one of the latest run.
+fn publish_analyzed(
+    sender: &Sender<Message>,
+    task_sender: &Sender<Task>,
+    run: &DiagnosticsRun,
+    uri: &Url,
+    hash: u64,
+    version: Option<i32>,
+    diagnostics: Vec<Diagnostic>,
+) -> anyhow::Result<()> {
+    let published = run.publish(uri, || {
+        publish::publish_diagnostics(sender, uri.clone(), version, diagnostics)?;
+        let _ = task_sender.send(Task::Analyzed {
+            uri: uri.clone(),
+            hash,
+        });
+        anyhow::Ok(())
+    });
+
+    published.unwrap_or(Ok(()))
+}
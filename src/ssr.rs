@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::ast::for_each_expr;
+
+// #Insight
+// A rule has the form `pattern ==>> template`, e.g.
+// `(if $c $a nil) ==>> (when $c $a)`. Symbols starting with `$` are
+// placeholders, they match any expression. A placeholder used more than once
+// matches equal expressions.
+
+/// The separator of the pattern and the template of a rule.
+const RULE_SEPARATOR: &str = "==>>";
+
+/// The prefix of placeholder symbols.
+const PLACEHOLDER_PREFIX: char = '$';
+
+/// A structural search and replace rule.
+pub struct Rule {
+    pattern: Ann<Expr>,
+    template: String,
+}
+
+/// A match of a rule, with the (char) range of the matched expression and the
+/// replacement text.
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
+impl Rule {
+    /// Parses a rule, the template may only use placeholders of the pattern.
+    pub fn parse(rule: &str) -> anyhow::Result<Self> {
+        let Some((pattern, template)) = rule.split_once(RULE_SEPARATOR) else {
+            bail!("invalid rule, expected `pattern {RULE_SEPARATOR} template`");
+        };
+
+        let mut exprs = parse_string_all(pattern.trim())
+            .map_err(|_| anyhow!("cannot parse the pattern `{}`", pattern.trim()))?;
+        exprs.retain(|expr| !matches!(expr.0, Expr::Comment(_)));
+        if exprs.len() != 1 {
+            bail!("the pattern should be a single expression");
+        }
+
+        let template = template.trim().to_owned();
+        if parse_string_all(&template).is_err() {
+            bail!("cannot parse the template `{template}`");
+        }
+
+        let mut placeholders = Vec::new();
+        for_each_expr(&exprs, &mut |expr| {
+            if let Ann(Expr::Symbol(sym), _) = expr {
+                if sym.starts_with(PLACEHOLDER_PREFIX) {
+                    placeholders.push(sym.clone());
+                }
+            }
+        });
+
+        for segment in template_segments(&template) {
+            if let Segment::Placeholder(name) = segment {
+                if !placeholders.contains(&name) {
+                    bail!("the placeholder `{name}` is not defined in the pattern");
+                }
+            }
+        }
+
+        Ok(Self {
+            pattern: exprs.remove(0),
+            template,
+        })
+    }
+
+    /// Returns the matches of the rule in a document. Matches never overlap,
+    /// the outermost expression wins.
+    pub fn find_matches(&self, exprs: &[Ann<Expr>], input: &str) -> Vec<Match> {
+        let input_chars: Vec<char> = input.chars().collect();
+        let mut matches: Vec<Match> = Vec::new();
+
+        for_each_expr(exprs, &mut |expr| {
+            let range = expr.get_range();
+
+            // Skip synthesized expressions, and the expressions nested in a
+            // previous match.
+            if range.start >= range.end || matches.last().is_some_and(|m| range.start < m.end) {
+                return;
+            }
+
+            let mut bindings = HashMap::new();
+            if !match_expr(&self.pattern, expr, &input_chars, &mut bindings) {
+                return;
+            }
+
+            matches.push(Match {
+                start: range.start,
+                end: range.end,
+                replacement: self.render(&bindings),
+            });
+        });
+
+        matches
+    }
+
+    /// Renders the template, replacing the placeholders with the source text
+    /// of the bound expressions.
+    fn render(&self, bindings: &HashMap<String, String>) -> String {
+        template_segments(&self.template)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text,
+                Segment::Placeholder(name) => bindings.get(&name).cloned().unwrap_or(name),
+            })
+            .collect()
+    }
+}
+
+/// A segment of a template.
+enum Segment {
+    Text(String),
+    Placeholder(String),
+}
+
+fn is_placeholder_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Splits a template into text and placeholders.
+fn template_segments(template: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != PLACEHOLDER_PREFIX {
+            text.push(c);
+            continue;
+        }
+
+        let mut name = String::from(c);
+        while let Some(&c) = chars.peek() {
+            if !is_placeholder_char(c) {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+
+        segments.push(Segment::Text(std::mem::take(&mut text)));
+        segments.push(Segment::Placeholder(name));
+    }
+
+    segments.push(Segment::Text(text));
+
+    segments
+}
+
+/// Returns the source text of an expression.
+fn source_text(expr: &Ann<Expr>, input_chars: &[char]) -> String {
+    let range = expr.get_range();
+    let end = range.end.min(input_chars.len());
+    input_chars
+        .get(range.start..end)
+        .unwrap_or_default()
+        .iter()
+        .collect()
+}
+
+/// Matches an expression against a pattern, binding the placeholders to the
+/// source text of the matched expressions. Comments are ignored.
+fn match_expr(
+    pattern: &Ann<Expr>,
+    expr: &Ann<Expr>,
+    input_chars: &[char],
+    bindings: &mut HashMap<String, String>,
+) -> bool {
+    match (&pattern.0, &expr.0) {
+        (Expr::Symbol(sym), _) if sym.starts_with(PLACEHOLDER_PREFIX) => {
+            let source = source_text(expr, input_chars);
+            match bindings.get(sym) {
+                Some(bound) => *bound == source,
+                None => {
+                    bindings.insert(sym.clone(), source);
+                    true
+                }
+            }
+        }
+        (Expr::List(pattern_terms), Expr::List(terms)) => {
+            let pattern_terms: Vec<_> = pattern_terms
+                .iter()
+                .filter(|term| !matches!(term.0, Expr::Comment(_)))
+                .collect();
+            let terms: Vec<_> = terms
+                .iter()
+                .filter(|term| !matches!(term.0, Expr::Comment(_)))
+                .collect();
+
+            pattern_terms.len() == terms.len()
+                && pattern_terms
+                    .iter()
+                    .zip(terms)
+                    .all(|(pattern, expr)| match_expr(pattern, expr, input_chars, bindings))
+        }
+        (Expr::List(_), _) | (_, Expr::List(_)) => false,
+        // #Insight
+        // The debug representation includes the kind of the leaf, e.g. a
+        // symbol never matches a string with the same text.
+        (pattern, expr) => format!("{pattern:?}") == format!("{expr:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the matches of a rule to an input.
+    fn replace_all(rule: &str, input: &str) -> String {
+        let rule = Rule::parse(rule).unwrap();
+        let exprs = parse_string_all(input).unwrap();
+        let mut chars: Vec<char> = input.chars().collect();
+
+        for m in rule.find_matches(&exprs, input).iter().rev() {
+            chars.splice(m.start..m.end, m.replacement.chars());
+        }
+
+        chars.into_iter().collect()
+    }
+
+    #[test]
+    fn parse_rejects_invalid_rules() {
+        assert!(Rule::parse("(if $c $a nil)").is_err());
+        assert!(Rule::parse("(f $a) (g $a) ==>> $a").is_err());
+        assert!(Rule::parse("(f $a) ==>> (g $b)").is_err());
+        assert!(Rule::parse("(f $a) ==>> (g $a").is_err());
+    }
+
+    #[test]
+    fn placeholders_are_replaced_with_the_source_text() {
+        let output = replace_all(
+            "(if $c $a nil) ==>> (when $c $a)",
+            "(if (> x 1) (writeln x) nil)\n(if y z w)\n",
+        );
+
+        assert_eq!(output, "(when (> x 1) (writeln x))\n(if y z w)\n");
+    }
+
+    #[test]
+    fn a_repeated_placeholder_matches_equal_expressions() {
+        let output = replace_all("(+ $a $a) ==>> (* 2 $a)", "(+ x x)\n(+ x y)\n");
+
+        assert_eq!(output, "(* 2 x)\n(+ x y)\n");
+    }
+
+    #[test]
+    fn the_outermost_match_wins() {
+        let output = replace_all("(not $a) ==>> $a", "(not (not x))\n");
+
+        assert_eq!(output, "(not x)\n");
+    }
+
+    #[test]
+    fn a_symbol_never_matches_a_string() {
+        let output = replace_all("(f x) ==>> (g x)", "(f \"x\")\n(f x)\n");
+
+        assert_eq!(output, "(f \"x\")\n(g x)\n");
+    }
+
+    #[test]
+    fn comments_are_ignored_by_the_matching() {
+        let output = replace_all("(f $a) ==>> (g $a)", "(f ; the argument\n 1)\n");
+
+        assert_eq!(output, "(g 1)\n");
+    }
+}
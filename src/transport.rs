@@ -0,0 +1,141 @@
+use std::{
+    io::{self, BufReader},
+    net::{TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam_channel::bounded;
+use lsp_server::{Connection, ErrorCode, Message, Response};
+use lsp_types::notification::{Exit, Notification as _};
+use tracing::{info, warn};
+
+// #Insight
+// A server process serves a single client, the state of the session (e.g. the
+// open documents) is not shared. The clients that connect to the socket while
+// a client is served are answered with an error, instead of interleaving
+// their messages with the session.
+
+/// The error message sent to the rejected clients.
+const BUSY_MESSAGE: &str =
+    "The Tan LSP server already serves a client, start a separate server for each client";
+
+/// The time a rejected client has to send its first request.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The threads that move the messages between the transport and the
+/// connection.
+pub enum IoThreads {
+    Stdio(lsp_server::IoThreads),
+    Socket {
+        reader: JoinHandle<io::Result<()>>,
+        writer: JoinHandle<io::Result<()>>,
+    },
+}
+
+impl IoThreads {
+    /// Waits for the threads to end, e.g. after the `exit` notification.
+    pub fn join(self) -> anyhow::Result<()> {
+        match self {
+            IoThreads::Stdio(io_threads) => io_threads.join()?,
+            IoThreads::Socket { reader, writer } => {
+                for thread in [reader, writer] {
+                    thread
+                        .join()
+                        .map_err(|_| anyhow::anyhow!("the transport thread panicked"))??;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a connection over stdio.
+pub fn stdio() -> (Connection, IoThreads) {
+    let (connection, io_threads) = Connection::stdio();
+
+    (connection, IoThreads::Stdio(io_threads))
+}
+
+/// Listens on the (TCP) address, and creates a connection with the first
+/// client. The clients that connect later are rejected.
+pub fn listen(addr: &str) -> anyhow::Result<(Connection, IoThreads)> {
+    let listener = TcpListener::bind(addr)?;
+    info!("listening on {}", listener.local_addr()?);
+
+    let (stream, peer) = listener.accept()?;
+    info!("client connected from {peer}");
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    thread::spawn(move || {
+                        if let Err(error) = reject(stream) {
+                            warn!("cannot reject the client: {error}");
+                        }
+                    });
+                }
+                Err(error) => warn!("cannot accept a client: {error}"),
+            }
+        }
+    });
+
+    socket_connection(stream)
+}
+
+/// Creates a connection over a socket stream.
+fn socket_connection(stream: TcpStream) -> anyhow::Result<(Connection, IoThreads)> {
+    let (reader_sender, reader_receiver) = bounded::<Message>(0);
+    let mut read_stream = BufReader::new(stream.try_clone()?);
+    let reader = thread::spawn(move || {
+        while let Some(msg) = Message::read(&mut read_stream)? {
+            let is_exit = matches!(&msg, Message::Notification(n) if n.method == Exit::METHOD);
+            if reader_sender.send(msg).is_err() || is_exit {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    let (writer_sender, writer_receiver) = bounded::<Message>(0);
+    let mut write_stream = stream;
+    let writer = thread::spawn(move || {
+        writer_receiver
+            .into_iter()
+            .try_for_each(|msg| msg.write(&mut write_stream))
+    });
+
+    let connection = Connection {
+        sender: writer_sender,
+        receiver: reader_receiver,
+    };
+
+    Ok((connection, IoThreads::Socket { reader, writer }))
+}
+
+/// Answers the first request of a client, typically `initialize`, with an
+/// error, and closes the connection. The notifications are ignored.
+fn reject(stream: TcpStream) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    warn!("rejected the client from {peer}, the server already serves a client");
+
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    while let Some(msg) = Message::read(&mut reader)? {
+        if let Message::Request(req) = msg {
+            let response = Response::new_err(
+                req.id,
+                ErrorCode::RequestFailed as i32,
+                BUSY_MESSAGE.to_owned(),
+            );
+            Message::Response(response).write(&mut writer)?;
+            break;
+        }
+    }
+
+    Ok(())
+}
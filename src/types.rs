@@ -0,0 +1,93 @@
+use tan::{ann::Ann, expr::Expr};
+
+use crate::{
+    ast::{find_path_at, head_symbol},
+    resolve::{bound_value, resolve_symbol_at},
+};
+
+// #Insight
+// The types are inferred statically: the Tan resolver evaluates the values of
+// the `let` bindings, i.e. it would execute the code of the workspace. Only
+// the type annotations, the literals, and the values of the bindings are
+// inferred.
+
+// #TODO infer the result types of the function calls.
+
+/// The maximum depth of the inference through bindings, e.g. `(let b a)`.
+const MAX_DEPTH: usize = 8;
+
+/// Returns the type annotation of an expression, e.g. `Int` for `#Int x`.
+pub fn annotated_type(expr: &Ann<Expr>) -> Option<String> {
+    expr.get_annotation("type")
+        .map(|type_expr| type_expr.to_string())
+}
+
+/// Infers the type of an expression of the document, `None` if unknown.
+pub fn infer_type(exprs: &[Ann<Expr>], expr: &Ann<Expr>) -> Option<String> {
+    infer(exprs, expr, 0)
+}
+
+fn infer(exprs: &[Ann<Expr>], expr: &Ann<Expr>, depth: usize) -> Option<String> {
+    if let Some(type_name) = annotated_type(expr) {
+        return Some(type_name);
+    }
+
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    let type_name = match &expr.0 {
+        Expr::Int(_) => "Int",
+        Expr::Float(_) => "Float",
+        Expr::String(_) => "String",
+        Expr::Char(_) => "Char",
+        Expr::Bool(_) => "Bool",
+        Expr::KeySymbol(_) => "KeySymbol",
+        Expr::Symbol(_) => {
+            let range = expr.get_range();
+            let binding = resolve_symbol_at(exprs, range.start)?;
+            if binding.name_range == range {
+                return None;
+            }
+
+            // The bound name may be annotated, e.g. a parameter `(Func (#Int n) ...)`.
+            let name_type = find_path_at(exprs, binding.name_range.start)
+                .last()
+                .filter(|name_expr| name_expr.get_range() == binding.name_range)
+                .and_then(|name_expr| annotated_type(name_expr));
+
+            return name_type.or_else(|| infer(exprs, bound_value(exprs, &binding)?, depth + 1));
+        }
+        Expr::List(terms) => {
+            let terms: Vec<&Ann<Expr>> = terms
+                .iter()
+                .filter(|term| !matches!(term, Ann(Expr::Comment(..), _)))
+                .collect();
+
+            match head_symbol(expr)? {
+                head @ ("Array" | "Dict" | "Func" | "Macro") => head,
+                "do" => return infer(exprs, terms[1..].last()?, depth + 1),
+                "if" => {
+                    let true_type = infer(exprs, terms.get(2)?, depth + 1)?;
+                    let false_type = infer(exprs, terms.get(3)?, depth + 1)?;
+                    return (true_type == false_type).then_some(true_type);
+                }
+                "+" | "-" | "*" | "/" => {
+                    let [first, rest @ ..] = &terms[1..] else {
+                        return None;
+                    };
+                    let first_type = infer(exprs, first, depth + 1)?;
+                    let is_number = first_type == "Int" || first_type == "Float";
+                    let same_types = rest
+                        .iter()
+                        .all(|arg| infer(exprs, arg, depth + 1).as_ref() == Some(&first_type));
+                    return (is_number && same_types).then_some(first_type);
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(type_name.to_owned())
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use lsp_types::{
+    notification::{DidChangeWatchedFiles, Notification},
+    ClientCapabilities, DidChangeWatchedFilesRegistrationOptions, FileChangeType, FileEvent,
+    FileSystemWatcher, GlobPattern, Registration, RegistrationParams, Url,
+};
+
+use crate::{
+    config::{Config, MANIFEST_FILE},
+    index::tan_files,
+    packages::LOCK_FILE,
+};
+
+// #Insight
+// The formatter options are read from the manifest, there is no separate
+// formatter config file to watch.
+
+/// The globs of the files watched by the client.
+pub const WATCHED_GLOBS: [&str; 3] = ["**/*.tan", "**/tan.toml", "**/tan.lock"];
+
+/// The interval between polls of the workspace, for clients that cannot
+/// watch files.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The delay between the first watched file event and the processing of the
+/// events, the events that arrive meanwhile are processed together.
+pub const COALESCE_DELAY: Duration = Duration::from_millis(100);
+
+/// The id of the watched files registration.
+const REGISTRATION_ID: &str = "tan/watchedFiles";
+
+/// The modification times of the polled files.
+pub type FileTimes = HashMap<PathBuf, SystemTime>;
+
+// #Insight
+// A `git checkout` may deliver thousands of events, in many notifications.
+// The events are coalesced, the files are read and analyzed once per burst.
+
+/// The watched file events waiting to be processed, the latest change per
+/// uri.
+#[derive(Debug, Default)]
+pub struct FileEventQueue {
+    events: HashMap<Url, FileChangeType>,
+    deadline: Option<Instant>,
+}
+
+impl FileEventQueue {
+    /// Queues events, the queue is due after the coalesce delay of the first
+    /// queued event.
+    pub fn push(&mut self, changes: Vec<FileEvent>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        self.deadline
+            .get_or_insert_with(|| Instant::now() + COALESCE_DELAY);
+
+        for change in changes {
+            let typ = match (self.events.get(&change.uri), change.typ) {
+                // A created file is new to the index, whatever happens next,
+                // unless it is deleted.
+                (Some(&FileChangeType::CREATED), FileChangeType::CHANGED) => {
+                    FileChangeType::CREATED
+                }
+                (Some(&FileChangeType::DELETED), FileChangeType::CHANGED) => {
+                    FileChangeType::CREATED
+                }
+                (_, typ) => typ,
+            };
+            self.events.insert(change.uri, typ);
+        }
+    }
+
+    /// Returns when the queued events are due, `None` if the queue is empty.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Takes the queued events, ordered by uri.
+    pub fn take(&mut self) -> Vec<FileEvent> {
+        self.deadline = None;
+        let mut changes: Vec<FileEvent> = self
+            .events
+            .drain()
+            .map(|(uri, typ)| FileEvent { uri, typ })
+            .collect();
+        changes.sort_by(|a, b| a.uri.cmp(&b.uri));
+        changes
+    }
+}
+
+/// Returns true if the client can register file watchers dynamically.
+pub fn supports_dynamic_registration(capabilities: &ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+        .and_then(|watched_files| watched_files.dynamic_registration)
+        .unwrap_or(false)
+}
+
+/// Returns the parameters of the `client/registerCapability` request that
+/// registers the file watchers.
+pub fn registration_params() -> RegistrationParams {
+    let watchers = WATCHED_GLOBS
+        .iter()
+        .map(|glob| FileSystemWatcher {
+            glob_pattern: GlobPattern::String(glob.to_string()),
+            kind: None,
+        })
+        .collect();
+
+    let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+
+    RegistrationParams {
+        registrations: vec![Registration {
+            id: REGISTRATION_ID.to_owned(),
+            method: DidChangeWatchedFiles::METHOD.to_owned(),
+            register_options: serde_json::to_value(options).ok(),
+        }],
+    }
+}
+
+/// Returns the modification times of the workspace files, the manifest and
+/// the lockfile.
+pub fn scan(config: &Config) -> FileTimes {
+    let mut times = FileTimes::new();
+
+    let Some(root_path) = &config.root_path else {
+        return times;
+    };
+
+    let mut paths = tan_files(root_path, &config.dependency_paths());
+    paths.push(root_path.join(MANIFEST_FILE));
+    paths.push(root_path.join(LOCK_FILE));
+
+    for path in paths {
+        if let Ok(modified) = std::fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            times.insert(path, modified);
+        }
+    }
+
+    times
+}
+
+/// Returns the changes between two scans, as watched file events.
+pub fn diff(old: &FileTimes, new: &FileTimes) -> Vec<FileEvent> {
+    let mut changes = Vec::new();
+
+    for (path, modified) in new {
+        let typ = match old.get(path) {
+            None => FileChangeType::CREATED,
+            Some(old_modified) if old_modified != modified => FileChangeType::CHANGED,
+            Some(_) => continue,
+        };
+        if let Ok(uri) = Url::from_file_path(path) {
+            changes.push(FileEvent { uri, typ });
+        }
+    }
+
+    for path in old.keys() {
+        if !new.contains_key(path) {
+            if let Ok(uri) = Url::from_file_path(path) {
+                changes.push(FileEvent {
+                    uri,
+                    typ: FileChangeType::DELETED,
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, typ: FileChangeType) -> FileEvent {
+        FileEvent {
+            uri: Url::parse(&format!("file:///workspace/{name}")).unwrap(),
+            typ,
+        }
+    }
+
+    fn taken(queue: &mut FileEventQueue) -> Vec<(String, FileChangeType)> {
+        queue
+            .take()
+            .into_iter()
+            .map(|event| (event.uri.path().to_owned(), event.typ))
+            .collect()
+    }
+
+    #[test]
+    fn file_event_queue_keeps_the_latest_change_per_uri() {
+        let mut queue = FileEventQueue::default();
+        queue.push(vec![
+            event("b.tan", FileChangeType::CHANGED),
+            event("a.tan", FileChangeType::CHANGED),
+        ]);
+        queue.push(vec![
+            event("b.tan", FileChangeType::DELETED),
+            event("a.tan", FileChangeType::CHANGED),
+        ]);
+
+        assert_eq!(
+            taken(&mut queue),
+            [
+                ("/workspace/a.tan".to_owned(), FileChangeType::CHANGED),
+                ("/workspace/b.tan".to_owned(), FileChangeType::DELETED),
+            ]
+        );
+    }
+
+    #[test]
+    fn file_event_queue_keeps_the_creations_unless_deleted() {
+        let mut queue = FileEventQueue::default();
+        queue.push(vec![
+            event("created.tan", FileChangeType::CREATED),
+            event("recreated.tan", FileChangeType::DELETED),
+            event("removed.tan", FileChangeType::CREATED),
+        ]);
+        queue.push(vec![
+            event("created.tan", FileChangeType::CHANGED),
+            event("recreated.tan", FileChangeType::CHANGED),
+            event("removed.tan", FileChangeType::DELETED),
+        ]);
+
+        assert_eq!(
+            taken(&mut queue),
+            [
+                ("/workspace/created.tan".to_owned(), FileChangeType::CREATED),
+                (
+                    "/workspace/recreated.tan".to_owned(),
+                    FileChangeType::CREATED
+                ),
+                ("/workspace/removed.tan".to_owned(), FileChangeType::DELETED),
+            ]
+        );
+    }
+
+    #[test]
+    fn file_event_queue_is_due_after_the_delay_of_the_first_event() {
+        let mut queue = FileEventQueue::default();
+        queue.push(Vec::new());
+        assert_eq!(queue.deadline(), None);
+
+        let before = Instant::now();
+        queue.push(vec![event("a.tan", FileChangeType::CHANGED)]);
+        let deadline = queue.deadline().unwrap();
+        assert!(deadline >= before + COALESCE_DELAY);
+
+        queue.push(vec![event("b.tan", FileChangeType::CHANGED)]);
+        assert_eq!(queue.deadline(), Some(deadline));
+
+        queue.take();
+        assert_eq!(queue.deadline(), None);
+        assert!(queue.take().is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    thread,
+};
+
+use crossbeam_channel::{unbounded, Sender};
+use lsp_types::request::{
+    CallHierarchyPrepare, Completion, DocumentHighlightRequest, DocumentSymbolRequest,
+    FoldingRangeRequest, Formatting, HoverRequest, OnTypeFormatting, PrepareRenameRequest,
+    RangeFormatting, Request, SelectionRangeRequest, SignatureHelpRequest,
+};
+use tracing::error;
+
+use crate::crash;
+
+// #Insight
+// Latency-sensitive requests are processed in a separate queue, so that
+// typing-time features never wait behind a workspace-wide search.
+
+// #Insight
+// An evaluation waits for its child process, up to the time limit of the
+// sandbox. The evaluations have their own queue, they never delay the
+// analysis, e.g. the diagnostics and the indexing.
+
+// #Insight
+// Tan expressions are not `Send`, tasks should capture the document text and
+// parse it in the worker thread.
+
+/// The number of worker threads for interactive tasks.
+const INTERACTIVE_THREAD_COUNT: usize = 2;
+
+/// The number of worker threads for background tasks.
+const BACKGROUND_THREAD_COUNT: usize = 2;
+
+/// The number of worker threads for the evaluations, they run one at a time.
+const EVALUATION_THREAD_COUNT: usize = 1;
+
+/// The priority of a task, selects the worker queue that processes the task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Latency-sensitive tasks, e.g. completion, hover, signature help.
+    Interactive,
+    /// Heavy tasks, e.g. references, workspace symbols, full semantic tokens.
+    Background,
+    /// The evaluations of Tan code, e.g. `tan.evaluate`.
+    Evaluation,
+}
+
+/// Returns the priority of a request method.
+pub fn request_priority(method: &str) -> Priority {
+    match method {
+        Completion::METHOD
+        | HoverRequest::METHOD
+        | SignatureHelpRequest::METHOD
+        | OnTypeFormatting::METHOD
+        | Formatting::METHOD
+        | RangeFormatting::METHOD
+        | DocumentSymbolRequest::METHOD
+        | DocumentHighlightRequest::METHOD
+        | SelectionRangeRequest::METHOD
+        | FoldingRangeRequest::METHOD
+        | PrepareRenameRequest::METHOD
+        | CallHierarchyPrepare::METHOD => Priority::Interactive,
+        // E.g. references, workspace symbols, full semantic tokens.
+        _ => Priority::Background,
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A pool of threads that process the tasks of a queue.
+struct Pool {
+    sender: Sender<Task>,
+}
+
+impl Pool {
+    fn new(name: &str, thread_count: usize) -> Self {
+        let (sender, receiver) = unbounded::<Task>();
+
+        for i in 0..thread_count {
+            let receiver = receiver.clone();
+            let thread_name = format!("{name}-{i}");
+            thread::Builder::new()
+                .name(thread_name.clone())
+                .spawn(move || {
+                    // A panic of a task does not kill the thread, the next
+                    // tasks are processed.
+                    crash::recover_from_panics();
+                    for task in receiver {
+                        if catch_unwind(AssertUnwindSafe(task)).is_err() {
+                            error!("a task panicked in worker thread `{thread_name}`");
+                        }
+                    }
+                })
+                .expect("cannot spawn worker thread");
+        }
+
+        Self { sender }
+    }
+}
+
+/// The worker threads, one pool per priority.
+pub struct Workers {
+    interactive: Pool,
+    background: Pool,
+    evaluation: Pool,
+}
+
+impl Default for Workers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Workers {
+    pub fn new() -> Self {
+        Self {
+            interactive: Pool::new("interactive", INTERACTIVE_THREAD_COUNT),
+            background: Pool::new("background", BACKGROUND_THREAD_COUNT),
+            evaluation: Pool::new("evaluation", EVALUATION_THREAD_COUNT),
+        }
+    }
+
+    fn pool(&self, priority: Priority) -> &Pool {
+        match priority {
+            Priority::Interactive => &self.interactive,
+            Priority::Background => &self.background,
+            Priority::Evaluation => &self.evaluation,
+        }
+    }
+
+    /// Queues a task for processing.
+    pub fn spawn(&self, priority: Priority, task: impl FnOnce() + Send + 'static) {
+        let pool = self.pool(priority);
+
+        // The worker threads survive the panics of the tasks and outlive the
+        // server loop, sending cannot fail.
+        pool.sender.send(Box::new(task)).unwrap();
+    }
+
+    /// Returns the number of tasks waiting in a queue.
+    pub fn queue_len(&self, priority: Priority) -> usize {
+        self.pool(priority).sender.len()
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use tan::api::parse_string_all;
+
+use crate::{config::Config, format::format_exprs, glob::glob_matches, index::tan_files};
+
+// #Insight
+// The workspace is formatted with the options of the configuration only, the
+// formatting options of the client (e.g. tabs) are ignored, the editor and
+// the headless check in CI must agree on the formatted text.
+
+/// Returns true if the file, or one of its directories, matches an ignore
+/// pattern of the formatting options.
+pub fn is_format_ignored(path: &Path, config: &Config) -> bool {
+    let Some(relative_path) = config
+        .root_path
+        .as_ref()
+        .and_then(|root_path| path.strip_prefix(root_path).ok())
+    else {
+        return false;
+    };
+
+    relative_path
+        .ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .any(|path| {
+            let path = path.to_string_lossy().replace('\\', "/");
+            config
+                .format
+                .ignore
+                .iter()
+                .any(|pattern| glob_matches(pattern.trim_end_matches('/'), &path))
+        })
+}
+
+/// Returns the files of the workspace formatting, i.e. the Tan files of the
+/// workspace, except the dependencies and the ignored files.
+pub fn workspace_format_files(config: &Config) -> Vec<PathBuf> {
+    let Some(root_path) = &config.root_path else {
+        return Vec::new();
+    };
+
+    tan_files(root_path, &config.dependency_paths())
+        .into_iter()
+        .filter(|path| !is_format_ignored(path, config))
+        .collect()
+}
+
+/// Formats a source, returns `None` if it has syntax errors.
+pub fn format_source(input: &str, config: &Config) -> Option<String> {
+    let exprs = parse_string_all(input).ok()?;
+
+    Some(format_exprs(&exprs, &config.format))
+}
+
+/// The outcome of the headless workspace formatting.
+#[derive(Debug, Default)]
+pub struct FormatReport {
+    /// The files that were not formatted, rewritten unless checking.
+    pub unformatted: Vec<PathBuf>,
+    /// The files with syntax errors, never rewritten.
+    pub invalid: Vec<PathBuf>,
+}
+
+/// Formats the workspace files on disk, or only checks that they are
+/// formatted, e.g. in CI.
+pub fn format_workspace_files(config: &Config, check: bool) -> anyhow::Result<FormatReport> {
+    if !check && config.read_only {
+        bail!("the files are never written in read-only mode");
+    }
+
+    let mut report = FormatReport::default();
+
+    for path in workspace_format_files(config) {
+        let input = std::fs::read_to_string(&path)?;
+
+        let Some(formatted) = format_source(&input, config) else {
+            report.invalid.push(path);
+            continue;
+        };
+
+        if formatted != input {
+            if !check {
+                std::fs::write(&path, formatted)?;
+            }
+            report.unformatted.push(path);
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::{Diagnostic, Url};
+
+use crate::{
+    config::Config,
+    diagnostics::{cap_diagnostics, compute_diagnostics},
+    index::Index,
+    module_graph::ModuleGraph,
+    vfs::Vfs,
+};
+
+// #Insight
+// The analysis is the one of the editor, without the LSP transport, e.g. for
+// the playground, build tools and pre-commit hooks. The positions of the
+// diagnostics are encoded in UTF-16, the default LSP position encoding.
+
+// #Insight
+// The analysis only depends on its options, not on the environment variables
+// of the server or the home directory, the callers read the environment if
+// they need to.
+
+/// The options of the analysis of a source.
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisOptions {
+    /// The path of the source, the imported modules are resolved relative to
+    /// the path.
+    pub path: Option<PathBuf>,
+    /// The root directory of the workspace. The manifest is read from the
+    /// root, and the files of the workspace are scanned to report the import
+    /// cycles.
+    pub root_path: Option<PathBuf>,
+    /// The directory of the standard library, for the imports of the standard
+    /// modules.
+    pub stdlib_path: Option<PathBuf>,
+    /// The directory of the downloaded package sources, for the imports of the
+    /// dependencies. There is no default, e.g. `~/.tan/packages` is only used
+    /// if it is given.
+    pub package_cache_path: Option<PathBuf>,
+    /// Report the visually confusable names.
+    pub confusables_lint: bool,
+    /// The maximum number of diagnostics, unlimited if `None`.
+    pub max_diagnostics: Option<usize>,
+}
+
+/// Analyzes the sources of a workspace. The workspace files are indexed once,
+/// when the analyzer is created, e.g. to analyze the files of a commit.
+pub struct Analyzer {
+    config: Config,
+    index: Index,
+}
+
+impl Analyzer {
+    /// Creates an analyzer, reads the manifest and indexes the files of the
+    /// workspace, if the options have a root path. The path of the options is
+    /// ignored, the sources are analyzed with their own paths.
+    pub fn new(options: &AnalysisOptions) -> Self {
+        let mut config = Config {
+            root_path: options.root_path.clone(),
+            stdlib_path: options.stdlib_path.clone(),
+            package_cache_path: options.package_cache_path.clone(),
+            confusables_lint: options.confusables_lint,
+            max_diagnostics_per_file: options.max_diagnostics.unwrap_or(usize::MAX),
+            ..Default::default()
+        };
+        config.update_from_manifest();
+
+        let index = match config.root_path {
+            Some(_) => Index::build(&config),
+            None => Index::default(),
+        };
+
+        Self { config, index }
+    }
+
+    /// Analyzes a Tan source, returns the diagnostics that the editor reports
+    /// for the source. The source shadows the indexed file of its path.
+    pub fn analyze(&self, text: &str, path: Option<&Path>) -> anyhow::Result<Vec<Diagnostic>> {
+        let uri = path.and_then(|path| Url::from_file_path(path).ok());
+
+        let mut graph = ModuleGraph::default();
+        if let Some(uri) = &uri {
+            graph.add_indexed_files(&self.index, &self.config, |indexed_uri| indexed_uri == uri);
+            graph.add_source(uri, text, &self.config);
+        }
+
+        // There are no open documents, the imported modules are read from disk.
+        let vfs = Vfs::default().snapshot();
+        let diagnostics = compute_diagnostics(text, path, &self.config, &vfs, &graph)?;
+
+        Ok(cap_diagnostics(
+            diagnostics,
+            self.config.max_diagnostics_per_file,
+        ))
+    }
+}
+
+/// Analyzes a Tan source, returns the diagnostics that the editor reports
+/// for the source. The workspace is indexed on every call, an `Analyzer`
+/// indexes it once for many sources.
+pub fn analyze_source(text: &str, options: &AnalysisOptions) -> anyhow::Result<Vec<Diagnostic>> {
+    Analyzer::new(options).analyze(text, options.path.as_deref())
+}
@@ -0,0 +1,82 @@
+use tan::{ann::Ann, expr::Expr};
+
+// #TODO consider moving these helpers to the `tan` crate.
+
+/// Returns true if the (char) index falls within the range of the expression.
+/// Synthesized expressions (without a range) never contain an index.
+pub fn contains_index(expr: &Ann<Expr>, index: usize) -> bool {
+    let range = expr.get_range();
+    range.start < range.end && range.start <= index && index < range.end
+}
+
+/// Returns the chain of expressions that contain the (char) index, from the
+/// top-level expression to the innermost one.
+pub fn find_path_at(exprs: &[Ann<Expr>], index: usize) -> Vec<&Ann<Expr>> {
+    let mut path = Vec::new();
+    let mut exprs = exprs;
+
+    'outer: loop {
+        for expr in exprs {
+            if contains_index(expr, index) {
+                path.push(expr);
+                if let Ann(Expr::List(terms), _) = expr {
+                    exprs = terms;
+                    continue 'outer;
+                }
+                break 'outer;
+            }
+        }
+        break;
+    }
+
+    path
+}
+
+/// Calls the function for each expression, recursively, in source order.
+pub fn for_each_expr<'a>(exprs: &'a [Ann<Expr>], f: &mut impl FnMut(&'a Ann<Expr>)) {
+    for expr in exprs {
+        f(expr);
+        if let Ann(Expr::List(terms), _) = expr {
+            for_each_expr(terms, f);
+        }
+    }
+}
+
+/// Returns the head symbol of a list expression, e.g. `let` for `(let a 1)`.
+pub fn head_symbol(expr: &Ann<Expr>) -> Option<&str> {
+    let Ann(Expr::List(terms), _) = expr else {
+        return None;
+    };
+
+    match terms.first() {
+        Some(Ann(Expr::Symbol(sym), _)) => Some(sym),
+        _ => None,
+    }
+}
+
+/// Strips the comment prefix (`;` or `--`) from a comment line.
+pub fn strip_comment_prefix(comment: &str) -> &str {
+    let text = if let Some(text) = comment.strip_prefix("--") {
+        text
+    } else {
+        comment.trim_start_matches(';')
+    };
+    text.strip_prefix(' ').unwrap_or(text)
+}
+
+/// Joins consecutive comment expressions into doc-comment text.
+pub fn comments_to_doc(comments: &[&Ann<Expr>]) -> Option<String> {
+    let lines: Vec<&str> = comments
+        .iter()
+        .filter_map(|expr| match expr {
+            Ann(Expr::Comment(s), _) => Some(strip_comment_prefix(s)),
+            _ => None,
+        })
+        .collect();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    mem::size_of,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+
+use lsp_types::{SemanticToken, Url};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+use tracing::trace;
+
+use crate::{ast::for_each_expr, index::text_hash};
+
+// #Insight
+// The caches are shared by the snapshots, the handlers of the open documents
+// reuse the parsed expressions and the semantic tokens of unchanged texts.
+// The entries are keyed by uri, a new text replaces the entry of the previous
+// one. When the estimated size exceeds the memory budget, the least recently
+// used entries of all the caches are evicted.
+
+// #Insight
+// The expressions are not `Send`, because of the `Rc` of the foreign
+// functions. The parser never creates foreign functions, the cached
+// expressions are checked to be plain data before they are shared between the
+// worker threads.
+
+/// The estimated heap size of the annotations of an expression, e.g. the
+/// range.
+const ANNOTATIONS_SIZE: usize = 128;
+
+/// The kind of a cached value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    /// The parsed expressions of a text.
+    Ast,
+    /// The semantic tokens of a text.
+    Tokens,
+}
+
+impl CacheKind {
+    pub const ALL: [CacheKind; 2] = [CacheKind::Ast, CacheKind::Tokens];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheKind::Ast => "ast",
+            CacheKind::Tokens => "tokens",
+        }
+    }
+}
+
+/// Parsed expressions, shared between the worker threads.
+#[derive(Clone)]
+struct SharedExprs(Arc<[Ann<Expr>]>);
+
+// SAFETY: the only field of `Expr` that is not `Send` and `Sync` is the `Rc`
+// of `ForeignFunc`, the shared expressions have no foreign functions (see
+// `is_plain`) and are never mutated.
+unsafe impl Send for SharedExprs {}
+unsafe impl Sync for SharedExprs {}
+
+#[derive(Clone)]
+enum CachedValue {
+    /// `None` if the text does not parse.
+    Ast(Option<SharedExprs>),
+    Tokens(Arc<[SemanticToken]>),
+}
+
+struct Entry {
+    /// The hash of the inputs of the value, e.g. the text.
+    key: u64,
+    value: CachedValue,
+    /// The estimated size of the entry, in bytes.
+    size: usize,
+    /// The tick of the last use.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<(CacheKind, Url), Entry>,
+    /// The estimated size of the entries, in bytes.
+    size: usize,
+    /// Increased on every use of an entry.
+    tick: u64,
+    evictions: u64,
+    last_eviction: Option<Instant>,
+}
+
+/// The usage of a cache.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheUsage {
+    pub entries: usize,
+    /// The estimated size of the entries, in bytes.
+    pub size: usize,
+}
+
+/// The usage of the caches.
+#[derive(Debug, Clone)]
+pub struct CachesUsage {
+    pub caches: Vec<(CacheKind, CacheUsage)>,
+    /// The memory budget, in bytes.
+    pub budget: usize,
+    /// The number of entries evicted to stay within the budget.
+    pub evictions: u64,
+    pub last_eviction: Option<Instant>,
+}
+
+/// The caches of the values computed from the documents, within a memory
+/// budget.
+pub struct Caches {
+    /// The memory budget, in bytes.
+    budget: usize,
+    state: Mutex<CacheState>,
+}
+
+impl Caches {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the parsed expressions of the text of a document, `None` if
+    /// the text does not parse.
+    pub fn ast(&self, uri: &Url, text: &str) -> Option<Arc<[Ann<Expr>]>> {
+        let key = text_hash(text);
+        if let Some(CachedValue::Ast(exprs)) = self.get(CacheKind::Ast, uri, key) {
+            return exprs.map(|exprs| exprs.0);
+        }
+
+        // The text is parsed without holding the lock, the handlers of other
+        // documents are not blocked.
+        let exprs: Option<Arc<[Ann<Expr>]>> = parse_string_all(text).ok().map(Arc::from);
+        match &exprs {
+            Some(exprs) if !exprs.iter().all(is_plain) => {}
+            Some(exprs) => {
+                let size = ast_size(exprs, text);
+                let value = CachedValue::Ast(Some(SharedExprs(exprs.clone())));
+                self.insert(CacheKind::Ast, uri, key, value, size);
+            }
+            None => self.insert(CacheKind::Ast, uri, key, CachedValue::Ast(None), 0),
+        }
+
+        exprs
+    }
+
+    /// Returns the cached semantic tokens of a document, for the hash of
+    /// their inputs.
+    pub fn tokens(&self, uri: &Url, key: u64) -> Option<Arc<[SemanticToken]>> {
+        match self.get(CacheKind::Tokens, uri, key) {
+            Some(CachedValue::Tokens(tokens)) => Some(tokens),
+            _ => None,
+        }
+    }
+
+    pub fn insert_tokens(&self, uri: &Url, key: u64, tokens: Arc<[SemanticToken]>) {
+        let size = tokens.len() * size_of::<SemanticToken>();
+        self.insert(
+            CacheKind::Tokens,
+            uri,
+            key,
+            CachedValue::Tokens(tokens),
+            size,
+        );
+    }
+
+    /// Removes the entries of the documents that are not retained, e.g. the
+    /// closed documents, and releases the unused capacity.
+    pub fn retain(&self, mut retained: impl FnMut(&Url) -> bool) {
+        let mut state = self.lock();
+        let mut size = state.size;
+        state.entries.retain(|(_, uri), entry| {
+            let retain = retained(uri);
+            if !retain {
+                size -= entry.size;
+            }
+            retain
+        });
+        state.size = size;
+        state.entries.shrink_to_fit();
+    }
+
+    pub fn usage(&self) -> CachesUsage {
+        let state = self.lock();
+
+        let caches = CacheKind::ALL
+            .into_iter()
+            .map(|kind| {
+                let mut usage = CacheUsage::default();
+                for ((entry_kind, _), entry) in &state.entries {
+                    if *entry_kind == kind {
+                        usage.entries += 1;
+                        usage.size += entry.size;
+                    }
+                }
+                (kind, usage)
+            })
+            .collect();
+
+        CachesUsage {
+            caches,
+            budget: self.budget,
+            evictions: state.evictions,
+            last_eviction: state.last_eviction,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, kind: CacheKind, uri: &Url, key: u64) -> Option<CachedValue> {
+        let mut state = self.lock();
+        state.tick += 1;
+        let tick = state.tick;
+
+        let entry = state.entries.get_mut(&(kind, uri.clone()))?;
+        if entry.key != key {
+            return None;
+        }
+        entry.last_used = tick;
+
+        Some(entry.value.clone())
+    }
+
+    fn insert(&self, kind: CacheKind, uri: &Url, key: u64, value: CachedValue, size: usize) {
+        let size = size + size_of::<Entry>() + uri.as_str().len();
+        let mut state = self.lock();
+
+        if let Some(previous) = state.entries.remove(&(kind, uri.clone())) {
+            state.size -= previous.size;
+        }
+
+        // A value larger than the budget is not cached.
+        if size > self.budget {
+            return;
+        }
+
+        state.tick += 1;
+        let last_used = state.tick;
+        state.entries.insert(
+            (kind, uri.clone()),
+            Entry {
+                key,
+                value,
+                size,
+                last_used,
+            },
+        );
+        state.size += size;
+
+        self.evict(&mut state);
+    }
+
+    /// Evicts the least recently used entries, until the caches are within
+    /// the budget.
+    fn evict(&self, state: &mut CacheState) {
+        let mut evicted = 0;
+
+        while state.size > self.budget {
+            let Some(lru) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&lru) {
+                state.size -= entry.size;
+                evicted += 1;
+            }
+        }
+
+        if evicted > 0 {
+            trace!("evicted {evicted} cache entries");
+            state.evictions += evicted;
+            state.last_eviction = Some(Instant::now());
+        }
+    }
+}
+
+/// Returns true if the expression is plain data, without foreign functions.
+fn is_plain(expr: &Ann<Expr>) -> bool {
+    is_plain_expr(&expr.0)
+        && expr
+            .1
+            .as_ref()
+            .is_none_or(|annotations| annotations.values().all(is_plain_expr))
+}
+
+fn is_plain_expr(expr: &Expr) -> bool {
+    match expr {
+        Expr::ForeignFunc(_) => false,
+        Expr::List(terms) => terms.iter().all(is_plain),
+        Expr::Array(items) => items.iter().all(is_plain_expr),
+        Expr::Dict(items) => items.values().all(is_plain_expr),
+        Expr::Func(params, body) | Expr::Macro(params, body) => {
+            params.iter().all(is_plain) && is_plain(body)
+        }
+        Expr::If(condition, then, otherwise) => {
+            is_plain(condition) && is_plain(then) && otherwise.as_deref().is_none_or(is_plain)
+        }
+        _ => true,
+    }
+}
+
+/// Returns the estimated size of the parsed expressions of a text, in bytes.
+fn ast_size(exprs: &[Ann<Expr>], text: &str) -> usize {
+    let mut count = 0;
+    for_each_expr(exprs, &mut |_| count += 1);
+
+    // The names and the strings are roughly the size of the text.
+    count * (size_of::<Ann<Expr>>() + ANNOTATIONS_SIZE) + text.len()
+}
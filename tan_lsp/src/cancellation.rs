@@ -0,0 +1,58 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// The error returned by handlers that stop early because the request was
+/// cancelled by the client.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The error returned by handlers that need the complete index, while the
+/// workspace is being indexed. The client should retry later.
+#[derive(Debug)]
+pub struct NotIndexed;
+
+impl fmt::Display for NotIndexed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the workspace is being indexed, retry later")
+    }
+}
+
+impl std::error::Error for NotIndexed {}
+
+/// A flag shared between the message loop and a request handler, set when the
+/// client cancels the request (`$/cancelRequest`).
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns an error if the request was cancelled, long-running handlers
+    /// should call this periodically.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use lsp_types::ClientCapabilities;
+use tracing::warn;
+
+use crate::{
+    format::{FormatOptions, WrapStrategy},
+    imports::ImportPlacement,
+    sandbox::SandboxPolicy,
+};
+
+/// The default maximum number of diagnostics published per file.
+pub const DEFAULT_MAX_DIAGNOSTICS_PER_FILE: usize = 100;
+
+/// The default memory budget of the caches, in megabytes.
+pub const DEFAULT_CACHE_MEMORY_LIMIT: usize = 256;
+
+/// The default time without messages after which the idle maintenance runs.
+pub const DEFAULT_IDLE_MAINTENANCE_DELAY: Duration = Duration::from_secs(10);
+
+/// The manifest (config) file of a Tan project, relative to the workspace root.
+pub const MANIFEST_FILE: &str = "tan.toml";
+
+/// The section of the client settings with the server options.
+pub const CONFIGURATION_SECTION: &str = "tan";
+
+/// The default dependency directory, relative to the workspace root.
+pub const DEFAULT_DEPENDENCY_DIR: &str = ".tan/deps";
+
+/// The package cache directory, relative to the home directory.
+pub const PACKAGE_CACHE_DIR: &str = ".tan/packages";
+
+// #Insight
+// The environment variables configure headless and container environments,
+// they are layered under the command-line arguments, the initialization
+// options and the workspace configuration.
+
+/// The log level, e.g. `debug`.
+pub const LOG_ENV_VAR: &str = "TAN_LSP_LOG";
+
+/// Logs the LSP messages, `full` or `redacted`.
+pub const LOG_MESSAGES_ENV_VAR: &str = "TAN_LSP_LOG_MESSAGES";
+
+/// The directory of the standard library sources, indexed as a dependency.
+pub const STDLIB_PATH_ENV_VAR: &str = "TAN_LSP_STDLIB_PATH";
+
+/// The package cache directory, overrides `~/.tan/packages`.
+pub const CACHE_DIR_ENV_VAR: &str = "TAN_LSP_CACHE_DIR";
+
+/// Enables the read-only mode, if `true` or `1`.
+pub const READ_ONLY_ENV_VAR: &str = "TAN_LSP_READ_ONLY";
+
+/// The maximum number of diagnostics published per file.
+pub const MAX_DIAGNOSTICS_ENV_VAR: &str = "TAN_LSP_MAX_DIAGNOSTICS";
+
+/// The memory budget of the caches, in megabytes.
+pub const CACHE_MEMORY_LIMIT_ENV_VAR: &str = "TAN_LSP_CACHE_MEMORY_LIMIT";
+
+/// The server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// In read-only mode the server never executes Tan code (eval, run, test,
+    /// macro-expansion) and never writes files. Use it to open untrusted
+    /// workspaces safely.
+    pub read_only: bool,
+    /// In dry-run mode the edits of the commands are only returned in the
+    /// responses, never applied with `workspace/applyEdit`, e.g. to preview
+    /// the edits in scripts.
+    pub dry_run: bool,
+    /// The maximum number of diagnostics published per file.
+    pub max_diagnostics_per_file: usize,
+    /// The capabilities of the client, sent at initialization.
+    pub client_capabilities: ClientCapabilities,
+    /// The root directory of the workspace.
+    pub root_path: Option<PathBuf>,
+    /// Additional dependency directories, relative to the workspace root.
+    pub extra_dependency_paths: Vec<PathBuf>,
+    /// The dependency directories of the manifest, relative to the workspace
+    /// root.
+    pub manifest_dependency_paths: Vec<PathBuf>,
+    /// The directory of the standard library sources.
+    pub stdlib_path: Option<PathBuf>,
+    /// The package cache directory, the server defaults to `~/.tan/packages`.
+    /// Without it, the imports of the packages are not resolved.
+    pub package_cache_path: Option<PathBuf>,
+    /// The interval of the `tan/metrics` notifications, disabled if `None`.
+    pub metrics_interval: Option<Duration>,
+    /// The time without messages after which the idle maintenance runs,
+    /// disabled if `None`.
+    pub idle_maintenance_delay: Option<Duration>,
+    /// The memory budget of the caches, in megabytes.
+    pub cache_memory_limit: usize,
+    /// The formatting options.
+    pub format: FormatOptions,
+    /// The header of new files, e.g. a license notice, from the manifest.
+    pub file_header: Option<String>,
+    /// The glob patterns of the data files, from the manifest.
+    pub data_files: Vec<String>,
+    /// Where the new imports are inserted, e.g. by the completion.
+    pub import_placement: ImportPlacement,
+    /// Show the reference count lenses.
+    pub reference_lens: bool,
+    /// Show the run lens of programs.
+    pub run_lens: bool,
+    /// Show the evaluate lenses of the top-level forms.
+    pub evaluate_lens: bool,
+    /// Show the parameter names of the call arguments, as inlay hints.
+    pub parameter_hints: bool,
+    /// Show the inferred types of the bound names, as inlay hints.
+    pub type_hints: bool,
+    /// The command that downloads the sources of a package, with the `{name}`,
+    /// `{version}` and `{dir}` placeholders. Disabled if empty.
+    pub source_download_command: Vec<String>,
+    /// The registry index, the dependency lenses show the latest published
+    /// versions. Disabled if `None`.
+    pub registry_index_path: Option<PathBuf>,
+    /// Report the visually confusable names, e.g. names that mix Latin and
+    /// Cyrillic letters.
+    pub confusables_lint: bool,
+    /// Report the unused local bindings and imports.
+    pub unused_lint: bool,
+    /// What evaluated Tan code may do.
+    pub sandbox: SandboxPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            dry_run: false,
+            max_diagnostics_per_file: DEFAULT_MAX_DIAGNOSTICS_PER_FILE,
+            client_capabilities: ClientCapabilities::default(),
+            root_path: None,
+            extra_dependency_paths: Vec::new(),
+            manifest_dependency_paths: Vec::new(),
+            data_files: Vec::new(),
+            stdlib_path: None,
+            package_cache_path: None,
+            metrics_interval: None,
+            idle_maintenance_delay: Some(DEFAULT_IDLE_MAINTENANCE_DELAY),
+            cache_memory_limit: DEFAULT_CACHE_MEMORY_LIMIT,
+            format: FormatOptions::default(),
+            file_header: None,
+            import_placement: ImportPlacement::Grouped,
+            reference_lens: true,
+            run_lens: true,
+            evaluate_lens: true,
+            parameter_hints: true,
+            type_hints: true,
+            registry_index_path: None,
+            source_download_command: Vec::new(),
+            confusables_lint: false,
+            unused_lint: true,
+            sandbox: SandboxPolicy::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Updates the configuration from the environment variables, `var`
+    /// returns the value of a variable.
+    pub fn update_from_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(path) = var(STDLIB_PATH_ENV_VAR).filter(|path| !path.is_empty()) {
+            self.stdlib_path = Some(PathBuf::from(path));
+        }
+
+        if let Some(path) = var(CACHE_DIR_ENV_VAR).filter(|path| !path.is_empty()) {
+            self.package_cache_path = Some(PathBuf::from(path));
+        }
+
+        if let Some(read_only) = var(READ_ONLY_ENV_VAR) {
+            self.read_only |= matches!(read_only.as_str(), "1" | "true");
+        }
+
+        if let Some(max) = var(MAX_DIAGNOSTICS_ENV_VAR) {
+            match max.parse() {
+                Ok(max) => self.max_diagnostics_per_file = max,
+                Err(_) => warn!("invalid `{MAX_DIAGNOSTICS_ENV_VAR}` value `{max}`"),
+            }
+        }
+
+        if let Some(limit) = var(CACHE_MEMORY_LIMIT_ENV_VAR) {
+            match limit.parse() {
+                Ok(limit) => self.cache_memory_limit = limit,
+                Err(_) => warn!("invalid `{CACHE_MEMORY_LIMIT_ENV_VAR}` value `{limit}`"),
+            }
+        }
+    }
+
+    /// Updates the configuration from the `initializationOptions` sent by the
+    /// client.
+    pub fn update_from_initialization_options(&mut self, options: Option<&serde_json::Value>) {
+        let Some(options) = options else {
+            return;
+        };
+
+        // #Insight
+        // The client can only restrict the server, a read-only server started
+        // from the command-line cannot be made writable.
+        if let Some(read_only) = options.get("readOnly").and_then(|v| v.as_bool()) {
+            self.read_only |= read_only;
+        }
+
+        if let Some(dry_run) = options.get("dryRun").and_then(|v| v.as_bool()) {
+            self.dry_run |= dry_run;
+        }
+
+        if let Some(max) = options
+            .get("maxDiagnosticsPerFile")
+            .and_then(|v| v.as_u64())
+        {
+            self.max_diagnostics_per_file = max as usize;
+        }
+
+        if let Some(paths) = options.get("dependencyPaths").and_then(|v| v.as_array()) {
+            self.extra_dependency_paths = paths
+                .iter()
+                .filter_map(|path| path.as_str())
+                .map(PathBuf::from)
+                .collect();
+        }
+
+        if let Some(seconds) = options.get("metricsInterval").and_then(|v| v.as_u64()) {
+            self.metrics_interval = (seconds > 0).then(|| Duration::from_secs(seconds));
+        }
+
+        if let Some(seconds) = options.get("idleMaintenanceDelay").and_then(|v| v.as_u64()) {
+            self.idle_maintenance_delay = (seconds > 0).then(|| Duration::from_secs(seconds));
+        }
+
+        if let Some(limit) = options.get("cacheMemoryLimit").and_then(|v| v.as_u64()) {
+            self.cache_memory_limit = limit as usize;
+        }
+
+        if let Some(width) = options.get("lineWidth").and_then(|v| v.as_u64()) {
+            self.format.line_width = width as usize;
+        }
+
+        if let Some(wrap) = options.get("wrapStrategy").and_then(|v| v.as_str()) {
+            match WrapStrategy::parse(wrap) {
+                Some(wrap) => self.format.wrap = wrap,
+                None => warn!("unknown wrap strategy `{wrap}`"),
+            }
+        }
+
+        if let Some(placement) = options.get("importPlacement").and_then(|v| v.as_str()) {
+            match ImportPlacement::parse(placement) {
+                Some(placement) => self.import_placement = placement,
+                None => warn!("unknown import placement `{placement}`"),
+            }
+        }
+
+        if let Some(enabled) = options.get("referenceLens").and_then(|v| v.as_bool()) {
+            self.reference_lens = enabled;
+        }
+
+        if let Some(enabled) = options.get("runLens").and_then(|v| v.as_bool()) {
+            self.run_lens = enabled;
+        }
+
+        if let Some(enabled) = options.get("evaluateLens").and_then(|v| v.as_bool()) {
+            self.evaluate_lens = enabled;
+        }
+
+        if let Some(enabled) = options.get("parameterHints").and_then(|v| v.as_bool()) {
+            self.parameter_hints = enabled;
+        }
+
+        if let Some(enabled) = options.get("typeHints").and_then(|v| v.as_bool()) {
+            self.type_hints = enabled;
+        }
+
+        if let Some(path) = options.get("registryIndexPath").and_then(|v| v.as_str()) {
+            self.registry_index_path = (!path.is_empty()).then(|| PathBuf::from(path));
+        }
+
+        if let Some(command) = options
+            .get("sourceDownloadCommand")
+            .and_then(|v| v.as_array())
+        {
+            self.source_download_command = command
+                .iter()
+                .filter_map(|arg| arg.as_str())
+                .map(str::to_owned)
+                .collect();
+        }
+
+        if let Some(enabled) = options.get("confusablesLint").and_then(|v| v.as_bool()) {
+            self.confusables_lint = enabled;
+        }
+
+        if let Some(enabled) = options.get("unusedLint").and_then(|v| v.as_bool()) {
+            self.unused_lint = enabled;
+        }
+
+        if let Some(sandbox) = options.get("sandbox") {
+            self.sandbox.update_from_options(sandbox);
+        }
+    }
+
+    /// Updates the configuration from the settings of a
+    /// `workspace/didChangeConfiguration` notification. The settings use the
+    /// keys of the initialization options, optionally in a `tan` section. The
+    /// manifest, read beforehand, is applied again.
+    pub fn update_from_settings(
+        &mut self,
+        settings: &serde_json::Value,
+        manifest: Option<&toml::Table>,
+    ) {
+        let settings = settings.get(CONFIGURATION_SECTION).unwrap_or(settings);
+        self.update_from_initialization_options(Some(settings));
+        if let Some(manifest) = manifest {
+            self.apply_manifest(manifest);
+        }
+    }
+
+    // #Insight
+    // The manifest options override the client options, the team settings of
+    // the project take precedence over the settings of a user.
+
+    /// Updates the configuration from the manifest file of the workspace, if
+    /// it exists.
+    pub fn update_from_manifest(&mut self) {
+        if let Some(manifest) = self.read_manifest() {
+            self.apply_manifest(&manifest);
+        }
+    }
+
+    /// Reads and parses the manifest file of the workspace, `None` if it does
+    /// not exist or is invalid.
+    pub fn read_manifest(&self) -> Option<toml::Table> {
+        let root_path = self.root_path.as_ref()?;
+        let text = std::fs::read_to_string(root_path.join(MANIFEST_FILE)).ok()?;
+
+        match toml::from_str(&text) {
+            Ok(manifest) => Some(manifest),
+            Err(error) => {
+                warn!("cannot parse `{MANIFEST_FILE}`: {error}");
+                None
+            }
+        }
+    }
+
+    /// Updates the configuration from a parsed manifest.
+    pub fn apply_manifest(&mut self, manifest: &toml::Table) {
+        self.file_header = manifest_file_header(manifest);
+
+        self.manifest_dependency_paths = manifest
+            .get("dependencies")
+            .and_then(|v| v.get("paths"))
+            .and_then(|v| v.as_array())
+            .map(|paths| {
+                paths
+                    .iter()
+                    .filter_map(|path| path.as_str())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        self.data_files = manifest
+            .get("dialects")
+            .and_then(|v| v.get("data"))
+            .and_then(|v| v.as_array())
+            .map(|patterns| {
+                patterns
+                    .iter()
+                    .filter_map(|pattern| pattern.as_str())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let Some(format) = manifest.get("format").and_then(|v| v.as_table()) else {
+            return;
+        };
+
+        if let Some(width) = format.get("line-width").and_then(|v| v.as_integer()) {
+            self.format.line_width = width.max(0) as usize;
+        }
+
+        if let Some(wrap) = format.get("wrap").and_then(|v| v.as_str()) {
+            match WrapStrategy::parse(wrap) {
+                Some(wrap) => self.format.wrap = wrap,
+                None => warn!("unknown wrap strategy `{wrap}` in `{MANIFEST_FILE}`"),
+            }
+        }
+
+        if let Some(ignore) = format.get("ignore").and_then(|v| v.as_array()) {
+            self.format.ignore = ignore
+                .iter()
+                .filter_map(|pattern| pattern.as_str())
+                .map(str::to_owned)
+                .collect();
+        }
+    }
+
+    /// Returns the dependency directories, i.e. the standard library, the
+    /// package cache and the dependency directories of the workspace.
+    pub fn dependency_paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+
+        if let Some(stdlib_path) = &self.stdlib_path {
+            paths.push(stdlib_path.clone());
+        }
+
+        if let Some(package_cache_path) = &self.package_cache_path {
+            paths.push(package_cache_path.clone());
+        }
+
+        if let Some(root_path) = &self.root_path {
+            paths.push(root_path.join(DEFAULT_DEPENDENCY_DIR));
+            for path in self
+                .extra_dependency_paths
+                .iter()
+                .chain(&self.manifest_dependency_paths)
+            {
+                paths.push(root_path.join(path));
+            }
+        }
+
+        paths
+    }
+
+    // #Insight
+    // Dependency sources are indexed for navigation and completion, but they
+    // are read-only: no diagnostics are published for them and they are never
+    // edited, e.g. by rename.
+
+    /// Returns true if the path is in a dependency directory.
+    pub fn is_dependency(&self, path: &Path) -> bool {
+        self.dependency_paths()
+            .iter()
+            .any(|dependency_path| path.starts_with(dependency_path))
+    }
+
+    /// Returns true if the path is indexed, i.e. in the workspace or in a
+    /// dependency directory.
+    pub fn is_indexed(&self, path: &Path) -> bool {
+        let is_in_workspace = self
+            .root_path
+            .as_ref()
+            .is_some_and(|root_path| path.starts_with(root_path));
+        is_in_workspace || self.is_dependency(path)
+    }
+
+    /// Returns true if the path is the manifest of the workspace.
+    pub fn is_manifest(&self, path: &Path) -> bool {
+        self.root_path
+            .as_ref()
+            .is_some_and(|root_path| path == root_path.join(MANIFEST_FILE))
+    }
+
+    /// Returns true if the server runs without a workspace root, e.g. for
+    /// scratch buffers. Only the open documents are analyzed, imports are
+    /// resolved relative to the document.
+    pub fn is_single_file_mode(&self) -> bool {
+        self.root_path.is_none()
+    }
+}
+
+/// Returns the header of new files, either the `template.header` of the
+/// manifest, or a license notice derived from `package.license`.
+fn manifest_file_header(manifest: &toml::Table) -> Option<String> {
+    let header = manifest
+        .get("template")
+        .and_then(|v| v.get("header"))
+        .and_then(|v| v.as_str());
+
+    if let Some(header) = header {
+        return Some(header.to_owned());
+    }
+
+    let license = manifest
+        .get("package")
+        .and_then(|v| v.get("license"))
+        .and_then(|v| v.as_str())?;
+
+    Some(format!("SPDX-License-Identifier: {license}"))
+}
@@ -0,0 +1,448 @@
+use std::{collections::HashMap, path::Path};
+
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag, Position, Range,
+    Url,
+};
+use tan::error::Error;
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Ranged};
+use tan_lint::{lints::snake_case_names_lint::SnakeCaseNamesLint, Lint};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    config::Config,
+    dialect::Dialect,
+    doc_tags::function_docs,
+    embedded::embedded_strings,
+    module::resolve_import,
+    module_graph::{Import, ModuleGraph},
+    snapshot::Snapshot,
+    unicode::{mixed_scripts, normalize_name, skeleton},
+    unused::{find_unused, UnusedKind},
+    util,
+    util::LineIndex,
+    vfs::VfsSnapshot,
+};
+
+pub fn compute_parse_error_diagnostics(
+    input: &str,
+    errors: Vec<Ranged<Error>>,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for error in errors {
+        diagnostics.push(Diagnostic {
+            range: util::lsp_range_from_range(&error.1, input),
+            severity: None,
+            code: None,
+            code_description: None,
+            source: None,
+            message: error.0.to_string(),
+            related_information: None,
+            tags: None,
+            data: None,
+        });
+    }
+
+    Ok(diagnostics)
+}
+
+/// Reports the `(use module)` expressions that cannot be resolved. These
+/// diagnostics depend on other files, they are republished when modules are
+/// created or deleted.
+fn import_diagnostics(
+    exprs: &[Ann<Expr>],
+    input: &str,
+    base_dir: &Path,
+    config: &Config,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for expr in exprs {
+        if head_symbol(expr) != Some("use") {
+            continue;
+        }
+
+        let Ann(Expr::List(terms), _) = expr else {
+            continue;
+        };
+
+        let Some(name_expr @ Ann(Expr::Symbol(name), _)) = terms.get(1) else {
+            continue;
+        };
+
+        if resolve_import(name, base_dir, config).is_some() {
+            continue;
+        }
+
+        diagnostics.push(Diagnostic {
+            range: util::lsp_range_from_range(&name_expr.get_range(), input),
+            severity: Some(DiagnosticSeverity::WARNING),
+            message: format!("module `{name}` not found"),
+            ..Default::default()
+        });
+    }
+
+    diagnostics
+}
+
+/// Reports the mismatches between the parameters documented with `@param`
+/// tags and the signature of the function.
+fn doc_tag_diagnostics(exprs: &[Ann<Expr>], input: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for doc in function_docs(exprs, input) {
+        for doc_param in doc.unknown_params() {
+            diagnostics.push(Diagnostic {
+                range: util::lsp_range_from_range(&doc_param.range, input),
+                severity: Some(DiagnosticSeverity::WARNING),
+                message: format!("`{}` is not a parameter of `{}`", doc_param.name, doc.name),
+                ..Default::default()
+            });
+        }
+
+        for param in doc.undocumented_params() {
+            diagnostics.push(Diagnostic {
+                range: util::lsp_range_from_range(&param.get_range(), input),
+                severity: Some(DiagnosticSeverity::WARNING),
+                message: format!(
+                    "the parameter `{}` of `{}` is not documented",
+                    param.0, doc.name
+                ),
+                ..Default::default()
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Reports the visually confusable names: names that mix scripts, e.g. a
+/// Latin name with a Cyrillic `а`, and distinct names with the same skeleton.
+/// The first occurrence of each name is reported.
+fn confusable_diagnostics(exprs: &[Ann<Expr>], input: &str) -> Vec<Diagnostic> {
+    let mut first_occurrences: Vec<(String, tan::range::Range)> = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+
+        let range = expr.get_range();
+        let name = normalize_name(sym);
+        if range.start < range.end && !first_occurrences.iter().any(|(n, _)| *n == name) {
+            first_occurrences.push((name.into_owned(), range));
+        }
+    });
+
+    let mut skeletons: HashMap<String, Vec<&str>> = HashMap::new();
+    for (name, _) in &first_occurrences {
+        skeletons.entry(skeleton(name)).or_default().push(name);
+    }
+
+    let mut diagnostics = Vec::new();
+
+    for (name, range) in &first_occurrences {
+        let message = if let Some((first, second)) = mixed_scripts(name) {
+            format!(
+                "`{name}` mixes {} and {} characters",
+                first.name(),
+                second.name()
+            )
+        } else if name.is_ascii() {
+            continue;
+        } else {
+            let Some(other) = skeletons[&skeleton(name)]
+                .iter()
+                .find(|other| **other != name)
+            else {
+                continue;
+            };
+            format!("`{name}` is confusable with `{other}`")
+        };
+
+        diagnostics.push(Diagnostic {
+            range: util::lsp_range_from_range(range, input),
+            severity: Some(DiagnosticSeverity::WARNING),
+            message,
+            ..Default::default()
+        });
+    }
+
+    diagnostics
+}
+
+/// Reports the unused local bindings and imports, with the `unnecessary` tag,
+/// e.g. the editors fade them out.
+fn unused_diagnostics(
+    exprs: &[Ann<Expr>],
+    input: &str,
+    base_dir: Option<&Path>,
+    config: &Config,
+    vfs: &VfsSnapshot,
+) -> Vec<Diagnostic> {
+    find_unused(exprs, input, base_dir, config, vfs)
+        .into_iter()
+        .map(|unused| Diagnostic {
+            range: util::lsp_range_from_range(&unused.name_range, input),
+            severity: Some(DiagnosticSeverity::WARNING),
+            message: match unused.kind {
+                UnusedKind::Binding => format!("`{}` is never used", unused.name),
+                UnusedKind::Import => format!("module `{}` is never used", unused.name),
+            },
+            tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Reports the syntax errors of the embedded documents, e.g. of the regular
+/// expressions, in the host document.
+fn embedded_diagnostics(exprs: &[Ann<Expr>], input: &str) -> Vec<Diagnostic> {
+    embedded_strings(exprs, input)
+        .into_iter()
+        .filter_map(|string| {
+            let (range, message) = string.syntax_error()?;
+            Some(Diagnostic {
+                range: util::lsp_range_from_range(&range, input),
+                severity: Some(DiagnosticSeverity::ERROR),
+                message: format!("invalid {}: {message}", string.language.id()),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Returns the name of the module that contains an import.
+fn importer_name(import: &Import) -> String {
+    import
+        .location
+        .uri
+        .to_file_path()
+        .ok()
+        .and_then(|path| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+}
+
+/// Reports the imports of a document that participate in import cycles, the
+/// related information walks the cycle.
+fn cycle_diagnostics(path: &Path, graph: &ModuleGraph) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for import in graph.imports_of(path) {
+        let Some(cycle) = graph.find_cycle(import) else {
+            continue;
+        };
+
+        let mut names = vec![importer_name(import)];
+        names.extend(cycle.iter().map(|import| import.name.clone()));
+
+        let related_information = cycle[1..]
+            .iter()
+            .map(|import| DiagnosticRelatedInformation {
+                location: import.location.clone(),
+                message: format!("`{}` imports `{}`", importer_name(import), import.name),
+            })
+            .collect();
+
+        diagnostics.push(Diagnostic {
+            range: import.location.range,
+            severity: Some(DiagnosticSeverity::WARNING),
+            message: format!("import cycle: {}", names.join(" → ")),
+            related_information: Some(related_information),
+            ..Default::default()
+        });
+    }
+
+    diagnostics
+}
+
+/// Computes the diagnostics of a document, `path` is used to resolve the
+/// imported modules. The imported modules that are open are read from the
+/// documents, e.g. with their unsaved definitions.
+pub fn compute_diagnostics(
+    input: &str,
+    path: Option<&Path>,
+    config: &Config,
+    vfs: &VfsSnapshot,
+    graph: &ModuleGraph,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let result = parse_string_all(input);
+
+    let diagnostics = match result {
+        Ok(exprs) => {
+            let mut diagnostics = Vec::new();
+
+            let is_code = Dialect::detect(path, input, config).is_code();
+
+            if is_code {
+                let mut lint = SnakeCaseNamesLint::new(input);
+                lint.run(&exprs);
+                diagnostics.append(&mut lint.diagnostics);
+            }
+
+            // #Insight
+            // The lints compute the positions from Tan (char) positions, they
+            // are converted to the negotiated position encoding.
+            let line_index = LineIndex::new(input);
+            for diagnostic in &mut diagnostics {
+                diagnostic.range = encode_char_range(&diagnostic.range, &line_index);
+            }
+
+            if is_code {
+                diagnostics.extend(doc_tag_diagnostics(&exprs, input));
+            }
+
+            if config.confusables_lint {
+                diagnostics.extend(confusable_diagnostics(&exprs, input));
+            }
+
+            diagnostics.extend(embedded_diagnostics(&exprs, input));
+
+            if config.unused_lint && is_code {
+                let base_dir = path.and_then(Path::parent);
+                diagnostics.extend(unused_diagnostics(&exprs, input, base_dir, config, vfs));
+            }
+
+            if let Some(base_dir) = path.and_then(Path::parent) {
+                diagnostics.extend(import_diagnostics(&exprs, input, base_dir, config));
+            }
+
+            if let Some(path) = path {
+                diagnostics.extend(cycle_diagnostics(path, graph));
+            }
+
+            diagnostics
+        }
+        Err(errors) => compute_parse_error_diagnostics(input, errors)?,
+    };
+
+    Ok(diagnostics)
+}
+
+/// Converts a range with (char) columns to the position encoding.
+fn encode_char_range(range: &Range, line_index: &LineIndex) -> Range {
+    Range::new(
+        line_index
+            .position_from_line_col(range.start.line as usize, range.start.character as usize),
+        line_index.position_from_line_col(range.end.line as usize, range.end.character as usize),
+    )
+}
+
+/// Returns the priority rank of a diagnostic severity, lower is more important.
+/// A missing severity is interpreted as an error.
+fn severity_rank(severity: Option<DiagnosticSeverity>) -> u8 {
+    match severity {
+        None | Some(DiagnosticSeverity::ERROR) => 0,
+        Some(DiagnosticSeverity::WARNING) => 1,
+        Some(DiagnosticSeverity::INFORMATION) => 2,
+        _ => 3,
+    }
+}
+
+/// Caps the number of diagnostics to `max`. Errors are prioritized over
+/// warnings and hints, earlier diagnostics over later ones. A summary
+/// diagnostic is appended when diagnostics are dropped.
+pub fn cap_diagnostics(mut diagnostics: Vec<Diagnostic>, max: usize) -> Vec<Diagnostic> {
+    if diagnostics.len() <= max {
+        return diagnostics;
+    }
+
+    diagnostics.sort_by_key(|d| {
+        (
+            severity_rank(d.severity),
+            d.range.start.line,
+            d.range.start.character,
+        )
+    });
+
+    let dropped_count = diagnostics.len() - max;
+    diagnostics.truncate(max);
+
+    // Restore the document order of the kept diagnostics.
+    diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+
+    diagnostics.push(Diagnostic {
+        range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+        severity: Some(DiagnosticSeverity::INFORMATION),
+        code: None,
+        code_description: None,
+        source: None,
+        message: format!("{dropped_count} more diagnostics not shown, the limit is {max} per file"),
+        related_information: None,
+        tags: None,
+        data: None,
+    });
+
+    diagnostics
+}
+
+/// Computes the diagnostics of a document against a snapshot, capped to the
+/// configured maximum.
+pub fn file_diagnostics(
+    uri: &Url,
+    input: &str,
+    snapshot: &Snapshot,
+    graph: &ModuleGraph,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let path = uri.to_file_path().ok();
+    let config = &snapshot.config;
+    let diagnostics = compute_diagnostics(input, path.as_deref(), config, &snapshot.vfs, graph)?;
+    Ok(cap_diagnostics(
+        diagnostics,
+        config.max_diagnostics_per_file,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(line: u32, severity: DiagnosticSeverity) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(line, 0), Position::new(line, 1)),
+            severity: Some(severity),
+            message: format!("line {line}"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cap_diagnostics_keeps_the_diagnostics_under_the_limit() {
+        let diagnostics = vec![
+            diagnostic(0, DiagnosticSeverity::WARNING),
+            diagnostic(1, DiagnosticSeverity::ERROR),
+        ];
+
+        assert_eq!(cap_diagnostics(diagnostics.clone(), 2), diagnostics);
+    }
+
+    #[test]
+    fn cap_diagnostics_prioritizes_errors_then_earlier_diagnostics() {
+        let diagnostics = vec![
+            diagnostic(0, DiagnosticSeverity::HINT),
+            diagnostic(1, DiagnosticSeverity::ERROR),
+            diagnostic(2, DiagnosticSeverity::WARNING),
+            diagnostic(3, DiagnosticSeverity::WARNING),
+            diagnostic(4, DiagnosticSeverity::ERROR),
+        ];
+
+        let capped = cap_diagnostics(diagnostics, 3);
+        let messages: Vec<&str> = capped.iter().map(|d| d.message.as_str()).collect();
+
+        // The kept diagnostics are in document order, then the summary.
+        assert_eq!(
+            messages,
+            [
+                "line 1",
+                "line 2",
+                "line 4",
+                "2 more diagnostics not shown, the limit is 3 per file"
+            ]
+        );
+        assert_eq!(capped[3].severity, Some(DiagnosticSeverity::INFORMATION));
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use crate::{config::Config, glob::glob_matches};
+
+// #Insight
+// Tan is also used as a data format, e.g. for configuration files. The data
+// files are parsed and checked like the code, but they bind no names, define
+// no functions, and are not evaluated: the lints of the code (naming, doc
+// tags, unused bindings) and the evaluate lenses are skipped.
+
+/// The suffix of the file names of the data files.
+pub const DATA_FILE_SUFFIX: &str = ".data.tan";
+
+/// The variant of Tan of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Tan code, the default.
+    Code,
+    /// Data only, e.g. a configuration file.
+    Data,
+}
+
+impl Dialect {
+    /// Detects the dialect of a file: a file with a shebang is an executable
+    /// script, a file named `*.data.tan`, or that matches a `data` glob
+    /// pattern of the `[dialects]` table of the manifest, is a data file.
+    pub fn detect(path: Option<&Path>, input: &str, config: &Config) -> Self {
+        if input.starts_with("#!") {
+            return Self::Code;
+        }
+
+        let Some(path) = path else {
+            return Self::Code;
+        };
+
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with(DATA_FILE_SUFFIX))
+        {
+            return Self::Data;
+        }
+
+        let relative_path = config
+            .root_path
+            .as_ref()
+            .and_then(|root_path| path.strip_prefix(root_path).ok())
+            .map(|path| path.to_string_lossy().replace('\\', "/"));
+
+        match relative_path {
+            Some(relative_path)
+                if config
+                    .data_files
+                    .iter()
+                    .any(|pattern| glob_matches(pattern, &relative_path)) =>
+            {
+                Self::Data
+            }
+            _ => Self::Code,
+        }
+    }
+
+    /// Returns true if the lints of the code run on the file.
+    pub fn is_code(self) -> bool {
+        self == Self::Code
+    }
+}
@@ -0,0 +1,223 @@
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{ast::head_symbol, module::is_separated};
+
+// #Insight
+// Doc comments document the function parameters with tags, e.g.
+//
+// ; Adds two numbers.
+// ; @param a the first number
+// ; @param b the second number
+// ; @returns the sum
+// (let add (Func (a b) (+ a b)))
+
+/// The prefix of doc-comment tags.
+pub const TAG_PREFIX: char = '@';
+
+/// The tag that documents a parameter.
+pub const PARAM_TAG: &str = "param";
+
+/// The supported doc-comment tags, with descriptions.
+pub const DOC_TAGS: [(&str, &str); 3] = [
+    (
+        PARAM_TAG,
+        "Documents a parameter, `@param name description`",
+    ),
+    ("returns", "Documents the returned value"),
+    ("example", "Introduces an example"),
+];
+
+/// A parameter documented with the `@param` tag.
+pub struct DocParam<'a> {
+    pub name: String,
+    /// The (char) range of the name.
+    pub range: Range,
+    /// The comment with the tag.
+    pub comment: &'a Ann<Expr>,
+}
+
+/// A top-level function definition, with its doc comments.
+pub struct FunctionDoc<'a> {
+    pub name: &'a str,
+    /// The (char) range of the definition, without the comments.
+    pub range: Range,
+    /// The parameter symbols of the signature.
+    pub params: Vec<&'a Ann<Expr>>,
+    /// The attached doc comments.
+    pub comments: Vec<&'a Ann<Expr>>,
+    pub documented_params: Vec<DocParam<'a>>,
+}
+
+impl FunctionDoc<'_> {
+    /// Returns true if the (char) index is in the doc comments or the
+    /// definition.
+    pub fn contains(&self, index: usize) -> bool {
+        let start = self
+            .comments
+            .first()
+            .map_or(self.range.start, |comment| comment.get_range().start);
+        start <= index && index <= self.range.end
+    }
+
+    /// Returns the documented parameters that are not in the signature.
+    pub fn unknown_params(&self) -> Vec<&DocParam<'_>> {
+        self.documented_params
+            .iter()
+            .filter(|doc_param| {
+                !self.params.iter().any(
+                    |param| matches!(param, Ann(Expr::Symbol(sym), _) if *sym == doc_param.name),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the parameters of the signature that are not documented. Only
+    /// reported if some parameters are documented.
+    pub fn undocumented_params(&self) -> Vec<&Ann<Expr>> {
+        if self.documented_params.is_empty() {
+            return Vec::new();
+        }
+
+        self.params
+            .iter()
+            .copied()
+            .filter(|param| {
+                !self
+                    .documented_params
+                    .iter()
+                    .any(|doc_param| matches!(param, Ann(Expr::Symbol(sym), _) if *sym == doc_param.name))
+            })
+            .collect()
+    }
+}
+
+/// Parses the `@param name` tag of a comment, returns the name and its (char)
+/// offset in the comment.
+pub fn param_tag(comment: &str) -> Option<(String, usize)> {
+    let tag = format!("{TAG_PREFIX}{PARAM_TAG}");
+    let chars: Vec<char> = comment.chars().collect();
+    let tag_chars: Vec<char> = tag.chars().collect();
+
+    let tag_start = chars
+        .windows(tag_chars.len())
+        .position(|window| window == tag_chars.as_slice())?;
+
+    let mut start = tag_start + tag_chars.len();
+    if !chars.get(start).is_some_and(|c| c.is_whitespace()) {
+        return None;
+    }
+    while chars.get(start).is_some_and(|c| c.is_whitespace()) {
+        start += 1;
+    }
+
+    let name: String = chars[start..]
+        .iter()
+        .take_while(|c| !c.is_whitespace())
+        .collect();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some((name, start))
+    }
+}
+
+/// Returns the parameters of a `(Func (params) body)` or `(Macro ...)`
+/// expression.
+pub fn signature_params(value: &Ann<Expr>) -> Option<Vec<&Ann<Expr>>> {
+    if !matches!(head_symbol(value), Some("Func") | Some("Macro")) {
+        return None;
+    }
+
+    let Ann(Expr::List(terms), _) = value else {
+        return None;
+    };
+
+    match terms.get(1) {
+        Some(Ann(Expr::List(params), _)) => Some(
+            params
+                .iter()
+                .filter(|param| matches!(param, Ann(Expr::Symbol(_), _)))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Returns the top-level function definitions, with their attached doc
+/// comments.
+pub fn function_docs<'a>(exprs: &'a [Ann<Expr>], input: &str) -> Vec<FunctionDoc<'a>> {
+    let mut docs = Vec::new();
+    let mut comments: Vec<&Ann<Expr>> = Vec::new();
+
+    for expr in exprs {
+        if let Ann(Expr::Comment(_), _) = expr {
+            if comments
+                .last()
+                .is_some_and(|last| is_separated(last, expr, input))
+            {
+                comments.clear();
+            }
+            comments.push(expr);
+            continue;
+        }
+
+        let attached = match comments.last() {
+            Some(last) if !is_separated(last, expr, input) => std::mem::take(&mut comments),
+            _ => Vec::new(),
+        };
+        comments.clear();
+
+        if head_symbol(expr) != Some("let") {
+            continue;
+        }
+
+        let Ann(Expr::List(terms), _) = expr else {
+            continue;
+        };
+
+        // Only `(let name (Func ...))` is documented by the comments.
+        let [_, Ann(Expr::Symbol(name), _), value] = terms.as_slice() else {
+            continue;
+        };
+
+        let Some(params) = signature_params(value) else {
+            continue;
+        };
+
+        let documented_params = attached
+            .iter()
+            .filter_map(|comment| {
+                let Ann(Expr::Comment(text), _) = comment else {
+                    return None;
+                };
+                let (name, offset) = param_tag(text)?;
+                let start = comment.get_range().start + offset;
+                Some(DocParam {
+                    range: start..start + name.chars().count(),
+                    name,
+                    comment,
+                })
+            })
+            .collect();
+
+        docs.push(FunctionDoc {
+            name,
+            range: expr.get_range(),
+            params,
+            comments: attached,
+            documented_params,
+        });
+    }
+
+    docs
+}
+
+/// Returns the tag prefix of a comment line, the text before the tag, e.g.
+/// `; ` for `; @param a`.
+pub fn tag_line_prefix(comment: &str) -> &str {
+    match comment.find(TAG_PREFIX) {
+        Some(i) => &comment[..i],
+        None => comment,
+    }
+}
@@ -0,0 +1,196 @@
+use lsp_types::Url;
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::ast::{for_each_expr, head_symbol};
+
+// #Insight
+// The embedded languages are detected by annotation, e.g. `#regex "a+b"`, or
+// by convention, i.e. the name of the binding, e.g. `(let email_regex "...")`.
+// Tan strings have no escape sequences, the text of an embedded document is
+// the text of the literal, the positions map to the host file by offset. The
+// strings cannot contain quotes, e.g. the JSON documents are arrays, numbers
+// or booleans.
+
+// #TODO validate the SQL documents.
+
+/// The URI scheme of the embedded documents.
+pub const EMBEDDED_SCHEME: &str = "tan-embedded";
+
+/// A language embedded in Tan strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddedLanguage {
+    Regex,
+    Json,
+    Sql,
+}
+
+impl EmbeddedLanguage {
+    const ALL: [EmbeddedLanguage; 3] = [Self::Regex, Self::Json, Self::Sql];
+
+    /// The language identifier, also the name of the annotation and the
+    /// extension of the embedded documents.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Regex => "regex",
+            Self::Json => "json",
+            Self::Sql => "sql",
+        }
+    }
+
+    /// Returns the language of a bound name, by its last segment, e.g. `regex`
+    /// for `email_regex`.
+    fn of_name(name: &str) -> Option<Self> {
+        let suffix = name.rsplit('_').next().unwrap_or(name);
+        Self::ALL
+            .into_iter()
+            .find(|language| suffix.eq_ignore_ascii_case(language.id()))
+    }
+}
+
+/// A string literal in an embedded language.
+#[derive(Debug)]
+pub struct EmbeddedString {
+    pub language: EmbeddedLanguage,
+    /// The (char) range of the text, without the quotes.
+    pub range: Range,
+    pub text: String,
+}
+
+impl EmbeddedString {
+    /// Returns the URI of the embedded document, by its position in the host
+    /// document.
+    pub fn uri(&self, host: &Url, index: usize) -> Option<Url> {
+        let uri = format!(
+            "{EMBEDDED_SCHEME}:{}/{index}.{}",
+            host.path(),
+            self.language.id()
+        );
+        Url::parse(&uri).ok()
+    }
+
+    /// Returns the syntax error of the text, its (char) range in the host
+    /// document and the message.
+    pub fn syntax_error(&self) -> Option<(Range, String)> {
+        let (range, message) = match self.language {
+            EmbeddedLanguage::Regex => regex_error(&self.text)?,
+            EmbeddedLanguage::Json => json_error(&self.text)?,
+            EmbeddedLanguage::Sql => return None,
+        };
+
+        let start = self.range.start + range.start;
+        let end = self.range.start + range.end.max(range.start);
+        Some((start..end, message))
+    }
+}
+
+/// Returns the string literals in embedded languages, in document order.
+pub fn embedded_strings(exprs: &[Ann<Expr>], input: &str) -> Vec<EmbeddedString> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut strings = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        // The annotations of the literals.
+        if let Some(language) = EmbeddedLanguage::ALL
+            .into_iter()
+            .find(|language| expr.get_annotation(language.id()).is_some())
+        {
+            strings.extend(embedded_string(expr, language, &chars));
+            return;
+        }
+
+        // The bindings named by convention.
+        if head_symbol(expr) != Some("let") {
+            return;
+        }
+        let Ann(Expr::List(terms), _) = expr else {
+            return;
+        };
+        for pair in terms[1..].chunks(2) {
+            let [Ann(Expr::Symbol(name), _), value] = pair else {
+                continue;
+            };
+            let language = EmbeddedLanguage::of_name(name);
+            let is_annotated = EmbeddedLanguage::ALL
+                .into_iter()
+                .any(|language| value.get_annotation(language.id()).is_some());
+            if let (Some(language), false) = (language, is_annotated) {
+                strings.extend(embedded_string(value, language, &chars));
+            }
+        }
+    });
+
+    strings.sort_by_key(|string| string.range.start);
+
+    strings
+}
+
+/// Returns the embedded string of a string literal, `None` for the other
+/// expressions and for the multi-line text literals, whose indentation is
+/// stripped.
+fn embedded_string(
+    expr: &Ann<Expr>,
+    language: EmbeddedLanguage,
+    chars: &[char],
+) -> Option<EmbeddedString> {
+    let Ann(Expr::String(value), _) = expr else {
+        return None;
+    };
+
+    let range = expr.get_range();
+    if range.len() < 2 || chars.get(range.start) != Some(&'"') {
+        return None;
+    }
+
+    let range = range.start + 1..range.end - 1;
+    let text: String = chars.get(range.clone())?.iter().collect();
+    if text != *value {
+        return None;
+    }
+
+    Some(EmbeddedString {
+        language,
+        range,
+        text,
+    })
+}
+
+/// Returns the (char) range and the message of the syntax error of a regular
+/// expression.
+fn regex_error(text: &str) -> Option<(Range, String)> {
+    let (span, message) = match regex_syntax::Parser::new().parse(text) {
+        Ok(_) => return None,
+        Err(regex_syntax::Error::Parse(error)) => (*error.span(), error.kind().to_string()),
+        Err(regex_syntax::Error::Translate(error)) => (*error.span(), error.kind().to_string()),
+        Err(error) => return Some((0..text.chars().count(), error.to_string())),
+    };
+
+    let char_index = |offset: usize| text.get(..offset).map_or(0, |s| s.chars().count());
+    Some((
+        char_index(span.start.offset)..char_index(span.end.offset),
+        message,
+    ))
+}
+
+/// Returns the (char) range and the message of the syntax error of a JSON
+/// text.
+fn json_error(text: &str) -> Option<(Range, String)> {
+    let error = serde_json::from_str::<serde_json::Value>(text).err()?;
+
+    // The line and the (byte) column are 1-based.
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(error.line().saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let offset = (line_start + error.column().saturating_sub(1)).min(text.len());
+    let start = text.get(..offset).map_or(0, |s| s.chars().count());
+    let end = (start + 1).min(text.chars().count());
+
+    // The message of the error includes the position.
+    let message = error.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+
+    Some((start..end, message.to_owned()))
+}
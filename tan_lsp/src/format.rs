@@ -0,0 +1,225 @@
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+use tan_fmt::pretty::Formatter;
+
+use crate::indent::INDENT_SIZE;
+
+// #TODO move line-width support to the `tan_fmt` crate.
+
+// #Insight
+// The Tan formatter lays out calls horizontally and ignores the line width.
+// The too-long lines of its output are wrapped in a second pass.
+
+/// The default maximum line width (char count).
+pub const DEFAULT_LINE_WIDTH: usize = 80;
+
+/// How the arguments of a call that doesn't fit in a line are wrapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapStrategy {
+    /// One argument per line.
+    OnePerLine,
+    /// As many arguments per line as fit.
+    Fill,
+}
+
+impl WrapStrategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "onePerLine" | "one-per-line" => Some(Self::OnePerLine),
+            "fill" => Some(Self::Fill),
+            _ => None,
+        }
+    }
+}
+
+/// The formatting options.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub line_width: usize,
+    pub wrap: WrapStrategy,
+    /// The glob patterns of the files that are never formatted in bulk, e.g.
+    /// by `tan.formatWorkspace`, relative to the workspace root.
+    pub ignore: Vec<String>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            line_width: DEFAULT_LINE_WIDTH,
+            wrap: WrapStrategy::OnePerLine,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// Formats the expressions with the Tan formatter, then wraps the lines that
+/// exceed the line width.
+pub fn format_exprs(exprs: &[Ann<Expr>], options: &FormatOptions) -> String {
+    let formatted = Formatter::new(exprs).format();
+
+    let mut output = String::new();
+    let mut in_string = false;
+
+    for line in formatted.lines() {
+        if in_string {
+            output.push_str(line);
+        } else {
+            output.push_str(&wrap_line(line, options));
+        }
+        output.push('\n');
+        in_string = ends_in_string(line, in_string);
+    }
+
+    output
+}
+
+/// Converts the indentation of the formatted text, in units of `INDENT_SIZE`
+/// spaces, to units of `tab_size` spaces, or tabs. The alignment spaces that
+/// are not a whole unit, and the lines in (multi-line) strings, are kept.
+pub fn convert_indentation(text: &str, tab_size: usize, use_tabs: bool) -> String {
+    if tab_size == INDENT_SIZE && !use_tabs {
+        return text.to_owned();
+    }
+
+    let unit = if use_tabs {
+        "\t".to_owned()
+    } else {
+        " ".repeat(tab_size)
+    };
+
+    let mut output = String::new();
+    let mut in_string = false;
+
+    for line in text.split_inclusive('\n') {
+        if in_string {
+            output.push_str(line);
+        } else {
+            let code = line.trim_start_matches(' ');
+            let indent = line.len() - code.len();
+            output.push_str(&unit.repeat(indent / INDENT_SIZE));
+            output.push_str(&" ".repeat(indent % INDENT_SIZE));
+            output.push_str(code);
+        }
+        in_string = ends_in_string(line, in_string);
+    }
+
+    output
+}
+
+/// Returns true if the line ends inside a (multi-line) string.
+fn ends_in_string(line: &str, in_string: bool) -> bool {
+    let mut in_string = in_string;
+    let mut chars = line.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => in_string = !in_string,
+            ';' if !in_string => break,
+            '-' if !in_string && chars.peek() == Some(&'-') => break,
+            _ => (),
+        }
+    }
+
+    in_string
+}
+
+/// Wraps a line that exceeds the line width, if it contains a single call.
+fn wrap_line(line: &str, options: &FormatOptions) -> String {
+    if line.chars().count() <= options.line_width {
+        return line.to_owned();
+    }
+
+    let code = line.trim_start();
+    let indent = line.len() - code.len();
+
+    let Ok(exprs) = parse_string_all(code) else {
+        return line.to_owned();
+    };
+
+    let [expr] = exprs.as_slice() else {
+        return line.to_owned();
+    };
+
+    format!(
+        "{}{}",
+        " ".repeat(indent),
+        layout(expr, indent, indent, options)
+    )
+}
+
+/// Returns the width of the first line of the text.
+fn first_line_width(text: &str) -> usize {
+    text.lines().next().unwrap_or("").chars().count()
+}
+
+/// Lays out an expression that starts at the given column, of a line with the
+/// given indentation.
+fn layout(expr: &Ann<Expr>, col: usize, line_indent: usize, options: &FormatOptions) -> String {
+    let flat = Formatter::new(&[]).format_expr(expr);
+
+    if col + flat.chars().count() <= options.line_width {
+        return flat;
+    }
+
+    let Ann(Expr::List(terms), _) = expr else {
+        return flat;
+    };
+
+    // Comments and special forms (already laid out vertically by the
+    // formatter) are kept as is.
+    if terms.len() < 2
+        || flat.contains('\n')
+        || terms.iter().any(|term| matches!(term.0, Expr::Comment(_)))
+    {
+        return flat;
+    }
+
+    // Keep the annotations, e.g. `#deprecated (...)`.
+    let bare = Formatter::new(&[]).format_expr(&Ann(expr.0.clone(), None));
+    let prefix = flat.strip_suffix(bare.as_str()).unwrap_or("");
+
+    let head = Formatter::new(&[]).format_expr(&terms[0]);
+
+    // #Insight
+    // Same indentation as on-type formatting, see `indent::reindent_lines`.
+    let args_col = line_indent + INDENT_SIZE;
+    let args_indent = " ".repeat(args_col);
+
+    let mut output = format!("{prefix}({head}");
+    let mut line_width = col + first_line_width(&output);
+    let mut line_indent = line_indent;
+
+    for (i, arg) in terms[1..].iter().enumerate() {
+        let same_line = match options.wrap {
+            // The first argument stays on the line of the head.
+            WrapStrategy::OnePerLine => i == 0,
+            WrapStrategy::Fill => {
+                let arg_flat = Formatter::new(&[]).format_expr(arg);
+                i == 0 || line_width + 1 + arg_flat.chars().count() <= options.line_width
+            }
+        };
+
+        if same_line {
+            let arg = layout(arg, line_width + 1, line_indent, options);
+            output.push(' ');
+            output.push_str(&arg);
+            line_width = match arg.rfind('\n') {
+                Some(i) => arg[i + 1..].chars().count(),
+                None => line_width + 1 + arg.chars().count(),
+            };
+        } else {
+            let arg = layout(arg, args_col, args_col, options);
+            line_indent = args_col;
+            output.push('\n');
+            output.push_str(&args_indent);
+            output.push_str(&arg);
+            line_width = match arg.rfind('\n') {
+                Some(i) => arg[i + 1..].chars().count(),
+                None => args_col + arg.chars().count(),
+            };
+        }
+    }
+
+    output.push(')');
+
+    output
+}
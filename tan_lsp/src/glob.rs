@@ -0,0 +1,35 @@
+// #Insight
+// The patterns are matched against paths relative to the workspace root, with
+// `/` separators. `*` matches any characters except `/`, `**/` matches any
+// directories (including none), a trailing `**` matches everything, and `?`
+// matches a single character except `/`.
+
+// #TODO support character classes, e.g. `[a-z]`.
+
+/// Returns true if the glob pattern matches the whole path.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+
+    matches_at(&pattern, &path)
+}
+
+fn matches_at(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*'] => true,
+        ['*', '*', '/', rest @ ..] => {
+            matches_at(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == '/')
+                    .any(|(i, _)| matches_at(rest, &path[i + 1..]))
+        }
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| matches_at(rest, &path[i..])),
+        ['?', rest @ ..] => path.first().is_some_and(|c| *c != '/') && matches_at(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && matches_at(rest, &path[1..]),
+    }
+}
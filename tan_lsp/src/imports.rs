@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use lsp_types::{Range, TextEdit, Url};
+use tan::{ann::Ann, api::parse_string_all, expr::Expr};
+
+use crate::{
+    ast::head_symbol,
+    config::Config,
+    module::{resolve_import, ModuleOrigin},
+    snapshot::Snapshot,
+    util::lsp_position_from_index,
+};
+
+// #Insight
+// Every feature that adds an import (completion, quick fixes, extractions)
+// inserts it with `import_insertion`, the imports of a document stay in the
+// same order whatever added them. The module header, i.e. the leading
+// comments, always stays first.
+
+/// Where the new imports are inserted, among the imports of the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportPlacement {
+    /// The standard library, the external and the local modules, each group
+    /// sorted by name.
+    Grouped,
+    /// Sorted by name.
+    Sorted,
+    /// After the last import.
+    Last,
+}
+
+impl ImportPlacement {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "grouped" => Some(Self::Grouped),
+            "sorted" => Some(Self::Sorted),
+            "last" => Some(Self::Last),
+            _ => None,
+        }
+    }
+}
+
+/// The group of an imported module, in the order of the groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportGroup {
+    Stdlib,
+    /// A dependency of the workspace.
+    External,
+    /// A module of the workspace, or a module not created yet.
+    Local,
+}
+
+/// Returns the group of an imported module, relative to the directory of the
+/// importing document.
+pub fn import_group(name: &str, base_dir: &Path, config: &Config) -> ImportGroup {
+    match resolve_import(name, base_dir, config) {
+        Some((path, ModuleOrigin::Package)) => {
+            if config
+                .stdlib_path
+                .as_ref()
+                .is_some_and(|stdlib_path| path.starts_with(stdlib_path))
+            {
+                ImportGroup::Stdlib
+            } else {
+                ImportGroup::External
+            }
+        }
+        _ => ImportGroup::Local,
+    }
+}
+
+/// Returns the key that orders the imports, by the placement of the
+/// configuration. `None` if the imports are not ordered.
+pub fn import_key(name: &str, base_dir: &Path, config: &Config) -> Option<(ImportGroup, String)> {
+    match config.import_placement {
+        ImportPlacement::Grouped => {
+            Some((import_group(name, base_dir, config), name.to_lowercase()))
+        }
+        ImportPlacement::Sorted => Some((ImportGroup::Local, name.to_lowercase())),
+        ImportPlacement::Last => None,
+    }
+}
+
+/// Returns the imported module name of a `(use module)` expression.
+pub fn imported_name(expr: &Ann<Expr>) -> Option<&str> {
+    let Ann(Expr::List(terms), _) = expr else {
+        return None;
+    };
+    if head_symbol(expr) != Some("use") {
+        return None;
+    }
+    match terms.get(1) {
+        Some(Ann(Expr::Symbol(name), _)) => Some(name),
+        _ => None,
+    }
+}
+
+/// The insertion of an import, the (char) index and the inserted text.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ImportInsertion {
+    pub index: usize,
+    pub text: String,
+}
+
+/// Returns where to insert the import of a module into a document, among
+/// its top-level imports, or after the module header if it has none.
+pub fn import_insertion(
+    input: &str,
+    exprs: &[Ann<Expr>],
+    module_name: &str,
+    base_dir: &Path,
+    config: &Config,
+) -> ImportInsertion {
+    let uses: Vec<(&Ann<Expr>, &str)> = exprs
+        .iter()
+        .filter_map(|expr| Some((expr, imported_name(expr)?)))
+        .collect();
+
+    let Some((last_use, _)) = uses.last() else {
+        let index = exprs
+            .iter()
+            .find(|expr| !matches!(expr.0, Expr::Comment(_)))
+            .map_or(input.chars().count(), |expr| expr.get_range().start);
+        return ImportInsertion {
+            index,
+            text: format!("(use {module_name})\n\n"),
+        };
+    };
+
+    // Before the first import that sorts after the new one.
+    if let Some(key) = import_key(module_name, base_dir, config) {
+        let next_use = uses.iter().find(|(_, name)| {
+            import_key(name, base_dir, config).is_some_and(|use_key| use_key > key)
+        });
+        if let Some((next_use, _)) = next_use {
+            return ImportInsertion {
+                index: next_use.get_range().start,
+                text: format!("(use {module_name})\n"),
+            };
+        }
+    }
+
+    ImportInsertion {
+        index: last_use.get_range().end,
+        text: format!("\n(use {module_name})"),
+    }
+}
+
+/// Returns the module name and the edit that import the module of a file into
+/// a document, if the module is not imported yet. The files of the directory
+/// of the document are part of the same module.
+pub fn import_edit(
+    snapshot: &Snapshot,
+    file_uri: &Url,
+    document: &Url,
+) -> anyhow::Result<Option<(String, TextEdit)>> {
+    let (Ok(file_path), Ok(document_path)) = (file_uri.to_file_path(), document.to_file_path())
+    else {
+        return Ok(None);
+    };
+    let (Some(module_dir), Some(base_dir)) = (file_path.parent(), document_path.parent()) else {
+        return Ok(None);
+    };
+    if module_dir == base_dir {
+        return Ok(None);
+    }
+
+    let input = snapshot.vfs.read(document)?;
+    let Ok(exprs) = parse_string_all(&input) else {
+        return Ok(None);
+    };
+
+    let is_imported = exprs.iter().filter_map(imported_name).any(|name| {
+        resolve_import(name, base_dir, &snapshot.config)
+            .is_some_and(|(path, _)| path == file_path || path == module_dir)
+    });
+    if is_imported {
+        return Ok(None);
+    }
+
+    let Some(module_name) = import_name(&file_path, base_dir, snapshot) else {
+        return Ok(None);
+    };
+
+    let insertion = import_insertion(&input, &exprs, &module_name, base_dir, &snapshot.config);
+    let position = lsp_position_from_index(insertion.index, &input);
+    let edit = TextEdit::new(Range::new(position, position), insertion.text);
+
+    Ok(Some((module_name, edit)))
+}
+
+/// Returns the name that imports the module of a file, i.e. its directory, or
+/// the file itself in the directory of the document, the workspace root or a
+/// dependency directory.
+fn import_name(file_path: &Path, base_dir: &Path, snapshot: &Snapshot) -> Option<String> {
+    let config = &snapshot.config;
+    let module_dir = file_path.parent()?;
+
+    let mut dirs = vec![base_dir.to_path_buf()];
+    dirs.extend(config.root_path.clone());
+    dirs.extend(config.dependency_paths());
+
+    dirs.iter().find_map(|dir| {
+        let relative_path = module_dir.strip_prefix(dir).ok()?;
+        let (name, module_path) = if relative_path.as_os_str().is_empty() {
+            let stem = file_path.file_stem()?.to_string_lossy().into_owned();
+            (stem, file_path)
+        } else {
+            let segments: Vec<String> = relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            (segments.join("/"), module_dir)
+        };
+        // The name must resolve to the module, e.g. not to a shadowing module
+        // of the document directory.
+        let (path, _) = resolve_import(&name, base_dir, config)?;
+        (path == module_path).then_some(name)
+    })
+}
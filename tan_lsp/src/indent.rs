@@ -0,0 +1,647 @@
+use lsp_types::{Position, Range, TextEdit};
+
+use crate::util::LineIndex;
+
+// #Insight
+// Indentation is computed with a lightweight scan of the text, instead of the
+// parser, so that it works with incomplete or erroneous input, and preserves
+// comments and strings verbatim.
+
+/// The canonical indentation size (char count), same as the Tan formatter.
+pub const INDENT_SIZE: usize = 4;
+
+pub fn is_opening_delimiter(ch: char) -> bool {
+    ch == '(' || ch == '[' || ch == '{'
+}
+
+pub fn is_closing_delimiter(ch: char) -> bool {
+    ch == ')' || ch == ']' || ch == '}'
+}
+
+/// The (lexical) state of the scanner at the start of a line.
+#[derive(Clone, Copy, PartialEq)]
+enum LineStart {
+    Code,
+    String,
+}
+
+/// An open delimiter, keeps the (computed) indentation of its line.
+struct Opener {
+    line_indent: usize,
+}
+
+/// Returns the indentation (char count) of the line.
+fn line_indent(line: &str) -> usize {
+    line.chars().take_while(|c| *c == ' ' || *c == '\t').count()
+}
+
+/// Computes the canonical indentation for the lines `start_line..=end_line`,
+/// and returns the edits needed to apply it. Lines that start inside a string
+/// are left untouched.
+pub fn reindent_lines(input: &str, start_line: usize, end_line: usize) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    let mut stack: Vec<Opener> = Vec::new();
+    let mut in_string = false;
+
+    for (line_number, line) in input.lines().enumerate() {
+        if line_number > end_line {
+            break;
+        }
+
+        let line_start = if in_string {
+            LineStart::String
+        } else {
+            LineStart::Code
+        };
+
+        let current_indent = line_indent(line);
+        let mut indent = current_indent;
+
+        if line_start == LineStart::Code && line_number >= start_line && !line.trim().is_empty() {
+            let first_char = line.trim_start().chars().next().unwrap_or(' ');
+
+            indent = match stack.last() {
+                Some(opener) if is_closing_delimiter(first_char) => opener.line_indent,
+                Some(opener) => opener.line_indent + INDENT_SIZE,
+                None => 0,
+            };
+
+            if indent != current_indent {
+                edits.push(TextEdit {
+                    range: Range {
+                        start: Position::new(line_number as u32, 0),
+                        end: Position::new(line_number as u32, current_indent as u32),
+                    },
+                    new_text: " ".repeat(indent),
+                });
+            }
+        }
+
+        let mut chars = line.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            if in_string {
+                if ch == '"' {
+                    in_string = false;
+                }
+                continue;
+            }
+
+            match ch {
+                '"' => in_string = true,
+                ';' => break,
+                '-' if chars.peek() == Some(&'-') => break,
+                _ if is_opening_delimiter(ch) => stack.push(Opener {
+                    line_indent: indent,
+                }),
+                _ if is_closing_delimiter(ch) => {
+                    stack.pop();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    edits
+}
+
+/// Returns the nesting depth at the start of each line, and at the end of
+/// the input. Delimiters in strings and comments are ignored.
+fn line_depths(input: &str) -> Vec<usize> {
+    let mut depths = Vec::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+
+    for line in input.lines() {
+        depths.push(depth);
+
+        let mut chars = line.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if in_string {
+                if ch == '"' {
+                    in_string = false;
+                }
+                continue;
+            }
+
+            match ch {
+                '"' => in_string = true,
+                ';' => break,
+                '-' if chars.peek() == Some(&'-') => break,
+                _ if is_opening_delimiter(ch) => depth += 1,
+                _ if is_closing_delimiter(ch) => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+    }
+
+    depths.push(depth);
+
+    depths
+}
+
+/// Expands the lines `start_line..=end_line` to balanced lines, e.g. for a
+/// pasted snippet that ends in the middle of a form: the range is extended
+/// to the line that closes the forms opened in the range, and back to the
+/// line that opens the forms closed in the range.
+pub fn expand_to_balanced_lines(input: &str, start_line: usize, end_line: usize) -> (usize, usize) {
+    let depths = line_depths(input);
+    let last_line = depths.len().saturating_sub(2);
+
+    let start_line = start_line.min(last_line);
+    let mut end_line = end_line.clamp(start_line, last_line);
+
+    let start_depth = depths[start_line];
+
+    // The forms opened in the range are closed below.
+    while end_line < last_line && depths[end_line + 1] > start_depth {
+        end_line += 1;
+    }
+
+    // The forms closed in the range are opened above.
+    let min_depth = depths[start_line..=end_line + 1]
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or(start_depth);
+    let mut start_line = start_line;
+    while start_line > 0 && depths[start_line] > min_depth {
+        start_line -= 1;
+    }
+
+    (start_line, end_line)
+}
+
+/// Expands a (char) range to the indentation before and the line break after
+/// it, e.g. to delete whole lines.
+pub fn whole_line_range(chars: &[char], range: std::ops::Range<usize>) -> std::ops::Range<usize> {
+    let mut start = range.start;
+    while start > 0 && matches!(chars[start - 1], ' ' | '\t') {
+        start -= 1;
+    }
+    let mut end = range.end;
+    while end < chars.len() && matches!(chars[end], ' ' | '\t') {
+        end += 1;
+    }
+    if end < chars.len() && chars[end] == '\n' {
+        end += 1;
+    }
+    start..end
+}
+
+/// Returns the (char) ranges of the top-level forms, e.g. for incremental
+/// re-indexing. Annotations are part of the annotated form, the comments
+/// between the forms are skipped. An unbalanced form extends to the end of the
+/// input.
+pub fn top_level_form_ranges(input: &str) -> Vec<std::ops::Range<usize>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut ranges = Vec::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut in_form = false;
+    let mut is_annotation = false;
+    let mut start = None;
+
+    for (i, &ch) in chars.iter().enumerate() {
+        if in_comment {
+            in_comment = ch != '\n';
+            continue;
+        }
+
+        if in_string {
+            in_string = ch != '"';
+            continue;
+        }
+
+        let is_comment = ch == ';' || (ch == '-' && chars.get(i + 1) == Some(&'-'));
+
+        if depth == 0 && (ch.is_whitespace() || is_comment) {
+            if in_form {
+                in_form = false;
+                if !is_annotation {
+                    if let Some(start) = start.take() {
+                        ranges.push(start..i);
+                    }
+                }
+            }
+            in_comment = is_comment;
+            continue;
+        }
+
+        if depth == 0 && !in_form {
+            in_form = true;
+            is_annotation = ch == '#';
+            start.get_or_insert(i);
+        }
+
+        match ch {
+            '"' => in_string = true,
+            _ if is_comment => in_comment = true,
+            _ if is_opening_delimiter(ch) => depth += 1,
+            _ if is_closing_delimiter(ch) => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+
+    if let Some(start) = start {
+        ranges.push(start..chars.len());
+    }
+
+    ranges
+}
+
+/// Returns the line of the open delimiter matching the closing delimiter at
+/// the given (char) index.
+pub fn find_opening_line(input: &str, close_index: usize) -> Option<usize> {
+    let mut stack: Vec<usize> = Vec::new();
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut line = 0;
+    let mut prev = ' ';
+
+    for (index, ch) in input.chars().enumerate() {
+        if ch == '\n' {
+            line += 1;
+            in_comment = false;
+            prev = ch;
+            continue;
+        }
+
+        if in_comment {
+            continue;
+        }
+
+        if in_string {
+            if ch == '"' {
+                in_string = false;
+            }
+        } else if ch == '"' {
+            in_string = true;
+        } else if ch == ';' || (ch == '-' && prev == '-') {
+            in_comment = true;
+        } else if is_opening_delimiter(ch) {
+            stack.push(line);
+        } else if is_closing_delimiter(ch) {
+            let opening_line = stack.pop();
+            if index == close_index {
+                return opening_line;
+            }
+        }
+
+        prev = ch;
+    }
+
+    None
+}
+
+fn matching_closing_delimiter(ch: char) -> char {
+    match ch {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+/// Returns the closing delimiters that balance the unclosed opening
+/// delimiters of the input, innermost first.
+pub fn missing_closing_delimiters(input: &str) -> String {
+    unclosed_delimiters(input, false)
+        .map(|stack| closing_delimiters(&stack))
+        .unwrap_or_default()
+}
+
+/// Returns the closing delimiters of the (stack of) opening delimiters,
+/// innermost first.
+fn closing_delimiters(stack: &[char]) -> String {
+    stack
+        .iter()
+        .rev()
+        .map(|ch| matching_closing_delimiter(*ch))
+        .collect()
+}
+
+/// Returns the unclosed opening delimiters of the input, outermost first.
+/// `None` if `strict` and a closing delimiter does not match.
+fn unclosed_delimiters(input: &str, strict: bool) -> Option<Vec<char>> {
+    let mut stack: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut prev = ' ';
+
+    for ch in input.chars() {
+        if ch == '\n' {
+            in_comment = false;
+            prev = ch;
+            continue;
+        }
+
+        if in_comment {
+            continue;
+        }
+
+        if in_string {
+            if ch == '"' {
+                in_string = false;
+            }
+        } else if ch == '"' {
+            in_string = true;
+        } else if ch == ';' || (ch == '-' && prev == '-') {
+            in_comment = true;
+        } else if is_opening_delimiter(ch) {
+            stack.push(ch);
+        } else if is_closing_delimiter(ch) {
+            let opening = stack.pop();
+            if strict && opening.map(matching_closing_delimiter) != Some(ch) {
+                return None;
+            }
+        }
+
+        prev = ch;
+    }
+
+    Some(stack)
+}
+
+/// Returns the insertions, (char) index and text, that close the unterminated
+/// forms: the missing closing delimiters of each top-level form are inserted
+/// after its last code line, before the next top-level form, i.e. the next
+/// line that starts at column 0. The forms with mismatched delimiters are
+/// skipped. Empty if the last line of an unterminated form ends with a
+/// comment.
+pub fn closing_insertions(input: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    let mut in_string = false;
+
+    for line in input.split('\n') {
+        let (ends_in_string, has_comment) = scan_line(line, in_string);
+        lines.push((line_start, line, in_string, has_comment));
+        in_string = ends_in_string;
+        line_start += line.chars().count() + 1;
+    }
+
+    let is_top_level = |(_, line, in_string, _): &(usize, &str, bool, bool)| {
+        !in_string
+            && !line.starts_with("--")
+            && line.starts_with(|ch: char| {
+                !ch.is_whitespace() && ch != ';' && !is_closing_delimiter(ch)
+            })
+    };
+
+    let mut insertions = Vec::new();
+    let mut form_lines = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        form_lines.push(line);
+        if lines.get(i + 1).is_some_and(|next| !is_top_level(next)) {
+            continue;
+        }
+
+        let form: Vec<&str> = form_lines.iter().map(|(_, line, ..)| *line).collect();
+        let delimiters = unclosed_delimiters(&form.join("\n"), true)
+            .map(|stack| closing_delimiters(&stack))
+            .unwrap_or_default();
+
+        if !delimiters.is_empty() {
+            let last_code_line = form_lines.iter().rev().find(|(_, line, in_string, _)| {
+                !line.trim().is_empty() && (*in_string || comment_marker(line).is_none())
+            });
+            match last_code_line {
+                Some((line_start, line, _, false)) => {
+                    insertions.push((line_start + line.trim_end().chars().count(), delimiters));
+                }
+                _ => return Vec::new(),
+            }
+        }
+
+        form_lines.clear();
+    }
+
+    insertions
+}
+
+/// Scans a line, starting inside a string if `in_string`. Returns true if the
+/// line ends inside a string, and true if the line contains a comment.
+fn scan_line(line: &str, mut in_string: bool) -> (bool, bool) {
+    let mut chars = line.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if in_string {
+            if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            ';' => return (false, true),
+            '-' if chars.peek() == Some(&'-') => return (false, true),
+            _ => (),
+        }
+    }
+
+    (in_string, false)
+}
+
+/// Joins the lines `start_line..=end_line` (at least two lines), and returns
+/// the edits. The indentation of the joined lines is removed, and a single
+/// space separates the joined lines, except after an opening or before a
+/// closing delimiter. Lines with comments are never joined to the next line,
+/// and line breaks inside strings are preserved.
+pub fn join_lines(input: &str, start_line: usize, end_line: usize) -> Vec<TextEdit> {
+    let lines: Vec<&str> = input.split('\n').collect();
+    let end_line = end_line
+        .max(start_line + 1)
+        .min(lines.len().saturating_sub(1));
+    let line_index = LineIndex::new(input);
+
+    let mut edits = Vec::new();
+    let mut in_string = false;
+
+    for (line_number, line) in lines.iter().enumerate().take(end_line) {
+        let (ends_in_string, has_comment) = scan_line(line, in_string);
+        in_string = ends_in_string;
+
+        if line_number < start_line || ends_in_string || has_comment {
+            continue;
+        }
+
+        let current = line.trim_end();
+        let next = lines[line_number + 1];
+
+        // Keep the whitespace of blank lines, it is removed by the next join.
+        let next_indent = if next.trim().is_empty() {
+            0
+        } else {
+            line_indent(next)
+        };
+
+        let last_char = current.chars().last();
+        let first_char = next.trim_start().chars().next();
+
+        let separator = match (last_char, first_char) {
+            (None, _) | (_, None) => "",
+            (Some(last), _) if is_opening_delimiter(last) => "",
+            (_, Some(first)) if is_closing_delimiter(first) => "",
+            _ => " ",
+        };
+
+        edits.push(TextEdit {
+            range: Range {
+                start: line_index.position_from_line_col(line_number, current.chars().count()),
+                end: line_index.position_from_line_col(line_number + 1, next_indent),
+            },
+            new_text: separator.to_owned(),
+        });
+    }
+
+    edits
+}
+
+/// Returns the comment marker (e.g. `;;` or `--`) of a full-line comment,
+/// including the space after the marker.
+fn comment_marker(line: &str) -> Option<&str> {
+    let text = line.trim_start();
+
+    let marker_len = if text.starts_with("--") {
+        2
+    } else {
+        text.chars().take_while(|c| *c == ';').count()
+    };
+
+    if marker_len == 0 {
+        return None;
+    }
+
+    let marker_len = if text[marker_len..].starts_with(' ') {
+        marker_len + 1
+    } else {
+        marker_len
+    };
+
+    Some(&text[..marker_len])
+}
+
+/// Returns the edits after a line break is inserted before `line`: the
+/// (doc) comment of the previous line is continued, and a string left
+/// unterminated by the line break is closed at the end of the previous line.
+pub fn on_enter_edits(input: &str, line: usize) -> Vec<TextEdit> {
+    let lines: Vec<&str> = input.split('\n').collect();
+    if line == 0 || line >= lines.len() {
+        return Vec::new();
+    }
+
+    let line_index = LineIndex::new(input);
+
+    let mut in_string = false;
+    let mut starts_in_string = false;
+    for prev in lines.iter().take(line) {
+        starts_in_string = in_string;
+        (in_string, _) = scan_line(prev, in_string);
+    }
+
+    let prev_line = lines[line - 1];
+
+    if in_string {
+        // #Insight
+        // Strings may span multiple lines, only a string that is never
+        // terminated is closed.
+        let mut document_in_string = in_string;
+        for next in &lines[line..] {
+            (document_in_string, _) = scan_line(next, document_in_string);
+        }
+
+        if !document_in_string {
+            return Vec::new();
+        }
+
+        let position = line_index.position_from_line_col(line - 1, prev_line.chars().count());
+        return vec![TextEdit {
+            range: Range::new(position, position),
+            new_text: "\"".to_owned(),
+        }];
+    }
+
+    if starts_in_string {
+        return Vec::new();
+    }
+
+    let Some(marker) = comment_marker(prev_line) else {
+        return Vec::new();
+    };
+
+    // An empty comment line ends the comment block.
+    if prev_line.trim() == marker.trim_end() {
+        return Vec::new();
+    }
+
+    let current_indent = line_indent(lines[line]);
+
+    vec![TextEdit {
+        range: Range::new(
+            Position::new(line as u32, 0),
+            Position::new(line as u32, current_indent as u32),
+        ),
+        new_text: format!("{}{marker}", " ".repeat(line_indent(prev_line))),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the edits of an ASCII input, from the last one.
+    fn apply(input: &str, edits: &[TextEdit]) -> String {
+        let offset = |position: Position| -> usize {
+            let line_start: usize = input
+                .split('\n')
+                .take(position.line as usize)
+                .map(|line| line.len() + 1)
+                .sum();
+            line_start + position.character as usize
+        };
+
+        let mut output = input.to_owned();
+        for edit in edits.iter().rev() {
+            output.replace_range(
+                offset(edit.range.start)..offset(edit.range.end),
+                &edit.new_text,
+            );
+        }
+        output
+    }
+
+    fn joined(input: &str, start_line: usize, end_line: usize) -> String {
+        apply(input, &join_lines(input, start_line, end_line))
+    }
+
+    #[test]
+    fn join_lines_separates_the_terms_with_a_single_space() {
+        assert_eq!(joined("(+ 1\n    2\n    3)\n", 0, 2), "(+ 1 2 3)\n");
+    }
+
+    #[test]
+    fn join_lines_adds_no_space_inside_the_delimiters() {
+        assert_eq!(joined("(f [\n  1\n  ])\n", 0, 2), "(f [1])\n");
+    }
+
+    #[test]
+    fn join_lines_joins_a_single_line_with_the_next() {
+        assert_eq!(joined("(f\n  x)\n(g)\n", 0, 0), "(f x)\n(g)\n");
+    }
+
+    #[test]
+    fn join_lines_never_joins_a_line_comment_into_code() {
+        assert_eq!(
+            joined("(f ; the function\n  x)\n", 0, 1),
+            "(f ; the function\n  x)\n"
+        );
+    }
+
+    #[test]
+    fn join_lines_preserves_the_line_breaks_of_strings() {
+        assert_eq!(joined("(f \"a\nb\"\n  x)\n", 0, 2), "(f \"a\nb\" x)\n");
+    }
+}
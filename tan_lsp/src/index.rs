@@ -0,0 +1,306 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use lsp_types::Url;
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+use tracing::{info, trace, warn};
+
+use crate::{
+    ast::for_each_expr,
+    config::Config,
+    indent::top_level_form_ranges,
+    symbols::{collect_definitions, Definition},
+    unicode::normalize_name,
+};
+
+/// The origin of an indexed file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// A file of the workspace.
+    Workspace,
+    /// A file of a dependency, read-only.
+    Dependency,
+}
+
+impl Origin {
+    /// Returns the origin of a file.
+    pub fn of(path: &Path, config: &Config) -> Self {
+        if config.is_dependency(path) {
+            Origin::Dependency
+        } else {
+            Origin::Workspace
+        }
+    }
+}
+
+// #Insight
+// A file is indexed per top-level form. When a file changes, only the forms
+// with a different text are parsed again, the definitions of the other forms
+// are moved to the new position.
+
+/// A top-level form of an indexed file.
+#[derive(Clone)]
+struct Form {
+    /// The (char) range of the form in the file.
+    range: Range,
+    /// The hash of the text of the form, to detect changes.
+    hash: u64,
+    /// The definitions of the form, relative to the start of the form.
+    definitions: Vec<Definition>,
+    /// The (normalized) names referenced in the form, i.e. the dependency
+    /// edges of the form.
+    symbols: HashSet<String>,
+}
+
+impl Form {
+    fn parse(range: Range, text: &str, hash: u64) -> Self {
+        // Forms with parse errors are indexed without definitions.
+        let Ok(exprs) = parse_string_all(text) else {
+            return Self {
+                range,
+                hash,
+                definitions: Vec::new(),
+                symbols: HashSet::new(),
+            };
+        };
+
+        let mut symbols = HashSet::new();
+        for_each_expr(&exprs, &mut |expr| {
+            if let Ann(Expr::Symbol(sym), _) = expr {
+                // A qualified name, e.g. `ops/add`, references the unqualified
+                // name.
+                let sym = normalize_name(sym);
+                if let Some((_, name)) = sym.rsplit_once('/') {
+                    symbols.insert(name.to_owned());
+                }
+                symbols.insert(sym.into_owned());
+            }
+        });
+
+        Self {
+            range,
+            hash,
+            definitions: collect_definitions(&exprs),
+            symbols,
+        }
+    }
+}
+
+/// Returns the content hash of a text.
+pub fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn shift_range(range: &Range, offset: usize) -> Range {
+    range.start + offset..range.end + offset
+}
+
+/// A file in the index.
+#[derive(Clone)]
+pub struct IndexedFile {
+    pub origin: Origin,
+    pub text: Arc<str>,
+    pub definitions: Vec<Definition>,
+    forms: Arc<[Form]>,
+}
+
+impl IndexedFile {
+    pub fn new(origin: Origin, text: Arc<str>) -> Self {
+        Self::with_previous_forms(origin, text, &[])
+    }
+
+    /// Re-indexes the file with a new text, e.g. after an edit. Only the
+    /// changed top-level forms are parsed.
+    pub fn update(&self, text: Arc<str>) -> Self {
+        Self::with_previous_forms(self.origin, text, &self.forms)
+    }
+
+    fn with_previous_forms(origin: Origin, text: Arc<str>, previous_forms: &[Form]) -> Self {
+        let previous_forms: HashMap<u64, &Form> = previous_forms
+            .iter()
+            .map(|form| (form.hash, form))
+            .collect();
+
+        let chars: Vec<char> = text.chars().collect();
+        let mut parsed_count = 0;
+
+        let forms: Vec<Form> = top_level_form_ranges(&text)
+            .into_iter()
+            .map(|range| {
+                let form_text: String = chars[range.clone()].iter().collect();
+                let hash = text_hash(&form_text);
+                match previous_forms.get(&hash) {
+                    Some(form) => Form {
+                        range,
+                        ..(*form).clone()
+                    },
+                    None => {
+                        parsed_count += 1;
+                        Form::parse(range, &form_text, hash)
+                    }
+                }
+            })
+            .collect();
+
+        if !previous_forms.is_empty() {
+            trace!("re-indexed {parsed_count} of {} forms", forms.len());
+        }
+
+        let definitions = forms
+            .iter()
+            .flat_map(|form| {
+                form.definitions.iter().map(|definition| Definition {
+                    range: shift_range(&definition.range, form.range.start),
+                    selection_range: shift_range(&definition.selection_range, form.range.start),
+                    ..definition.clone()
+                })
+            })
+            .collect();
+
+        Self {
+            origin,
+            text,
+            definitions,
+            forms: Arc::from(forms),
+        }
+    }
+
+    /// Reads a file from disk, returns `None` if the file cannot be read,
+    /// e.g. when it was deleted.
+    pub fn read(path: &Path, config: &Config) -> Option<Self> {
+        let text = std::fs::read_to_string(path).ok()?;
+
+        Some(Self::new(Origin::of(path, config), Arc::from(text)))
+    }
+
+    /// Returns true if a form of the file references (or defines) the name.
+    pub fn mentions(&self, name: &str) -> bool {
+        let name = normalize_name(name);
+        self.forms.iter().any(|form| form.symbols.contains(&*name))
+    }
+}
+
+/// The definitions of the files in the workspace and in the dependency
+/// directories, used for navigation and completion across files.
+#[derive(Default, Clone)]
+pub struct Index {
+    files: HashMap<Url, IndexedFile>,
+}
+
+impl Index {
+    /// Builds the index of the workspace and the dependency directories.
+    pub fn build(config: &Config) -> Self {
+        let mut index = Self::default();
+
+        if let Some(root_path) = &config.root_path {
+            let dependency_paths = config.dependency_paths();
+            for path in tan_files(root_path, &dependency_paths) {
+                index.insert_file(&path, Origin::Workspace);
+            }
+
+            for dependency_path in &dependency_paths {
+                for path in tan_files(dependency_path, &[]) {
+                    index.insert_file(&path, Origin::Dependency);
+                }
+            }
+        }
+
+        info!("indexed {} files", index.files.len());
+
+        index
+    }
+
+    fn insert_file(&mut self, path: &Path, origin: Origin) {
+        let Ok(uri) = Url::from_file_path(path) else {
+            return;
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(text) => self.insert(uri, Arc::from(text), origin),
+            Err(error) => warn!("cannot index `{}`: {error}", path.display()),
+        }
+    }
+
+    /// Indexes the text of a file.
+    pub fn insert(&mut self, uri: Url, text: Arc<str>, origin: Origin) {
+        self.files.insert(uri, IndexedFile::new(origin, text));
+    }
+
+    /// Replaces the entry of a file, or removes it if the file is `None`.
+    pub fn replace(&mut self, uri: Url, file: Option<IndexedFile>) {
+        match file {
+            Some(file) => self.files.insert(uri, file),
+            None => self.files.remove(&uri),
+        };
+    }
+
+    /// Removes the entries of files, e.g. deleted without a file event, and
+    /// releases the unused capacity.
+    pub fn compact(&mut self, uris: &[Url]) {
+        for uri in uris {
+            self.files.remove(uri);
+        }
+        self.files.shrink_to_fit();
+    }
+
+    pub fn get(&self, uri: &Url) -> Option<&IndexedFile> {
+        self.files.get(uri)
+    }
+
+    /// Returns the number of indexed files.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns true if no file is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns the number of indexed definitions.
+    pub fn definition_count(&self) -> usize {
+        self.files.values().map(|file| file.definitions.len()).sum()
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&Url, &IndexedFile)> {
+        self.files.iter()
+    }
+}
+
+/// Returns the `.tan` files under a directory, recursively. Hidden
+/// directories and the excluded directories are skipped.
+pub fn tan_files(dir: &Path, excluded: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                let is_hidden = path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+                if !is_hidden && !excluded.contains(&path) {
+                    dirs.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "tan") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+
+    files
+}
@@ -1,3 +1,38 @@
+// #Insight
+// The analysis of the Tan language support, without the LSP transport: the
+// server is built on it, and the `analyze_source` API exposes the diagnostics
+// of the editor to other tools, e.g. the playground, build tools and
+// pre-commit hooks. The crate has no dependencies on the server runtime, e.g.
+// the transport, the worker threads or the sandbox processes.
+
+mod analysis;
+pub mod ast;
+pub mod cache;
+pub mod cancellation;
+pub mod config;
+pub mod diagnostics;
+pub mod dialect;
+pub mod doc_tags;
+pub mod embedded;
+pub mod format;
+pub mod glob;
+pub mod imports;
+pub mod indent;
+pub mod index;
+pub mod module;
+pub mod module_graph;
+pub mod resolve;
+pub mod sandbox;
+pub mod snapshot;
+pub mod symbols;
+pub mod unicode;
+pub mod unused;
+pub mod util;
+pub mod vfs;
+
+pub use analysis::{analyze_source, AnalysisOptions, Analyzer};
+pub use lsp_types::Diagnostic;
+
 pub fn zonk() {
     println!("zonk");
 }
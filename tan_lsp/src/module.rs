@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+
+use lsp_types::Url;
+use tan::{ann::Ann, api::parse_string_all, expr::Expr, range::Range};
+
+use crate::{
+    ast::{comments_to_doc, head_symbol},
+    config::Config,
+    unicode::same_name,
+    vfs::VfsSnapshot,
+};
+
+// #Insight
+// In Tan, `(use name)` imports a directory as a module, all `.tan` files in
+// the directory contribute to the module.
+
+/// A summary of a module, used e.g. for hover information.
+pub struct ModuleSummary {
+    pub path: PathBuf,
+    pub doc: Option<String>,
+    pub exports: Vec<String>,
+}
+
+/// Resolves a module name to a directory (or single `.tan` file), relative
+/// to `base_dir`.
+pub fn resolve_module_path(name: &str, base_dir: &Path) -> Option<PathBuf> {
+    let dir_path = base_dir.join(name);
+    if dir_path.is_dir() {
+        return Some(dir_path);
+    }
+
+    let file_path = base_dir.join(format!("{name}.tan"));
+    if file_path.is_file() {
+        return Some(file_path);
+    }
+
+    None
+}
+
+/// Where an imported module was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleOrigin {
+    /// Relative to the importing document.
+    Local,
+    /// Relative to the workspace root.
+    Workspace,
+    /// In a dependency directory, e.g. a package-qualified import.
+    Package,
+}
+
+/// Resolves an imported module name, relative to the importing document, the
+/// workspace root, and the dependency directories, in this order.
+pub fn resolve_import(
+    name: &str,
+    base_dir: &Path,
+    config: &Config,
+) -> Option<(PathBuf, ModuleOrigin)> {
+    if let Some(path) = resolve_module_path(name, base_dir) {
+        return Some((path, ModuleOrigin::Local));
+    }
+
+    if let Some(root_path) = &config.root_path {
+        if let Some(path) = resolve_module_path(name, root_path) {
+            return Some((path, ModuleOrigin::Workspace));
+        }
+    }
+
+    config
+        .dependency_paths()
+        .iter()
+        .find_map(|dir| resolve_module_path(name, dir))
+        .map(|path| (path, ModuleOrigin::Package))
+}
+
+/// Returns the source files of a module, sorted by path.
+pub fn module_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let file_path = entry?.path();
+        if file_path.extension().is_some_and(|ext| ext == "tan") {
+            files.push(file_path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Returns the source files of the modules imported by a file, i.e. the
+/// direct imports.
+pub fn imported_files(path: &Path, input: &str, config: &Config) -> Vec<PathBuf> {
+    let Ok(exprs) = parse_string_all(input) else {
+        return Vec::new();
+    };
+
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let mut files = Vec::new();
+
+    for expr in exprs.iter().filter(|expr| head_symbol(expr) == Some("use")) {
+        let Ann(Expr::List(terms), _) = expr else {
+            continue;
+        };
+        let Some(Ann(Expr::Symbol(name), _)) = terms.get(1) else {
+            continue;
+        };
+        let Some((module_path, _)) = resolve_import(name, base_dir, config) else {
+            continue;
+        };
+        for file in module_files(&module_path).unwrap_or_default() {
+            if file != path && !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+
+    files
+}
+
+/// Returns the names defined by the top-level `let` expressions.
+pub fn top_level_definitions(exprs: &[Ann<Expr>]) -> Vec<&Ann<Expr>> {
+    let mut names = Vec::new();
+
+    for expr in exprs {
+        if head_symbol(expr) != Some("let") {
+            continue;
+        }
+
+        let Ann(Expr::List(terms), _) = expr else {
+            continue;
+        };
+
+        for pair in terms[1..].chunks(2) {
+            if let Ann(Expr::Symbol(_), _) = &pair[0] {
+                names.push(&pair[0]);
+            }
+        }
+    }
+
+    names
+}
+
+/// Returns true if one of the symbols references the name, directly or
+/// qualified with the module name, e.g. `ops/add` for `(use math/ops)`.
+pub fn references_name(symbols: &[(&str, Range)], name: &str, module_name: &str) -> bool {
+    let last_segment = module_name.rsplit('/').next().unwrap_or(module_name);
+
+    symbols.iter().any(|(sym, _)| {
+        same_name(sym, name)
+            || sym.rsplit_once('/').is_some_and(|(module, _)| {
+                same_name(module, module_name) || same_name(module, last_segment)
+            })
+    })
+}
+
+/// Returns the comments attached to the top-level expression at the position,
+/// i.e. the preceding comments not separated by an empty line.
+pub fn attached_comments<'a>(
+    exprs: &'a [Ann<Expr>],
+    position: usize,
+    input: &str,
+) -> Vec<&'a Ann<Expr>> {
+    let mut comments = Vec::new();
+    let mut next = &exprs[position];
+
+    for expr in exprs[..position].iter().rev() {
+        if !matches!(expr, Ann(Expr::Comment(_), _)) || is_separated(expr, next, input) {
+            break;
+        }
+        comments.push(expr);
+        next = expr;
+    }
+
+    comments.reverse();
+
+    comments
+}
+
+/// Returns true if the input text between the two expressions contains an
+/// empty line. Lines with annotations, e.g. `#deprecated`, are not empty.
+pub fn is_separated(prev: &Ann<Expr>, next: &Ann<Expr>, input: &str) -> bool {
+    let prev_end = prev.get_range().end;
+    let gap: String = input
+        .chars()
+        .skip(prev_end)
+        .take(next.get_range().start.saturating_sub(prev_end))
+        .collect();
+
+    // #Insight
+    // The comment range includes the trailing newline, the last line of the
+    // gap is the line of the next expression.
+
+    gap.split('\n')
+        .rev()
+        .skip(1)
+        .any(|line| line.trim().is_empty())
+}
+
+/// Extracts the module doc comment, i.e. the leading comment block of the
+/// input that is separated from the next expression by an empty line.
+pub fn module_doc(exprs: &[Ann<Expr>], input: &str) -> Option<String> {
+    let mut comments: Vec<&Ann<Expr>> = Vec::new();
+
+    for expr in exprs {
+        let Ann(Expr::Comment(_), _) = expr else {
+            // A doc comment directly attached to the first definition is not a
+            // module doc comment.
+            if !comments
+                .last()
+                .is_some_and(|last| is_separated(last, expr, input))
+            {
+                return None;
+            }
+            break;
+        };
+
+        if let Some(last) = comments.last() {
+            if is_separated(last, expr, input) {
+                break;
+            }
+        }
+
+        comments.push(expr);
+    }
+
+    comments_to_doc(&comments)
+}
+
+/// Summarizes the module at the given path. The open documents shadow the
+/// files on disk.
+pub fn summarize_module(path: &Path, vfs: &VfsSnapshot) -> anyhow::Result<ModuleSummary> {
+    let mut doc = None;
+    let mut exports = Vec::new();
+
+    for file_path in module_files(path)? {
+        let Ok(uri) = Url::from_file_path(&file_path) else {
+            continue;
+        };
+        let input = vfs.read(&uri)?;
+
+        let Ok(exprs) = parse_string_all(&input) else {
+            // Skip files with parse errors, they are reported separately.
+            continue;
+        };
+
+        if doc.is_none() {
+            doc = module_doc(&exprs, &input);
+        }
+
+        for name in top_level_definitions(&exprs) {
+            exports.push(name.0.to_string());
+        }
+    }
+
+    Ok(ModuleSummary {
+        path: path.to_path_buf(),
+        doc,
+        exports,
+    })
+}
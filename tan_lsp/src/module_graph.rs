@@ -0,0 +1,216 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use lsp_types::{Location, SymbolKind, Url};
+use tan::api::parse_string_all;
+
+use crate::{
+    config::Config,
+    index::Index,
+    module::resolve_import,
+    snapshot::Snapshot,
+    symbols::{collect_definitions, Definition},
+    util::lsp_range_from_range,
+};
+
+// #Insight
+// The nodes of the graph are modules, i.e. directories and files. A file is
+// the module of its path, and contributes to the module of its directory, the
+// imports of the file are edges from both modules.
+
+/// A resolved `(use module)` expression.
+#[derive(Debug, Clone)]
+pub struct Import {
+    /// The imported module name.
+    pub name: String,
+    /// The location of the module name in the importing file.
+    pub location: Location,
+    /// The path of the imported module.
+    pub target: PathBuf,
+}
+
+/// The imports of the workspace modules.
+#[derive(Default)]
+pub struct ModuleGraph {
+    edges: HashMap<PathBuf, Vec<Import>>,
+    /// The names of the imports that do not resolve, e.g. of deleted modules,
+    /// and the importing files.
+    unresolved: Vec<(String, Url)>,
+}
+
+/// Returns the modules of a file, the file and its directory.
+fn owner_modules(path: &Path) -> impl Iterator<Item = &Path> {
+    std::iter::once(path).chain(path.parent())
+}
+
+impl ModuleGraph {
+    /// Builds the graph of the workspace modules, from the open documents and
+    /// the indexed files. Dependencies are excluded.
+    pub fn build(snapshot: &Snapshot) -> Self {
+        let mut graph = Self::default();
+
+        for (uri, input) in snapshot.vfs.documents() {
+            graph.add_source(uri, input, &snapshot.config);
+        }
+
+        graph.add_indexed_files(&snapshot.index, &snapshot.config, |uri| {
+            snapshot.vfs.get(uri).is_some()
+        });
+
+        graph
+    }
+
+    /// Adds the imports of a source file, e.g. an open document.
+    pub fn add_source(&mut self, uri: &Url, input: &str, config: &Config) {
+        let Ok(exprs) = parse_string_all(input) else {
+            return;
+        };
+        self.add_file(uri, input, &collect_definitions(&exprs), config);
+    }
+
+    /// Adds the imports of the indexed files, except the shadowed ones, e.g.
+    /// by open documents.
+    pub fn add_indexed_files(
+        &mut self,
+        index: &Index,
+        config: &Config,
+        is_shadowed: impl Fn(&Url) -> bool,
+    ) {
+        for (uri, file) in index.files() {
+            if !is_shadowed(uri) {
+                self.add_file(uri, &file.text, &file.definitions, config);
+            }
+        }
+    }
+
+    fn add_file(&mut self, uri: &Url, input: &str, definitions: &[Definition], config: &Config) {
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+
+        if config.is_dependency(&path) {
+            return;
+        }
+
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+
+        for definition in definitions {
+            if definition.kind != SymbolKind::MODULE {
+                continue;
+            }
+
+            let Some((target, _)) = resolve_import(&definition.name, base_dir, config) else {
+                self.unresolved.push((definition.name.clone(), uri.clone()));
+                continue;
+            };
+
+            let import = Import {
+                name: definition.name.clone(),
+                location: Location::new(
+                    uri.clone(),
+                    lsp_range_from_range(&definition.selection_range, input),
+                ),
+                target,
+            };
+
+            for module in owner_modules(&path) {
+                self.edges
+                    .entry(module.to_path_buf())
+                    .or_default()
+                    .push(import.clone());
+            }
+        }
+    }
+
+    /// Returns the imports of a file.
+    pub fn imports_of(&self, path: &Path) -> &[Import] {
+        self.edges.get(path).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Returns the files that import the modules of the given files, created
+    /// or deleted. The imports of deleted modules are matched by name, they do
+    /// not resolve anymore.
+    pub fn dependents_of(&self, paths: &[PathBuf]) -> HashSet<&Url> {
+        let modules: Vec<&Path> = paths.iter().flat_map(|path| owner_modules(path)).collect();
+
+        let resolved = self
+            .edges
+            .values()
+            .flatten()
+            .filter(|import| modules.contains(&import.target.as_path()))
+            .map(|import| &import.location.uri);
+
+        // E.g. `util/helpers` is the module of `util/helpers.tan`.
+        let unresolved = self
+            .unresolved
+            .iter()
+            .filter(|(name, _)| {
+                modules.iter().any(|module| {
+                    let module = if module.extension().is_some_and(|ext| ext == "tan") {
+                        module.with_extension("")
+                    } else {
+                        module.to_path_buf()
+                    };
+                    module.ends_with(name)
+                })
+            })
+            .map(|(_, uri)| uri);
+
+        resolved.chain(unresolved).collect()
+    }
+
+    /// Returns the cycle that the import participates in, as the chain of
+    /// imports from the given one back to the importing file, if any.
+    pub fn find_cycle<'a>(&'a self, import: &'a Import) -> Option<Vec<&'a Import>> {
+        let source = import.location.uri.to_file_path().ok()?;
+        let owners: Vec<&Path> = owner_modules(&source).collect();
+
+        // Breadth-first search, for the shortest cycle. Maps each reached
+        // module to the module and the import it was reached from.
+        let start = import.target.as_path();
+        let mut previous: HashMap<&Path, (&Path, &Import)> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(module) = queue.pop_front() {
+            if owners.contains(&module) {
+                let mut chain = Vec::new();
+                let mut current = module;
+                while current != start {
+                    let (from, edge) = previous[current];
+                    chain.push(edge);
+                    current = from;
+                }
+                chain.push(import);
+                chain.reverse();
+                return Some(chain);
+            }
+
+            for edge in self.edges.get(module).into_iter().flatten() {
+                let target = edge.target.as_path();
+                if target != start && !previous.contains_key(target) {
+                    previous.insert(target, (module, edge));
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the number of imports that participate in cycles.
+    pub fn cyclic_import_count(&self) -> usize {
+        let mut imports: HashSet<(&Url, u32, u32)> = HashSet::new();
+
+        for import in self.edges.values().flatten() {
+            let start = import.location.range.start;
+            let key = (&import.location.uri, start.line, start.character);
+            if !imports.contains(&key) && self.find_cycle(import).is_some() {
+                imports.insert(key);
+            }
+        }
+
+        imports.len()
+    }
+}
@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use lsp_types::{SymbolKind, Url};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::{find_path_at, for_each_expr, head_symbol},
+    snapshot::Snapshot,
+    symbols::collect_definitions,
+    unicode::same_name,
+};
+
+// #TODO resolve the imported names, to the definitions of the module.
+
+// #Insight
+// Names are resolved lexically: the parameters of the enclosing functions and
+// the preceding `let` bindings of the enclosing `do` blocks shadow the
+// module-level definitions. Module-level definitions are visible in the whole
+// file, e.g. for mutually recursive functions.
+
+/// The binding of a name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    /// The range of the binding form, e.g. the `let` expression.
+    pub range: Range,
+    /// The range of the bound name.
+    pub name_range: Range,
+}
+
+/// Resolves the symbol at the (char) index to its binding in the same file.
+/// Returns `None` if the index is not on a symbol, or the name is not bound
+/// in the file, e.g. an imported or builtin name.
+pub fn resolve_symbol_at(exprs: &[Ann<Expr>], index: usize) -> Option<Binding> {
+    if let Some(binding) = resolve_local_symbol_at(exprs, index) {
+        return Some(binding);
+    }
+
+    let Some(Ann(Expr::Symbol(name), _)) = find_path_at(exprs, index).last() else {
+        return None;
+    };
+
+    collect_definitions(exprs)
+        .into_iter()
+        .find(|definition| {
+            same_name(&definition.name, name) && definition.kind != SymbolKind::MODULE
+        })
+        .map(|definition| Binding {
+            range: definition.range,
+            name_range: definition.selection_range,
+        })
+}
+
+/// The binding of a symbol, in the document or in another file.
+pub struct Target {
+    pub uri: Url,
+    /// The text of the binding file.
+    pub text: Arc<str>,
+    pub binding: Binding,
+}
+
+/// Looks up the binding of the symbol at the (char) index of a document: the
+/// bindings of the document, then the definitions of the indexed files.
+/// Shared by navigation and hover.
+pub fn lookup_symbol_at(
+    snapshot: &Snapshot,
+    uri: &Url,
+    input: &Arc<str>,
+    exprs: &[Ann<Expr>],
+    index: usize,
+) -> Option<Target> {
+    if let Some(binding) = resolve_symbol_at(exprs, index) {
+        return Some(Target {
+            uri: uri.clone(),
+            text: input.clone(),
+            binding,
+        });
+    }
+
+    let Some(Ann(Expr::Symbol(name), _)) = find_path_at(exprs, index).last() else {
+        return None;
+    };
+
+    // A qualified name, e.g. `ops/add`, is looked up unqualified.
+    let name = name
+        .rsplit_once('/')
+        .map_or(name.as_str(), |(_, name)| name);
+
+    // The files are sorted, for deterministic results.
+    let mut files: Vec<_> = snapshot
+        .index
+        .files()
+        .filter(|(file_uri, _)| *file_uri != uri)
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+    files.into_iter().find_map(|(file_uri, file)| {
+        let definition = file.definitions.iter().find(|definition| {
+            same_name(&definition.name, name) && definition.kind != SymbolKind::MODULE
+        })?;
+        Some(Target {
+            uri: file_uri.clone(),
+            text: file.text.clone(),
+            binding: Binding {
+                range: definition.range.clone(),
+                name_range: definition.selection_range.clone(),
+            },
+        })
+    })
+}
+
+/// Returns the value bound by a `let` binding, from the parsed text of the
+/// binding file. `None` for the parameters.
+pub fn bound_value<'a>(exprs: &'a [Ann<Expr>], binding: &Binding) -> Option<&'a Ann<Expr>> {
+    let form = find_path_at(exprs, binding.name_range.start)
+        .into_iter()
+        .rev()
+        .find(|expr| expr.get_range() == binding.range)?;
+
+    if matches!(head_symbol(form), Some("Func") | Some("Macro")) {
+        return None;
+    }
+
+    let Ann(Expr::List(terms), _) = form else {
+        return None;
+    };
+
+    // The value follows the name in a `let` pair.
+    terms
+        .iter()
+        .position(|term| term.get_range() == binding.name_range)
+        .and_then(|i| terms.get(i + 1))
+}
+
+/// Returns the symbols (and their ranges) of the expressions, except the
+/// locally bound ones. The bindings are resolved in all the expressions of the
+/// document.
+pub fn module_level_symbols<'a>(
+    all_exprs: &[Ann<Expr>],
+    exprs: &'a [Ann<Expr>],
+) -> Vec<(&'a str, Range)> {
+    let mut symbols = Vec::new();
+
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(sym), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        if range.start < range.end && resolve_local_symbol_at(all_exprs, range.start).is_none() {
+            symbols.push((sym.as_str(), range));
+        }
+    });
+
+    symbols
+}
+
+/// Resolves the symbol at the (char) index to a local binding, i.e. a function
+/// parameter or a nested `let`. Returns `None` for the module-level names.
+pub fn resolve_local_symbol_at(exprs: &[Ann<Expr>], index: usize) -> Option<Binding> {
+    let path = find_path_at(exprs, index);
+
+    let Some(Ann(Expr::Symbol(name), _)) = path.last() else {
+        return None;
+    };
+
+    local_scopes(&path).into_iter().find_map(|parent| {
+        bound_names(parent, index)
+            .into_iter()
+            .rev()
+            .find(|(bound_name, _)| same_name(bound_name, name))
+            .map(|(_, binding)| binding)
+    })
+}
+
+/// The kind of a local binding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LocalKind {
+    Parameter,
+    /// A nested `let` with a function value.
+    Function,
+    /// A nested `let` with a macro value.
+    Macro,
+    /// A nested `let` with any other value.
+    Variable,
+}
+
+/// Returns the kind of the local binding of the symbol at the (char) index,
+/// `None` for the module-level names.
+pub fn local_binding_kind(exprs: &[Ann<Expr>], index: usize) -> Option<LocalKind> {
+    let binding = resolve_local_symbol_at(exprs, index)?;
+
+    let path = find_path_at(exprs, binding.range.start);
+    let form = path
+        .into_iter()
+        .rev()
+        .find(|expr| expr.get_range() == binding.range)?;
+
+    if matches!(head_symbol(form), Some("Func") | Some("Macro")) {
+        return Some(LocalKind::Parameter);
+    }
+
+    // The value follows the name in a `let` pair.
+    let Ann(Expr::List(terms), _) = form else {
+        return Some(LocalKind::Variable);
+    };
+    let value = terms
+        .iter()
+        .position(|term| term.get_range() == binding.name_range)
+        .and_then(|i| terms.get(i + 1));
+
+    Some(match value.and_then(head_symbol) {
+        Some("Func") => LocalKind::Function,
+        Some("Macro") => LocalKind::Macro,
+        _ => LocalKind::Variable,
+    })
+}
+
+/// Returns the local bindings visible at the (char) index, the innermost
+/// first. The shadowed bindings are included, after the shadowing ones.
+pub fn visible_local_bindings(exprs: &[Ann<Expr>], index: usize) -> Vec<(&str, Binding)> {
+    let path = find_path_at(exprs, index);
+
+    local_scopes(&path)
+        .into_iter()
+        .flat_map(|parent| bound_names(parent, index).into_iter().rev())
+        .collect()
+}
+
+/// Returns the expressions of the path that may bind local names, the
+/// innermost first.
+fn local_scopes<'a>(path: &[&'a Ann<Expr>]) -> Vec<&'a Ann<Expr>> {
+    path.iter()
+        .copied()
+        .enumerate()
+        .rev()
+        // The top-level `let` and `do` expressions bind module-level names.
+        .filter(|(i, expr)| *i > 0 || !matches!(head_symbol(expr), Some("let") | Some("do")))
+        .map(|(_, expr)| expr)
+        .collect()
+}
+
+/// Returns the names bound by the parent expression that are visible at the
+/// (char) index, in order: the later names shadow the earlier ones.
+fn bound_names(parent: &Ann<Expr>, index: usize) -> Vec<(&str, Binding)> {
+    let Ann(Expr::List(terms), _) = parent else {
+        return Vec::new();
+    };
+
+    // #Insight
+    // The index may fall between the terms, e.g. when completing, the position
+    // is the one of the next term.
+    let position = terms
+        .iter()
+        .position(|term| index < term.get_range().end)
+        .unwrap_or(terms.len());
+
+    // The head of a list is never bound by the list.
+    if position == 0 {
+        return Vec::new();
+    }
+
+    match head_symbol(parent) {
+        Some("Func") | Some("Macro") => {
+            let Some(Ann(Expr::List(params), _)) = terms.get(1) else {
+                return Vec::new();
+            };
+            // The first parameter with a name wins.
+            params
+                .iter()
+                .rev()
+                .filter_map(|param| binding(parent, param))
+                .collect()
+        }
+        Some("let") => {
+            // The name of a pair is the binding itself.
+            if position % 2 == 1 {
+                return terms
+                    .get(position)
+                    .and_then(|name_expr| binding(parent, name_expr))
+                    .into_iter()
+                    .collect();
+            }
+            // The names of the preceding pairs are visible in a value, and the
+            // own name of a function, for recursion.
+            let is_function = terms
+                .get(position)
+                .is_some_and(|value| matches!(head_symbol(value), Some("Func") | Some("Macro")));
+            let end = if is_function {
+                position + 1
+            } else {
+                position - 1
+            };
+            let_names(parent, &terms[1..end])
+        }
+        // The lets that precede the index are visible, the latest binding
+        // shadows the previous ones.
+        Some("do") => terms[1..position]
+            .iter()
+            .filter(|term| head_symbol(term) == Some("let"))
+            .flat_map(|term| match term {
+                Ann(Expr::List(let_terms), _) => let_names(term, &let_terms[1..]),
+                _ => Vec::new(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the names bound by the pairs of a `let` expression.
+fn let_names<'a>(let_expr: &Ann<Expr>, pairs: &'a [Ann<Expr>]) -> Vec<(&'a str, Binding)> {
+    pairs
+        .chunks(2)
+        .filter_map(|pair| binding(let_expr, pair.first()?))
+        .collect()
+}
+
+/// Returns the binding of a name expression by a binding form.
+fn binding<'a>(form: &Ann<Expr>, name_expr: &'a Ann<Expr>) -> Option<(&'a str, Binding)> {
+    let Ann(Expr::Symbol(name), _) = name_expr else {
+        return None;
+    };
+
+    Some((
+        name.as_str(),
+        Binding {
+            range: form.get_range(),
+            name_range: name_expr.get_range(),
+        },
+    ))
+}
@@ -0,0 +1,107 @@
+use std::{fmt, time::Duration};
+
+use tracing::warn;
+
+// #TODO enforce the policy in the run and test commands, they are currently
+// executed by the client. The server enforces it in `tan.evaluate`.
+
+// #Insight
+// The Tan prelude has no network or process functions, the evaluated code can
+// neither use the network nor spawn processes, the policy has no options for
+// them.
+
+// #Insight
+// The sandbox is configured by the client only, not by the manifest: an
+// untrusted workspace should not be able to allow itself more.
+
+/// The default time limit of an evaluation.
+pub const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(10);
+
+/// The default memory limit of an evaluation, in MiB.
+pub const DEFAULT_MEMORY_LIMIT: u64 = 256;
+
+/// The filesystem access of evaluated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilesystemAccess {
+    None,
+    /// Read files, the reads are not restricted to the workspace.
+    Read,
+    /// Read and write files. The prelude has no file writing functions, it is
+    /// the same as `Read`.
+    Write,
+}
+
+impl FilesystemAccess {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(Self::None),
+            "read" => Some(Self::Read),
+            "write" => Some(Self::Write),
+            _ => None,
+        }
+    }
+}
+
+/// What evaluated code may do, e.g. in the eval and test commands.
+#[derive(Debug, Clone)]
+pub struct SandboxPolicy {
+    pub filesystem: FilesystemAccess,
+    /// The maximum duration of an evaluation, unlimited if `None`.
+    pub time_limit: Option<Duration>,
+    /// The maximum memory (address space) of an evaluation in MiB, unlimited
+    /// if `None`. Enforced on Unix only.
+    pub memory_limit: Option<u64>,
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        Self {
+            filesystem: FilesystemAccess::Read,
+            time_limit: Some(DEFAULT_TIME_LIMIT),
+            memory_limit: Some(DEFAULT_MEMORY_LIMIT),
+        }
+    }
+}
+
+impl SandboxPolicy {
+    /// Updates the policy from the `sandbox` section of the client options.
+    /// A limit of 0 disables the limit.
+    pub fn update_from_options(&mut self, options: &serde_json::Value) {
+        if let Some(filesystem) = options.get("filesystem").and_then(|v| v.as_str()) {
+            match FilesystemAccess::parse(filesystem) {
+                Some(filesystem) => self.filesystem = filesystem,
+                None => warn!("unknown sandbox filesystem access `{filesystem}`"),
+            }
+        }
+
+        if let Some(seconds) = options.get("timeLimit").and_then(|v| v.as_u64()) {
+            self.time_limit = (seconds > 0).then(|| Duration::from_secs(seconds));
+        }
+
+        if let Some(mebibytes) = options.get("memoryLimit").and_then(|v| v.as_u64()) {
+            self.memory_limit = (mebibytes > 0).then_some(mebibytes);
+        }
+    }
+}
+
+impl fmt::Display for SandboxPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filesystem = match self.filesystem {
+            FilesystemAccess::None => "no filesystem",
+            FilesystemAccess::Read => "read-only filesystem",
+            FilesystemAccess::Write => "writable filesystem",
+        };
+        write!(f, "{filesystem}")?;
+
+        match self.time_limit {
+            Some(time_limit) => write!(f, ", {}s", time_limit.as_secs())?,
+            None => write!(f, ", no time limit")?,
+        }
+
+        // The memory limit is not enforced on the other platforms.
+        match self.memory_limit {
+            Some(memory_limit) if cfg!(unix) => write!(f, ", {memory_limit} MiB"),
+            _ => write!(f, ", no memory limit"),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use lsp_types::Url;
+
+use crate::{
+    cache::Caches,
+    cancellation::{CancellationToken, NotIndexed},
+    config::Config,
+    index::Index,
+    vfs::VfsSnapshot,
+};
+
+// #Insight
+// Snapshots are cheap to create, they only clone `Arc`s.
+
+/// An immutable view of the server state. Requests are processed against a
+/// snapshot, in worker threads, while the message loop keeps updating the
+/// live state.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub config: Arc<Config>,
+    pub vfs: VfsSnapshot,
+    pub index: Arc<Index>,
+    /// The caches of the values computed from the open documents, shared by
+    /// the snapshots.
+    pub caches: Arc<Caches>,
+    /// True while the workspace is being indexed, the index is incomplete.
+    pub indexing: bool,
+    /// Cancelled if the client cancels the request.
+    pub cancellation: CancellationToken,
+}
+
+impl Snapshot {
+    /// Returns an error while the workspace is being indexed, for handlers
+    /// that cannot serve partial results, e.g. rename.
+    pub fn check_indexed(&self) -> Result<(), NotIndexed> {
+        if self.indexing {
+            Err(NotIndexed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the text of the open documents and the indexed files, the open
+    /// documents shadow the indexed files.
+    pub fn sources(&self) -> impl Iterator<Item = (&Url, &Arc<str>)> {
+        self.vfs.documents().chain(
+            self.index
+                .files()
+                .filter(|(uri, _)| self.vfs.get(uri).is_none())
+                .map(|(uri, file)| (uri, &file.text)),
+        )
+    }
+}
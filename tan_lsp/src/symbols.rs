@@ -0,0 +1,256 @@
+use lsp_types::{
+    DocumentSymbol, Location, SymbolInformation, SymbolKind, SymbolKindCapability, SymbolTag,
+    TagSupport,
+};
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::ast::head_symbol;
+
+/// A definition (binding) extracted from a document.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// True if the bound value is a `Macro`, classified as a function.
+    pub is_macro: bool,
+    pub deprecated: bool,
+    /// The range of the whole defining expression.
+    pub range: Range,
+    /// The range of the defined name.
+    pub selection_range: Range,
+}
+
+/// Returns true if the expression is annotated with `#deprecated`.
+fn is_deprecated(expr: &Ann<Expr>) -> bool {
+    expr.contains_annotation("deprecated")
+}
+
+/// Classifies a definition from the defined name and the bound value.
+pub fn definition_kind(name: &str, value: &Ann<Expr>) -> SymbolKind {
+    match head_symbol(value) {
+        Some("Func") | Some("Macro") => return SymbolKind::FUNCTION,
+        Some("Array") => return SymbolKind::ARRAY,
+        Some("Dict") => return SymbolKind::OBJECT,
+        _ => (),
+    }
+
+    // #Insight
+    // Uppercase names are reserved for types, by convention.
+    if name.chars().next().is_some_and(|c| c.is_uppercase()) {
+        return SymbolKind::STRUCT;
+    }
+
+    match value.0 {
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::String(_)
+        | Expr::Char(_)
+        | Expr::KeySymbol(_) => SymbolKind::CONSTANT,
+        _ => SymbolKind::VARIABLE,
+    }
+}
+
+/// Returns the definitions of a `let` expression, with the bound values.
+pub fn let_definitions(expr: &Ann<Expr>) -> Vec<(Definition, &Ann<Expr>)> {
+    let mut definitions = Vec::new();
+
+    let Ann(Expr::List(terms), _) = expr else {
+        return definitions;
+    };
+
+    for pair in terms[1..].chunks(2) {
+        let [name_expr, value] = pair else {
+            break;
+        };
+
+        let Ann(Expr::Symbol(name), _) = name_expr else {
+            continue;
+        };
+
+        let definition = Definition {
+            name: name.clone(),
+            kind: definition_kind(name, value),
+            is_macro: head_symbol(value) == Some("Macro"),
+            deprecated: is_deprecated(expr) || is_deprecated(name_expr) || is_deprecated(value),
+            range: expr.get_range(),
+            selection_range: name_expr.get_range(),
+        };
+
+        definitions.push((definition, value));
+    }
+
+    definitions
+}
+
+/// Collects the definitions of a `let` expression.
+fn collect_let_definitions(expr: &Ann<Expr>, definitions: &mut Vec<Definition>) {
+    definitions.extend(
+        let_definitions(expr)
+            .into_iter()
+            .map(|(definition, _)| definition),
+    );
+}
+
+/// Collects the module-level definitions, i.e. top-level `let` and `use`
+/// expressions, including the ones nested in top-level `do` blocks.
+pub fn collect_definitions(exprs: &[Ann<Expr>]) -> Vec<Definition> {
+    let mut definitions = Vec::new();
+
+    for expr in exprs {
+        match head_symbol(expr) {
+            Some("let") => collect_let_definitions(expr, &mut definitions),
+            Some("use") => {
+                if let Ann(Expr::List(terms), _) = expr {
+                    if let Some(name_expr @ Ann(Expr::Symbol(name), _)) = terms.get(1) {
+                        definitions.push(Definition {
+                            name: name.clone(),
+                            kind: SymbolKind::MODULE,
+                            is_macro: false,
+                            deprecated: false,
+                            range: expr.get_range(),
+                            selection_range: name_expr.get_range(),
+                        });
+                    }
+                }
+            }
+            Some("do") => {
+                if let Ann(Expr::List(terms), _) = expr {
+                    definitions.extend(collect_definitions(&terms[1..]));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    definitions
+}
+
+/// The symbol kinds supported by clients that don't declare a value set,
+/// i.e. the kinds from `File` to `Array` defined in the initial version of
+/// the protocol.
+const DEFAULT_SYMBOL_KINDS: [SymbolKind; 18] = [
+    SymbolKind::FILE,
+    SymbolKind::MODULE,
+    SymbolKind::NAMESPACE,
+    SymbolKind::PACKAGE,
+    SymbolKind::CLASS,
+    SymbolKind::METHOD,
+    SymbolKind::PROPERTY,
+    SymbolKind::FIELD,
+    SymbolKind::CONSTRUCTOR,
+    SymbolKind::ENUM,
+    SymbolKind::INTERFACE,
+    SymbolKind::FUNCTION,
+    SymbolKind::VARIABLE,
+    SymbolKind::CONSTANT,
+    SymbolKind::STRING,
+    SymbolKind::NUMBER,
+    SymbolKind::BOOLEAN,
+    SymbolKind::ARRAY,
+];
+
+/// The symbol kinds and tags supported by the client.
+pub struct SymbolSupport {
+    kinds: Vec<SymbolKind>,
+    deprecated_tag: bool,
+}
+
+impl SymbolSupport {
+    pub fn new(
+        symbol_kind: Option<&SymbolKindCapability>,
+        tag_support: Option<&TagSupport<SymbolTag>>,
+    ) -> Self {
+        let kinds = symbol_kind
+            .and_then(|capability| capability.value_set.clone())
+            .unwrap_or_else(|| DEFAULT_SYMBOL_KINDS.to_vec());
+
+        let deprecated_tag = tag_support.is_some_and(|support| {
+            // #Insight
+            // Old clients declare tag support with a boolean, the value set is empty.
+            support.value_set.is_empty() || support.value_set.contains(&SymbolTag::DEPRECATED)
+        });
+
+        Self {
+            kinds,
+            deprecated_tag,
+        }
+    }
+
+    /// Returns the kind, or a supported fallback kind if the client does not
+    /// support it.
+    pub fn kind(&self, kind: SymbolKind) -> SymbolKind {
+        if self.kinds.contains(&kind) {
+            return kind;
+        }
+
+        let fallback = match kind {
+            SymbolKind::STRUCT => SymbolKind::CLASS,
+            _ => SymbolKind::VARIABLE,
+        };
+
+        if self.kinds.contains(&fallback) {
+            fallback
+        } else {
+            kind
+        }
+    }
+
+    /// Returns the tags of a definition, `None` if the client does not support
+    /// tags, use the `deprecated` flag instead.
+    pub fn tags(&self, definition: &Definition) -> Option<Vec<SymbolTag>> {
+        if self.deprecated_tag && definition.deprecated {
+            Some(vec![SymbolTag::DEPRECATED])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the (legacy) deprecated flag, for clients without tag support.
+    pub fn deprecated(&self, definition: &Definition) -> Option<bool> {
+        if !self.deprecated_tag && definition.deprecated {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Converts a definition to a document symbol, the ranges are LSP ranges.
+    pub fn document_symbol(
+        &self,
+        definition: &Definition,
+        range: lsp_types::Range,
+        selection_range: lsp_types::Range,
+        detail: Option<String>,
+        children: Vec<DocumentSymbol>,
+    ) -> DocumentSymbol {
+        #[allow(deprecated)]
+        DocumentSymbol {
+            name: definition.name.clone(),
+            detail,
+            kind: self.kind(definition.kind),
+            tags: self.tags(definition),
+            deprecated: self.deprecated(definition),
+            range,
+            selection_range,
+            children: (!children.is_empty()).then_some(children),
+        }
+    }
+
+    /// Converts a definition to symbol information.
+    pub fn symbol_information(
+        &self,
+        definition: &Definition,
+        location: Location,
+    ) -> SymbolInformation {
+        #[allow(deprecated)]
+        SymbolInformation {
+            name: definition.name.clone(),
+            kind: self.kind(definition.kind),
+            tags: self.tags(definition),
+            deprecated: self.deprecated(definition),
+            location,
+            container_name: None,
+        }
+    }
+}
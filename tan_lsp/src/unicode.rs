@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+
+use unicode_normalization::{char::is_combining_mark, is_nfc, UnicodeNormalization};
+
+// #Insight
+// Names are compared in Normalization Form C, e.g. `café` typed with a
+// combining accent is the same name as `café` with a precomposed `é`.
+
+/// Returns the name in Normalization Form C.
+pub fn normalize_name(name: &str) -> Cow<'_, str> {
+    if is_nfc(name) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(name.nfc().collect())
+    }
+}
+
+/// Returns true if the names are equal, after normalization.
+pub fn same_name(a: &str, b: &str) -> bool {
+    a == b || normalize_name(a) == normalize_name(b)
+}
+
+/// Returns true if the character can be part of a name, for whole-word
+/// matching. Combining marks are part of the preceding letter.
+pub fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || is_combining_mark(c)
+}
+
+/// Returns true if the input may contain the name, without parsing. Inputs
+/// that are not normalized are never skipped.
+pub fn may_contain_name(input: &str, name: &str) -> bool {
+    !is_nfc(input) || input.contains(&*normalize_name(name))
+}
+
+/// The scripts with common homoglyphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+impl Script {
+    pub fn name(self) -> &'static str {
+        match self {
+            Script::Latin => "Latin",
+            Script::Greek => "Greek",
+            Script::Cyrillic => "Cyrillic",
+        }
+    }
+}
+
+/// Returns the script of a letter, `None` for the other characters.
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+        '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+        '\u{0400}'..='\u{04FF}' => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+/// Returns the first two scripts of a name that mixes scripts.
+pub fn mixed_scripts(name: &str) -> Option<(Script, Script)> {
+    let mut scripts = name.chars().filter_map(script);
+    let first = scripts.next()?;
+    let second = scripts.find(|script| *script != first)?;
+    Some((first, second))
+}
+
+/// The Greek and Cyrillic letters that look like Latin letters.
+const HOMOGLYPHS: [(char, char); 38] = [
+    ('а', 'a'),
+    ('е', 'e'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('ѕ', 's'),
+    ('ԁ', 'd'),
+    ('А', 'A'),
+    ('В', 'B'),
+    ('Е', 'E'),
+    ('К', 'K'),
+    ('М', 'M'),
+    ('Н', 'H'),
+    ('О', 'O'),
+    ('Р', 'P'),
+    ('С', 'C'),
+    ('Т', 'T'),
+    ('Х', 'X'),
+    ('ο', 'o'),
+    ('ν', 'v'),
+    ('ρ', 'p'),
+    ('Α', 'A'),
+    ('Β', 'B'),
+    ('Ε', 'E'),
+    ('Ζ', 'Z'),
+    ('Η', 'H'),
+    ('Ι', 'I'),
+    ('Κ', 'K'),
+    ('Μ', 'M'),
+    ('Ν', 'N'),
+    ('Ο', 'O'),
+    ('Ρ', 'P'),
+    ('Τ', 'T'),
+    ('Χ', 'X'),
+];
+
+/// Returns the skeleton of a name, the homoglyphs replaced with the Latin
+/// letters. Confusable names have the same skeleton.
+pub fn skeleton(name: &str) -> String {
+    normalize_name(name)
+        .chars()
+        .map(|c| {
+            HOMOGLYPHS
+                .iter()
+                .find(|(homoglyph, _)| *homoglyph == c)
+                .map_or(c, |(_, latin)| *latin)
+        })
+        .collect()
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashSet, path::Path};
+
+use tan::{ann::Ann, expr::Expr, range::Range};
+
+use crate::{
+    ast::{for_each_expr, head_symbol},
+    config::Config,
+    imports::imported_name,
+    indent::whole_line_range,
+    module::{references_name, resolve_import, summarize_module},
+    resolve::{module_level_symbols, resolve_local_symbol_at},
+    vfs::VfsSnapshot,
+};
+
+// #Insight
+// The top-level definitions are exported by the module, they may be used by
+// other files, see `tan.unusedPublicSymbols`. Only the local `let` bindings
+// of the functions and the imports are reported as unused. The parameters are
+// not reported, they are part of the signature.
+
+/// What is unused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnusedKind {
+    /// A local `let` binding.
+    Binding,
+    /// A top-level `(use module)` import.
+    Import,
+}
+
+/// An unused binding or import of a document.
+#[derive(Debug)]
+pub struct Unused {
+    pub kind: UnusedKind,
+    /// The bound name, or the imported module name.
+    pub name: String,
+    /// The (char) range of the name.
+    pub name_range: Range,
+    /// The (char) range deleted to remove the binding or the import.
+    pub removal: Range,
+}
+
+/// Finds the unused local bindings and imports of a document, ordered by
+/// position. The imports are resolved relative to `base_dir`, if any.
+pub fn find_unused(
+    exprs: &[Ann<Expr>],
+    input: &str,
+    base_dir: Option<&Path>,
+    config: &Config,
+    vfs: &VfsSnapshot,
+) -> Vec<Unused> {
+    let chars: Vec<char> = input.chars().collect();
+
+    let mut unused = unused_bindings(exprs, &chars);
+    if let Some(base_dir) = base_dir {
+        unused.extend(unused_imports(exprs, &chars, base_dir, config, vfs));
+    }
+
+    unused.sort_by_key(|unused| unused.name_range.start);
+
+    unused
+}
+
+/// Returns the local `let` bindings that are never referenced. The names
+/// that start with `_` are unused on purpose.
+fn unused_bindings(exprs: &[Ann<Expr>], chars: &[char]) -> Vec<Unused> {
+    let mut lets = Vec::new();
+    for expr in exprs {
+        collect_local_lets(expr, false, &mut lets);
+    }
+
+    let declarations: HashSet<Range> = lets
+        .iter()
+        .flat_map(|let_expr| let_pairs(let_expr))
+        .map(|(name_expr, _)| name_expr.get_range())
+        .collect();
+
+    let mut used = HashSet::new();
+    for_each_expr(exprs, &mut |expr| {
+        let Ann(Expr::Symbol(_), _) = expr else {
+            return;
+        };
+        let range = expr.get_range();
+        if range.start >= range.end || declarations.contains(&range) {
+            return;
+        }
+        if let Some(binding) = resolve_local_symbol_at(exprs, range.start) {
+            used.insert(binding.name_range);
+        }
+    });
+
+    let mut unused = Vec::new();
+
+    for let_expr in lets {
+        let Ann(Expr::List(terms), _) = let_expr else {
+            continue;
+        };
+        for (i, (name_expr, value)) in let_pairs(let_expr).into_iter().enumerate() {
+            let Ann(Expr::Symbol(name), _) = name_expr else {
+                continue;
+            };
+            let name_range = name_expr.get_range();
+            if name.starts_with('_') || used.contains(&name_range) {
+                continue;
+            }
+
+            // A `let` with a single binding is removed, otherwise only the
+            // binding.
+            let removal = if terms.len() == 3 {
+                removal_range(chars, let_expr.get_range())
+            } else {
+                terms[2 * i].get_range().end..value.get_range().end
+            };
+
+            unused.push(Unused {
+                kind: UnusedKind::Binding,
+                name: name.clone(),
+                name_range,
+                removal,
+            });
+        }
+    }
+
+    unused
+}
+
+/// Collects the `let` expressions in the bodies of the functions.
+fn collect_local_lets<'a>(expr: &'a Ann<Expr>, in_function: bool, lets: &mut Vec<&'a Ann<Expr>>) {
+    let Ann(Expr::List(terms), _) = expr else {
+        return;
+    };
+
+    let head = head_symbol(expr);
+    if in_function && head == Some("let") {
+        lets.push(expr);
+    }
+
+    let in_function = in_function || matches!(head, Some("Func") | Some("Macro"));
+    for term in terms {
+        collect_local_lets(term, in_function, lets);
+    }
+}
+
+/// Returns the name and value pairs of a `let` expression.
+fn let_pairs(let_expr: &Ann<Expr>) -> Vec<(&Ann<Expr>, &Ann<Expr>)> {
+    let Ann(Expr::List(terms), _) = let_expr else {
+        return Vec::new();
+    };
+
+    terms[1..]
+        .chunks(2)
+        .filter_map(|pair| match pair {
+            [name_expr, value] => Some((name_expr, value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the top-level imports whose module exports are never referenced.
+/// The imports of modules that cannot be summarized are not reported.
+fn unused_imports(
+    exprs: &[Ann<Expr>],
+    chars: &[char],
+    base_dir: &Path,
+    config: &Config,
+    vfs: &VfsSnapshot,
+) -> Vec<Unused> {
+    let symbols: Vec<(&str, Range)> = exprs
+        .iter()
+        .filter(|expr| head_symbol(expr) != Some("use"))
+        .flat_map(|expr| module_level_symbols(exprs, std::slice::from_ref(expr)))
+        .collect();
+
+    let mut unused = Vec::new();
+
+    for use_expr in exprs {
+        let Some(module_name) = imported_name(use_expr) else {
+            continue;
+        };
+        let Some((module_path, _)) = resolve_import(module_name, base_dir, config) else {
+            continue;
+        };
+        let Ok(summary) = summarize_module(&module_path, vfs) else {
+            continue;
+        };
+        if summary.exports.is_empty()
+            || summary
+                .exports
+                .iter()
+                .any(|export| references_name(&symbols, export, module_name))
+        {
+            continue;
+        }
+
+        let Ann(Expr::List(terms), _) = use_expr else {
+            continue;
+        };
+        unused.push(Unused {
+            kind: UnusedKind::Import,
+            name: module_name.to_owned(),
+            name_range: terms[1].get_range(),
+            removal: removal_range(chars, use_expr.get_range()),
+        });
+    }
+
+    unused
+}
+
+/// Returns the (char) range deleted to remove a form: the whole lines of the
+/// form, and the empty line after it, if the form is alone between empty
+/// lines, e.g. the last import of a group. If the form shares its lines with
+/// other code, only the spaces after it are deleted.
+pub fn removal_range(chars: &[char], range: Range) -> Range {
+    let lines = whole_line_range(chars, range.clone());
+    let starts_line = lines.start == 0 || chars[lines.start - 1] == '\n';
+    let ends_line = lines.end == chars.len() || chars[lines.end - 1] == '\n';
+
+    if !starts_line || !ends_line {
+        let mut end = range.end;
+        while end < chars.len() && matches!(chars[end], ' ' | '\t') {
+            end += 1;
+        }
+        return range.start..end;
+    }
+
+    let after_empty_line = lines.start == 0 || is_empty_line_before(chars, lines.start);
+    if after_empty_line {
+        let mut end = lines.end;
+        while end < chars.len() && matches!(chars[end], ' ' | '\t') {
+            end += 1;
+        }
+        if end < chars.len() && chars[end] == '\n' {
+            return lines.start..end + 1;
+        }
+    }
+
+    lines
+}
+
+/// Returns true if the line that ends before the (char) index, at the start
+/// of a line, is empty.
+fn is_empty_line_before(chars: &[char], index: usize) -> bool {
+    chars[..index - 1]
+        .iter()
+        .rev()
+        .take_while(|ch| **ch != '\n')
+        .all(|ch| matches!(ch, ' ' | '\t'))
+}
@@ -0,0 +1,360 @@
+use std::sync::OnceLock;
+
+use lsp_types::PositionEncodingKind;
+use tan::range::Range;
+
+// #Insight
+// Tan ranges are char (code point) indices, LSP positions count code units
+// of the negotiated position encoding, UTF-16 by default. All positions are
+// converted with the helpers in this module.
+
+/// The encoding of the LSP position columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Selects the encoding from the encodings supported by the client, in
+    /// order of preference. UTF-32 matches the Tan ranges, no conversion is
+    /// needed.
+    pub fn negotiate(supported: Option<&[PositionEncodingKind]>) -> Self {
+        let supported = supported.unwrap_or_default();
+
+        if supported.contains(&PositionEncodingKind::UTF32) {
+            Self::Utf32
+        } else if supported.contains(&PositionEncodingKind::UTF8) {
+            Self::Utf8
+        } else {
+            Self::Utf16
+        }
+    }
+
+    pub fn kind(&self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// Returns the number of code units of a char.
+    pub fn len(&self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+}
+
+static POSITION_ENCODING: OnceLock<PositionEncoding> = OnceLock::new();
+
+/// Sets the negotiated position encoding, once, at initialization.
+pub fn set_position_encoding(encoding: PositionEncoding) {
+    let _ = POSITION_ENCODING.set(encoding);
+}
+
+/// Returns the negotiated position encoding.
+pub fn position_encoding() -> PositionEncoding {
+    POSITION_ENCODING
+        .get()
+        .copied()
+        .unwrap_or(PositionEncoding::Utf16)
+}
+
+/// Converts a (char) index into the input text to an LSP position.
+pub fn lsp_position_from_index(index: usize, input: &str) -> lsp_types::Position {
+    position_from_index(index, input, position_encoding())
+}
+
+fn position_from_index(
+    index: usize,
+    input: &str,
+    encoding: PositionEncoding,
+) -> lsp_types::Position {
+    let mut line = 0;
+    let mut character = 0;
+
+    for c in input.chars().take(index) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += encoding.len(c);
+        }
+    }
+
+    lsp_types::Position {
+        line,
+        character: character as u32,
+    }
+}
+
+/// Converts a (char) range into the input text to an LSP range.
+pub fn lsp_range_from_range(range: &Range, input: &str) -> lsp_types::Range {
+    lsp_types::Range {
+        start: lsp_position_from_index(range.start, input),
+        end: lsp_position_from_index(range.end, input),
+    }
+}
+
+/// Converts an LSP position to a (char) index into the input text.
+/// Positions past the end of a line are clamped to the end of the line, and
+/// positions within a char are moved to the start of the char.
+pub fn index_from_lsp_position(position: &lsp_types::Position, input: &str) -> usize {
+    index_from_position(position, input, position_encoding())
+}
+
+fn index_from_position(
+    position: &lsp_types::Position,
+    input: &str,
+    encoding: PositionEncoding,
+) -> usize {
+    let target = position.character as usize;
+    let mut index = 0;
+    let mut line = 0;
+    let mut col = 0;
+
+    for c in input.chars() {
+        if line == position.line && (c == '\n' || col + encoding.len(c) > target) {
+            break;
+        }
+
+        index += 1;
+
+        if c == '\n' {
+            line += 1;
+            col = 0;
+        } else {
+            col += encoding.len(c);
+        }
+    }
+
+    index
+}
+
+/// The (char) indices of the line starts of an input text, converts indices
+/// to positions without re-scanning the input. Use it when converting many
+/// indices of the same input.
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    /// The (char) indices of the chars with more than one code unit, and the
+    /// number of extra code units.
+    wide_chars: Vec<(usize, usize)>,
+}
+
+impl LineIndex {
+    pub fn new(input: &str) -> Self {
+        Self::with_encoding(input, position_encoding())
+    }
+
+    fn with_encoding(input: &str, encoding: PositionEncoding) -> Self {
+        let mut line_starts = vec![0];
+        let mut wide_chars = Vec::new();
+
+        for (i, c) in input.chars().enumerate() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            } else if encoding.len(c) > 1 {
+                wide_chars.push((i, encoding.len(c) - 1));
+            }
+        }
+
+        Self {
+            line_starts,
+            wide_chars,
+        }
+    }
+
+    /// Converts a (char) index to an LSP position.
+    pub fn position(&self, index: usize) -> lsp_types::Position {
+        let line = self.line_starts.partition_point(|&start| start <= index) - 1;
+        let line_start = self.line_starts[line];
+
+        let from = self.wide_chars.partition_point(|(i, _)| *i < line_start);
+        let to = self.wide_chars.partition_point(|(i, _)| *i < index);
+        let extra: usize = self.wide_chars[from..to].iter().map(|(_, n)| n).sum();
+
+        lsp_types::Position {
+            line: line as u32,
+            character: (index - line_start + extra) as u32,
+        }
+    }
+
+    /// Converts a (char) line and column, e.g. a `tan::range::Position`, to an
+    /// LSP position.
+    pub fn position_from_line_col(&self, line: usize, col: usize) -> lsp_types::Position {
+        let line_start = self.line_starts.get(line).copied().unwrap_or_default();
+        self.position(line_start + col)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use super::*;
+
+    /// A crab, and a ZWJ sequence (woman, zero width joiner, laptop) at the
+    /// end of a line.
+    const INPUT: &str = "a🦀b\n👩\u{200d}💻\n";
+
+    const ENCODINGS: [PositionEncoding; 3] = [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ];
+
+    /// The positions of the (char) indices of `INPUT`, in an encoding.
+    fn expected_positions(encoding: PositionEncoding) -> Vec<Position> {
+        let columns: [(u32, u32); 9] = match encoding {
+            PositionEncoding::Utf8 => [
+                (0, 0),
+                (0, 1),
+                (0, 5),
+                (0, 6),
+                (1, 0),
+                (1, 4),
+                (1, 7),
+                (1, 11),
+                (2, 0),
+            ],
+            PositionEncoding::Utf16 => [
+                (0, 0),
+                (0, 1),
+                (0, 3),
+                (0, 4),
+                (1, 0),
+                (1, 2),
+                (1, 3),
+                (1, 5),
+                (2, 0),
+            ],
+            PositionEncoding::Utf32 => [
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (0, 3),
+                (1, 0),
+                (1, 1),
+                (1, 2),
+                (1, 3),
+                (2, 0),
+            ],
+        };
+        columns
+            .into_iter()
+            .map(|(line, character)| Position { line, character })
+            .collect()
+    }
+
+    #[test]
+    fn position_from_index_counts_the_code_units() {
+        for encoding in ENCODINGS {
+            for (index, expected) in expected_positions(encoding).into_iter().enumerate() {
+                assert_eq!(
+                    position_from_index(index, INPUT, encoding),
+                    expected,
+                    "{encoding:?} {index}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn index_from_position_is_the_inverse_of_position_from_index() {
+        for encoding in ENCODINGS {
+            for (index, position) in expected_positions(encoding).into_iter().enumerate() {
+                assert_eq!(
+                    index_from_position(&position, INPUT, encoding),
+                    index,
+                    "{encoding:?} {position:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn index_from_position_moves_to_the_start_of_the_char() {
+        let within_crab = [(PositionEncoding::Utf8, 3), (PositionEncoding::Utf16, 2)];
+        for (encoding, character) in within_crab {
+            let position = Position { line: 0, character };
+            assert_eq!(index_from_position(&position, INPUT, encoding), 1);
+        }
+
+        // Within the laptop, after the joiner.
+        let position = Position {
+            line: 1,
+            character: 8,
+        };
+        assert_eq!(
+            index_from_position(&position, INPUT, PositionEncoding::Utf8),
+            6
+        );
+        let position = Position {
+            line: 1,
+            character: 4,
+        };
+        assert_eq!(
+            index_from_position(&position, INPUT, PositionEncoding::Utf16),
+            6
+        );
+    }
+
+    #[test]
+    fn index_from_position_clamps_to_the_end_of_the_line() {
+        for encoding in ENCODINGS {
+            let position = Position {
+                line: 0,
+                character: 100,
+            };
+            assert_eq!(index_from_position(&position, INPUT, encoding), 3);
+            let position = Position {
+                line: 1,
+                character: 100,
+            };
+            assert_eq!(index_from_position(&position, INPUT, encoding), 7);
+            let position = Position {
+                line: 5,
+                character: 0,
+            };
+            assert_eq!(index_from_position(&position, INPUT, encoding), 8);
+        }
+    }
+
+    #[test]
+    fn line_index_matches_position_from_index() {
+        for encoding in ENCODINGS {
+            let line_index = LineIndex::with_encoding(INPUT, encoding);
+            for (index, expected) in expected_positions(encoding).into_iter().enumerate() {
+                assert_eq!(line_index.position(index), expected, "{encoding:?} {index}");
+            }
+        }
+    }
+
+    #[test]
+    fn line_index_converts_the_line_columns() {
+        for encoding in ENCODINGS {
+            let line_index = LineIndex::with_encoding(INPUT, encoding);
+            let expected = expected_positions(encoding);
+            assert_eq!(line_index.position_from_line_col(0, 2), expected[2]);
+            // The end of the ZWJ sequence, at the end of the line.
+            assert_eq!(line_index.position_from_line_col(1, 3), expected[7]);
+        }
+    }
+
+    #[test]
+    fn line_index_converts_the_end_of_an_input_without_newline() {
+        let input = "x🦀";
+        for encoding in ENCODINGS {
+            let line_index = LineIndex::with_encoding(input, encoding);
+            let expected = position_from_index(2, input, encoding);
+            assert_eq!(line_index.position(2), expected);
+            assert_eq!(expected.character as usize, 1 + encoding.len('🦀'));
+            assert_eq!(index_from_position(&expected, input, encoding), 2);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::HashMap, sync::Arc};
+
+use lsp_types::{Position, TextDocumentContentChangeEvent, Url};
+use ropey::Rope;
+use tracing::warn;
+
+use crate::util::{position_encoding, PositionEncoding};
+
+// #Insight
+// The documents map is shared with the snapshots. It is copied-on-write, only
+// if a snapshot is still alive when the map is updated.
+
+// #Insight
+// The changes are applied to a rope, the text is copied once per change
+// notification (not per change) for the handlers, that work on `&str`.
+
+/// A document open in the editor.
+#[derive(Clone)]
+pub struct Document {
+    pub text: Arc<str>,
+    /// The version of the document, increased by the client on every change.
+    pub version: i32,
+    rope: Rope,
+}
+
+impl Document {
+    fn new(text: String, version: i32) -> Self {
+        Self {
+            rope: Rope::from_str(&text),
+            text: Arc::from(text),
+            version,
+        }
+    }
+
+    /// Applies the content changes, in order. A change without a range
+    /// replaces the whole text.
+    fn apply_changes(
+        &mut self,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        version: i32,
+        encoding: PositionEncoding,
+    ) {
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = char_index(&self.rope, &range.start, encoding);
+                    let end = char_index(&self.rope, &range.end, encoding).max(start);
+                    self.rope.remove(start..end);
+                    self.rope.insert(start, &change.text);
+                }
+                None => self.rope = Rope::from_str(&change.text),
+            }
+        }
+
+        self.text = Arc::from(self.rope.to_string());
+        self.version = version;
+    }
+}
+
+/// Converts an LSP position to a (char) index into the rope, with the same
+/// clamping as `index_from_lsp_position`.
+fn char_index(rope: &Rope, position: &Position, encoding: PositionEncoding) -> usize {
+    let line = position.line as usize;
+    if line >= rope.len_lines() {
+        return rope.len_chars();
+    }
+
+    let target = position.character as usize;
+    let mut index = rope.line_to_char(line);
+    let mut col = 0;
+
+    for c in rope.line(line).chars() {
+        if c == '\n' || col + encoding.len(c) > target {
+            break;
+        }
+        index += 1;
+        col += encoding.len(c);
+    }
+
+    index
+}
+
+/// The documents open in the editor, keyed by uri.
+pub type Documents = HashMap<Url, Document>;
+
+/// The virtual file system, keeps the text of the documents that are open in
+/// the editor. The open documents shadow the files on disk.
+#[derive(Default)]
+pub struct Vfs {
+    documents: Arc<Documents>,
+}
+
+impl Vfs {
+    pub fn open(&mut self, uri: Url, text: String, version: i32) {
+        Arc::make_mut(&mut self.documents).insert(uri, Document::new(text, version));
+    }
+
+    /// Applies the (incremental or full) changes to an open document.
+    pub fn update(
+        &mut self,
+        uri: &Url,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        version: i32,
+    ) {
+        let Some(document) = Arc::make_mut(&mut self.documents).get_mut(uri) else {
+            warn!("cannot change `{uri}`, the document is not open");
+            return;
+        };
+
+        document.apply_changes(changes, version, position_encoding());
+    }
+
+    pub fn close(&mut self, uri: &Url) {
+        Arc::make_mut(&mut self.documents).remove(uri);
+    }
+
+    /// Returns the number of open documents.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Returns true if no document is open.
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Returns an immutable view of the open documents.
+    pub fn snapshot(&self) -> VfsSnapshot {
+        VfsSnapshot {
+            documents: self.documents.clone(),
+        }
+    }
+}
+
+/// An immutable view of the virtual file system.
+#[derive(Clone)]
+pub struct VfsSnapshot {
+    documents: Arc<Documents>,
+}
+
+impl VfsSnapshot {
+    /// Returns the text of an open document.
+    pub fn get(&self, uri: &Url) -> Option<Arc<str>> {
+        self.documents
+            .get(uri)
+            .map(|document| document.text.clone())
+    }
+
+    /// Returns the version of an open document.
+    pub fn version(&self, uri: &Url) -> Option<i32> {
+        self.documents.get(uri).map(|document| document.version)
+    }
+
+    /// Returns the open documents.
+    pub fn documents(&self) -> impl Iterator<Item = (&Url, &Arc<str>)> {
+        self.documents
+            .iter()
+            .map(|(uri, document)| (uri, &document.text))
+    }
+
+    // #Insight
+    // The file system may be slow (e.g. network file systems), documents that
+    // are not open should be read in worker threads, to avoid stalling the
+    // message loop.
+
+    /// Reads the text of a document, from the editor if the document is open,
+    /// or from disk otherwise.
+    pub fn read(&self, uri: &Url) -> anyhow::Result<Arc<str>> {
+        if let Some(text) = self.get(uri) {
+            return Ok(text);
+        }
+
+        let path = uri
+            .to_file_path()
+            .map_err(|()| anyhow::anyhow!("`{uri}` is not a file path"))?;
+
+        Ok(Arc::from(std::fs::read_to_string(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Range;
+
+    use super::*;
+
+    /// A crab, and a ZWJ sequence (woman, zero width joiner, laptop) at the
+    /// end of a line.
+    const TEXT: &str = "a🦀b\n👩\u{200d}💻\n";
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
+            }),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn char_index_counts_the_code_units() {
+        let rope = Rope::from_str(TEXT);
+        let cases = [
+            (
+                PositionEncoding::Utf8,
+                [(0, 5, 2), (0, 6, 3), (1, 4, 5), (1, 11, 7)],
+            ),
+            (
+                PositionEncoding::Utf16,
+                [(0, 3, 2), (0, 4, 3), (1, 2, 5), (1, 5, 7)],
+            ),
+            (
+                PositionEncoding::Utf32,
+                [(0, 2, 2), (0, 3, 3), (1, 1, 5), (1, 3, 7)],
+            ),
+        ];
+
+        for (encoding, positions) in cases {
+            for (line, character, index) in positions {
+                let position = Position::new(line, character);
+                assert_eq!(
+                    char_index(&rope, &position, encoding),
+                    index,
+                    "{encoding:?} {position:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn char_index_clamps_the_positions() {
+        let rope = Rope::from_str(TEXT);
+        // Within the crab.
+        assert_eq!(
+            char_index(&rope, &Position::new(0, 3), PositionEncoding::Utf8),
+            1
+        );
+        assert_eq!(
+            char_index(&rope, &Position::new(0, 2), PositionEncoding::Utf16),
+            1
+        );
+
+        // Past the end of the line, past the last line.
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            assert_eq!(char_index(&rope, &Position::new(1, 100), encoding), 7);
+            assert_eq!(char_index(&rope, &Position::new(5, 0), encoding), 8);
+        }
+    }
+
+    #[test]
+    fn apply_changes_replaces_the_astral_chars() {
+        let cases = [
+            (PositionEncoding::Utf8, [(0, 1), (0, 5), (1, 11)]),
+            (PositionEncoding::Utf16, [(0, 1), (0, 3), (1, 5)]),
+            (PositionEncoding::Utf32, [(0, 1), (0, 2), (1, 3)]),
+        ];
+
+        for (encoding, [crab_start, crab_end, line_end]) in cases {
+            let mut document = Document::new(TEXT.to_owned(), 1);
+            let changes = vec![
+                // Appended at the end of the line of the ZWJ sequence.
+                change(line_end, line_end, "!"),
+                change(crab_start, crab_end, "c"),
+            ];
+            document.apply_changes(changes, 2, encoding);
+
+            assert_eq!(&*document.text, "acb\n👩\u{200d}💻!\n", "{encoding:?}");
+            assert_eq!(document.version, 2);
+        }
+    }
+
+    #[test]
+    fn apply_changes_removes_the_zwj_sequence() {
+        let mut document = Document::new(TEXT.to_owned(), 1);
+        let changes = vec![change((1, 0), (1, 5), "")];
+        document.apply_changes(changes, 2, PositionEncoding::Utf16);
+
+        assert_eq!(&*document.text, "a🦀b\n\n");
+    }
+
+    #[test]
+    fn update_applies_the_incremental_and_full_changes() {
+        let uri = Url::parse("file:///workspace/main.tan").unwrap();
+        let mut vfs = Vfs::default();
+        vfs.open(uri.clone(), TEXT.to_owned(), 1);
+
+        // The default encoding is UTF-16.
+        vfs.update(&uri, vec![change((0, 3), (0, 3), "🦀")], 2);
+        let snapshot = vfs.snapshot();
+        assert_eq!(
+            snapshot.get(&uri).as_deref(),
+            Some("a🦀🦀b\n👩\u{200d}💻\n")
+        );
+        assert_eq!(snapshot.version(&uri), Some(2));
+
+        let full = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "🦀".to_owned(),
+        };
+        vfs.update(&uri, vec![full, change((0, 2), (0, 2), "!")], 3);
+        assert_eq!(vfs.snapshot().get(&uri).as_deref(), Some("🦀!"));
+        // The earlier snapshot is not changed.
+        assert_eq!(
+            snapshot.get(&uri).as_deref(),
+            Some("a🦀🦀b\n👩\u{200d}💻\n")
+        );
+    }
+}